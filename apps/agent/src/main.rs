@@ -1,3 +1,4 @@
+use elafry::types::management::{Command, Request, Response, CHANNEL};
 use elafry::Component;
 
 struct Agent {
@@ -16,58 +17,58 @@ impl elafry::Component for Agent {
     fn run(&mut self, services: &mut elafry::Services) {
         self.loop_count += 1;
 
+        // log replies from the runner
+        while let Some(message) = services.communication.get_message(CHANNEL) {
+            match Response::decode(&message.data) {
+                Some(response) => log::info!("Runner replied {:?}", response),
+                None => log::error!("Failed to decode runner reply"),
+            }
+        }
+
         // PLANT CONFIGURATION
 
         // when loop_count is 5000, send a message
         if self.loop_count == 5000 && self.plant {
             log::info!("-----Plant Configuration 1-----");
-            let control_data_buf = "plant_1.yaml".as_bytes().to_vec();
-            services.communication.send_message(1, control_data_buf);
+            self.request(services, Command::Load("plant_1.yaml".to_string()));
         }
         // when loop_count is 25000, send a message
         if self.loop_count == 15000 && self.plant {
-        log::info!("-----Plant Configuration 2-----");
-        let control_data_buf = "plant_2.yaml".as_bytes().to_vec();
-        services.communication.send_message(1, control_data_buf);
+            log::info!("-----Plant Configuration 2-----");
+            self.request(services, Command::Load("plant_2.yaml".to_string()));
         }
         // when loop_count is 45000, send a message
         if self.loop_count == 45000 && self.plant {
-        log::info!("-----Plant Configuration 3-----");
-        let control_data_buf = "plant_3.yaml".as_bytes().to_vec();
-        services.communication.send_message(1, control_data_buf);
+            log::info!("-----Plant Configuration 3-----");
+            self.request(services, Command::Load("plant_3.yaml".to_string()));
         }
         // when loop_count is 60000, send a message
         if self.loop_count == 60000 && self.plant {
-        log::info!("-----END-----");
-        let control_data_buf = "kill".as_bytes().to_vec();
-        services.communication.send_message(0, control_data_buf);
+            log::info!("-----END-----");
+            self.request(services, Command::Shutdown);
         }
 
         // DEMO CONFIGURATION
 
         // when loop_count is 5, send a message
         if self.loop_count == 5 && !self.plant {
-        log::info!("-----Demo Configuration 1-----");
-        let control_data_buf = "demo_1.yaml".as_bytes().to_vec();
-        services.communication.send_message(1, control_data_buf);
+            log::info!("-----Demo Configuration 1-----");
+            self.request(services, Command::Load("demo_1.yaml".to_string()));
         }
         // when loop_count is 1005, send a message
         if self.loop_count == 1005 && !self.plant {
-        log::info!("-----Demo Configuration 2-----");
-        let control_data_buf = "demo_2.yaml".as_bytes().to_vec();
-        services.communication.send_message(1, control_data_buf);
+            log::info!("-----Demo Configuration 2-----");
+            self.request(services, Command::Load("demo_2.yaml".to_string()));
         }
         // when loop_count is 2005, send a message
         if self.loop_count == 2005 && !self.plant {
-        log::info!("-----Demo Configuration 3-----");
-        let control_data_buf = "demo_3.yaml".as_bytes().to_vec();
-        services.communication.send_message(1, control_data_buf);
+            log::info!("-----Demo Configuration 3-----");
+            self.request(services, Command::Load("demo_3.yaml".to_string()));
         }
         // when loop_count is 2100, send a message
         if self.loop_count == 2100 && !self.plant {
-        log::info!("-----END-----");
-        let control_data_buf = "kill".as_bytes().to_vec();
-        services.communication.send_message(0, control_data_buf);
+            log::info!("-----END-----");
+            self.request(services, Command::Shutdown);
         }
     }
 
@@ -84,6 +85,16 @@ impl elafry::Component for Agent {
     }
 }
 
+impl Agent {
+    fn request(&self, services: &mut elafry::Services, command: Command) {
+        // the loop count is unique enough to match replies to requests
+        let request = Request::new(self.loop_count as u64, command);
+        services
+            .communication
+            .send_message(CHANNEL, request.encode());
+    }
+}
+
 fn main() {
     elafry::run(Agent::new());
 }
\ No newline at end of file
@@ -53,9 +53,7 @@ impl PIDController {
         // use kn to filter the derivative term
         let filtered_derivative = self.kn * derivative;
 
-        let pid_output = self.kp * error + self.ki * self.integral + self.kd * filtered_derivative;
-
-        pid_output
+        self.kp * error + self.ki * self.integral + self.kd * filtered_derivative
     }
 }
 
@@ -109,7 +107,7 @@ impl elafry::Component for FcsB {
         }
 
         // do stuff
-        self.state.thrust = self.state.pid_controller.compute(self.state.position).max(0.0).min(100.0);
+        self.state.thrust = self.state.pid_controller.compute(self.state.position).clamp(0.0, 100.0);
 
         // send message
         let control_data = ControlData {
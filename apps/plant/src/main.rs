@@ -16,21 +16,21 @@ pub struct ControlData {
 }
 
 struct PlantModel {
-    last_update: std::time::Instant,
+    last_update: Option<std::time::Duration>,
 }
 
 impl PlantModel {
     fn new() -> PlantModel {
-        PlantModel {
-            last_update: std::time::Instant::now(),
-        }
+        PlantModel { last_update: None }
     }
 
-    fn update(&mut self, position: &mut f64, velocity: &mut f64, thrust: f64) {
-        // calculate dt
-        let now = std::time::Instant::now();
-        let dt = now.duration_since(self.last_update).as_secs_f64();
-        self.last_update = now;
+    fn update(&mut self, now: std::time::Duration, position: &mut f64, velocity: &mut f64, thrust: f64) {
+        // calculate dt, time comes from the runner so a simulation gives the same results every run
+        let dt = match self.last_update {
+            Some(last_update) => now.saturating_sub(last_update).as_secs_f64(),
+            None => 0.0,
+        };
+        self.last_update = Some(now);
 
         let gravity = 9.81;
         let mass = 1.0;
@@ -109,8 +109,9 @@ impl elafry::Component for Plant {
         }
 
         // do stuff
+        let now = services.time.now();
         self.state.state_count += 1;
-        self.plant_model.update(&mut self.state.position, &mut self.state.velocity, self.state.thrust);
+        self.plant_model.update(now, &mut self.state.position, &mut self.state.velocity, self.state.thrust);
 
         // at 200, set setpoint to 50
         if self.state.state_count == 200 {
@@ -132,10 +133,7 @@ impl elafry::Component for Plant {
         //     self.state.setpoint = 10.0;
         // }
 
-        let timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_micros() as u64;
+        let timestamp = now.as_micros() as u64;
 
         // form sensor data
         let sensor_data = SensorData {
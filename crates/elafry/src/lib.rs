@@ -1,4 +1,8 @@
+pub mod loader;
 pub mod services;
+pub mod shutdown;
+pub mod signature;
+pub mod telemetry;
 pub mod types;
 
 pub trait Component {
@@ -7,11 +11,14 @@ pub trait Component {
     fn load_state(&mut self, data: Vec<u8>);
     fn save_state(&self) -> Vec<u8>;
     fn reset_state(&mut self);
+    /// Called once when the runner shuts down, messages sent here are still delivered.
+    fn on_shutdown(&mut self, _services: &mut Services) {}
 }
 
 pub struct Services {
     pub communication: services::communication::Manager,
     pub state: services::state::Manager,
+    pub time: services::time::Manager,
 }
 
 use std::os::{
@@ -24,6 +31,11 @@ pub fn run<T: Component + 'static>(mut component: T) {
 
     log::info!("Starting component");
 
+    // the runner asks components to stop with SIGTERM
+    if let Err(e) = shutdown::install() {
+        log::error!("{}", e);
+    }
+
     // establish socket with parent
     let child_data_socket_fd: RawFd = unsafe { std::os::unix::io::FromRawFd::from_raw_fd(10) };
     let child_state_socket_fd: RawFd = unsafe { std::os::unix::io::FromRawFd::from_raw_fd(11) };
@@ -36,10 +48,17 @@ pub fn run<T: Component + 'static>(mut component: T) {
     let child_state_socket = unsafe { UnixStream::from_raw_fd(child_state_socket_fd) };
     child_state_socket.set_nonblocking(true).unwrap();
 
+    // a simulating runner passes the clock it advances
+    let clock = std::env::var("ELAFRY_CLOCK")
+        .ok()
+        .and_then(|fd| fd.parse::<RawFd>().ok())
+        .map(|fd| unsafe { std::fs::File::from_raw_fd(fd) });
+
     // setup services
     let mut services = Services {
         communication: services::communication::Manager::new(child_data_socket),
         state: services::state::Manager::new(child_state_socket),
+        time: services::time::Manager::new(clock),
     };
 
     // initialize the component
@@ -52,29 +71,38 @@ pub fn run<T: Component + 'static>(mut component: T) {
     log::debug!("Instrumentation enabled");
 
     #[cfg(feature = "instrument")]
-    let mut times = Vec::new();
+    let (mut recorder, component_id, mut frame) = {
+        // the runner tells the component its id so records can be matched up
+        let component_id = std::env::var("ELAFRY_COMPONENT_ID")
+            .ok()
+            .and_then(|id| uuid::Uuid::parse_str(&id).ok())
+            .unwrap_or_default();
+        let path = format!("telemetry-{}.bin", component_id);
+        let recorder = telemetry::Recorder::new(&path, 4096).expect("Failed to open telemetry");
+        (recorder, component_id, 0u64)
+    };
 
     // do work
     loop {
         // log::info!("Running component");
 
-        // suspend self
-        let pid = unsafe { libc::getpid() };
-        if unsafe { libc::kill(pid, libc::SIGSTOP) } != 0 {
-            panic!("Failed to suspend child");
+        // suspend self, unless the runner is already waiting for us to stop
+        if !shutdown::requested() {
+            let pid = unsafe { libc::getpid() };
+            if unsafe { libc::kill(pid, libc::SIGSTOP) } != 0 {
+                panic!("Failed to suspend child");
+            }
         }
 
         // log::info!("Resumed");
 
-        #[cfg(feature = "instrument")]
-        {
-            let timestamp = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_micros() as u64;
-            times.push(timestamp);
+        if shutdown::requested() {
+            break;
         }
 
+        #[cfg(feature = "instrument")]
+        let start = telemetry::now();
+
         // run the services
         services.state.run();
         services.communication.run();
@@ -84,6 +112,30 @@ pub fn run<T: Component + 'static>(mut component: T) {
         component.run(&mut services);
         services.state.set_data(component.save_state());
 
+        #[cfg(feature = "instrument")]
+        {
+            recorder.record(telemetry::Record {
+                frame,
+                span: telemetry::Span::Component(component_id),
+                start,
+                end: telemetry::now(),
+                value: 0,
+            });
+            frame += 1;
+        }
+
         // log::info!("Component done");
     }
+
+    log::info!("Shutting down component");
+
+    services.state.run();
+    services.communication.run();
+    component.load_state(services.state.get_data());
+    component.on_shutdown(&mut services);
+
+    #[cfg(feature = "instrument")]
+    recorder.close();
+
+    std::process::exit(0);
 }
@@ -0,0 +1,229 @@
+//! The file formats configurations can be written in.
+//!
+//! Every format is read into a yaml value so names, includes and templates
+//! work the same in all of them. Enums can be written as yaml tags,
+//! `actions: !blocking [...]`, or as a mapping with the variant as its only
+//! key, `"actions": {"blocking": [...]}`, which is what serde produces for
+//! json and toml.
+
+use std::fmt;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde_yaml::Value;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Yaml => write!(f, "yaml"),
+            Format::Json => write!(f, "json"),
+            Format::Toml => write!(f, "toml"),
+        }
+    }
+}
+
+impl Format {
+    /// The format of a file from its extension, or its content if the extension is unknown.
+    pub fn of(path: &Path, text: &str) -> Format {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml") | Some("yml") => Format::Yaml,
+            Some("json") => Format::Json,
+            Some("toml") => Format::Toml,
+            _ => Format::sniff(text),
+        }
+    }
+
+    /// Guess the format from the first line that is not blank or a comment.
+    pub fn sniff(text: &str) -> Format {
+        let line = text
+            .lines()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .unwrap_or("");
+
+        if line.starts_with('{') {
+            return Format::Json;
+        }
+
+        // toml starts with a table header or a `key = value` pair
+        let header = line.trim_start_matches('[').trim_end_matches(']');
+        let is_key = |key: &str| {
+            !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        };
+        if (line.starts_with('[') && line.ends_with(']') && is_key(header))
+            || line
+                .split_once('=')
+                .is_some_and(|(key, _)| is_key(key.trim()))
+        {
+            return Format::Toml;
+        }
+
+        Format::Yaml
+    }
+
+    pub fn parse(self, text: &str) -> Result<Value, String> {
+        let value = match self {
+            Format::Yaml => serde_yaml::from_str(text).map_err(|e| e.to_string()),
+            Format::Json => serde_json::from_str::<serde_json::Value>(text)
+                .map_err(|e| e.to_string())
+                .and_then(|value| serde_yaml::to_value(value).map_err(|e| e.to_string())),
+            Format::Toml => toml::from_str::<toml::Value>(text)
+                .map_err(|e| e.to_string())
+                .and_then(|value| serde_yaml::to_value(value).map_err(|e| e.to_string())),
+        };
+
+        value.map_err(|e| format!("Failed to parse {}; err = {}", self, e))
+    }
+}
+
+/// Deserialize a value read from any format into the configuration types.
+pub fn deserialize<T: DeserializeOwned>(value: Value) -> Result<T, String> {
+    serde_json::from_value(json(value)?).map_err(|e| e.to_string())
+}
+
+/// Convert to json, which reads enums from both tags and single key mappings.
+fn json(value: Value) -> Result<serde_json::Value, String> {
+    Ok(match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Bool(value) => serde_json::Value::Bool(value),
+        Value::Number(number) => {
+            if let Some(number) = number.as_u64() {
+                number.into()
+            } else if let Some(number) = number.as_i64() {
+                number.into()
+            } else {
+                number.as_f64().map_or(serde_json::Value::Null, Into::into)
+            }
+        }
+        Value::String(value) => serde_json::Value::String(value),
+        Value::Sequence(sequence) => serde_json::Value::Array(
+            sequence
+                .into_iter()
+                .map(json)
+                .collect::<Result<_, String>>()?,
+        ),
+        Value::Mapping(mapping) => {
+            let mut object = serde_json::Map::new();
+            for (key, value) in mapping {
+                let key = match key {
+                    Value::String(key) => key,
+                    Value::Number(key) => key.to_string(),
+                    Value::Bool(key) => key.to_string(),
+                    key => return Err(format!("Mapping key {:?} is not a string", key)),
+                };
+                object.insert(key, json(value)?);
+            }
+            serde_json::Value::Object(object)
+        }
+        Value::Tagged(tagged) => {
+            let tag = tagged.tag.to_string();
+            let mut object = serde_json::Map::new();
+            object.insert(tag.trim_start_matches('!').to_string(), json(tagged.value)?);
+            serde_json::Value::Object(object)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::from_text;
+    use crate::types::configuration::{Action, Configuration};
+    use crate::types::desired::Document;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_sniff() {
+        setup();
+
+        assert_eq!(Format::sniff("{\"tasks\": []}"), Format::Json);
+        assert_eq!(Format::sniff("\n# comment\n  {\n"), Format::Json);
+        assert_eq!(Format::sniff("tasks = []\n"), Format::Toml);
+        assert_eq!(Format::sniff("[[tasks]]\nid = \"x\"\n"), Format::Toml);
+        assert_eq!(Format::sniff("[names.components]\n"), Format::Toml);
+        assert_eq!(Format::sniff("tasks: []\n"), Format::Yaml);
+        assert_eq!(Format::sniff("- [a, b]\n"), Format::Yaml);
+        assert_eq!(Format::sniff("[1, 2]\n"), Format::Yaml);
+        assert_eq!(Format::sniff(""), Format::Yaml);
+
+        assert_eq!(
+            Format::of(Path::new("plant.json"), "tasks: []"),
+            Format::Json
+        );
+        assert_eq!(Format::of(Path::new("plant.yml"), "{}"), Format::Yaml);
+        assert_eq!(Format::of(Path::new("plant"), "tasks = []"), Format::Toml);
+    }
+
+    #[test]
+    fn test_formats_without_tags() {
+        setup();
+
+        let json = r#"{
+            "names": {"components": {"plant": "9a069153-c335-47c5-a653-dec6c0e7c280"}},
+            "tasks": [{
+                "id": "6acdc42f-fb43-418d-b12f-1b7b0cbafba6",
+                "actions": {"blocking": [{
+                    "id": "4e682edb-22f1-42bd-995b-6282f114688e",
+                    "data": {"add-route": {
+                        "source": {"endpoint": {"component-id": "plant"}, "channel-id": 0},
+                        "target": {"endpoint": "runner", "channel-id": 0}
+                    }}
+                }]}
+            }]
+        }"#;
+        let toml = r#"
+            [names.components]
+            plant = "9a069153-c335-47c5-a653-dec6c0e7c280"
+
+            [[tasks]]
+            id = "6acdc42f-fb43-418d-b12f-1b7b0cbafba6"
+
+            [[tasks.actions.blocking]]
+            id = "4e682edb-22f1-42bd-995b-6282f114688e"
+
+            [tasks.actions.blocking.data.add-route]
+            source = { endpoint = { component-id = "plant" }, channel-id = 0 }
+            target = { endpoint = "runner", channel-id = 0 }
+        "#;
+        // yaml may be written without tags as well
+        let yaml = "names:\n  components:\n    plant: 9a069153-c335-47c5-a653-dec6c0e7c280\ntasks:\n  - id: 6acdc42f-fb43-418d-b12f-1b7b0cbafba6\n    actions:\n      blocking:\n        - id: 4e682edb-22f1-42bd-995b-6282f114688e\n          data:\n            add-route:\n              source: {endpoint: {component-id: plant}, channel-id: 0}\n              target: {endpoint: runner, channel-id: 0}\n";
+        let tagged = "names:\n  components:\n    plant: 9a069153-c335-47c5-a653-dec6c0e7c280\ntasks:\n  - id: 6acdc42f-fb43-418d-b12f-1b7b0cbafba6\n    actions: !blocking\n      - id: 4e682edb-22f1-42bd-995b-6282f114688e\n        data: !add-route\n          source: {endpoint: !component-id plant, channel-id: 0}\n          target: {endpoint: !runner , channel-id: 0}\n";
+
+        let expected = from_text(tagged, Format::Yaml).unwrap();
+        match &expected {
+            Document::Tasks(Configuration { tasks }) => match &tasks[0].actions {
+                Action::Blocking(actions) => assert_eq!(actions.len(), 1),
+                Action::NonBlocking(_) => panic!("expected blocking actions"),
+            },
+            document => panic!("unexpected document {:?}", document),
+        }
+        assert_eq!(from_text(json, Format::Json).unwrap(), expected);
+        assert_eq!(from_text(toml, Format::Toml).unwrap(), expected);
+        assert_eq!(from_text(yaml, Format::Yaml).unwrap(), expected);
+
+        assert!(from_text("{\"tasks\": [", Format::Json)
+            .unwrap_err()
+            .starts_with("Failed to parse json"));
+        assert!(from_text("tasks = [", Format::Toml)
+            .unwrap_err()
+            .starts_with("Failed to parse toml"));
+    }
+}
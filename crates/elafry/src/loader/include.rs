@@ -0,0 +1,279 @@
+//! Sharing definitions between configuration files.
+//!
+//! A file may list other files under `include`:
+//!
+//! ```yaml
+//! include:
+//!   - include/plant.yaml
+//! ```
+//!
+//! The `names`, `variables` and `templates` of the included files are added to
+//! those of the including file, included files may include others in turn.
+//! Paths are relative to the directory of the file first loaded, so shared
+//! files are found in the same place however deeply they are included. A
+//! definition may appear in several files as long as it is the same everywhere.
+
+use std::path::{Component, Path, PathBuf};
+
+use serde_yaml::{Mapping, Value};
+
+use super::format::Format;
+
+/// Sections an included file may define.
+const SECTIONS: [&str; 3] = ["names", "variables", "templates"];
+
+/// Read a file in any of the supported formats.
+pub fn read(path: &Path) -> Result<Value, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to open {}; err = {}", path.display(), e))?;
+    Format::of(path, &text)
+        .parse(&text)
+        .map_err(|e| format!("Failed to read {}; err = {}", path.display(), e))
+}
+
+/// Merge the definitions of included files and drop the `include` section.
+///
+/// Without a directory the file came from elsewhere and may not include others.
+pub fn resolve(value: &mut Value, directory: Option<&Path>) -> Result<(), String> {
+    resolve_from(value, directory, &mut vec![])
+}
+
+fn resolve_from(
+    value: &mut Value,
+    directory: Option<&Path>,
+    stack: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    let includes = match value
+        .as_mapping_mut()
+        .and_then(|mapping| mapping.remove("include"))
+    {
+        Some(includes) => serde_yaml::from_value::<Vec<String>>(includes)
+            .map_err(|e| format!("Invalid include section; err = {}", e))?,
+        None => return Ok(()),
+    };
+    let directory = directory.ok_or("Includes need the configuration to be read from a file")?;
+
+    for include in includes {
+        // stay inside the configuration directory
+        let relative = Path::new(&include);
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(format!(
+                "Include {} must be a relative path inside the configuration directory",
+                include
+            ));
+        }
+        let path = directory.join(relative);
+        if stack.contains(&path) {
+            return Err(format!("Include {} includes itself", include));
+        }
+
+        let mut included = read(&path)?;
+        stack.push(path);
+        resolve_from(&mut included, Some(directory), stack)?;
+        stack.pop();
+
+        let included = match included {
+            Value::Mapping(mapping) => mapping,
+            Value::Null => Mapping::new(),
+            _ => return Err(format!("Included file {} is not a mapping", include)),
+        };
+        for (key, section) in included {
+            match key.as_str() {
+                Some("names") => {
+                    let section = mapping(section, &include, "names")?;
+                    for (kind, names) in section {
+                        let kind = kind
+                            .as_str()
+                            .ok_or(format!("Invalid names section in {}", include))?
+                            .to_string();
+                        let names = mapping(names, &include, &kind)?;
+                        merge(section_mut(value, &["names", &kind])?, names, &include)?;
+                    }
+                }
+                Some(key) if SECTIONS.contains(&key) => {
+                    let section = mapping(section, &include, key)?;
+                    merge(section_mut(value, &[key])?, section, &include)?;
+                }
+                _ => {
+                    return Err(format!(
+                        "Included file {} can only define {}",
+                        include,
+                        SECTIONS.join(", ")
+                    ))
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn mapping(value: Value, include: &str, section: &str) -> Result<Mapping, String> {
+    match value {
+        Value::Mapping(mapping) => Ok(mapping),
+        Value::Null => Ok(Mapping::new()),
+        _ => Err(format!(
+            "Section {} in {} is not a mapping",
+            section, include
+        )),
+    }
+}
+
+/// The mapping at a path of keys, created if missing.
+fn section_mut<'a>(value: &'a mut Value, keys: &[&str]) -> Result<&'a mut Mapping, String> {
+    let mut value = value;
+    for key in keys {
+        let mapping = match value {
+            Value::Mapping(mapping) => mapping,
+            _ => return Err(format!("Section {} is not a mapping", key)),
+        };
+        value = mapping
+            .entry(Value::String(key.to_string()))
+            .or_insert(Value::Mapping(Mapping::new()));
+        if value.is_null() {
+            *value = Value::Mapping(Mapping::new());
+        }
+    }
+
+    match value {
+        Value::Mapping(mapping) => Ok(mapping),
+        _ => Err(format!("Section {} is not a mapping", keys.join("."))),
+    }
+}
+
+fn merge(target: &mut Mapping, source: Mapping, include: &str) -> Result<(), String> {
+    for (key, value) in source {
+        match target.get(&key) {
+            Some(existing) if existing != &value => {
+                return Err(format!(
+                    "{} is defined differently in {}",
+                    key.as_str().unwrap_or("A definition"),
+                    include
+                ))
+            }
+            Some(_) => {}
+            None => {
+                target.insert(key, value);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::loader::{from_file, from_yaml};
+    use crate::types::configuration::{Action, BlockingData};
+    use crate::types::desired::Document;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    const PLANT: &str = "9a069153-c335-47c5-a653-dec6c0e7c280";
+
+    fn directory(files: &[(&str, &str)]) -> std::path::PathBuf {
+        let directory = std::env::temp_dir().join(format!("elafry-{}", uuid::Uuid::new_v4()));
+        for (name, text) in files {
+            let path = directory.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        directory
+    }
+
+    const SHARED: &str = "names:\n  components:\n    plant: 9a069153-c335-47c5-a653-dec6c0e7c280\n";
+    const COMMON: &str = "include:\n  - shared/names.yaml\nvariables:\n  deadline: 150\ntemplates:\n  start:\n    parameters: [component]\n    body:\n      id: ${uuid}\n      data: !start-component\n        component-id: ${component}\n";
+
+    #[test]
+    fn test_include() {
+        setup();
+
+        let directory = directory(&[
+            ("shared/names.yaml", SHARED),
+            ("shared/common.yaml", COMMON),
+            // included twice, once through common
+            (
+                "main.yaml",
+                "include:\n  - shared/common.yaml\n  - shared/names.yaml\nnames:\n  channels:\n    sensor: 1\ntasks:\n  - id: 6acdc42f-fb43-418d-b12f-1b7b0cbafba6\n    actions: !blocking\n      - use: start\n        with: {component: plant}\n",
+            ),
+        ]);
+
+        let configuration = match from_file(&directory.join("main.yaml")).unwrap() {
+            Document::Tasks(configuration) => configuration,
+            document => panic!("unexpected document {:?}", document),
+        };
+        match &configuration.tasks[0].actions {
+            Action::Blocking(actions) => match &actions[0].data {
+                BlockingData::StartComponent(data) => {
+                    assert_eq!(data.component_id, PLANT.parse().unwrap())
+                }
+                data => panic!("unexpected action {:?}", data),
+            },
+            Action::NonBlocking(_) => panic!("expected blocking actions"),
+        }
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_include_errors() {
+        setup();
+
+        let directory = directory(&[
+            ("loop.yaml", "include:\n  - loop.yaml\n"),
+            ("tasks.yaml", "tasks: []\n"),
+            ("names.yaml", SHARED),
+            (
+                "other.yaml",
+                "names:\n  components:\n    plant: 4d2f439b-ae60-42b6-ae45-b4db5d020f21\n",
+            ),
+        ]);
+        let load = |text: &str| {
+            std::fs::write(directory.join("main.yaml"), text).unwrap();
+            // drop the name of the file being loaded
+            from_file(&directory.join("main.yaml"))
+                .map_err(|e| e.split_once("; err = ").unwrap().1.to_string())
+        };
+
+        assert_eq!(
+            load("include:\n  - loop.yaml\ntasks: []\n"),
+            Err("Include loop.yaml includes itself".to_string())
+        );
+        assert_eq!(
+            load("include:\n  - tasks.yaml\ntasks: []\n"),
+            Err("Included file tasks.yaml can only define names, variables, templates".to_string())
+        );
+        assert_eq!(
+            load("include:\n  - names.yaml\n  - other.yaml\ntasks: []\n"),
+            Err("plant is defined differently in other.yaml".to_string())
+        );
+        assert_eq!(
+            load("include:\n  - ../names.yaml\ntasks: []\n"),
+            Err(
+                "Include ../names.yaml must be a relative path inside the configuration directory"
+                    .to_string()
+            )
+        );
+        assert!(load("include:\n  - missing.yaml\ntasks: []\n")
+            .unwrap_err()
+            .starts_with("Failed to open"));
+
+        // text without a file has nothing to be relative to
+        assert_eq!(
+            from_yaml("include:\n  - names.yaml\ntasks: []\n"),
+            Err("Includes need the configuration to be read from a file".to_string())
+        );
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+//! Reading configuration files into the types the runner works with.
+
+use std::path::Path;
+
+use serde_yaml::Value;
+
+use crate::signature::Signature;
+use crate::types::desired::Document;
+
+use self::format::Format;
+
+pub mod format;
+pub mod include;
+pub mod names;
+pub mod template;
+
+/// A configuration and the signature in its `signature` section, if any.
+#[derive(PartialEq, Debug)]
+pub struct Signed {
+    pub document: Document,
+    pub signature: Option<Signature>,
+}
+
+/// Parse a configuration, files with a top level `tasks` list are imperative.
+///
+/// Names declared in the `names` section are resolved and templates and
+/// variables expanded before the file is checked against the configuration
+/// types. Text not read from a file cannot include other files.
+pub fn from_text(text: &str, format: Format) -> Result<Document, String> {
+    signed_from_text(text, format).map(|signed| signed.document)
+}
+
+/// Parse a configuration and keep its signature.
+pub fn signed_from_text(text: &str, format: Format) -> Result<Signed, String> {
+    load(format.parse(text)?, None)
+}
+
+/// Parse a yaml configuration.
+pub fn from_yaml(text: &str) -> Result<Document, String> {
+    from_text(text, Format::Yaml)
+}
+
+/// Read a configuration file, the format follows from its extension or content.
+///
+/// Includes are relative to the directory of the file.
+pub fn from_file(path: &Path) -> Result<Document, String> {
+    signed_from_file(path).map(|signed| signed.document)
+}
+
+/// Read a configuration file and keep its signature.
+pub fn signed_from_file(path: &Path) -> Result<Signed, String> {
+    let value = include::read(path)?;
+
+    load(value, Some(path.parent().unwrap_or(Path::new("."))))
+        .map_err(|e| format!("Failed to load {}; err = {}", path.display(), e))
+}
+
+fn load(mut value: Value, directory: Option<&Path>) -> Result<Signed, String> {
+    // the signature covers everything else, so it is not part of the configuration
+    let signature = match value
+        .as_mapping_mut()
+        .and_then(|mapping| mapping.remove("signature"))
+    {
+        Some(signature) => Some(
+            format::deserialize::<Signature>(signature)
+                .map_err(|e| format!("Invalid signature section; err = {}", e))?,
+        ),
+        None => None,
+    };
+
+    // includes bring in names and templates, templates may use names
+    include::resolve(&mut value, directory)?;
+    template::expand(&mut value)?;
+    names::resolve(&mut value)?;
+
+    let document = if value.get("tasks").is_some() {
+        format::deserialize(value)
+            .map(Document::Tasks)
+            .map_err(|e| format!("Invalid configuration; err = {}", e))?
+    } else {
+        format::deserialize(value)
+            .map(Document::Desired)
+            .map_err(|e| format!("Invalid desired state; err = {}", e))?
+    };
+
+    Ok(Signed {
+        document,
+        signature,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::configuration::Configuration;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_from_yaml() {
+        setup();
+
+        assert_eq!(
+            from_yaml("tasks: []\n").unwrap(),
+            Document::Tasks(Configuration { tasks: vec![] })
+        );
+
+        // state-from is optional
+        let id = uuid::Uuid::new_v4();
+        let text = format!(
+            "components:\n  - component-id: {}\n    component: agent\n    core: 2\n    version: 1.0.0\nroutes: []\nschedule:\n  deadline: 1000\n  major-frames: []\n",
+            id
+        );
+        match from_yaml(&text).unwrap() {
+            Document::Desired(desired) => {
+                assert_eq!(desired.components[0].component_id, id);
+                assert_eq!(desired.components[0].state_from, None);
+            }
+            document => panic!("unexpected document {:?}", document),
+        }
+
+        assert!(from_yaml("components: 3\n")
+            .unwrap_err()
+            .starts_with("Invalid desired state"));
+        assert!(from_yaml("tasks: 3\n")
+            .unwrap_err()
+            .starts_with("Invalid configuration"));
+        assert!(from_yaml("tasks: [\n")
+            .unwrap_err()
+            .starts_with("Failed to parse yaml"));
+    }
+
+    #[test]
+    fn test_signed() {
+        setup();
+
+        let key = crate::signature::generate_key().unwrap();
+        let document = Document::Tasks(Configuration { tasks: vec![] });
+        let signature = crate::signature::sign(&document, &key);
+
+        // the signature section is taken off before the rest is read
+        let text = format!(
+            "tasks: []\nsignature:\n  key: {}\n  signature: {}\n",
+            signature.key, signature.signature
+        );
+        let signed = signed_from_text(&text, Format::Yaml).unwrap();
+        assert_eq!(signed.document, document);
+        assert_eq!(signed.signature, Some(signature.clone()));
+        assert_eq!(
+            crate::signature::verify(&signed.document, &signature, &[key.verifying_key()]),
+            Ok(())
+        );
+        assert_eq!(from_yaml(&text).unwrap(), document);
+
+        assert_eq!(
+            signed_from_text("tasks: []\n", Format::Yaml)
+                .unwrap()
+                .signature,
+            None
+        );
+        assert!(signed_from_text("tasks: []\nsignature: 3\n", Format::Yaml)
+            .unwrap_err()
+            .starts_with("Invalid signature section"));
+    }
+
+    #[test]
+    fn test_from_yaml_files() {
+        setup();
+
+        // every configuration shipped with the repository loads
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/../../configuration");
+        for entry in std::fs::read_dir(directory).unwrap() {
+            // shared files live in subdirectories and are only included
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                continue;
+            }
+            if let Err(e) = from_file(&path) {
+                panic!("{}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Names for component ids and channel ids in configuration files.
+//!
+//! A file may start with a `names` section:
+//!
+//! ```yaml
+//! names:
+//!   components:
+//!     plant: 9a069153-c335-47c5-a653-dec6c0e7c280
+//!   channels:
+//!     sensor: 1
+//! ```
+//!
+//! after which `plant` can be written wherever a component id is expected and
+//! `sensor` wherever a channel id is expected. Several names may refer to the
+//! same id. Ids can still be written out in full.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde_yaml::Value;
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Names {
+    #[serde(default)]
+    components: BTreeMap<String, uuid::Uuid>,
+    #[serde(default)]
+    channels: BTreeMap<String, u32>,
+}
+
+/// Keys whose value is a component id.
+const COMPONENT_KEYS: [&str; 2] = ["component-id", "state-from"];
+
+/// Keys whose value is a channel id.
+const CHANNEL_KEYS: [&str; 1] = ["channel-id"];
+
+/// Replace names with the ids they stand for and drop the `names` section.
+pub fn resolve(value: &mut Value) -> Result<(), String> {
+    let names = match value
+        .as_mapping_mut()
+        .and_then(|mapping| mapping.remove("names"))
+    {
+        Some(names) => serde_yaml::from_value::<Names>(names)
+            .map_err(|e| format!("Invalid names section; err = {}", e))?,
+        None => Names::default(),
+    };
+
+    // a name that reads as an id would mean different things in different places
+    for name in names.components.keys() {
+        if uuid::Uuid::parse_str(name).is_ok() {
+            return Err(format!(
+                "Component name {} is ambiguous with a component id",
+                name
+            ));
+        }
+    }
+    for name in names.channels.keys() {
+        if name.parse::<u32>().is_ok() {
+            return Err(format!(
+                "Channel name {} is ambiguous with a channel id",
+                name
+            ));
+        }
+    }
+
+    names.walk(value)
+}
+
+impl Names {
+    fn walk(&self, value: &mut Value) -> Result<(), String> {
+        match value {
+            Value::Mapping(mapping) => {
+                for (key, value) in mapping.iter_mut() {
+                    match key.as_str() {
+                        Some(key) if COMPONENT_KEYS.contains(&key) => self.component(value)?,
+                        Some(key) if CHANNEL_KEYS.contains(&key) => self.channel(value)?,
+                        _ => self.walk(value)?,
+                    }
+                }
+            }
+            Value::Sequence(sequence) => {
+                for value in sequence.iter_mut() {
+                    self.walk(value)?;
+                }
+            }
+            // endpoints are written as `!component-id plant`
+            Value::Tagged(tagged) if tagged.tag == "component-id" => {
+                self.component(&mut tagged.value)?
+            }
+            Value::Tagged(tagged) => self.walk(&mut tagged.value)?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn component(&self, value: &mut Value) -> Result<(), String> {
+        if let Value::String(name) = value {
+            if uuid::Uuid::parse_str(name).is_err() {
+                let id = self
+                    .components
+                    .get(name.as_str())
+                    .ok_or(format!("Unknown component name {}", name))?;
+                *value = Value::String(id.to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn channel(&self, value: &mut Value) -> Result<(), String> {
+        if let Value::String(name) = value {
+            let id = self
+                .channels
+                .get(name.as_str())
+                .ok_or(format!("Unknown channel name {}", name))?;
+            *value = Value::Number((*id).into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::loader::from_yaml;
+    use crate::types::configuration::{Action, BlockingData, Endpoint, RouteEndpoint};
+    use crate::types::desired::Document;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    const PLANT: &str = "9a069153-c335-47c5-a653-dec6c0e7c280";
+    const FCS: &str = "4d2f439b-ae60-42b6-ae45-b4db5d020f21";
+
+    fn configuration(names: &str, body: &str) -> String {
+        format!(
+            "{}tasks:\n  - id: 6acdc42f-fb43-418d-b12f-1b7b0cbafba6\n    actions: !blocking\n{}",
+            names, body
+        )
+    }
+
+    const NAMES: &str = "names:\n  components:\n    plant: 9a069153-c335-47c5-a653-dec6c0e7c280\n    fcs: 4d2f439b-ae60-42b6-ae45-b4db5d020f21\n    controller: 4d2f439b-ae60-42b6-ae45-b4db5d020f21\n  channels:\n    sensor: 1\n";
+
+    const ROUTE: &str = "      - id: 4e682edb-22f1-42bd-995b-6282f114688e\n        data: !add-route\n          source:\n            endpoint: !component-id plant\n            channel-id: sensor\n          target:\n            endpoint: !component-id controller\n            channel-id: 2\n";
+
+    #[test]
+    fn test_resolve_names() {
+        setup();
+
+        let start = "      - id: 2308c934-60f7-42db-8abd-eb005935505c\n        data: !start-component\n          component-id: fcs\n";
+        let text = configuration(NAMES, &format!("{}{}", ROUTE, start));
+
+        let configuration = match from_yaml(&text).unwrap() {
+            Document::Tasks(configuration) => configuration,
+            document => panic!("unexpected document {:?}", document),
+        };
+        let actions = match &configuration.tasks[0].actions {
+            Action::Blocking(actions) => actions,
+            Action::NonBlocking(_) => panic!("expected blocking actions"),
+        };
+
+        // aliases resolve to the same id, numbers and ids are left alone
+        match &actions[0].data {
+            BlockingData::AddRoute(data) => {
+                assert_eq!(
+                    data.source,
+                    RouteEndpoint {
+                        endpoint: Endpoint::Component(PLANT.parse().unwrap()),
+                        channel_id: 1,
+                    }
+                );
+                assert_eq!(
+                    data.target,
+                    RouteEndpoint {
+                        endpoint: Endpoint::Component(FCS.parse().unwrap()),
+                        channel_id: 2,
+                    }
+                );
+            }
+            data => panic!("unexpected action {:?}", data),
+        }
+        match &actions[1].data {
+            BlockingData::StartComponent(data) => {
+                assert_eq!(data.component_id, FCS.parse().unwrap())
+            }
+            data => panic!("unexpected action {:?}", data),
+        }
+    }
+
+    #[test]
+    fn test_resolve_desired_state() {
+        setup();
+
+        let text = format!(
+            "{}components:\n  - component-id: plant\n    component: plant\n    core: 2\n    version: 1.0.0\n    state-from: fcs\nroutes: []\nschedule:\n  deadline: 1000\n  major-frames:\n    - minor-frames:\n        - component-id: plant\n          deadline: 150\n",
+            NAMES
+        );
+
+        match from_yaml(&text).unwrap() {
+            Document::Desired(desired) => {
+                assert_eq!(desired.components[0].component_id, PLANT.parse().unwrap());
+                assert_eq!(desired.components[0].state_from, Some(FCS.parse().unwrap()));
+                assert_eq!(
+                    desired.schedule.major_frames[0].minor_frames[0].component_id,
+                    PLANT.parse().unwrap()
+                );
+            }
+            document => panic!("unexpected document {:?}", document),
+        }
+    }
+
+    #[test]
+    fn test_resolve_errors() {
+        setup();
+
+        // a typo in a name is reported by name rather than as a bad uuid
+        let remove = "      - id: 54f954d1-4f4e-4d1d-8d05-de1daba57444\n        data: !stop-component\n          component-id: fsc\n";
+        assert_eq!(
+            from_yaml(&configuration(NAMES, remove)),
+            Err("Unknown component name fsc".to_string())
+        );
+        assert_eq!(
+            from_yaml(&configuration("", ROUTE)),
+            Err("Unknown component name plant".to_string())
+        );
+
+        let names = "names:\n  components:\n    plant: 9a069153-c335-47c5-a653-dec6c0e7c280\n    fcs: 4d2f439b-ae60-42b6-ae45-b4db5d020f21\n    controller: 4d2f439b-ae60-42b6-ae45-b4db5d020f21\n";
+        assert_eq!(
+            from_yaml(&configuration(names, ROUTE)),
+            Err("Unknown channel name sensor".to_string())
+        );
+
+        // names that could be read as ids
+        let names = format!("names:\n  components:\n    {}: {}\n", FCS, PLANT);
+        assert_eq!(
+            from_yaml(&configuration(&names, ROUTE)),
+            Err(format!(
+                "Component name {} is ambiguous with a component id",
+                FCS
+            ))
+        );
+        assert_eq!(
+            from_yaml(&configuration("names:\n  channels:\n    \"2\": 1\n", ROUTE)),
+            Err("Channel name 2 is ambiguous with a channel id".to_string())
+        );
+
+        // a name defined twice could stand for either id
+        let names = "names:\n  components:\n    plant: 9a069153-c335-47c5-a653-dec6c0e7c280\n    plant: 4d2f439b-ae60-42b6-ae45-b4db5d020f21\n";
+        assert!(from_yaml(&configuration(names, ROUTE))
+            .unwrap_err()
+            .contains("duplicate entry"));
+
+        let names = "names:\n  component:\n    plant: 9a069153-c335-47c5-a653-dec6c0e7c280\n";
+        assert!(from_yaml(&configuration(names, ROUTE))
+            .unwrap_err()
+            .starts_with("Invalid names section"));
+    }
+}
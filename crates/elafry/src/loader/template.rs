@@ -0,0 +1,431 @@
+//! Variables and templates in configuration files.
+//!
+//! A file may define `variables` and `templates` sections:
+//!
+//! ```yaml
+//! variables:
+//!   period: 1000
+//! templates:
+//!   controller-routes:
+//!     parameters: [controller]
+//!     body:
+//!       - id: ${uuid}
+//!         data: !add-route
+//!           source:
+//!             endpoint: !component-id plant
+//!             channel-id: sensor
+//!           target:
+//!             endpoint: !component-id ${controller}
+//!             channel-id: sensor
+//! ```
+//!
+//! `${name}` in a string is replaced by the variable or parameter of that
+//! name, a string that is nothing but `${name}` takes the value with its type
+//! so numbers stay numbers. `${uuid}` is a new id every time it is used. The
+//! ids are derived from the file, loading it again gives the same ids so a
+//! signature over the expanded configuration stays valid.
+//!
+//! A mapping of the form `{use: controller-routes, with: {controller: fcs_a}}`
+//! is replaced by the body of the template. When it appears in a list and the
+//! body is a list as well, the items are spliced into the surrounding list.
+//! Template bodies see the variables and their own parameters.
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use sha2::{Digest, Sha256};
+
+/// How deep templates may use other templates, stops a template using itself.
+const MAX_DEPTH: usize = 16;
+
+/// Variable that gives a new id every time it is used.
+const UUID: &str = "uuid";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Template {
+    #[serde(default)]
+    parameters: Vec<String>,
+    body: Value,
+}
+
+struct Templates {
+    templates: BTreeMap<String, Template>,
+    ids: Ids,
+}
+
+/// Ids for `${uuid}`, the same sequence for the same file.
+struct Ids {
+    seed: Vec<u8>,
+    count: Cell<u64>,
+}
+
+impl Ids {
+    fn new(value: &Value) -> Ids {
+        Ids {
+            seed: Sha256::digest(serde_yaml::to_string(value).unwrap_or_default()).to_vec(),
+            count: Cell::new(0),
+        }
+    }
+
+    fn next(&self) -> uuid::Uuid {
+        let count = self.count.get();
+        self.count.set(count + 1);
+
+        let mut hasher = Sha256::new();
+        hasher.update(&self.seed);
+        hasher.update(count.to_be_bytes());
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&hasher.finalize()[..16]);
+        uuid::Builder::from_random_bytes(bytes).into_uuid()
+    }
+}
+
+/// Expand templates and variables and drop the `variables` and `templates` sections.
+pub fn expand(value: &mut Value) -> Result<(), String> {
+    let ids = Ids::new(value);
+    let (variables, templates) = match value.as_mapping_mut() {
+        Some(mapping) => (mapping.remove("variables"), mapping.remove("templates")),
+        None => (None, None),
+    };
+
+    let variables: BTreeMap<String, Value> = match variables {
+        Some(variables) => serde_yaml::from_value(variables)
+            .map_err(|e| format!("Invalid variables section; err = {}", e))?,
+        None => BTreeMap::new(),
+    };
+    if variables.contains_key(UUID) {
+        return Err(format!("Variable {} is reserved", UUID));
+    }
+
+    let templates = Templates {
+        templates: match templates {
+            Some(templates) => serde_yaml::from_value(templates)
+                .map_err(|e| format!("Invalid templates section; err = {}", e))?,
+            None => BTreeMap::new(),
+        },
+        ids,
+    };
+
+    *value = templates.expand(value, &variables, 0)?;
+
+    Ok(())
+}
+
+/// Mappings with a `use` key and at most a `with` key next to it.
+fn is_use(mapping: &Mapping) -> bool {
+    mapping.contains_key("use")
+        && mapping
+            .keys()
+            .all(|key| matches!(key.as_str(), Some("use") | Some("with")))
+}
+
+impl Templates {
+    fn expand(
+        &self,
+        value: &Value,
+        variables: &BTreeMap<String, Value>,
+        depth: usize,
+    ) -> Result<Value, String> {
+        match value {
+            Value::Mapping(mapping) if is_use(mapping) => self.apply(mapping, variables, depth),
+            Value::Mapping(mapping) => {
+                let mut expanded = Mapping::new();
+                for (key, value) in mapping.iter() {
+                    expanded.insert(key.clone(), self.expand(value, variables, depth)?);
+                }
+                Ok(Value::Mapping(expanded))
+            }
+            Value::Sequence(sequence) => {
+                let mut expanded = Vec::new();
+                for value in sequence.iter() {
+                    match value {
+                        // a template of several actions adds them all to the list
+                        Value::Mapping(mapping) if is_use(mapping) => {
+                            match self.apply(mapping, variables, depth)? {
+                                Value::Sequence(items) => expanded.extend(items),
+                                item => expanded.push(item),
+                            }
+                        }
+                        value => expanded.push(self.expand(value, variables, depth)?),
+                    }
+                }
+                Ok(Value::Sequence(expanded))
+            }
+            Value::Tagged(tagged) => {
+                let mut tagged = tagged.clone();
+                tagged.value = self.expand(&tagged.value, variables, depth)?;
+                Ok(Value::Tagged(tagged))
+            }
+            Value::String(text) => substitute(text, variables, &self.ids),
+            value => Ok(value.clone()),
+        }
+    }
+
+    fn apply(
+        &self,
+        mapping: &Mapping,
+        variables: &BTreeMap<String, Value>,
+        depth: usize,
+    ) -> Result<Value, String> {
+        let name = match self.expand(&mapping["use"], variables, depth)? {
+            Value::String(name) => name,
+            name => return Err(format!("Template name {:?} is not a string", name)),
+        };
+        if depth >= MAX_DEPTH {
+            return Err(format!(
+                "Templates nested more than {} deep at {}, does it use itself?",
+                MAX_DEPTH, name
+            ));
+        }
+        let template = self
+            .templates
+            .get(&name)
+            .ok_or(format!("Unknown template {}", name))?;
+
+        // arguments are expanded where the template is used
+        let arguments = match mapping.get("with") {
+            Some(Value::Mapping(arguments)) => arguments.clone(),
+            Some(Value::Null) | None => Mapping::new(),
+            Some(_) => return Err(format!("Arguments to template {} are not a mapping", name)),
+        };
+        let mut scope = variables.clone();
+        for (key, value) in arguments.iter() {
+            let key = key.as_str().ok_or(format!(
+                "Template {} has a parameter that is not a string",
+                name
+            ))?;
+            if !template.parameters.iter().any(|parameter| parameter == key) {
+                return Err(format!("Template {} has no parameter {}", name, key));
+            }
+            scope.insert(key.to_string(), self.expand(value, variables, depth)?);
+        }
+        for parameter in template.parameters.iter() {
+            if !arguments.contains_key(parameter.as_str()) {
+                return Err(format!("Template {} needs parameter {}", name, parameter));
+            }
+        }
+
+        self.expand(&template.body, &scope, depth + 1)
+    }
+}
+
+/// Replace `${name}` in a string with the values of variables.
+fn substitute(text: &str, variables: &BTreeMap<String, Value>, ids: &Ids) -> Result<Value, String> {
+    let lookup = |name: &str| -> Result<Value, String> {
+        if name == UUID {
+            return Ok(Value::String(ids.next().to_string()));
+        }
+        variables
+            .get(name)
+            .cloned()
+            .ok_or(format!("Unknown variable {}", name))
+    };
+
+    // the whole string is one variable, keep its type
+    if let Some(name) = text.strip_prefix("${").and_then(|t| t.strip_suffix('}')) {
+        if !name.contains('}') {
+            return lookup(name);
+        }
+    }
+
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or(format!("Unterminated variable in {}", text))?;
+        let name = &rest[start + 2..start + end];
+        match lookup(name)? {
+            Value::String(value) => result.push_str(&value),
+            Value::Number(value) => result.push_str(&value.to_string()),
+            Value::Bool(value) => result.push_str(&value.to_string()),
+            _ => {
+                return Err(format!(
+                    "Variable {} is not a plain value and cannot be part of {}",
+                    name, text
+                ))
+            }
+        }
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(Value::String(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::loader::from_yaml;
+    use crate::types::configuration::{Action, BlockingData, Endpoint};
+    use crate::types::desired::Document;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    const PLANT: &str = "9a069153-c335-47c5-a653-dec6c0e7c280";
+    const FCS: &str = "4d2f439b-ae60-42b6-ae45-b4db5d020f21";
+
+    const HEADER: &str = "names:\n  components:\n    plant: 9a069153-c335-47c5-a653-dec6c0e7c280\n    fcs: 4d2f439b-ae60-42b6-ae45-b4db5d020f21\nvariables:\n  period: 1000\n  sensor: 1\ntemplates:\n  route:\n    parameters: [from, to]\n    body:\n      id: ${uuid}\n      data: !add-route\n        source:\n          endpoint: !component-id ${from}\n          channel-id: ${sensor}\n        target:\n          endpoint: !component-id ${to}\n          channel-id: ${sensor}\n  routes:\n    parameters: [controller]\n    body:\n      - use: route\n        with: {from: plant, to: \"${controller}\"}\n      - use: route\n        with: {from: \"${controller}\", to: plant}\n";
+
+    fn blocking(text: &str) -> Result<Vec<BlockingData>, String> {
+        let configuration = match from_yaml(text)? {
+            Document::Tasks(configuration) => configuration,
+            document => panic!("unexpected document {:?}", document),
+        };
+        match &configuration.tasks[0].actions {
+            Action::Blocking(actions) => Ok(actions.iter().map(|a| a.data.clone()).collect()),
+            Action::NonBlocking(_) => panic!("expected blocking actions"),
+        }
+    }
+
+    fn task(body: &str) -> String {
+        format!(
+            "{}tasks:\n  - id: 6acdc42f-fb43-418d-b12f-1b7b0cbafba6\n    actions: !blocking\n{}",
+            HEADER, body
+        )
+    }
+
+    #[test]
+    fn test_expand_templates() {
+        setup();
+
+        let text = task("      - use: routes\n        with:\n          controller: fcs\n      - id: 286855f8-752e-4116-9703-ec6ad9d84fb1\n        data: !set-schedule\n          deadline: ${period}\n          major-frames: []\n");
+        let actions = blocking(&text).unwrap();
+
+        // the two routes are spliced into the list ahead of the schedule
+        assert_eq!(actions.len(), 3);
+        match (&actions[0], &actions[1]) {
+            (BlockingData::AddRoute(first), BlockingData::AddRoute(second)) => {
+                assert_eq!(
+                    first.source.endpoint,
+                    Endpoint::Component(PLANT.parse().unwrap())
+                );
+                assert_eq!(
+                    first.target.endpoint,
+                    Endpoint::Component(FCS.parse().unwrap())
+                );
+                assert_eq!(first.target.channel_id, 1);
+                assert_eq!(
+                    second.source.endpoint,
+                    Endpoint::Component(FCS.parse().unwrap())
+                );
+                assert_eq!(
+                    second.target.endpoint,
+                    Endpoint::Component(PLANT.parse().unwrap())
+                );
+            }
+            data => panic!("unexpected actions {:?}", data),
+        }
+        match &actions[2] {
+            BlockingData::SetSchedule(data) => assert_eq!(data.deadline, 1000),
+            data => panic!("unexpected action {:?}", data),
+        }
+
+        // every use of a template gets its own action ids
+        let configuration = match from_yaml(&text).unwrap() {
+            Document::Tasks(configuration) => configuration,
+            document => panic!("unexpected document {:?}", document),
+        };
+        match &configuration.tasks[0].actions {
+            Action::Blocking(actions) => assert_ne!(actions[0].id, actions[1].id),
+            Action::NonBlocking(_) => panic!("expected blocking actions"),
+        }
+
+        // but loading the file again gives the same ids
+        assert_eq!(from_yaml(&text).unwrap(), Document::Tasks(configuration));
+
+        // and a different file different ids
+        let other = text.replace("${period}", "2000");
+        let ids = |text: &str| match from_yaml(text).unwrap() {
+            Document::Tasks(configuration) => match &configuration.tasks[0].actions {
+                Action::Blocking(actions) => actions[0].id,
+                Action::NonBlocking(_) => panic!("expected blocking actions"),
+            },
+            document => panic!("unexpected document {:?}", document),
+        };
+        assert_ne!(ids(&text), ids(&other));
+    }
+
+    #[test]
+    fn test_substitute() {
+        setup();
+
+        let variables = [
+            (
+                "name".to_string(),
+                serde_yaml::Value::String("fcs".to_string()),
+            ),
+            ("core".to_string(), serde_yaml::Value::Number(3.into())),
+        ]
+        .into_iter()
+        .collect();
+        let ids = super::Ids::new(&serde_yaml::Value::Null);
+
+        assert_eq!(
+            super::substitute("${core}", &variables, &ids),
+            Ok(serde_yaml::Value::Number(3.into()))
+        );
+        assert_eq!(
+            super::substitute("${name}_b on ${core}", &variables, &ids),
+            Ok(serde_yaml::Value::String("fcs_b on 3".to_string()))
+        );
+        assert_eq!(
+            super::substitute("no variables", &variables, &ids),
+            Ok(serde_yaml::Value::String("no variables".to_string()))
+        );
+        assert_eq!(
+            super::substitute("${name", &variables, &ids),
+            Err("Unterminated variable in ${name".to_string())
+        );
+        assert_eq!(
+            super::substitute("a ${missing}", &variables, &ids),
+            Err("Unknown variable missing".to_string())
+        );
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        setup();
+
+        assert_eq!(
+            blocking(&task("      - use: router\n")),
+            Err("Unknown template router".to_string())
+        );
+        assert_eq!(
+            blocking(&task("      - use: routes\n")),
+            Err("Template routes needs parameter controller".to_string())
+        );
+        assert_eq!(
+            blocking(&task(
+                "      - use: routes\n        with: {controller: fcs, core: 3}\n"
+            )),
+            Err("Template routes has no parameter core".to_string())
+        );
+        assert_eq!(
+            blocking(&task(
+                "      - use: route\n        with: {from: plant, to: \"${target}\"}\n"
+            )),
+            Err("Unknown variable target".to_string())
+        );
+        assert_eq!(
+            blocking("variables:\n  uuid: 1\ntasks: []\n"),
+            Err("Variable uuid is reserved".to_string())
+        );
+
+        // a template using itself
+        let text = "templates:\n  loop:\n    body:\n      use: loop\ntasks:\n  - use: loop\n";
+        assert!(from_yaml(text)
+            .unwrap_err()
+            .starts_with("Templates nested more than 16 deep at loop"));
+    }
+}
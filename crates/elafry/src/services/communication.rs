@@ -68,8 +68,8 @@ impl Manager {
                         self.state.receive_count = message.count;
                     }
 
-                    // increment receive count
-                    self.state.receive_count += 1;
+                    // increment receive count, wrapping like the sender's
+                    self.state.receive_count = self.state.receive_count.wrapping_add(1);
 
                     // add message to hashmap
                     let channel_id = message.channel_id;
@@ -83,7 +83,7 @@ impl Manager {
 
         // for each channel_id in hashmap, sort messages by count in descending order
         for (_, messages) in self.messages.iter_mut() {
-            messages.sort_by(|a, b| b.count.cmp(&a.count));
+            messages.sort_by_key(|message| std::cmp::Reverse(message.count));
         }
     }
 
@@ -92,10 +92,9 @@ impl Manager {
         if self.messages.contains_key(&channel_id) {
             // get message from vector
             let messages = self.messages.get_mut(&channel_id).unwrap();
-            let message = messages.pop();
-            return message;
+            messages.pop()
         } else {
-            return None;
+            None
         }
     }
 
@@ -115,8 +114,8 @@ impl Manager {
         let mut length_buf = length.to_be_bytes().to_vec();
         length_buf.append(&mut message_buf.clone());
 
-        // increment send count
-        self.state.send_count += 1;
+        // increment send count, it wraps after 255 messages
+        self.state.send_count = self.state.send_count.wrapping_add(1);
 
         // if going to block, don't send message
         match stream.write_all(&length_buf) {
@@ -1,2 +1,3 @@
 pub mod communication;
 pub mod state;
+pub mod time;
@@ -133,7 +133,7 @@ mod tests {
         let mut manager_2 = Manager::new(child_socket);
 
         // check empty data
-        assert_eq!(manager_2.get_data(), vec![]);
+        assert_eq!(manager_2.get_data(), Vec::<u8>::new());
 
         // set data
         let data = vec![1, 2, 3, 4];
@@ -175,7 +175,7 @@ mod tests {
         manager.run();
 
         let data = manager.get_data();
-        assert_eq!(data, vec![]);
+        assert_eq!(data, Vec::<u8>::new());
     }
 
     #[test]
@@ -0,0 +1,82 @@
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::time::Duration;
+
+/// Time as seen by a component.
+///
+/// In a simulation the runner hands the component a clock it advances itself,
+/// holding the virtual start of the slot being run. Otherwise time is read from
+/// the monotonic clock. Components reading time here rather than from the
+/// system give the same outputs every time a simulation is run.
+pub struct Manager {
+    clock: Option<File>,
+}
+
+impl Manager {
+    pub fn new(clock: Option<File>) -> Manager {
+        Manager { clock }
+    }
+
+    /// Whether time is virtual.
+    pub fn simulated(&self) -> bool {
+        self.clock.is_some()
+    }
+
+    /// Time since an arbitrary start, the start of the simulation when simulated.
+    pub fn now(&self) -> Duration {
+        let clock = match &self.clock {
+            Some(clock) => clock,
+            None => return Duration::from_nanos(crate::telemetry::now()),
+        };
+
+        // nanoseconds written by the runner before resuming the component
+        let mut buf = [0; 8];
+        match clock.read_exact_at(&mut buf, 0) {
+            Ok(_) => Duration::from_nanos(u64::from_ne_bytes(buf)),
+            Err(e) => {
+                log::error!("Failed to read clock; err = {}", e);
+                Duration::ZERO
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_time() {
+        setup();
+
+        let time = Manager::new(None);
+        assert!(!time.simulated());
+        assert!(time.now() <= time.now());
+
+        // the clock only moves when written
+        let path = std::env::temp_dir().join(format!("clock-{}", uuid::Uuid::new_v4()));
+        let clock = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        clock.write_all_at(&1_500_000u64.to_ne_bytes(), 0).unwrap();
+        let time = Manager::new(Some(clock.try_clone().unwrap()));
+        assert!(time.simulated());
+        assert_eq!(time.now(), Duration::from_micros(1500));
+        assert_eq!(time.now(), Duration::from_micros(1500));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
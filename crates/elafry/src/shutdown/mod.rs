@@ -0,0 +1,78 @@
+//! Shutdown requested by SIGINT or SIGTERM.
+//!
+//! The handler only counts signals, the runner and components check the count
+//! between frames and shut down in their own time.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static SIGNALS: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn handle(_signal: libc::c_int) {
+    SIGNALS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Count SIGINT and SIGTERM instead of exiting on them.
+pub fn install() -> Result<(), String> {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+
+            if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+                return Err(format!(
+                    "Failed to handle signal {}; err = {}",
+                    signal,
+                    std::io::Error::last_os_error()
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a shutdown signal has arrived.
+pub fn requested() -> bool {
+    signals() > 0
+}
+
+/// Number of shutdown signals so far, a second one asks to hurry up.
+pub fn signals() -> usize {
+    SIGNALS.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_shutdown_signals() {
+        setup();
+
+        install().unwrap();
+        assert!(!requested());
+
+        // the test process keeps running, the signal is only counted
+        unsafe {
+            libc::raise(libc::SIGTERM);
+        }
+        assert!(requested());
+        assert_eq!(signals(), 1);
+
+        unsafe {
+            libc::raise(libc::SIGINT);
+        }
+        assert_eq!(signals(), 2);
+    }
+}
@@ -0,0 +1,191 @@
+//! Signatures on configurations and hashes of component binaries.
+//!
+//! A configuration is signed with Ed25519 over its canonical bytes, the
+//! bincode encoding of the document after names, includes and templates have
+//! been expanded. How the file is written therefore does not matter, only
+//! what the runner will do with it. Keys and signatures are hex encoded.
+
+use ed25519_dalek::{Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::types::desired::Document;
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+/// Written in front of the document so a signature cannot be used for anything else.
+const CONTEXT: &[u8] = b"elafry configuration v1\0";
+
+/// The `signature` section of a configuration file.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Signature {
+    /// Public key of the signer.
+    pub key: String,
+    pub signature: String,
+}
+
+/// The bytes a configuration signature covers.
+pub fn canonical(document: &Document) -> Vec<u8> {
+    let mut bytes = CONTEXT.to_vec();
+    match document {
+        Document::Tasks(configuration) => {
+            bytes.push(0);
+            bytes.extend(bincode::serialize(configuration).unwrap());
+        }
+        Document::Desired(desired) => {
+            bytes.push(1);
+            bytes.extend(bincode::serialize(desired).unwrap());
+        }
+    }
+    bytes
+}
+
+pub fn sign(document: &Document, key: &SigningKey) -> Signature {
+    Signature {
+        key: to_hex(key.verifying_key().as_bytes()),
+        signature: to_hex(&key.sign(&canonical(document)).to_bytes()),
+    }
+}
+
+/// Check that a trusted key signed the document.
+pub fn verify(
+    document: &Document,
+    signature: &Signature,
+    trusted: &[VerifyingKey],
+) -> Result<(), String> {
+    let key = verifying_key(&signature.key)?;
+    if !trusted.contains(&key) {
+        return Err(format!("Key {} is not trusted", signature.key));
+    }
+
+    let bytes: [u8; 64] = from_hex(&signature.signature)?
+        .try_into()
+        .map_err(|_| "Signature is not 64 bytes".to_string())?;
+    key.verify(
+        &canonical(document),
+        &ed25519_dalek::Signature::from_bytes(&bytes),
+    )
+    .map_err(|_| "Signature does not match the configuration".to_string())
+}
+
+/// A new random signing key.
+pub fn generate_key() -> Result<SigningKey, String> {
+    let mut secret = [0; 32];
+    getrandom::getrandom(&mut secret)
+        .map_err(|e| format!("Failed to generate key; err = {}", e))?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+/// Read the hex encoded secret of a signing key.
+pub fn signing_key(text: &str) -> Result<SigningKey, String> {
+    let secret: [u8; 32] = from_hex(text.trim())?
+        .try_into()
+        .map_err(|_| "Signing key is not 32 bytes".to_string())?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+/// Read a hex encoded public key.
+pub fn verifying_key(text: &str) -> Result<VerifyingKey, String> {
+    let bytes: [u8; 32] = from_hex(text.trim())?
+        .try_into()
+        .map_err(|_| format!("Key {} is not 32 bytes", text))?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| format!("Invalid key {}; err = {}", text, e))
+}
+
+/// Hex encoded SHA-256, as given for component binaries.
+pub fn sha256(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(text: &str) -> Result<Vec<u8>, String> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return Err(format!("Invalid hex {}", text));
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| format!("Invalid hex {}", text))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::configuration::Configuration;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        setup();
+
+        let key = generate_key().unwrap();
+        let other = generate_key().unwrap();
+        let document = Document::Tasks(Configuration { tasks: vec![] });
+        let signature = sign(&document, &key);
+
+        assert_eq!(
+            verify(&document, &signature, &[key.verifying_key()]),
+            Ok(())
+        );
+        assert_eq!(
+            verify(&document, &signature, &[other.verifying_key()]),
+            Err(format!("Key {} is not trusted", signature.key))
+        );
+
+        // a signature over a different document or by a different key
+        let changed = Document::Tasks(Configuration {
+            tasks: vec![crate::types::configuration::Task {
+                id: uuid::Uuid::new_v4(),
+                actions: crate::types::configuration::Action::Blocking(vec![]),
+            }],
+        });
+        assert_eq!(
+            verify(&changed, &signature, &[key.verifying_key()]),
+            Err("Signature does not match the configuration".to_string())
+        );
+        let forged = Signature {
+            key: signature.key.clone(),
+            signature: sign(&document, &other).signature,
+        };
+        assert_eq!(
+            verify(&document, &forged, &[key.verifying_key()]),
+            Err("Signature does not match the configuration".to_string())
+        );
+
+        // keys survive being written out
+        let secret = to_hex(key.as_bytes());
+        assert_eq!(signing_key(&secret).unwrap(), key);
+        assert_eq!(verifying_key(&signature.key).unwrap(), key.verifying_key());
+        assert!(verifying_key("abcd").is_err());
+    }
+
+    #[test]
+    fn test_hex_sha256() {
+        setup();
+
+        assert_eq!(to_hex(&[0, 15, 255]), "000fff");
+        assert_eq!(from_hex("000fff"), Ok(vec![0, 15, 255]));
+        assert_eq!(from_hex("000FFF"), Ok(vec![0, 15, 255]));
+        assert_eq!(from_hex("0f0"), Err("Invalid hex 0f0".to_string()));
+        assert_eq!(from_hex("zz"), Err("Invalid hex zz".to_string()));
+
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,448 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+pub mod ring;
+
+use ring::RingBuffer;
+
+/// Magic bytes at the start of every telemetry file.
+pub const MAGIC: [u8; 4] = *b"ELTM";
+/// Version of the record layout that follows the header.
+pub const VERSION: u16 = 1;
+/// Size of one encoded record in bytes.
+pub const RECORD_SIZE: usize = 49;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Service {
+    Scheduler,
+    Communication,
+    State,
+    Management,
+}
+
+impl Service {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Service::Scheduler => "scheduler",
+            Service::Communication => "communication",
+            Service::State => "state",
+            Service::Management => "management",
+        }
+    }
+}
+
+/// Reconfiguration actions, named as in configuration files.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ActionKind {
+    StartComponent,
+    StopComponent,
+    AddRoute,
+    RemoveRoute,
+    SetSchedule,
+    AddStateSync,
+    RemoveStateSync,
+    AddComponent,
+    RemoveComponent,
+    WaitStateSync,
+}
+
+impl ActionKind {
+    const ALL: [ActionKind; 10] = [
+        ActionKind::StartComponent,
+        ActionKind::StopComponent,
+        ActionKind::AddRoute,
+        ActionKind::RemoveRoute,
+        ActionKind::SetSchedule,
+        ActionKind::AddStateSync,
+        ActionKind::RemoveStateSync,
+        ActionKind::AddComponent,
+        ActionKind::RemoveComponent,
+        ActionKind::WaitStateSync,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ActionKind::StartComponent => "start-component",
+            ActionKind::StopComponent => "stop-component",
+            ActionKind::AddRoute => "add-route",
+            ActionKind::RemoveRoute => "remove-route",
+            ActionKind::SetSchedule => "set-schedule",
+            ActionKind::AddStateSync => "add-state-sync",
+            ActionKind::RemoveStateSync => "remove-state-sync",
+            ActionKind::AddComponent => "add-component",
+            ActionKind::RemoveComponent => "remove-component",
+            ActionKind::WaitStateSync => "wait-state-sync",
+        }
+    }
+}
+
+/// Value of an action record when a non-blocking action starts.
+pub const ACTION_STARTED: u64 = 0;
+/// Value of an action record when an action has taken effect.
+pub const ACTION_COMPLETED: u64 = 1;
+/// Value of an action record when an action failed and the reconfiguration was rolled back.
+pub const ACTION_FAILED: u64 = 2;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Span {
+    /// A whole frame of the runner loop.
+    #[default]
+    Frame,
+    /// One of the runner services within a frame.
+    Service(Service),
+    /// A component's slot in the schedule, or its own run when recorded by the component.
+    Component(uuid::Uuid),
+    /// An instant in the management service, identified by the action id.
+    Action(ActionKind, uuid::Uuid),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Record {
+    pub frame: u64,
+    pub span: Span,
+    /// Monotonic clock time in nanoseconds.
+    pub start: u64,
+    /// Monotonic clock time in nanoseconds.
+    pub end: u64,
+    /// Span specific value, the overrun count for frames, the cpu time in
+    /// nanoseconds for component slots recorded by the runner and
+    /// [`ACTION_STARTED`], [`ACTION_COMPLETED`] or [`ACTION_FAILED`] for actions.
+    pub value: u64,
+}
+
+impl Record {
+    pub fn duration(&self) -> u64 {
+        self.end.saturating_sub(self.start)
+    }
+
+    pub fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut data = [0; RECORD_SIZE];
+
+        // first byte is the span kind, then 16 bytes of span id
+        let (kind, id) = match self.span {
+            Span::Frame => (0, [0; 16]),
+            Span::Service(Service::Scheduler) => (1, [0; 16]),
+            Span::Service(Service::Communication) => (2, [0; 16]),
+            Span::Service(Service::State) => (3, [0; 16]),
+            Span::Service(Service::Management) => (4, [0; 16]),
+            Span::Component(id) => (5, *id.as_bytes()),
+            Span::Action(kind, id) => (16 + kind as u8, *id.as_bytes()),
+        };
+        data[0] = kind;
+        data[1..17].copy_from_slice(&id);
+
+        // the rest are little endian integers
+        data[17..25].copy_from_slice(&self.frame.to_le_bytes());
+        data[25..33].copy_from_slice(&self.start.to_le_bytes());
+        data[33..41].copy_from_slice(&self.end.to_le_bytes());
+        data[41..49].copy_from_slice(&self.value.to_le_bytes());
+
+        data
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Record> {
+        if data.len() < RECORD_SIZE {
+            return None;
+        }
+
+        let span = match data[0] {
+            0 => Span::Frame,
+            1 => Span::Service(Service::Scheduler),
+            2 => Span::Service(Service::Communication),
+            3 => Span::Service(Service::State),
+            4 => Span::Service(Service::Management),
+            5 => Span::Component(uuid::Uuid::from_slice(&data[1..17]).ok()?),
+            kind @ 16.. => Span::Action(
+                *ActionKind::ALL.get((kind - 16) as usize)?,
+                uuid::Uuid::from_slice(&data[1..17]).ok()?,
+            ),
+            _ => return None,
+        };
+
+        let integer = |offset: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&data[offset..offset + 8]);
+            u64::from_le_bytes(bytes)
+        };
+
+        Some(Record {
+            frame: integer(17),
+            span,
+            start: integer(25),
+            end: integer(33),
+            value: integer(41),
+        })
+    }
+}
+
+/// Current value of the monotonic clock in nanoseconds, shared by the runner and components.
+pub fn now() -> u64 {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time);
+    }
+
+    time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+}
+
+/// Streams records to a file from a background thread.
+///
+/// Recording only copies into a preallocated ring buffer, so memory use is
+/// bounded and the caller never waits on the disk.
+pub struct Recorder {
+    ring: Arc<RingBuffer>,
+    stop: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Recorder {
+    pub fn new(path: &str, capacity: usize) -> io::Result<Recorder> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        // write the file header
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+
+        let ring = Arc::new(RingBuffer::new(capacity));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let ring = ring.clone();
+            let stop = stop.clone();
+            std::thread::Builder::new()
+                .name("telemetry".to_string())
+                .spawn(move || write(ring, stop, writer))?
+        };
+
+        Ok(Recorder {
+            ring,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn record(&mut self, record: Record) {
+        self.ring.push(record);
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.ring.dropped()
+    }
+
+    /// Write out everything recorded so far and stop the writer thread.
+    pub fn close(&mut self) {
+        self.stop.store(true, Ordering::Release);
+
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::error!("Telemetry writer panicked");
+            }
+        }
+
+        if self.ring.dropped() > 0 {
+            log::warn!("Telemetry dropped {} records", self.ring.dropped());
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn write(ring: Arc<RingBuffer>, stop: Arc<AtomicBool>, mut writer: BufWriter<File>) {
+    // never compete with the frame loop for the cpu
+    unsafe {
+        let ret = libc::pthread_setschedparam(
+            libc::pthread_self(),
+            libc::SCHED_IDLE,
+            &libc::sched_param { sched_priority: 0 },
+        );
+        if ret != 0 {
+            log::warn!("Failed to set telemetry writer scheduler");
+        }
+    }
+
+    loop {
+        // check before draining so nothing pushed before the stop is lost
+        let stopping = stop.load(Ordering::Acquire);
+
+        while let Some(record) = ring.pop() {
+            if let Err(e) = writer.write_all(&record.encode()) {
+                log::error!("Failed to write telemetry; err = {:?}", e);
+                return;
+            }
+        }
+
+        if let Err(e) = writer.flush() {
+            log::error!("Failed to flush telemetry; err = {:?}", e);
+            return;
+        }
+
+        if stopping {
+            break;
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
+
+/// Reads records back from a telemetry file.
+pub struct Reader<R: Read> {
+    reader: R,
+}
+
+impl Reader<BufReader<File>> {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Reader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 6];
+        reader.read_exact(&mut header)?;
+
+        // check the header
+        if header[0..4] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a telemetry file",
+            ));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported telemetry version {}", version),
+            ));
+        }
+
+        Ok(Reader { reader })
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut data = [0; RECORD_SIZE];
+
+        match self.reader.read_exact(&mut data) {
+            Ok(_) => match Record::decode(&data) {
+                Some(record) => Some(Ok(record)),
+                None => Some(Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid telemetry record",
+                ))),
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        setup();
+
+        let spans = [
+            Span::Frame,
+            Span::Service(Service::Scheduler),
+            Span::Service(Service::Communication),
+            Span::Service(Service::State),
+            Span::Service(Service::Management),
+            Span::Component(uuid::Uuid::new_v4()),
+            Span::Action(ActionKind::StartComponent, uuid::Uuid::new_v4()),
+            Span::Action(ActionKind::WaitStateSync, uuid::Uuid::new_v4()),
+        ];
+
+        for span in spans {
+            let record = Record {
+                frame: 7,
+                span,
+                start: 100,
+                end: 250,
+                value: 3,
+            };
+
+            let decoded = Record::decode(&record.encode()).unwrap();
+            assert_eq!(record, decoded);
+            assert_eq!(decoded.duration(), 150);
+        }
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        setup();
+
+        assert_eq!(Record::decode(&[0; 10]), None);
+
+        let mut data = [0; RECORD_SIZE];
+        data[0] = 100;
+        assert_eq!(Record::decode(&data), None);
+    }
+
+    #[test]
+    fn test_recorder_reader() {
+        setup();
+
+        let path = std::env::temp_dir().join(format!("telemetry-{}.bin", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+
+        let mut recorder = Recorder::new(path, 8).unwrap();
+        for frame in 0..100 {
+            recorder.record(Record {
+                frame,
+                span: Span::Frame,
+                start: frame * 10,
+                end: frame * 10 + 5,
+                value: 0,
+            });
+
+            // give the writer a chance to keep up
+            if frame % 4 == 0 {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+            }
+        }
+        recorder.close();
+
+        let records: Vec<Record> = Reader::open(path)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(records.len() as u64 + recorder.dropped(), 100);
+        assert_eq!(records[0].frame, 0);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_reader_invalid() {
+        setup();
+
+        assert!(Reader::new(&b"NOPE\x01\x00"[..]).is_err());
+        assert!(Reader::new(&b"ELTM\x09\x00"[..]).is_err());
+        assert!(Reader::new(&b"ELTM\x01\x00"[..]).unwrap().next().is_none());
+    }
+}
@@ -0,0 +1,193 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use super::Record;
+
+/// Fixed capacity single producer, single consumer queue of records.
+///
+/// All storage is allocated up front so pushing from the frame loop never
+/// allocates or blocks, when the queue is full the record is dropped and counted.
+pub struct RingBuffer {
+    slots: Box<[UnsafeCell<Record>]>,
+    mask: usize,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+// the producer only writes slots between tail and head, the consumer only reads them
+unsafe impl Sync for RingBuffer {}
+unsafe impl Send for RingBuffer {}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> RingBuffer {
+        // round up so indexes can wrap with a mask
+        let capacity = capacity.max(2).next_power_of_two();
+
+        RingBuffer {
+            slots: (0..capacity)
+                .map(|_| UnsafeCell::new(Record::default()))
+                .collect(),
+            mask: capacity - 1,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        head.wrapping_sub(tail)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Add a record, must only be called from the single producer.
+    pub fn push(&self, record: Record) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+
+        // check if full
+        if head.wrapping_sub(tail) == self.slots.len() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        unsafe {
+            *self.slots[head & self.mask].get() = record;
+        }
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+
+        true
+    }
+
+    /// Take the oldest record, must only be called from the single consumer.
+    pub fn pop(&self) -> Option<Record> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+
+        // check if empty
+        if head == tail {
+            return None;
+        }
+
+        let record = unsafe { *self.slots[tail & self.mask].get() };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+
+        Some(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::Span;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    fn record(frame: u64) -> Record {
+        Record {
+            frame,
+            span: Span::Frame,
+            start: frame,
+            end: frame + 1,
+            value: 0,
+        }
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        setup();
+
+        let ring = RingBuffer::new(4);
+        assert_eq!(ring.capacity(), 4);
+        assert!(ring.is_empty());
+
+        assert!(ring.push(record(1)));
+        assert!(ring.push(record(2)));
+        assert_eq!(ring.len(), 2);
+
+        assert_eq!(ring.pop(), Some(record(1)));
+        assert_eq!(ring.pop(), Some(record(2)));
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn test_ring_buffer_full() {
+        setup();
+
+        let ring = RingBuffer::new(3);
+        assert_eq!(ring.capacity(), 4);
+
+        for frame in 0..6 {
+            ring.push(record(frame));
+        }
+
+        assert_eq!(ring.len(), 4);
+        assert_eq!(ring.dropped(), 2);
+
+        // oldest records are kept, newest dropped
+        assert_eq!(ring.pop(), Some(record(0)));
+    }
+
+    #[test]
+    fn test_ring_buffer_wrap() {
+        setup();
+
+        let ring = RingBuffer::new(2);
+
+        for frame in 0..10 {
+            assert!(ring.push(record(frame)));
+            assert_eq!(ring.pop(), Some(record(frame)));
+        }
+
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn test_ring_buffer_threads() {
+        setup();
+
+        let ring = std::sync::Arc::new(RingBuffer::new(16));
+
+        let consumer = {
+            let ring = ring.clone();
+            std::thread::spawn(move || {
+                let mut expected = 0;
+                while expected < 1000 {
+                    if let Some(record) = ring.pop() {
+                        assert_eq!(record.frame, expected);
+                        expected += 1;
+                    }
+                }
+            })
+        };
+
+        let mut frame = 0;
+        while frame < 1000 {
+            if ring.push(record(frame)) {
+                frame += 1;
+            }
+        }
+
+        consumer.join().unwrap();
+    }
+}
@@ -6,7 +6,7 @@ pub struct Message {
 }
 
 impl Message {
-    pub fn decode(data: &Vec<u8>) -> Option<Message> {
+    pub fn decode(data: &[u8]) -> Option<Message> {
         if data.len() < 5 {
             return None;
         }
@@ -66,6 +66,111 @@ pub struct AddComponentData {
     pub component: String,
     pub core: usize,
     pub version: String,
+    /// Hex encoded SHA-256 the component binary must have.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// What to do when the component process exits on its own.
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    /// Resources the component process may use.
+    #[serde(default)]
+    pub limits: Limits,
+}
+
+/// Resource limits for a component process, unlimited where not given.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct Limits {
+    /// Bytes of memory, the process is killed if it cannot be kept below.
+    #[serde(rename = "memory-max", default)]
+    pub memory_max: Option<u64>,
+    /// Percent of one cpu, the process is throttled above it.
+    #[serde(rename = "cpu-quota", default)]
+    pub cpu_quota: Option<u32>,
+    /// Processes and threads, creating more fails.
+    #[serde(rename = "pids-max", default)]
+    pub pids_max: Option<u64>,
+    /// Share of disk bandwidth from 1 to 10000, 100 being the default.
+    #[serde(rename = "io-weight", default)]
+    pub io_weight: Option<u16>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        *self == Limits::default()
+    }
+
+    /// Check the limits are values the kernel accepts.
+    pub fn check(&self) -> Result<(), String> {
+        if self.memory_max == Some(0) {
+            return Err("memory-max must be greater than 0".to_string());
+        }
+        if self.cpu_quota == Some(0) {
+            return Err("cpu-quota must be greater than 0".to_string());
+        }
+        if self.pids_max == Some(0) {
+            return Err("pids-max must be greater than 0".to_string());
+        }
+        if let Some(io_weight) = self.io_weight {
+            if !(1..=10000).contains(&io_weight) {
+                return Err(format!(
+                    "io-weight must be from 1 to 10000; io-weight = {}",
+                    io_weight
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the runner does when a component process exits without being removed.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct RestartPolicy {
+    #[serde(default)]
+    pub policy: Restart,
+    /// Restarts allowed before the component is left stopped, unlimited if not given.
+    #[serde(rename = "max-restarts", default)]
+    pub max_restarts: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub enum Restart {
+    #[default]
+    #[serde(rename = "never")]
+    Never,
+    /// Restart at once.
+    #[serde(rename = "always")]
+    Always,
+    #[serde(rename = "backoff")]
+    Backoff(BackoffData),
+}
+
+/// Wait `initial` milliseconds before the first restart and twice as long
+/// before each one after, up to `max`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct BackoffData {
+    pub initial: u64,
+    pub max: u64,
+}
+
+impl RestartPolicy {
+    /// How long to wait before restarting a component restarted `restarts`
+    /// times already, or `None` if it is not restarted again.
+    pub fn delay(&self, restarts: u32) -> Option<std::time::Duration> {
+        if self.max_restarts.is_some_and(|max| restarts >= max) {
+            return None;
+        }
+
+        match &self.policy {
+            Restart::Never => None,
+            Restart::Always => Some(std::time::Duration::ZERO),
+            Restart::Backoff(backoff) => {
+                let delay = backoff
+                    .initial
+                    .saturating_mul(1u64.checked_shl(restarts).unwrap_or(u64::MAX));
+                Some(std::time::Duration::from_millis(delay.min(backoff.max)))
+            }
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -95,6 +200,9 @@ pub struct AddRouteData {
 #[derive(Serialize, Deserialize, PartialEq, Debug, Eq, Hash, Clone)]
 pub struct RemoveRouteData {
     pub source: RouteEndpoint,
+    /// The route to remove, every route from the source if not given.
+    #[serde(default)]
+    pub target: Option<RouteEndpoint>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Eq, Hash, Clone)]
@@ -163,6 +271,8 @@ pub struct StateEndpoint {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::loader::format::Format;
+    use crate::types::desired::Document;
 
     // setup logging
     fn setup() {
@@ -223,6 +333,25 @@ mod tests {
                     ]),
                 },
                 
+                Task {
+                    id: uuid::Uuid::new_v4(),
+                    actions: Action::Blocking(vec![
+                        BlockingAction {
+                            id: uuid::Uuid::new_v4(),
+                            data: BlockingData::AddRoute(AddRouteData {
+                                source: RouteEndpoint {
+                                    endpoint: Endpoint::Address("127.0.0.1:7000".to_string()),
+                                    channel_id: 0,
+                                },
+                                target: RouteEndpoint {
+                                    endpoint: Endpoint::Runner,
+                                    channel_id: 0,
+                                },
+                            }),
+                        },
+                    ]),
+                },
+
                 Task {
                     id: uuid::Uuid::new_v4(),
                     actions: Action::Blocking(vec![
@@ -233,6 +362,10 @@ mod tests {
                                     endpoint: Endpoint::Component(uuid::Uuid::new_v4()),
                                     channel_id: 1,
                                 },
+                                target: Some(RouteEndpoint {
+                                    endpoint: Endpoint::Runner,
+                                    channel_id: 2,
+                                }),
                             }),
                         },
                     ]),
@@ -300,6 +433,17 @@ mod tests {
                                 component: "component".to_string(),
                                 core: 1,
                                 version: "version".to_string(),
+                                sha256: Some(crate::signature::sha256(b"component")),
+                                restart: RestartPolicy {
+                                    policy: Restart::Backoff(BackoffData { initial: 100, max: 5000 }),
+                                    max_restarts: Some(10),
+                                },
+                                limits: Limits {
+                                    memory_max: Some(64 * 1024 * 1024),
+                                    cpu_quota: Some(50),
+                                    pids_max: Some(16),
+                                    io_weight: None,
+                                },
                             }),
                         },
                     ]),
@@ -338,6 +482,22 @@ mod tests {
         let deserialized: Configuration = serde_yaml::from_str(&serialized).unwrap();
 
         assert_eq!(configuration, deserialized);
+
+        // the loader reads every format the configuration can be written in
+        let json = serde_json::to_string_pretty(&configuration).unwrap();
+        let toml = toml::to_string(&configuration).unwrap();
+        for (text, format) in [
+            (serialized, Format::Yaml),
+            (json, Format::Json),
+            (toml, Format::Toml),
+        ] {
+            assert_eq!(
+                crate::loader::from_text(&text, format),
+                Ok(Document::Tasks(configuration.clone())),
+                "{}",
+                format
+            );
+        }
     }
 
     #[test]
@@ -362,7 +522,7 @@ mod tests {
 
         let action = Action::Blocking(vec![]);
         let serialized = format!("{:?}", action);
-        let expected = format!("Blocking([])");
+        let expected = "Blocking([])".to_string();
         assert_eq!(serialized, expected);
 
         let blocking_action = BlockingAction {
@@ -381,11 +541,14 @@ mod tests {
                 component_id: uuid,
                 component: "component".to_string(),
                 core: 1,
-                version: "version".to_string()
+                version: "version".to_string(),
+                sha256: None,
+                restart: RestartPolicy::default(),
+                limits: Limits::default()
             })
         };
         let serialized = format!("{:?}", non_blocking_action);
-        let expected = format!("NonBlockingAction {{ id: {}, data: AddComponent(AddComponentData {{ component_id: {}, component: \"component\", core: 1, version: \"version\" }}) }}", uuid, uuid);
+        let expected = format!("NonBlockingAction {{ id: {}, data: AddComponent(AddComponentData {{ component_id: {}, component: \"component\", core: 1, version: \"version\", sha256: None, restart: RestartPolicy {{ policy: Never, max_restarts: None }}, limits: Limits {{ memory_max: None, cpu_quota: None, pids_max: None, io_weight: None }} }}) }}", uuid, uuid);
         assert_eq!(serialized, expected);
 
         let blocking_data = BlockingData::StartComponent(StartComponentData {
@@ -399,10 +562,13 @@ mod tests {
             component_id: uuid,
             component: "component".to_string(),
             core: 1,
-            version: "version".to_string()
+            version: "version".to_string(),
+            sha256: None,
+            restart: RestartPolicy::default(),
+            limits: Limits::default()
         });
         let serialized = format!("{:?}", non_blocking_data);
-        let expected = format!("AddComponent(AddComponentData {{ component_id: {}, component: \"component\", core: 1, version: \"version\" }})", uuid);
+        let expected = format!("AddComponent(AddComponentData {{ component_id: {}, component: \"component\", core: 1, version: \"version\", sha256: None, restart: RestartPolicy {{ policy: Never, max_restarts: None }}, limits: Limits {{ memory_max: None, cpu_quota: None, pids_max: None, io_weight: None }} }})", uuid);
         assert_eq!(serialized, expected);
 
         let blocking_data = BlockingData::StartComponent(StartComponentData {
@@ -416,10 +582,13 @@ mod tests {
             component_id: uuid,
             component: "component".to_string(),
             core: 1,
-            version: "version".to_string()
+            version: "version".to_string(),
+            sha256: None,
+            restart: RestartPolicy::default(),
+            limits: Limits::default()
         });
         let serialized = format!("{:?}", non_blocking_data);
-        let expected = format!("AddComponent(AddComponentData {{ component_id: {}, component: \"component\", core: 1, version: \"version\" }})", uuid);
+        let expected = format!("AddComponent(AddComponentData {{ component_id: {}, component: \"component\", core: 1, version: \"version\", sha256: None, restart: RestartPolicy {{ policy: Never, max_restarts: None }}, limits: Limits {{ memory_max: None, cpu_quota: None, pids_max: None, io_weight: None }} }})", uuid);
         assert_eq!(serialized, expected);
 
         let remove_component_data = RemoveComponentData {
@@ -447,10 +616,87 @@ mod tests {
             source: RouteEndpoint {
                 endpoint: Endpoint::Component(uuid),
                 channel_id: 1
-            }
+            },
+            target: None
         };
         let serialized = format!("{:?}", remove_route_data);
-        let expected = format!("RemoveRouteData {{ source: RouteEndpoint {{ endpoint: Component({}), channel_id: 1 }} }}", uuid);
+        let expected = format!("RemoveRouteData {{ source: RouteEndpoint {{ endpoint: Component({}), channel_id: 1 }}, target: None }}", uuid);
         assert_eq!(serialized, expected);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_restart_policy() {
+        setup();
+
+        // components are not restarted unless asked for
+        let data: AddComponentData = serde_yaml::from_str("component-id: 9a069153-c335-47c5-a653-dec6c0e7c280\ncomponent: plant\ncore: 2\nversion: 1.0.0\n").unwrap();
+        assert_eq!(data.restart, RestartPolicy::default());
+        assert_eq!(data.restart.delay(0), None);
+
+        let policy: RestartPolicy =
+            serde_yaml::from_str("policy: always\nmax-restarts: 2\n").unwrap();
+        assert_eq!(policy.delay(0), Some(std::time::Duration::ZERO));
+        assert_eq!(policy.delay(1), Some(std::time::Duration::ZERO));
+        assert_eq!(policy.delay(2), None);
+
+        let policy: RestartPolicy =
+            serde_yaml::from_str("policy: !backoff\n  initial: 100\n  max: 1000\n").unwrap();
+        assert_eq!(
+            policy.policy,
+            Restart::Backoff(BackoffData {
+                initial: 100,
+                max: 1000
+            })
+        );
+        assert_eq!(policy.delay(0), Some(std::time::Duration::from_millis(100)));
+        assert_eq!(policy.delay(3), Some(std::time::Duration::from_millis(800)));
+        assert_eq!(
+            policy.delay(4),
+            Some(std::time::Duration::from_millis(1000))
+        );
+        assert_eq!(
+            policy.delay(100),
+            Some(std::time::Duration::from_millis(1000))
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        setup();
+
+        // components are unlimited unless limits are given
+        let data: AddComponentData = serde_yaml::from_str("component-id: 9a069153-c335-47c5-a653-dec6c0e7c280\ncomponent: plant\ncore: 2\nversion: 1.0.0\n").unwrap();
+        assert!(data.limits.is_empty());
+        assert_eq!(data.limits.check(), Ok(()));
+
+        let limits: Limits = serde_yaml::from_str(
+            "memory-max: 1048576\ncpu-quota: 25\npids-max: 8\nio-weight: 200\n",
+        )
+        .unwrap();
+        assert_eq!(
+            limits,
+            Limits {
+                memory_max: Some(1048576),
+                cpu_quota: Some(25),
+                pids_max: Some(8),
+                io_weight: Some(200)
+            }
+        );
+        assert!(!limits.is_empty());
+        assert_eq!(limits.check(), Ok(()));
+
+        let limits = Limits {
+            io_weight: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            limits.check(),
+            Err("io-weight must be from 1 to 10000; io-weight = 0".to_string())
+        );
+        let limits = Limits {
+            memory_max: Some(0),
+            ..Default::default()
+        };
+        assert!(limits.check().is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+
+use super::configuration::{Configuration, Limits, RestartPolicy, RouteEndpoint, SetScheduleData};
+
+/// The system a runner should end up running, the runner plans the tasks to get there.
+///
+/// Components, routes and the schedule not listed are removed. Routes to and
+/// from the runner, such as the management route, have to be listed as well.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct DesiredState {
+    pub components: Vec<DesiredComponent>,
+    pub routes: Vec<Route>,
+    pub schedule: SetScheduleData,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct DesiredComponent {
+    #[serde(rename = "component-id")]
+    pub component_id: uuid::Uuid,
+    pub component: String,
+    pub core: usize,
+    pub version: String,
+    /// Hex encoded SHA-256 the component binary must have.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// What to do when the component process exits on its own, taken when it is added.
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    /// Resources the component process may use, taken when it is added.
+    #[serde(default)]
+    pub limits: Limits,
+    /// Running component whose state is handed over before this one takes its place.
+    #[serde(rename = "state-from", default)]
+    pub state_from: Option<uuid::Uuid>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Route {
+    pub source: RouteEndpoint,
+    pub target: RouteEndpoint,
+}
+
+/// A configuration file, either the steps to take or the system to end up with.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Document {
+    Tasks(Configuration),
+    Desired(DesiredState),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::format::Format;
+    use crate::types::configuration::{Endpoint, MajorFrame, MinorFrame};
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_desired_state() {
+        setup();
+
+        let plant = uuid::Uuid::new_v4();
+        let fcs_a = uuid::Uuid::new_v4();
+        let fcs_b = uuid::Uuid::new_v4();
+
+        let desired = DesiredState {
+            components: vec![
+                DesiredComponent {
+                    component_id: plant,
+                    component: "plant".to_string(),
+                    core: 2,
+                    version: "1.0.0".to_string(),
+                    sha256: Some(crate::signature::sha256(b"plant")),
+                    restart: RestartPolicy {
+                        policy: super::super::configuration::Restart::Always,
+                        max_restarts: None,
+                    },
+                    limits: Limits {
+                        memory_max: Some(1 << 26),
+                        ..Default::default()
+                    },
+                    state_from: None,
+                },
+                DesiredComponent {
+                    component_id: fcs_b,
+                    component: "fcs_b".to_string(),
+                    core: 3,
+                    version: "1.0.0".to_string(),
+                    sha256: None,
+                    restart: RestartPolicy::default(),
+                    limits: Limits::default(),
+                    state_from: Some(fcs_a),
+                },
+            ],
+            routes: vec![Route {
+                source: RouteEndpoint {
+                    endpoint: Endpoint::Component(plant),
+                    channel_id: 1,
+                },
+                target: RouteEndpoint {
+                    endpoint: Endpoint::Component(fcs_b),
+                    channel_id: 1,
+                },
+            }],
+            schedule: SetScheduleData {
+                deadline: 1000,
+                major_frames: vec![MajorFrame {
+                    minor_frames: vec![MinorFrame {
+                        component_id: plant,
+                        deadline: 150,
+                    }],
+                }],
+            },
+        };
+
+        let serialized = serde_yaml::to_string(&desired).unwrap();
+        let json = serde_json::to_string(&desired).unwrap();
+        let toml = toml::to_string(&desired).unwrap();
+        for (text, format) in [
+            (serialized, Format::Yaml),
+            (json, Format::Json),
+            (toml, Format::Toml),
+        ] {
+            assert_eq!(
+                crate::loader::from_text(&text, format),
+                Ok(Document::Desired(desired.clone())),
+                "{}",
+                format
+            );
+        }
+
+        // the encoding used on the management channel
+        let encoded = bincode::serialize(&desired).unwrap();
+        assert_eq!(
+            bincode::deserialize::<DesiredState>(&encoded).unwrap(),
+            desired
+        );
+    }
+}
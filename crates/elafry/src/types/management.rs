@@ -0,0 +1,590 @@
+use std::io::{self, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use super::configuration::{Configuration, RouteEndpoint, SetScheduleData};
+use super::desired::DesiredState;
+
+/// Version of the management protocol, requests with any other version are rejected.
+pub const VERSION: u16 = 1;
+
+/// Runner channel that management requests are routed to, replies are sent
+/// back on the channel the request came from.
+pub const CHANNEL: u32 = 0;
+
+/// Bytes of a document per [`Chunk`], small enough for a chunk request to fit
+/// the 1024 byte datagrams the runner reads from the network.
+pub const CHUNK_SIZE: usize = 896;
+
+/// Longest frame on the control socket, an inline configuration may be as
+/// large as an uploaded document, 1 MiB, with room for the request around it.
+pub const MAX_FRAME: usize = (1 << 20) + 4096;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Request {
+    pub version: u16,
+    #[serde(rename = "request-id")]
+    pub request_id: u64,
+    pub command: Command,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum Command {
+    /// Load a configuration file by name from the runner's configuration directory.
+    #[serde(rename = "load")]
+    Load(String),
+    /// Load a configuration sent with the request.
+    #[serde(rename = "load-inline")]
+    LoadInline(Configuration),
+    /// Bring the system to a desired state sent with the request, replies like a load.
+    #[serde(rename = "apply-inline")]
+    ApplyInline(DesiredState),
+    /// Plan the tasks to reach a desired state without applying them.
+    #[serde(rename = "plan-inline")]
+    PlanInline(DesiredState),
+    /// Check a configuration file against the running system without applying it.
+    #[serde(rename = "validate")]
+    Validate(String),
+    /// Check a configuration sent with the request without applying it.
+    #[serde(rename = "validate-inline")]
+    ValidateInline(Configuration),
+    /// Part of a configuration document, replied to with [`Reply::Received`].
+    #[serde(rename = "upload")]
+    Upload(Chunk),
+    /// Load a configuration document once all its chunks are uploaded, replies like a load.
+    #[serde(rename = "load-upload")]
+    LoadUpload(u64),
+    /// Check an uploaded configuration document without applying it.
+    #[serde(rename = "validate-upload")]
+    ValidateUpload(u64),
+    #[serde(rename = "status")]
+    Status,
+    #[serde(rename = "list-components")]
+    ListComponents,
+    #[serde(rename = "list-routes")]
+    ListRoutes,
+    #[serde(rename = "schedule")]
+    Schedule,
+    /// Copy a component binary on the runner host into the registry, replied to
+    /// with [`Reply::Installed`] once it is copied.
+    #[serde(rename = "install")]
+    Install(InstallData),
+    /// Remove a version no component is running from the registry.
+    #[serde(rename = "uninstall")]
+    Uninstall(ComponentVersion),
+    #[serde(rename = "list-registry")]
+    ListRegistry,
+    /// Stop the reconfiguration in progress before its next task.
+    #[serde(rename = "abort")]
+    Abort,
+    #[serde(rename = "shutdown")]
+    Shutdown,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Response {
+    pub version: u16,
+    #[serde(rename = "request-id")]
+    pub request_id: u64,
+    pub reply: Reply,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum Reply {
+    /// The configuration will be loaded or the binary installed, the outcome
+    /// follows with the same request id when it is done.
+    #[serde(rename = "accepted")]
+    Accepted,
+    #[serde(rename = "loaded")]
+    Loaded,
+    /// Sent for both the abort request and the load it stopped.
+    #[serde(rename = "aborted")]
+    Aborted,
+    #[serde(rename = "status")]
+    Status(Status),
+    #[serde(rename = "components")]
+    Components(Vec<ComponentStatus>),
+    #[serde(rename = "routes")]
+    Routes(Vec<RouteStatus>),
+    /// The current schedule, in the same form as a set-schedule action.
+    #[serde(rename = "schedule")]
+    Schedule(SetScheduleData),
+    #[serde(rename = "shutting-down")]
+    ShuttingDown,
+    /// The tasks planned to reach a desired state.
+    #[serde(rename = "plan")]
+    Plan(Configuration),
+    /// Problems a dry run found, the configuration would load if none are errors.
+    #[serde(rename = "report")]
+    Report(Vec<Issue>),
+    /// Number of chunks of the upload received so far.
+    #[serde(rename = "received")]
+    Received(u32),
+    /// Sha256 of the binary installed in the registry.
+    #[serde(rename = "installed")]
+    Installed(String),
+    #[serde(rename = "uninstalled")]
+    Uninstalled,
+    #[serde(rename = "registry")]
+    Registry(Vec<RegistryEntry>),
+    #[serde(rename = "error")]
+    Error(Error),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum Error {
+    /// The request could not be decoded, replies to these have a request id of 0.
+    #[serde(rename = "malformed")]
+    Malformed,
+    #[serde(rename = "unsupported-version")]
+    UnsupportedVersion(u16),
+    /// Another reconfiguration is in progress.
+    #[serde(rename = "busy")]
+    Busy,
+    #[serde(rename = "nothing-to-abort")]
+    NothingToAbort,
+    #[serde(rename = "invalid-name")]
+    InvalidName(String),
+    #[serde(rename = "load-failed")]
+    LoadFailed(String),
+    /// An action failed or the reconfiguration timed out, every change it made was undone.
+    #[serde(rename = "rolled-back")]
+    RolledBack(String),
+    /// No upload with this id has all its chunks.
+    #[serde(rename = "unknown-upload")]
+    UnknownUpload(u64),
+    #[serde(rename = "invalid-chunk")]
+    InvalidChunk(String),
+    /// The runner only loads signed configurations, inline ones carry no signature.
+    #[serde(rename = "unsigned")]
+    Unsigned,
+    /// The runner has no component registry configured.
+    #[serde(rename = "no-registry")]
+    NoRegistry,
+    #[serde(rename = "registry-failed")]
+    RegistryFailed(String),
+}
+
+/// A piece of a configuration document too large for a single request.
+///
+/// The document is the text of a configuration file in any format the loader
+/// reads, it cannot include other files.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Chunk {
+    /// Chosen by the sender, the chunks of one document share it.
+    pub upload: u64,
+    pub index: u32,
+    pub count: u32,
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    /// Split a document into chunks of at most [`CHUNK_SIZE`] bytes.
+    pub fn split(upload: u64, document: &[u8]) -> Vec<Chunk> {
+        let count = document.len().div_ceil(CHUNK_SIZE).max(1) as u32;
+        (0..count)
+            .map(|index| {
+                let start = index as usize * CHUNK_SIZE;
+                let end = (start + CHUNK_SIZE).min(document.len());
+                Chunk {
+                    upload,
+                    index,
+                    count,
+                    data: document[start..end].to_vec(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum ManagementState {
+    #[serde(rename = "idle")]
+    Idle,
+    #[serde(rename = "waiting")]
+    Waiting,
+    #[serde(rename = "loading")]
+    Loading,
+    #[serde(rename = "running")]
+    Running,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Status {
+    pub state: ManagementState,
+    /// Index of the task being run while reconfiguring.
+    pub task: Option<usize>,
+    pub tasks: usize,
+    pub components: usize,
+    pub routes: usize,
+    /// Schedule period in microseconds.
+    pub period: u64,
+    /// The runner runs without real-time scheduling, timing guarantees are off.
+    #[serde(rename = "best-effort")]
+    pub best_effort: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ComponentStatus {
+    #[serde(rename = "component-id")]
+    pub component_id: uuid::Uuid,
+    pub component: String,
+    pub version: String,
+    pub core: usize,
+    pub running: bool,
+    /// The process exited on its own and has not been restarted yet.
+    pub crashed: bool,
+    /// Times the process has been restarted.
+    pub restarts: u32,
+    /// Times the process hit its resource limits.
+    pub faults: u64,
+    #[serde(rename = "cpu-time")]
+    pub cpu_time: CpuTime,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct InstallData {
+    pub component: String,
+    pub version: String,
+    /// Path of the binary on the runner host.
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ComponentVersion {
+    pub component: String,
+    pub version: String,
+}
+
+/// A component version installed in the registry.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RegistryEntry {
+    pub component: String,
+    pub version: String,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RouteStatus {
+    pub source: RouteEndpoint,
+    pub target: RouteEndpoint,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum Severity {
+    /// The action would fail and the load be rolled back, or leave the system broken.
+    #[serde(rename = "error")]
+    Error,
+    /// The action would succeed but probably not do what was meant.
+    #[serde(rename = "warning")]
+    Warning,
+}
+
+/// A problem found while validating a configuration.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Issue {
+    pub severity: Severity,
+    /// Index of the task, `None` for problems with the configuration as a whole.
+    pub task: Option<usize>,
+    pub action: Option<uuid::Uuid>,
+    pub message: String,
+}
+
+/// Cpu time used per schedule slot, in nanoseconds.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct CpuTime {
+    pub count: u64,
+    pub min: u64,
+    pub mean: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl Request {
+    pub fn new(request_id: u64, command: Command) -> Request {
+        Request {
+            version: VERSION,
+            request_id,
+            command,
+        }
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Request> {
+        bincode::deserialize(data).ok()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+}
+
+impl Response {
+    pub fn new(request_id: u64, reply: Reply) -> Response {
+        Response {
+            version: VERSION,
+            request_id,
+            reply,
+        }
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Response> {
+        bincode::deserialize(data).ok()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+}
+
+/// Write a length prefixed frame, as used on the runner control socket.
+pub fn write_frame<W: Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    if data.len() > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("frame length {} over {}", data.len(), MAX_FRAME),
+        ));
+    }
+
+    let mut frame = (data.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(data);
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+/// Read a length prefixed frame written by [`write_frame`].
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut length_buf = [0; 4];
+    reader.read_exact(&mut length_buf)?;
+
+    // checked before allocating, the length comes from the other end
+    let length = u32::from_be_bytes(length_buf) as usize;
+    if length > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame length {} over {}", length, MAX_FRAME),
+        ));
+    }
+    let mut data = vec![0; length];
+    reader.read_exact(&mut data)?;
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_request_encode_decode() {
+        setup();
+
+        let commands = vec![
+            Command::Load("plant_1.yaml".to_string()),
+            Command::LoadInline(Configuration { tasks: vec![] }),
+            Command::ApplyInline(DesiredState {
+                components: vec![],
+                routes: vec![],
+                schedule: SetScheduleData {
+                    deadline: 1000,
+                    major_frames: vec![],
+                },
+            }),
+            Command::Validate("plant_2.yaml".to_string()),
+            Command::ValidateInline(Configuration { tasks: vec![] }),
+            Command::Upload(Chunk {
+                upload: 3,
+                index: 0,
+                count: 2,
+                data: b"tasks: []".to_vec(),
+            }),
+            Command::LoadUpload(3),
+            Command::ValidateUpload(3),
+            Command::Install(InstallData {
+                component: "fcs".to_string(),
+                version: "2.0.0".to_string(),
+                path: "/tmp/fcs".to_string(),
+            }),
+            Command::Uninstall(ComponentVersion {
+                component: "fcs".to_string(),
+                version: "1.0.0".to_string(),
+            }),
+            Command::ListRegistry,
+            Command::Status,
+            Command::ListComponents,
+            Command::ListRoutes,
+            Command::Schedule,
+            Command::Abort,
+            Command::Shutdown,
+        ];
+
+        for (request_id, command) in commands.into_iter().enumerate() {
+            let request = Request::new(request_id as u64, command);
+            assert_eq!(request.version, VERSION);
+            assert_eq!(Request::decode(&request.encode()), Some(request));
+        }
+    }
+
+    #[test]
+    fn test_response_encode_decode() {
+        setup();
+
+        let replies = vec![
+            Reply::Accepted,
+            Reply::Loaded,
+            Reply::Aborted,
+            Reply::Status(Status {
+                state: ManagementState::Running,
+                task: Some(2),
+                tasks: 4,
+                components: 3,
+                routes: 5,
+                period: 1000,
+                best_effort: true,
+            }),
+            Reply::Components(vec![ComponentStatus {
+                component_id: uuid::Uuid::new_v4(),
+                component: "plant".to_string(),
+                version: "1.0.0".to_string(),
+                core: 2,
+                running: true,
+                crashed: false,
+                restarts: 1,
+                faults: 2,
+                cpu_time: CpuTime::default(),
+            }]),
+            Reply::Routes(vec![RouteStatus {
+                source: RouteEndpoint {
+                    endpoint: crate::types::configuration::Endpoint::Component(uuid::Uuid::new_v4()),
+                    channel_id: 1,
+                },
+                target: RouteEndpoint {
+                    endpoint: crate::types::configuration::Endpoint::Address(
+                        "127.0.0.1:5000".to_string(),
+                    ),
+                    channel_id: 2,
+                },
+            }]),
+            Reply::Schedule(SetScheduleData {
+                deadline: 1000,
+                major_frames: vec![],
+            }),
+            Reply::ShuttingDown,
+            Reply::Plan(Configuration { tasks: vec![] }),
+            Reply::Report(vec![Issue {
+                severity: Severity::Error,
+                task: Some(1),
+                action: Some(uuid::Uuid::new_v4()),
+                message: "Component not found".to_string(),
+            }]),
+            Reply::Error(Error::LoadFailed("missing".to_string())),
+            Reply::Received(2),
+            Reply::Error(Error::RolledBack("timed out".to_string())),
+            Reply::Error(Error::UnknownUpload(3)),
+            Reply::Error(Error::InvalidChunk("too large".to_string())),
+            Reply::Error(Error::Unsigned),
+            Reply::Installed("ba7816bf".to_string()),
+            Reply::Uninstalled,
+            Reply::Registry(vec![RegistryEntry {
+                component: "fcs".to_string(),
+                version: "1.0.0".to_string(),
+                sha256: "ba7816bf".to_string(),
+            }]),
+            Reply::Error(Error::NoRegistry),
+            Reply::Error(Error::RegistryFailed("in use".to_string())),
+        ];
+
+        for reply in replies {
+            let response = Response::new(7, reply);
+            assert_eq!(Response::decode(&response.encode()), Some(response));
+        }
+    }
+
+    #[test]
+    fn test_chunks() {
+        setup();
+
+        let document: Vec<u8> = (0..CHUNK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        let chunks = Chunk::split(9, &document);
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.upload == 9 && chunk.count == 3));
+        assert_eq!(chunks[2].index, 2);
+        assert_eq!(chunks[2].data.len(), 10);
+        assert_eq!(
+            chunks
+                .iter()
+                .flat_map(|chunk| chunk.data.clone())
+                .collect::<Vec<u8>>(),
+            document
+        );
+
+        // an empty document is still one chunk
+        assert_eq!(Chunk::split(9, &[]).len(), 1);
+
+        // a full chunk fits a datagram with the length and message header
+        let request = Request::new(u64::MAX, Command::Upload(chunks[0].clone()));
+        let message = crate::types::communication::Message {
+            channel_id: CHANNEL,
+            count: 0,
+            data: request.encode(),
+        };
+        assert!(message.encode().len() + 4 <= 1024);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        setup();
+
+        assert_eq!(Request::decode(&[]), None);
+        assert_eq!(Request::decode(b"plant_1.yaml"), None);
+        assert_eq!(Response::decode(&[1]), None);
+    }
+
+    #[test]
+    fn test_frames() {
+        setup();
+
+        let mut buf = vec![];
+        write_frame(&mut buf, &[1, 2, 3]).unwrap();
+        write_frame(&mut buf, &[]).unwrap();
+        assert_eq!(buf.len(), 11);
+
+        let mut reader = &buf[..];
+        assert_eq!(read_frame(&mut reader).unwrap(), vec![1, 2, 3]);
+        assert_eq!(read_frame(&mut reader).unwrap(), Vec::<u8>::new());
+        assert!(read_frame(&mut reader).is_err());
+
+        // a length over the limit is refused before anything is allocated for it
+        let mut reader = &u32::MAX.to_be_bytes()[..];
+        let error = read_frame(&mut reader).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            error.to_string(),
+            format!("frame length {} over {}", u32::MAX, MAX_FRAME)
+        );
+        let mut reader = &((MAX_FRAME + 1) as u32).to_be_bytes()[..];
+        assert_eq!(
+            read_frame(&mut reader).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut buf = vec![];
+        assert_eq!(
+            write_frame(&mut buf, &vec![0; MAX_FRAME + 1])
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(buf.is_empty());
+    }
+}
@@ -1,2 +1,4 @@
 pub mod communication;
 pub mod configuration;
+pub mod desired;
+pub mod management;
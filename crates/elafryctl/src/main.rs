@@ -0,0 +1,888 @@
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use elafry::loader::format::Format;
+use elafry::signature::Signature;
+use elafry::types::configuration::{Endpoint, RouteEndpoint, SetScheduleData};
+use elafry::types::desired::Document;
+use elafry::types::management::{
+    read_frame, write_frame, Chunk, Command, ComponentStatus, ComponentVersion, Error, InstallData,
+    Issue, ManagementState, RegistryEntry, Reply, Request, Response, RouteStatus, Severity, Status,
+};
+
+fn usage() -> ! {
+    eprintln!("Usage: elafryctl [--socket <path>] <command>");
+    eprintln!();
+    eprintln!("Commands:");
+    eprintln!("  load [--no-wait] <name>           load a configuration from the runner directory");
+    eprintln!("  load [--no-wait] --inline <file>  load a configuration file from here");
+    eprintln!(
+        "  load [--no-wait] --upload <file>  send a configuration file for the runner to parse"
+    );
+    eprintln!("  plan <file>                       show the tasks to reach a desired state");
+    eprintln!("  validate <name>                   check a configuration without loading it");
+    eprintln!("  validate --inline <file>          check a configuration file from here");
+    eprintln!(
+        "  validate --upload <file>          check a configuration file parsed by the runner"
+    );
+    eprintln!("  status                            show the management state");
+    eprintln!("  components                        list components and their cpu time");
+    eprintln!("  routes                            list routes");
+    eprintln!("  schedule                          show the current schedule");
+    eprintln!("  watch                             follow a reconfiguration until it is done");
+    eprintln!("  abort                             stop the reconfiguration in progress");
+    eprintln!("  stop                              shut the runner down");
+    eprintln!("  install <component> <version> <binary>  copy a binary into the runner registry");
+    eprintln!("  uninstall <component> <version>   remove a version from the runner registry");
+    eprintln!("  registry                          list installed component versions");
+    eprintln!(
+        "  keygen <key-file>                 write a new signing key and print its public key"
+    );
+    eprintln!("  sign --key <key-file> <file>      add a signature to a configuration file");
+    eprintln!("  hash <file>                       print the sha256 of a component binary");
+    eprintln!();
+    eprintln!("The socket defaults to $ELAFRY_SOCKET or elafry.sock.");
+    std::process::exit(2);
+}
+
+/// Connection to the runner control socket.
+struct Client<S: Read + Write> {
+    stream: S,
+    next_request_id: u64,
+}
+
+impl<S: Read + Write> Client<S> {
+    fn new(stream: S) -> Client<S> {
+        Client {
+            stream,
+            next_request_id: 1,
+        }
+    }
+
+    /// Send a request and return its id, replies are read with [`Client::reply`].
+    fn send(&mut self, command: Command) -> io::Result<u64> {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+
+        write_frame(
+            &mut self.stream,
+            &Request::new(request_id, command).encode(),
+        )?;
+
+        Ok(request_id)
+    }
+
+    /// Wait for the next reply to a request, replies to other requests are skipped.
+    fn reply(&mut self, request_id: u64) -> io::Result<Reply> {
+        loop {
+            let data = read_frame(&mut self.stream)?;
+            let response = match Response::decode(&data) {
+                Some(response) => response,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Failed to decode response",
+                    ))
+                }
+            };
+
+            // malformed requests are answered with id 0
+            if response.request_id == request_id || response.request_id == 0 {
+                return Ok(response.reply);
+            }
+
+            log::debug!("Skipping reply to request {}", response.request_id);
+        }
+    }
+
+    fn request(&mut self, command: Command) -> io::Result<Reply> {
+        let request_id = self.send(command)?;
+        self.reply(request_id)
+    }
+
+    /// Send a document in chunks, returns the upload id or the reply rejecting a chunk.
+    fn upload(&mut self, document: &[u8]) -> io::Result<Result<u64, Reply>> {
+        let upload = uuid::Uuid::new_v4().as_u64_pair().0;
+
+        for chunk in Chunk::split(upload, document) {
+            match self.request(Command::Upload(chunk))? {
+                Reply::Received(_) => {}
+                reply => return Ok(Err(reply)),
+            }
+        }
+
+        Ok(Ok(upload))
+    }
+
+    /// Load a configuration or change the registry, optionally waiting for the outcome.
+    fn load(&mut self, command: Command, wait: bool) -> io::Result<Reply> {
+        let request_id = self.send(command)?;
+
+        let reply = self.reply(request_id)?;
+        if reply != Reply::Accepted || !wait {
+            return Ok(reply);
+        }
+
+        // the outcome is sent with the same request id once the load is done
+        self.reply(request_id)
+    }
+}
+
+fn describe_error(error: &Error) -> String {
+    match error {
+        Error::Malformed => "the runner could not decode the request".to_string(),
+        Error::UnsupportedVersion(version) => {
+            format!("the runner does not support protocol version {}", version)
+        }
+        Error::Busy => "another reconfiguration is in progress".to_string(),
+        Error::NothingToAbort => "no reconfiguration is in progress".to_string(),
+        Error::InvalidName(name) => format!("invalid configuration name {:?}", name),
+        Error::LoadFailed(reason) => format!("load failed: {}", reason),
+        Error::RolledBack(reason) => format!("load failed and was rolled back: {}", reason),
+        Error::UnknownUpload(upload) => format!("upload {} is not complete", upload),
+        Error::InvalidChunk(reason) => format!("upload failed: {}", reason),
+        Error::Unsigned => {
+            "the runner only loads signed configurations, use a file or --upload".to_string()
+        }
+        Error::NoRegistry => "the runner has no component registry".to_string(),
+        Error::RegistryFailed(reason) => format!("registry change failed: {}", reason),
+    }
+}
+
+fn state_name(state: ManagementState) -> &'static str {
+    match state {
+        ManagementState::Idle => "idle",
+        ManagementState::Waiting => "waiting",
+        ManagementState::Loading => "loading",
+        ManagementState::Running => "running",
+    }
+}
+
+fn format_status(status: &Status) -> String {
+    let progress = match status.task {
+        Some(task) => format!(" (task {}/{})", task + 1, status.tasks),
+        None => String::new(),
+    };
+
+    let mut output = format!(
+        "state: {}{}\ncomponents: {}\nroutes: {}\nperiod: {}us\n",
+        state_name(status.state),
+        progress,
+        status.components,
+        status.routes,
+        status.period
+    );
+    if status.best_effort {
+        output.push_str("scheduling: best-effort, no timing guarantees\n");
+    }
+    output
+}
+
+fn format_components(components: &[ComponentStatus]) -> String {
+    let mut output = format!(
+        "{:<36}  {:<4}  {:<7}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  {:<8}  {}\n",
+        "ID",
+        "CORE",
+        "RUNNING",
+        "RESTARTS",
+        "FAULTS",
+        "MIN(us)",
+        "MEAN(us)",
+        "P99(us)",
+        "MAX(us)",
+        "VERSION",
+        "COMPONENT"
+    );
+
+    for component in components {
+        let cpu_time = &component.cpu_time;
+        output.push_str(&format!(
+            "{:<36}  {:<4}  {:<7}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  {:<8}  {}\n",
+            component.component_id,
+            component.core,
+            match (component.crashed, component.running) {
+                (true, _) => "crashed",
+                (false, true) => "yes",
+                (false, false) => "no",
+            },
+            component.restarts,
+            component.faults,
+            cpu_time.min / 1000,
+            cpu_time.mean / 1000,
+            cpu_time.p99 / 1000,
+            cpu_time.max / 1000,
+            component.version,
+            component.component
+        ));
+    }
+
+    output
+}
+
+fn format_registry(entries: &[RegistryEntry]) -> String {
+    let mut output = format!("{:<24}  {:<12}  {}\n", "COMPONENT", "VERSION", "SHA256");
+
+    for entry in entries {
+        output.push_str(&format!(
+            "{:<24}  {:<12}  {}\n",
+            entry.component, entry.version, entry.sha256
+        ));
+    }
+
+    output
+}
+
+fn format_endpoint(endpoint: &RouteEndpoint) -> String {
+    match &endpoint.endpoint {
+        Endpoint::Component(id) => format!("component:{}/{}", id, endpoint.channel_id),
+        Endpoint::Address(address) => format!("address:{}/{}", address, endpoint.channel_id),
+        Endpoint::Runner => format!("runner/{}", endpoint.channel_id),
+    }
+}
+
+fn format_routes(routes: &[RouteStatus]) -> String {
+    routes
+        .iter()
+        .map(|route| {
+            format!(
+                "{} -> {}\n",
+                format_endpoint(&route.source),
+                format_endpoint(&route.target)
+            )
+        })
+        .collect()
+}
+
+fn format_schedule(schedule: &SetScheduleData) -> String {
+    let mut output = format!("period: {}us\n", schedule.deadline);
+
+    for (index, major_frame) in schedule.major_frames.iter().enumerate() {
+        output.push_str(&format!("major frame {}\n", index));
+        for minor_frame in major_frame.minor_frames.iter() {
+            output.push_str(&format!(
+                "  {} {}us\n",
+                minor_frame.component_id, minor_frame.deadline
+            ));
+        }
+    }
+
+    output
+}
+
+fn format_report(issues: &[Issue]) -> String {
+    if issues.is_empty() {
+        return "ok\n".to_string();
+    }
+
+    let mut output = String::new();
+    for issue in issues {
+        let severity = match issue.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let location = match (issue.task, issue.action) {
+            (Some(task), Some(action)) => format!("task {} action {}: ", task, action),
+            (Some(task), None) => format!("task {}: ", task),
+            _ => String::new(),
+        };
+        output.push_str(&format!("{}: {}{}\n", severity, location, issue.message));
+    }
+
+    output
+}
+
+/// Print a reply, returning false if it was an error.
+fn print_reply(reply: &Reply) -> bool {
+    match reply {
+        Reply::Accepted => println!("accepted"),
+        Reply::Loaded => println!("loaded"),
+        Reply::Aborted => println!("aborted"),
+        Reply::Status(status) => print!("{}", format_status(status)),
+        Reply::Components(components) => print!("{}", format_components(components)),
+        Reply::Routes(routes) => print!("{}", format_routes(routes)),
+        Reply::Schedule(schedule) => print!("{}", format_schedule(schedule)),
+        Reply::ShuttingDown => println!("shutting down"),
+        Reply::Plan(configuration) => match serde_yaml::to_string(configuration) {
+            Ok(text) => print!("{}", text),
+            Err(e) => {
+                eprintln!("Failed to format plan; err = {}", e);
+                return false;
+            }
+        },
+        Reply::Report(issues) => {
+            print!("{}", format_report(issues));
+            return !issues.iter().any(|issue| issue.severity == Severity::Error);
+        }
+        Reply::Received(chunks) => println!("received {} chunks", chunks),
+        Reply::Installed(sha256) => println!("installed sha256 {}", sha256),
+        Reply::Uninstalled => println!("uninstalled"),
+        Reply::Registry(entries) => print!("{}", format_registry(entries)),
+        Reply::Error(error) => {
+            eprintln!("error: {}", describe_error(error));
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Poll the status and print each change until the runner is idle again.
+fn watch<S: Read + Write>(client: &mut Client<S>) -> io::Result<bool> {
+    let mut last: Option<Status> = None;
+    let mut busy = false;
+
+    loop {
+        let status = match client.request(Command::Status)? {
+            Reply::Status(status) => status,
+            reply => return Ok(print_reply(&reply)),
+        };
+
+        if last.as_ref() != Some(&status) {
+            print!("{}", format_status(&status));
+            println!();
+        }
+
+        match status.state {
+            ManagementState::Idle if busy => return Ok(true),
+            ManagementState::Idle => {}
+            _ => busy = true,
+        }
+
+        last = Some(status);
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Read a local configuration file, either a list of tasks or a desired state.
+fn load_inline(path: &str) -> io::Result<Document> {
+    elafry::loader::from_file(Path::new(path))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Add a `signature` section to the text of a configuration file.
+fn append_signature(text: &str, format: Format, signature: &Signature) -> Result<String, String> {
+    match format {
+        Format::Yaml => Ok(format!(
+            "{}{}signature:\n  key: {}\n  signature: {}\n",
+            text,
+            if text.ends_with('\n') || text.is_empty() {
+                ""
+            } else {
+                "\n"
+            },
+            signature.key,
+            signature.signature
+        )),
+        // a table header at the end does not change the tables before it
+        Format::Toml => Ok(format!(
+            "{}{}\n[signature]\nkey = \"{}\"\nsignature = \"{}\"\n",
+            text,
+            if text.ends_with('\n') || text.is_empty() {
+                ""
+            } else {
+                "\n"
+            },
+            signature.key,
+            signature.signature
+        )),
+        Format::Json => {
+            let mut value: serde_json::Value = serde_json::from_str(text)
+                .map_err(|e| format!("Failed to parse json; err = {}", e))?;
+            let object = value
+                .as_object_mut()
+                .ok_or("Configuration is not a json object")?;
+            object.insert(
+                "signature".to_string(),
+                serde_json::json!({"key": signature.key, "signature": signature.signature}),
+            );
+            serde_json::to_string_pretty(&value)
+                .map(|text| text + "\n")
+                .map_err(|e| format!("Failed to write json; err = {}", e))
+        }
+    }
+}
+
+/// Commands that only work on local files, they do not talk to the runner.
+fn run_local(args: &[String]) -> Option<Result<(), String>> {
+    let command = args.first().map(|command| command.as_str());
+
+    let result = match command {
+        Some("keygen") => {
+            let path = match &args[1..] {
+                [path] => path,
+                _ => usage(),
+            };
+
+            // the secret is only readable by its owner and never overwritten
+            elafry::signature::generate_key().and_then(|key| {
+                use std::os::unix::fs::OpenOptionsExt;
+
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(path)
+                    .and_then(|mut file| {
+                        writeln!(file, "{}", elafry::signature::to_hex(key.as_bytes()))
+                    })
+                    .map_err(|e| format!("Failed to write {}; err = {}", path, e))?;
+                println!(
+                    "{}",
+                    elafry::signature::to_hex(key.verifying_key().as_bytes())
+                );
+                Ok(())
+            })
+        }
+        Some("sign") => {
+            let (key, path) = match &args[1..] {
+                [flag, key, path] if flag == "--key" => (key, path),
+                _ => usage(),
+            };
+
+            (|| {
+                let key = std::fs::read_to_string(key)
+                    .map_err(|e| format!("Failed to read {}; err = {}", key, e))
+                    .and_then(|text| elafry::signature::signing_key(&text))?;
+                let signed = elafry::loader::signed_from_file(Path::new(path))?;
+                if signed.signature.is_some() {
+                    return Err(format!("{} is already signed", path));
+                }
+
+                let signature = elafry::signature::sign(&signed.document, &key);
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read {}; err = {}", path, e))?;
+                let text = append_signature(&text, Format::of(Path::new(path), &text), &signature)?;
+                std::fs::write(path, text)
+                    .map_err(|e| format!("Failed to write {}; err = {}", path, e))?;
+                println!("signed with {}", signature.key);
+                Ok(())
+            })()
+        }
+        Some("hash") => {
+            let path = match &args[1..] {
+                [path] => path,
+                _ => usage(),
+            };
+
+            std::fs::read(path)
+                .map(|data| println!("{}  {}", elafry::signature::sha256(&data), path))
+                .map_err(|e| format!("Failed to read {}; err = {}", path, e))
+        }
+        _ => return None,
+    };
+
+    Some(result)
+}
+
+fn run<S: Read + Write>(client: &mut Client<S>, args: &[String]) -> io::Result<bool> {
+    let command = args.first().map(|command| command.as_str());
+
+    let reply = match command {
+        Some("load") => {
+            let mut wait = true;
+            let mut inline = false;
+            let mut upload = false;
+            let mut name = None;
+
+            for arg in args[1..].iter() {
+                match arg.as_str() {
+                    "--no-wait" => wait = false,
+                    "--inline" if !upload => inline = true,
+                    "--upload" if !inline => upload = true,
+                    _ if name.is_none() => name = Some(arg.clone()),
+                    _ => usage(),
+                }
+            }
+
+            let name = name.unwrap_or_else(|| usage());
+            let command = if inline {
+                match load_inline(&name)? {
+                    Document::Tasks(configuration) => Command::LoadInline(configuration),
+                    Document::Desired(desired) => Command::ApplyInline(desired),
+                }
+            } else if upload {
+                match client.upload(&std::fs::read(&name)?)? {
+                    Ok(upload) => Command::LoadUpload(upload),
+                    Err(reply) => return Ok(print_reply(&reply)),
+                }
+            } else {
+                Command::Load(name)
+            };
+
+            client.load(command, wait)?
+        }
+        Some("validate") => {
+            let (flag, name) = match &args[1..] {
+                [name] => (None, name),
+                [flag, name] if flag == "--inline" || flag == "--upload" => {
+                    (Some(flag.as_str()), name)
+                }
+                _ => usage(),
+            };
+
+            let command = if flag.is_none() {
+                Command::Validate(name.clone())
+            } else if flag == Some("--upload") {
+                match client.upload(&std::fs::read(name)?)? {
+                    Ok(upload) => Command::ValidateUpload(upload),
+                    Err(reply) => return Ok(print_reply(&reply)),
+                }
+            } else {
+                match load_inline(name)? {
+                    Document::Tasks(configuration) => Command::ValidateInline(configuration),
+                    // the runner plans a desired state before checking the plan
+                    Document::Desired(desired) => {
+                        match client.load(Command::PlanInline(desired), true)? {
+                            Reply::Plan(configuration) if configuration.tasks.is_empty() => {
+                                return Ok(print_reply(&Reply::Report(vec![])))
+                            }
+                            Reply::Plan(configuration) => Command::ValidateInline(configuration),
+                            reply => return Ok(print_reply(&reply)),
+                        }
+                    }
+                }
+            };
+
+            // the runner checks the configuration in the background, the report follows the acceptance
+            client.load(command, true)?
+        }
+        Some("plan") => {
+            let path = match &args[1..] {
+                [path] => path,
+                _ => usage(),
+            };
+
+            match load_inline(path)? {
+                Document::Desired(desired) => client.load(Command::PlanInline(desired), true)?,
+                Document::Tasks(_) => {
+                    eprintln!("error: {} is a list of tasks, not a desired state", path);
+                    return Ok(false);
+                }
+            }
+        }
+        Some("status") => client.request(Command::Status)?,
+        Some("components") => client.request(Command::ListComponents)?,
+        Some("routes") => client.request(Command::ListRoutes)?,
+        Some("schedule") => client.request(Command::Schedule)?,
+        Some("watch") => return watch(client),
+        Some("abort") => client.request(Command::Abort)?,
+        Some("stop") => client.request(Command::Shutdown)?,
+        Some("install") => {
+            let (component, version, path) = match &args[1..] {
+                [component, version, path] => (component, version, path),
+                _ => usage(),
+            };
+
+            // the runner copies the file, so the path must make sense on its side
+            let path = std::fs::canonicalize(path)?;
+            let command = Command::Install(InstallData {
+                component: component.clone(),
+                version: version.clone(),
+                path: path.to_string_lossy().to_string(),
+            });
+            client.load(command, true)?
+        }
+        Some("uninstall") => match &args[1..] {
+            [component, version] => {
+                let command = Command::Uninstall(ComponentVersion {
+                    component: component.clone(),
+                    version: version.clone(),
+                });
+                client.load(command, true)?
+            }
+            _ => usage(),
+        },
+        Some("registry") => client.request(Command::ListRegistry)?,
+        _ => usage(),
+    };
+
+    Ok(print_reply(&reply))
+}
+
+fn main() {
+    env_logger::init();
+
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    let mut socket = std::env::var("ELAFRY_SOCKET").unwrap_or_else(|_| "elafry.sock".to_string());
+    if args.first().map(|arg| arg.as_str()) == Some("--socket") {
+        if args.len() < 2 {
+            usage();
+        }
+        socket = args.remove(1);
+        args.remove(0);
+    }
+
+    if let Some(result) = run_local(&args) {
+        if let Err(e) = result {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let stream = match UnixStream::connect(&socket) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("Failed to connect to {}; err = {}", socket, e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut client = Client::new(stream);
+
+    match run(&mut client, &args) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("Failed to talk to the runner; err = {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elafry::types::configuration::{MajorFrame, MinorFrame};
+    use elafry::types::management::CpuTime;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    /// Answer requests on the other end of a socket pair.
+    fn serve(mut stream: UnixStream, replies: Vec<Vec<(u64, Reply)>>) {
+        std::thread::spawn(move || {
+            for replies in replies {
+                read_frame(&mut stream).unwrap();
+                for (request_id, reply) in replies {
+                    write_frame(&mut stream, &Response::new(request_id, reply).encode()).unwrap();
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_client_load() {
+        setup();
+
+        let (stream, runner) = UnixStream::pair().unwrap();
+        serve(
+            runner,
+            vec![
+                // a stale reply to another request is skipped
+                vec![(9, Reply::Loaded), (1, Reply::Accepted), (1, Reply::Loaded)],
+                vec![(2, Reply::Error(Error::Busy))],
+            ],
+        );
+
+        let mut client = Client::new(stream);
+        assert_eq!(
+            client
+                .load(Command::Load("plant_1.yaml".to_string()), true)
+                .unwrap(),
+            Reply::Loaded
+        );
+        assert_eq!(
+            client
+                .load(Command::Load("plant_2.yaml".to_string()), true)
+                .unwrap(),
+            Reply::Error(Error::Busy)
+        );
+    }
+
+    #[test]
+    fn test_client_upload() {
+        setup();
+
+        let (stream, runner) = UnixStream::pair().unwrap();
+        serve(
+            runner,
+            vec![
+                vec![(1, Reply::Received(1))],
+                vec![(2, Reply::Received(2))],
+                vec![(3, Reply::Received(3))],
+                vec![(
+                    4,
+                    Reply::Error(Error::InvalidChunk("too large".to_string())),
+                )],
+            ],
+        );
+
+        // a document of three chunks, each acknowledged
+        let mut client = Client::new(stream);
+        let document = vec![b'#'; elafry::types::management::CHUNK_SIZE * 2 + 1];
+        assert!(client.upload(&document).unwrap().is_ok());
+        assert_eq!(
+            client.upload(&document).unwrap(),
+            Err(Reply::Error(Error::InvalidChunk("too large".to_string())))
+        );
+    }
+
+    #[test]
+    fn test_append_signature() {
+        setup();
+
+        let key = elafry::signature::generate_key().unwrap();
+        let files = [
+            ("tasks: []", Format::Yaml),
+            ("tasks = []\n", Format::Toml),
+            ("{\"tasks\": []}", Format::Json),
+        ];
+
+        // the signature reads back from every format and matches what was signed
+        for (text, format) in files {
+            let document = elafry::loader::from_text(text, format).unwrap();
+            let signature = elafry::signature::sign(&document, &key);
+            let text = append_signature(text, format, &signature).unwrap();
+
+            let signed = elafry::loader::signed_from_text(&text, format).unwrap();
+            assert_eq!(signed.document, document);
+            assert_eq!(signed.signature, Some(signature));
+        }
+
+        assert!(append_signature(
+            "[]",
+            Format::Json,
+            &elafry::signature::sign(&elafry::loader::from_yaml("tasks: []").unwrap(), &key)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_client_malformed() {
+        setup();
+
+        let (stream, runner) = UnixStream::pair().unwrap();
+        serve(runner, vec![vec![(0, Reply::Error(Error::Malformed))]]);
+
+        let mut client = Client::new(stream);
+        assert_eq!(
+            client.request(Command::Status).unwrap(),
+            Reply::Error(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        setup();
+
+        let id = uuid::Uuid::new_v4();
+
+        let status = Status {
+            state: ManagementState::Running,
+            task: Some(1),
+            tasks: 4,
+            components: 2,
+            routes: 3,
+            period: 1000,
+            best_effort: false,
+        };
+        assert_eq!(
+            format_status(&status),
+            "state: running (task 2/4)\ncomponents: 2\nroutes: 3\nperiod: 1000us\n"
+        );
+
+        // said only when timing guarantees are off
+        let status = Status {
+            best_effort: true,
+            ..status
+        };
+        assert!(format_status(&status)
+            .ends_with("period: 1000us\nscheduling: best-effort, no timing guarantees\n"));
+
+        let components = format_components(&[ComponentStatus {
+            component_id: id,
+            component: "target/release/plant".to_string(),
+            version: "1.2.0".to_string(),
+            core: 2,
+            running: true,
+            crashed: true,
+            restarts: 3,
+            faults: 5,
+            cpu_time: CpuTime {
+                count: 10,
+                min: 1000,
+                mean: 2000,
+                p99: 3000,
+                max: 4000,
+            },
+        }]);
+        let lines: Vec<&str> = components.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with(&format!("{}  2     crashed", id)));
+        assert!(lines[1].ends_with(
+            "3         5         1         2         3         4  1.2.0     target/release/plant"
+        ));
+
+        let registry = format_registry(&[RegistryEntry {
+            component: "fcs_a".to_string(),
+            version: "1.0.0".to_string(),
+            sha256: "ba7816bf".to_string(),
+        }]);
+        assert_eq!(
+            registry.lines().nth(1),
+            Some("fcs_a                     1.0.0         ba7816bf")
+        );
+
+        let routes = format_routes(&[RouteStatus {
+            source: RouteEndpoint {
+                endpoint: Endpoint::Component(id),
+                channel_id: 1,
+            },
+            target: RouteEndpoint {
+                endpoint: Endpoint::Address("127.0.0.1:5000".to_string()),
+                channel_id: 2,
+            },
+        }]);
+        assert_eq!(
+            routes,
+            format!("component:{}/1 -> address:127.0.0.1:5000/2\n", id)
+        );
+
+        let schedule = format_schedule(&SetScheduleData {
+            deadline: 1000,
+            major_frames: vec![MajorFrame {
+                minor_frames: vec![MinorFrame {
+                    component_id: id,
+                    deadline: 250,
+                }],
+            }],
+        });
+        assert_eq!(
+            schedule,
+            format!("period: 1000us\nmajor frame 0\n  {} 250us\n", id)
+        );
+
+        assert_eq!(format_report(&[]), "ok\n");
+        let report = format_report(&[
+            Issue {
+                severity: Severity::Error,
+                task: Some(1),
+                action: Some(id),
+                message: "Component not found".to_string(),
+            },
+            Issue {
+                severity: Severity::Warning,
+                task: None,
+                action: None,
+                message: "Component is scheduled but not running".to_string(),
+            },
+        ]);
+        assert_eq!(
+            report,
+            format!(
+                "error: task 1 action {}: Component not found\nwarning: Component is scheduled but not running\n",
+                id
+            )
+        );
+    }
+}
@@ -0,0 +1,322 @@
+//! End-to-end scenarios against a runner started in-process.
+//!
+//! A [`Scenario`] copies the configurations into a temporary directory, builds
+//! the component binaries and runs a simulating runner on them, so frames run
+//! back to back on virtual time and reconfigurations take effect in the same
+//! frame every run. Management requests are sent at given frames the way the
+//! agent sends them, and the runner's state is inspected between frames.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use elafry::types::management::{Command, Reply, Request, Response, CHANNEL};
+use runner::global_state::GlobalState;
+use runner::services::communication::{Endpoint, RouteEndpoint, RouteStats};
+use runner::settings::Settings;
+use runner::simulation::Simulation;
+use runner::Runner;
+
+/// Runner channel replies to the scenario's requests are routed to.
+const REPLIES: u32 = u32::MAX;
+
+/// Components the scenarios run, built once per test run.
+const COMPONENTS: [&str; 4] = ["agent", "plant", "fcs_a", "fcs_b"];
+
+/// Scenarios share the process working directory, the exit flag of component
+/// processes and the scheduling mode, so only one runs at a time.
+static RUNNING: Mutex<()> = Mutex::new(());
+
+pub struct Scenario {
+    runner: Option<Runner>,
+    settings: Settings,
+    directory: PathBuf,
+    /// Requests not sent yet, by the frame to send them in.
+    requests: Vec<(u64, Request)>,
+    /// Replies received and the frame they were received after.
+    responses: Vec<(u64, Response)>,
+    next_request_id: u64,
+    _running: MutexGuard<'static, ()>,
+}
+
+impl Scenario {
+    /// A scenario whose configuration directory holds a copy of the repository
+    /// configurations, the runner is not started yet.
+    pub fn new() -> Scenario {
+        let running = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+        build();
+
+        let directory =
+            std::env::temp_dir().join(format!("elafry-scenario-{}", uuid::Uuid::new_v4()));
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("../..");
+        copy(
+            &root.join("configuration"),
+            &directory.join("configuration"),
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+
+        // components write their outputs next to the configurations
+        std::env::set_current_dir(&directory).unwrap();
+
+        let settings = Settings {
+            port: 0,
+            configuration_directory: directory
+                .join("configuration")
+                .to_string_lossy()
+                .into_owned(),
+            telemetry: directory
+                .join("telemetry.bin")
+                .to_string_lossy()
+                .into_owned(),
+            metrics: String::new(),
+            control: String::new(),
+            scheduling: runner::realtime::Scheduling::BestEffort,
+            simulation: Some(Simulation { frames: None }),
+            ..Settings::default()
+        };
+
+        Scenario {
+            runner: None,
+            settings,
+            directory,
+            requests: Vec::new(),
+            responses: Vec::new(),
+            next_request_id: 1,
+            _running: running,
+        }
+    }
+
+    /// Write a configuration file, before starting or to load by name later.
+    pub fn write(&self, name: &str, text: &str) {
+        std::fs::write(self.directory.join("configuration").join(name), text).unwrap();
+    }
+
+    /// Start the runner on an initial configuration.
+    pub fn start(&mut self, configuration: &str) {
+        assert!(self.runner.is_none(), "Scenario already started");
+
+        let settings = Settings {
+            configuration: configuration.to_string(),
+            ..self.settings.clone()
+        };
+        self.runner = Some(Runner::new(settings).unwrap_or_else(|e| panic!("{}", e)));
+    }
+
+    /// Settings the runner is started with, to change before starting it.
+    pub fn settings(&mut self) -> &mut Settings {
+        &mut self.settings
+    }
+
+    /// Path of a file in the scenario directory.
+    pub fn path(&self, name: &str) -> String {
+        self.directory.join(name).to_string_lossy().into_owned()
+    }
+
+    /// Send a management request at the start of a frame, returning its request id.
+    pub fn at(&mut self, frame: u64, command: Command) -> u64 {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        self.requests
+            .push((frame, Request::new(request_id, command)));
+        request_id
+    }
+
+    /// Run one frame, returning false once the runner is done.
+    pub fn step(&mut self) -> bool {
+        let runner = self.runner.as_mut().expect("Scenario not started");
+
+        // requests arrive on the management channel like routed ones
+        let frame = runner.frame();
+        let state = runner.state();
+        for (_, request) in self.requests.iter().filter(|(at, _)| *at == frame) {
+            let message = elafry::types::communication::Message {
+                channel_id: CHANNEL,
+                count: 0,
+                data: request.encode(),
+            };
+            state.messages.entry(CHANNEL).or_default().push((
+                RouteEndpoint {
+                    endpoint: Endpoint::Runner,
+                    channel_id: REPLIES,
+                },
+                message,
+            ));
+        }
+        self.requests.retain(|(at, _)| *at != frame);
+
+        let running = runner.step();
+
+        while let Some((_, message)) = runner.state().get_message(REPLIES) {
+            match Response::decode(&message.data) {
+                Some(response) => self.responses.push((frame, response)),
+                None => panic!("Failed to decode runner reply in frame {}", frame),
+            }
+        }
+
+        running
+    }
+
+    /// Run frames until the given frame is next, panicking if the runner stops first.
+    pub fn run_to(&mut self, frame: u64) {
+        while self.frame() < frame {
+            assert!(self.step(), "Runner stopped in frame {}", self.frame());
+        }
+    }
+
+    /// Run frames until a condition on the state holds, at most `frames` of them,
+    /// returning the frame it held after.
+    pub fn run_until(
+        &mut self,
+        frames: u64,
+        mut condition: impl FnMut(&mut Scenario) -> bool,
+    ) -> u64 {
+        for _ in 0..frames {
+            let frame = self.frame();
+            assert!(self.step(), "Runner stopped in frame {}", frame);
+            if condition(self) {
+                return frame;
+            }
+        }
+        panic!("Condition not met within {} frames", frames);
+    }
+
+    /// Run frames until a request has its outcome, returning it and the frame it
+    /// was received after. Accepted replies are skipped.
+    pub fn outcome(&mut self, request_id: u64, frames: u64) -> (u64, Reply) {
+        self.run_until(frames, |scenario| scenario.reply(request_id).is_some());
+        self.reply(request_id).unwrap()
+    }
+
+    /// The last reply to a request other than accepted, and the frame it was received after.
+    pub fn reply(&self, request_id: u64) -> Option<(u64, Reply)> {
+        self.responses
+            .iter()
+            .rev()
+            .find(|(_, response)| {
+                response.request_id == request_id && response.reply != Reply::Accepted
+            })
+            .map(|(frame, response)| (*frame, response.reply.clone()))
+    }
+
+    /// Every reply to a request in the order received.
+    pub fn replies(&self, request_id: u64) -> Vec<Reply> {
+        self.responses
+            .iter()
+            .filter(|(_, response)| response.request_id == request_id)
+            .map(|(_, response)| response.reply.clone())
+            .collect()
+    }
+
+    /// Index of the frame run next.
+    pub fn frame(&self) -> u64 {
+        self.runner.as_ref().expect("Scenario not started").frame()
+    }
+
+    pub fn state(&mut self) -> &mut GlobalState {
+        self.runner.as_mut().expect("Scenario not started").state()
+    }
+
+    /// Messages counted between two endpoints, zero if none were.
+    pub fn route_stats(&self, source: RouteEndpoint, target: RouteEndpoint) -> RouteStats {
+        self.runner
+            .as_ref()
+            .expect("Scenario not started")
+            .communication()
+            .route_stats()
+            .get(&(source, target))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Shut the runner down, returning its exit status.
+    pub fn finish(mut self) -> i32 {
+        let mut runner = self.runner.take().expect("Scenario not started");
+        runner.state().set_done(true);
+        runner.shutdown()
+    }
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario::new()
+    }
+}
+
+impl Drop for Scenario {
+    fn drop(&mut self) {
+        // a failed scenario still stops its component processes
+        if let Some(mut runner) = self.runner.take() {
+            runner.state().set_done(true);
+            runner.shutdown();
+        }
+        let _ = std::fs::remove_dir_all(&self.directory);
+    }
+}
+
+/// Endpoint of a component channel.
+pub fn component(id: &str, channel_id: u32) -> RouteEndpoint {
+    RouteEndpoint {
+        endpoint: Endpoint::Component(uuid::Uuid::parse_str(id).unwrap()),
+        channel_id,
+    }
+}
+
+/// Build the component binaries with the profile of the tests and put them on the PATH.
+fn build() {
+    static BUILT: OnceLock<()> = OnceLock::new();
+
+    BUILT.get_or_init(|| {
+        // the test binary is in deps of the profile directory
+        let exe = std::env::current_exe().unwrap();
+        let profile = exe.parent().unwrap().parent().unwrap();
+
+        let mut command = std::process::Command::new(
+            std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()),
+        );
+        command
+            .arg("build")
+            .arg("--target-dir")
+            .arg(profile.parent().unwrap())
+            .current_dir(env!("CARGO_MANIFEST_DIR"));
+        if profile.ends_with("release") {
+            command.arg("--release");
+        }
+        for component in COMPONENTS {
+            command.args(["-p", component]);
+        }
+        let status = command.status().unwrap();
+        assert!(
+            status.success(),
+            "Failed to build components; status = {}",
+            status
+        );
+
+        // component names resolve to binaries on the PATH without a registry
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let mut paths = vec![profile.to_path_buf()];
+        paths.extend(std::env::split_paths(&path));
+        std::env::set_var("PATH", std::env::join_paths(paths).unwrap());
+    });
+}
+
+fn copy(source: &Path, target: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(target)
+        .map_err(|e| format!("Failed to create {}; err = {}", target.display(), e))?;
+    let entries = std::fs::read_dir(source)
+        .map_err(|e| format!("Failed to read {}; err = {}", source.display(), e))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("Failed to read {}; err = {}", source.display(), e))?
+            .path();
+        let target = target.join(path.file_name().unwrap());
+        if path.is_dir() {
+            copy(&path, &target)?;
+        } else {
+            std::fs::copy(&path, &target)
+                .map_err(|e| format!("Failed to copy {}; err = {}", path.display(), e))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod plant;
@@ -0,0 +1,434 @@
+//! The plant configurations, reconfigured the way the agent drives them.
+
+use elafry::types::management::{Command, Reply};
+use runner::global_state::StateSyncStatus;
+use runner::services::communication::{Endpoint, RouteEndpoint};
+
+use crate::{component, Scenario};
+
+use runner::recording::{Entry, Replay};
+
+// component ids and channels from include/plant.yaml
+const AGENT: &str = "a8b72b60-d420-4c9a-8211-3afb36a1af7a";
+const PLANT: &str = "9a069153-c335-47c5-a653-dec6c0e7c280";
+const FCS_A: &str = "4d2f439b-ae60-42b6-ae45-b4db5d020f21";
+const FCS_B: &str = "4310fde1-8c21-4054-a5d6-8b64c2c2afcd";
+const MANAGEMENT: u32 = 0;
+const SENSOR: u32 = 1;
+const CONTROL: u32 = 2;
+
+const STATE_SYNC: &str = "33eecdf3-3b52-4183-bd2d-fd10309bf92c";
+
+// setup logging
+fn setup() {
+    let _ = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
+        .is_test(true)
+        .try_init();
+}
+
+fn id(id: &str) -> uuid::Uuid {
+    uuid::Uuid::parse_str(id).unwrap()
+}
+
+/// Ids of the components with a running process.
+fn running(scenario: &mut Scenario) -> Vec<uuid::Uuid> {
+    let mut running: Vec<uuid::Uuid> = scenario
+        .state()
+        .components
+        .iter()
+        .filter(|(_, component)| component.run && component.implentation.is_some())
+        .map(|(id, _)| *id)
+        .collect();
+    running.sort();
+    running
+}
+
+fn sorted(mut ids: Vec<uuid::Uuid>) -> Vec<uuid::Uuid> {
+    ids.sort();
+    ids
+}
+
+#[test]
+fn test_plant_sequence() {
+    setup();
+
+    let mut scenario = Scenario::new();
+    scenario.start("default.yaml");
+
+    // the agent is started and can talk to the runner
+    scenario.run_until(1000, |scenario| running(scenario) == vec![id(AGENT)]);
+    assert_eq!(
+        scenario.state().routes.get(&component(AGENT, MANAGEMENT)),
+        Some(&vec![RouteEndpoint {
+            endpoint: Endpoint::Runner,
+            channel_id: MANAGEMENT,
+        }])
+    );
+
+    // plant_1 runs the plant with fcs_a
+    let load = scenario.at(100, Command::Load("plant_1.yaml".to_string()));
+    let (loaded, reply) = scenario.outcome(load, 1000);
+    assert_eq!(reply, Reply::Loaded);
+    assert_eq!(scenario.replies(load), vec![Reply::Accepted, Reply::Loaded]);
+    assert_eq!(
+        running(&mut scenario),
+        sorted(vec![id(AGENT), id(PLANT), id(FCS_A)])
+    );
+    assert_eq!(
+        scenario.state().routes.get(&component(PLANT, SENSOR)),
+        Some(&vec![component(FCS_A, SENSOR)])
+    );
+    assert_eq!(
+        scenario.state().routes.get(&component(FCS_A, CONTROL)),
+        Some(&vec![component(PLANT, CONTROL)])
+    );
+    assert_eq!(
+        scenario.state().schedule.major_frames[0].minor_frames.len(),
+        3
+    );
+
+    // sensor readings and control flow every frame the plant and fcs_a run
+    scenario.run_to(loaded + 500);
+    let sensor = scenario.route_stats(component(PLANT, SENSOR), component(FCS_A, SENSOR));
+    let control = scenario.route_stats(component(FCS_A, CONTROL), component(PLANT, CONTROL));
+    assert!(sensor.routed >= 490, "{:?}", sensor);
+    assert!(control.routed >= 490, "{:?}", control);
+    assert_eq!(sensor.dropped, 0);
+    assert_eq!(control.dropped, 0);
+
+    // plant_2 hands the state of fcs_a to fcs_b before switching over
+    let load = scenario.at(scenario.frame(), Command::Load("plant_2.yaml".to_string()));
+    let mut statuses = Vec::new();
+    scenario.run_until(1000, |scenario| {
+        if let Ok(status) = scenario.state().get_state_sync_status(id(STATE_SYNC)) {
+            if statuses.last() != Some(&status) {
+                statuses.push(status);
+            }
+        }
+        scenario.reply(load).is_some()
+    });
+    assert_eq!(scenario.reply(load).unwrap().1, Reply::Loaded);
+    assert_eq!(statuses.last(), Some(&StateSyncStatus::Synced));
+    assert!(scenario.state().state_sync.is_empty());
+    assert_eq!(
+        running(&mut scenario),
+        sorted(vec![id(AGENT), id(PLANT), id(FCS_B)])
+    );
+    assert!(!scenario.state().components.contains_key(&id(FCS_A)));
+    assert_eq!(
+        scenario.state().routes.get(&component(PLANT, SENSOR)),
+        Some(&vec![component(FCS_B, SENSOR)])
+    );
+    assert_eq!(
+        scenario.state().routes.get(&component(FCS_A, CONTROL)),
+        None
+    );
+
+    // fcs_b takes over the control loop
+    let before = scenario.route_stats(component(FCS_B, CONTROL), component(PLANT, CONTROL));
+    let frame = scenario.frame();
+    scenario.run_to(frame + 100);
+    let after = scenario.route_stats(component(FCS_B, CONTROL), component(PLANT, CONTROL));
+    assert!(after.routed >= before.routed + 90, "{:?}", after);
+
+    // plant_3 leaves only the agent
+    let load = scenario.at(scenario.frame(), Command::Load("plant_3.yaml".to_string()));
+    assert_eq!(scenario.outcome(load, 1000).1, Reply::Loaded);
+    scenario.run_until(1000, |scenario| scenario.state().components.len() == 1);
+    assert_eq!(running(&mut scenario), vec![id(AGENT)]);
+    assert_eq!(scenario.state().routes.get(&component(PLANT, SENSOR)), None);
+
+    assert_eq!(scenario.finish(), runner::shutdown::CLEAN);
+}
+
+#[test]
+fn test_invalid_configuration() {
+    setup();
+
+    let mut scenario = Scenario::new();
+    scenario.write(
+        "broken.yaml",
+        "tasks:\n  - id: 8327c2a1-0dbb-425e-aece-897c28c3f9b2\n    actions: !blocking\n      - id: f8b89ead-0d83-4165-9c2b-e80013377580\n        data: !start-component\n          component-id: 9a069153-c335-47c5-a653-dec6c0e7c280\n",
+    );
+    scenario.start("default.yaml");
+    scenario.run_until(1000, |scenario| running(scenario) == vec![id(AGENT)]);
+
+    // starting a component that was never added fails and is rolled back
+    let load = scenario.at(
+        scenario.frame() + 1,
+        Command::Load("broken.yaml".to_string()),
+    );
+    let (_, reply) = scenario.outcome(load, 1000);
+    assert!(matches!(reply, Reply::Error(_)), "{:?}", reply);
+    assert_eq!(running(&mut scenario), vec![id(AGENT)]);
+    assert_eq!(scenario.state().routes.len(), 1);
+
+    assert_eq!(scenario.finish(), runner::shutdown::CLEAN);
+}
+
+// fcs_b shadows fcs_a on the same sensor readings while the runner logs them
+const FAN_OUT: &str = "include:
+  - include/plant.yaml
+
+tasks:
+  - id: 2c4f6a8b-0d1e-4f3a-8b5c-7d9e1f2a3b4c
+    actions: !non-blocking
+      - use: add-controller
+        with:
+          controller: fcs_b
+          core: ${controller-core}
+
+  - id: 8e0a2c4d-6f7b-4d9e-a1b3-c5d7e9f1a3b5
+    actions: !blocking
+      - id: 3d5f7b9c-1e2a-4c4d-8e6f-a0b2c4d6e8f0
+        data: !start-component
+          component-id: fcs_b
+
+      - id: 4e6a8c0d-2f3b-4d5e-9f7a-b1c3d5e7f9a1
+        data: !add-route
+          source:
+            endpoint: !component-id plant
+            channel-id: sensor
+          target:
+            endpoint: !component-id fcs_b
+            channel-id: sensor
+
+      - id: 5f7b9d1e-3a4c-4e6f-a08b-c2d4e6f8a0b2
+        data: !add-route
+          source:
+            endpoint: !component-id plant
+            channel-id: sensor
+          target:
+            endpoint: !runner
+            channel-id: 8
+
+      - id: 6a8c0e2f-4b5d-4f7a-b19c-d3e5f7a9b1c3
+        data: !add-route
+          source:
+            endpoint: !component-id fcs_b
+            channel-id: control
+          target:
+            endpoint: !runner
+            channel-id: 9
+
+      - id: 7b9d1f3a-5c6e-4a8b-82ad-e4f6a8b0c2d4
+        data: !set-schedule
+          deadline: ${period}
+          major-frames:
+            - minor-frames:
+                - component-id: agent
+                  deadline: 50
+                - component-id: plant
+                  deadline: 150
+                - component-id: fcs_a
+                  deadline: 150
+                - component-id: fcs_b
+                  deadline: 150
+";
+
+// stops logging the sensor readings, fcs_a and fcs_b keep getting them
+const UNLOG: &str = "include:
+  - include/plant.yaml
+
+tasks:
+  - id: 9c1e3a5b-7d8f-4b0c-93be-f5a7b9c1d3e5
+    actions: !blocking
+      - id: 0d2f4b6c-8e9a-4c1d-a4cf-a6b8c0d2e4f6
+        data: !remove-route
+          source:
+            endpoint: !component-id plant
+            channel-id: sensor
+          target:
+            endpoint: !runner
+            channel-id: 8
+";
+
+#[test]
+fn test_fan_out() {
+    setup();
+
+    let mut scenario = Scenario::new();
+    scenario.write("fan_out.yaml", FAN_OUT);
+    scenario.write("unlog.yaml", UNLOG);
+    scenario.start("default.yaml");
+    let load = scenario.at(10, Command::Load("plant_1.yaml".to_string()));
+    assert_eq!(scenario.outcome(load, 1000).1, Reply::Loaded);
+
+    // the second and third target of the sensor readings leave the first alone
+    let load = scenario.at(scenario.frame(), Command::Load("fan_out.yaml".to_string()));
+    let (loaded, reply) = scenario.outcome(load, 1000);
+    assert_eq!(reply, Reply::Loaded);
+    let logger = RouteEndpoint {
+        endpoint: Endpoint::Runner,
+        channel_id: 8,
+    };
+    assert_eq!(
+        scenario.state().routes.get(&component(PLANT, SENSOR)),
+        Some(&vec![
+            component(FCS_A, SENSOR),
+            component(FCS_B, SENSOR),
+            logger
+        ])
+    );
+
+    // every target gets every reading
+    let before = [
+        scenario.route_stats(component(PLANT, SENSOR), component(FCS_A, SENSOR)),
+        scenario.route_stats(component(PLANT, SENSOR), component(FCS_B, SENSOR)),
+        scenario.route_stats(component(PLANT, SENSOR), logger),
+    ];
+    let mut logged = 0;
+    let mut shadowed = 0;
+    scenario.run_until(1000, |scenario| {
+        while scenario.state().get_message(8).is_some() {
+            logged += 1;
+        }
+        while scenario.state().get_message(9).is_some() {
+            shadowed += 1;
+        }
+        scenario.frame() >= loaded + 200
+    });
+    let after = [
+        scenario.route_stats(component(PLANT, SENSOR), component(FCS_A, SENSOR)),
+        scenario.route_stats(component(PLANT, SENSOR), component(FCS_B, SENSOR)),
+        scenario.route_stats(component(PLANT, SENSOR), logger),
+    ];
+    for (before, after) in before.iter().zip(after.iter()) {
+        assert!(after.routed >= before.routed + 190, "{:?}", after);
+        assert_eq!(after.dropped, 0);
+    }
+    assert_eq!(
+        after[1].routed - before[1].routed,
+        after[0].routed - before[0].routed
+    );
+    assert_eq!(
+        after[2].routed - before[2].routed,
+        after[0].routed - before[0].routed
+    );
+    assert!(logged >= 190, "{}", logged);
+    assert!(shadowed >= 190, "{}", shadowed);
+
+    // fcs_a still closes the loop
+    let control = scenario.route_stats(component(FCS_A, CONTROL), component(PLANT, CONTROL));
+    assert_eq!(control.dropped, 0);
+
+    // one target is removed on its own
+    let load = scenario.at(scenario.frame(), Command::Load("unlog.yaml".to_string()));
+    assert_eq!(scenario.outcome(load, 1000).1, Reply::Loaded);
+    assert_eq!(
+        scenario.state().routes.get(&component(PLANT, SENSOR)),
+        Some(&vec![component(FCS_A, SENSOR), component(FCS_B, SENSOR)])
+    );
+
+    assert_eq!(scenario.finish(), runner::shutdown::CLEAN);
+}
+
+// fcs_b alone, its control output routed to the runner instead of the plant
+const SHADOW: &str = "include:
+  - include/plant.yaml
+
+tasks:
+  - id: 0f7d3f0e-3b0a-4d44-9d0c-3c1f0f5b2a61
+    actions: !non-blocking
+      - use: add-controller
+        with:
+          controller: fcs_b
+          core: ${controller-core}
+
+  - id: 5a0b8f55-6f0e-4b8e-8a55-0b6c1d2e7f10
+    actions: !blocking
+      - id: 9d2c6f3e-1b7a-4c55-8e2d-3f4a5b6c7d80
+        data: !start-component
+          component-id: fcs_b
+
+      - id: 1e2f3a4b-5c6d-4e7f-8a9b-0c1d2e3f4a5b
+        data: !add-route
+          source:
+            endpoint: !component-id fcs_b
+            channel-id: control
+          target:
+            endpoint: !runner
+            channel-id: 9
+
+      - id: 6b7c8d9e-0f1a-4b2c-9d3e-4f5a6b7c8d9e
+        data: !set-schedule
+          deadline: ${period}
+          major-frames:
+            - minor-frames:
+                - component-id: agent
+                  deadline: 50
+                - component-id: fcs_b
+                  deadline: 150
+";
+
+/// Replay the sensor readings recorded to fcs_a into fcs_b, returning what fcs_b sent back.
+fn shadow(recording: &str) -> Vec<Vec<u8>> {
+    let mut scenario = Scenario::new();
+    scenario.write("shadow.yaml", SHADOW);
+    scenario.settings().replay = Some(Replay {
+        recording: recording.to_string(),
+        component_id: id(FCS_B),
+        recorded_component_id: Some(id(FCS_A)),
+    });
+    scenario.start("default.yaml");
+    scenario.run_until(1000, |scenario| running(scenario) == vec![id(AGENT)]);
+
+    let load = scenario.at(scenario.frame(), Command::Load("shadow.yaml".to_string()));
+    assert_eq!(scenario.outcome(load, 1000).1, Reply::Loaded);
+
+    let mut control = Vec::new();
+    let frame = scenario.frame();
+    scenario.run_until(1000, |scenario| {
+        while let Some((_, message)) = scenario.state().get_message(9) {
+            control.push(message.data);
+        }
+        scenario.frame() >= frame + 400
+    });
+
+    // the plant is not running, every reading fcs_b got was replayed
+    let replayed = scenario.route_stats(component(PLANT, SENSOR), component(FCS_B, SENSOR));
+    assert!(replayed.routed >= 300, "{:?}", replayed);
+
+    assert_eq!(scenario.finish(), runner::shutdown::CLEAN);
+    control
+}
+
+#[test]
+fn test_record_replay() {
+    setup();
+
+    // record plant_1 running for a while
+    let mut scenario = Scenario::new();
+    let path = std::env::temp_dir().join(format!("elafry-recording-{}.bin", uuid::Uuid::new_v4()));
+    let recording = path.to_str().unwrap();
+    scenario.settings().record = recording.to_string();
+    scenario.start("default.yaml");
+    let load = scenario.at(10, Command::Load("plant_1.yaml".to_string()));
+    let (loaded, _) = scenario.outcome(load, 1000);
+    scenario.run_to(loaded + 300);
+    let sensor = scenario.route_stats(component(PLANT, SENSOR), component(FCS_A, SENSOR));
+    assert_eq!(scenario.finish(), runner::shutdown::CLEAN);
+
+    // every routed message is in the recording, including management replies
+    let entries = runner::recording::read_file(recording).unwrap();
+    let to_fcs_a: Vec<&Entry> = entries
+        .iter()
+        .filter(|entry| entry.target == component(FCS_A, SENSOR))
+        .collect();
+    assert_eq!(to_fcs_a.len() as u64, sensor.routed);
+    assert!(to_fcs_a
+        .windows(2)
+        .all(|pair| pair[0].frame <= pair[1].frame && pair[0].time <= pair[1].time));
+    assert!(entries
+        .iter()
+        .any(|entry| entry.source.endpoint == Endpoint::Runner
+            && entry.source.channel_id == MANAGEMENT));
+
+    // fcs_b answers the recorded readings the same way every time
+    let first = shadow(recording);
+    let second = shadow(recording);
+    assert!(first.len() >= 300, "{}", first.len());
+    assert_eq!(first, second);
+
+    std::fs::remove_file(path).unwrap();
+}
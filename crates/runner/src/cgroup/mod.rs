@@ -0,0 +1,344 @@
+//! Cgroup v2 resource limits for component processes.
+//!
+//! The runner owns a subtree of the cgroup hierarchy and puts each component
+//! process in its own leaf, so its limits apply to it alone. The runner stays
+//! outside the subtree, a component hitting its limits is throttled or killed
+//! without taking memory, cpu or pids from the runner.
+
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use elafry::types::configuration::Limits;
+use uuid::Uuid;
+
+/// Controllers the limits need, enabled for the leaves when available.
+const CONTROLLERS: [&str; 4] = ["cpu", "memory", "pids", "io"];
+
+/// Cpu quota period in microseconds.
+const CPU_PERIOD: u64 = 100_000;
+
+/// The subtree component cgroups are created in.
+pub struct Cgroups {
+    root: PathBuf,
+}
+
+impl Cgroups {
+    /// Create the subtree if needed and enable the controllers for its leaves.
+    pub fn open(root: &Path) -> Result<Cgroups, String> {
+        std::fs::create_dir_all(root)
+            .map_err(|e| format!("Failed to create cgroup {}; err = {}", root.display(), e))?;
+
+        let available = std::fs::read_to_string(root.join("cgroup.controllers")).map_err(|e| {
+            format!(
+                "{} is not a cgroup v2 directory; err = {}",
+                root.display(),
+                e
+            )
+        })?;
+        let enable: Vec<String> = CONTROLLERS
+            .iter()
+            .filter(|controller| {
+                available
+                    .split_whitespace()
+                    .any(|name| name == **controller)
+            })
+            .map(|controller| format!("+{}", controller))
+            .collect();
+        if enable.len() < CONTROLLERS.len() {
+            log::warn!(
+                "Cgroup {} only has the controllers {}",
+                root.display(),
+                available.trim()
+            );
+        }
+        if !enable.is_empty() {
+            write(&root.join("cgroup.subtree_control"), &enable.join(" "))?;
+        }
+
+        Ok(Cgroups {
+            root: root.to_path_buf(),
+        })
+    }
+
+    /// Create the leaf for a component process with its limits set.
+    pub fn create(&self, id: Uuid, limits: &Limits) -> Result<Cgroup, String> {
+        limits.check()?;
+
+        // a leaf left by a process that crashed is reused
+        let path = self.root.join(id.to_string());
+        match std::fs::create_dir(&path) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => {
+                return Err(format!(
+                    "Failed to create cgroup {}; err = {}",
+                    path.display(),
+                    e
+                ))
+            }
+        }
+
+        if let Some(memory_max) = limits.memory_max {
+            write(&path.join("memory.max"), &memory_max.to_string())?;
+            // the whole component is killed rather than one of its threads
+            write(&path.join("memory.oom.group"), "1")?;
+        }
+        if let Some(cpu_quota) = limits.cpu_quota {
+            let quota = cpu_quota as u64 * CPU_PERIOD / 100;
+            write(&path.join("cpu.max"), &format!("{} {}", quota, CPU_PERIOD))?;
+        }
+        if let Some(pids_max) = limits.pids_max {
+            write(&path.join("pids.max"), &pids_max.to_string())?;
+        }
+        if let Some(io_weight) = limits.io_weight {
+            write(&path.join("io.weight"), &format!("default {}", io_weight))?;
+        }
+
+        let procs = File::options()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path.join("cgroup.procs"))
+            .map_err(|e| {
+                format!(
+                    "Failed to open {}/cgroup.procs; err = {}",
+                    path.display(),
+                    e
+                )
+            })?;
+
+        Ok(Cgroup {
+            // missing when the controller is not enabled, its limit cannot be hit either
+            memory_events: File::open(path.join("memory.events")).ok(),
+            pids_events: File::open(path.join("pids.events")).ok(),
+            seen: Hits::default(),
+            procs,
+            path,
+        })
+    }
+}
+
+/// The leaf of one component process, removed when dropped.
+pub struct Cgroup {
+    path: PathBuf,
+    /// Kept open so the child can join without touching the filesystem.
+    procs: File,
+    /// Kept open so counting limit hits does not allocate.
+    memory_events: Option<File>,
+    pids_events: Option<File>,
+    /// Hits already reported.
+    seen: Hits,
+}
+
+/// Times a component process hit its limits.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Hits {
+    /// Memory could not be kept below `memory-max`.
+    pub memory: u64,
+    /// The process was killed for running out of memory.
+    pub oom_kill: u64,
+    /// Creating a process or thread failed on `pids-max`.
+    pub pids: u64,
+}
+
+impl Hits {
+    pub fn total(&self) -> u64 {
+        self.memory + self.oom_kill + self.pids
+    }
+
+    fn since(&self, earlier: &Hits) -> Hits {
+        Hits {
+            memory: self.memory.saturating_sub(earlier.memory),
+            oom_kill: self.oom_kill.saturating_sub(earlier.oom_kill),
+            pids: self.pids.saturating_sub(earlier.pids),
+        }
+    }
+}
+
+impl Cgroup {
+    /// File the spawned child writes to, to move itself into the cgroup.
+    pub fn procs(&self) -> RawFd {
+        self.procs.as_raw_fd()
+    }
+
+    /// Limit hits counted by the kernel since the last call.
+    pub fn hits(&mut self) -> Hits {
+        let mut buf = [0; 256];
+        let mut hits = self.seen;
+
+        if let Some(length) = read(self.memory_events.as_ref(), &mut buf) {
+            let events = &buf[..length];
+            hits.memory = count(events, b"max");
+            hits.oom_kill = count(events, b"oom_kill");
+        }
+        if let Some(length) = read(self.pids_events.as_ref(), &mut buf) {
+            hits.pids = count(&buf[..length], b"max");
+        }
+
+        let new = hits.since(&self.seen);
+        self.seen = hits;
+        new
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // only succeeds once the process has exited
+        if let Err(e) = std::fs::remove_dir(&self.path) {
+            log::debug!(
+                "Failed to remove cgroup {}; err = {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Move the calling process into the cgroup, called in the child before exec.
+///
+/// Only makes a system call, so it is safe to call after fork.
+pub fn join(procs: RawFd) -> std::io::Result<()> {
+    // writing 0 moves the process doing the write
+    let ret = unsafe { libc::write(procs, b"0".as_ptr() as *const libc::c_void, 1) };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn write(path: &Path, value: &str) -> Result<(), String> {
+    File::options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(value.as_bytes()))
+        .map_err(|e| {
+            format!(
+                "Failed to write {} to {}; err = {}",
+                value,
+                path.display(),
+                e
+            )
+        })
+}
+
+fn read(file: Option<&File>, buf: &mut [u8]) -> Option<usize> {
+    file?.read_at(buf, 0).ok()
+}
+
+/// Value of a `<key> <count>` line in an events file.
+fn count(events: &[u8], key: &[u8]) -> u64 {
+    for line in events.split(|byte| *byte == b'\n') {
+        if let Some(value) = line
+            .strip_prefix(key)
+            .and_then(|rest| rest.strip_prefix(b" "))
+        {
+            return value
+                .iter()
+                .take_while(|byte| byte.is_ascii_digit())
+                .fold(0, |count, byte| count * 10 + (byte - b'0') as u64);
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_cgroups() {
+        setup();
+
+        // a plain directory stands in for cgroupfs, the files written are the same
+        let root = std::env::temp_dir().join(format!("elafry-cgroup-{}", Uuid::new_v4()));
+        assert!(Cgroups::open(&root).is_err());
+        std::fs::write(root.join("cgroup.controllers"), "cpuset cpu io memory\n").unwrap();
+        let cgroups = Cgroups::open(&root).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("cgroup.subtree_control")).unwrap(),
+            "+cpu +memory +io"
+        );
+
+        // a leaf left behind is reused, the kernel keeps counting in it
+        let id = Uuid::new_v4();
+        let path = root.join(id.to_string());
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(path.join("memory.events"), "max 2\noom 0\noom_kill 0\n").unwrap();
+
+        let limits = Limits {
+            memory_max: Some(1 << 20),
+            cpu_quota: Some(25),
+            pids_max: None,
+            io_weight: Some(200),
+        };
+        let mut cgroup = cgroups.create(id, &limits).unwrap();
+        let read = |name: &str| std::fs::read_to_string(path.join(name)).unwrap();
+        assert_eq!(read("memory.max"), "1048576");
+        assert_eq!(read("memory.oom.group"), "1");
+        assert_eq!(read("cpu.max"), "25000 100000");
+        assert_eq!(read("io.weight"), "default 200");
+        assert!(!path.join("pids.max").exists());
+
+        // only new hits are reported, a missing events file counts none
+        let hits = cgroup.hits();
+        assert_eq!(
+            hits,
+            Hits {
+                memory: 2,
+                oom_kill: 0,
+                pids: 0
+            }
+        );
+        assert_eq!(cgroup.hits(), Hits::default());
+        std::fs::write(path.join("memory.events"), "max 3\noom 1\noom_kill 1\n").unwrap();
+        assert_eq!(
+            cgroup.hits(),
+            Hits {
+                memory: 1,
+                oom_kill: 1,
+                pids: 0
+            }
+        );
+
+        // limits the kernel would refuse are not written
+        let invalid = Limits {
+            io_weight: Some(0),
+            ..Default::default()
+        };
+        assert!(cgroups.create(Uuid::new_v4(), &invalid).is_err());
+
+        drop(cgroup);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_count() {
+        setup();
+
+        let events = b"low 0\nhigh 4\nmax 12\noom 1\noom_kill 1\noom_group_kill 0\n";
+        assert_eq!(count(events, b"max"), 12);
+        assert_eq!(count(events, b"oom_kill"), 1);
+        assert_eq!(count(events, b"missing"), 0);
+
+        let hits = Hits {
+            memory: 12,
+            oom_kill: 1,
+            pids: 2,
+        };
+        assert_eq!(hits.total(), 15);
+    }
+}
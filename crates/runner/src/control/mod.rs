@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use elafry::types::management::{read_frame, write_frame};
+
+type Frames = Arc<Mutex<Vec<(u64, Vec<u8>)>>>;
+
+/// Requests a client may have waiting for the frame loop, a client that sends
+/// more is disconnected.
+const MAX_WAITING: usize = 16;
+
+/// Local control socket for tools such as elafryctl.
+///
+/// Each connection is a client, frames it sends are management requests and
+/// replies are written back to the same connection. All socket work happens on
+/// background threads, the frame loop only trades buffers with them.
+pub struct Control {
+    inbox: Frames,
+    outbox: Frames,
+    /// Clients that have gone, reported again for each reply they miss.
+    disconnected: Arc<Mutex<Vec<u64>>>,
+    clients: Arc<Mutex<HashMap<u64, UnixStream>>>,
+    /// Replies that could not be handed over yet because the lock was busy.
+    pending: Vec<(u64, Vec<u8>)>,
+}
+
+impl Control {
+    pub fn new(path: &str) -> io::Result<Control> {
+        // remove the socket left behind by a previous run
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+
+        let inbox: Frames = Arc::new(Mutex::new(Vec::new()));
+        let outbox: Frames = Arc::new(Mutex::new(Vec::new()));
+        let disconnected = Arc::new(Mutex::new(Vec::new()));
+        let clients: Arc<Mutex<HashMap<u64, UnixStream>>> = Arc::new(Mutex::new(HashMap::new()));
+
+        {
+            let inbox = inbox.clone();
+            let clients = clients.clone();
+            let disconnected = disconnected.clone();
+            std::thread::Builder::new()
+                .name("control".to_string())
+                .spawn(move || accept(listener, inbox, clients, disconnected))?;
+        }
+
+        {
+            let outbox = outbox.clone();
+            let clients = clients.clone();
+            let disconnected = disconnected.clone();
+            std::thread::Builder::new()
+                .name("control-writer".to_string())
+                .spawn(move || write(outbox, clients, disconnected))?;
+        }
+
+        log::info!("Listening for control connections on {}", path);
+
+        Ok(Control {
+            inbox,
+            outbox,
+            disconnected,
+            clients,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Take the requests received since the last call, as client and frame.
+    pub fn receive(&mut self) -> Vec<(u64, Vec<u8>)> {
+        match self.inbox.try_lock() {
+            Ok(mut inbox) => std::mem::take(&mut *inbox),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Take the clients that have disconnected since the last call.
+    pub fn disconnected(&mut self) -> Vec<u64> {
+        match self.disconnected.try_lock() {
+            Ok(mut disconnected) => std::mem::take(&mut *disconnected),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Queue a reply to a client, it is written out from the background.
+    pub fn send(&mut self, client: u64, data: Vec<u8>) {
+        self.pending.push((client, data));
+
+        if let Ok(mut outbox) = self.outbox.try_lock() {
+            outbox.append(&mut self.pending);
+        }
+    }
+
+    /// Wait for queued replies to be written, used once the frame loop has stopped.
+    pub fn close(&mut self) {
+        self.outbox.lock().unwrap().append(&mut self.pending);
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(1);
+        while !self.outbox.lock().unwrap().is_empty() && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // the writer holds the clients while it writes a batch
+        drop(self.clients.lock().unwrap());
+    }
+}
+
+fn accept(
+    listener: UnixListener,
+    inbox: Frames,
+    clients: Arc<Mutex<HashMap<u64, UnixStream>>>,
+    disconnected: Arc<Mutex<Vec<u64>>>,
+) {
+    crate::realtime::background("control");
+
+    let mut next_client = 1;
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Failed to accept control connection; err = {:?}", e);
+                continue;
+            }
+        };
+
+        let client = next_client;
+        next_client += 1;
+
+        let writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(e) => {
+                log::warn!("Failed to clone control connection; err = {:?}", e);
+                continue;
+            }
+        };
+        // a client that stops reading must not hold up the others
+        let _ = writer.set_write_timeout(Some(Duration::from_secs(1)));
+        clients.lock().unwrap().insert(client, writer);
+
+        log::debug!("Control client {} connected", client);
+
+        let inbox = inbox.clone();
+        let clients = clients.clone();
+        let disconnected = disconnected.clone();
+        let spawned = std::thread::Builder::new()
+            .name(format!("control-{}", client))
+            .spawn(move || {
+                crate::realtime::background("control");
+                read(client, stream, inbox);
+                clients.lock().unwrap().remove(&client);
+                disconnected.lock().unwrap().push(client);
+                log::debug!("Control client {} disconnected", client);
+            });
+        if let Err(e) = spawned {
+            log::warn!("Failed to start control client thread; err = {:?}", e);
+        }
+    }
+}
+
+fn read(client: u64, mut stream: UnixStream, inbox: Frames) {
+    loop {
+        match read_frame(&mut stream) {
+            Ok(data) => {
+                let mut inbox = inbox.lock().unwrap();
+                let waiting = inbox.iter().filter(|(id, _)| *id == client).count();
+                if waiting >= MAX_WAITING {
+                    log::warn!(
+                        "Control client {} has {} requests waiting, disconnecting",
+                        client,
+                        waiting
+                    );
+                    break;
+                }
+                inbox.push((client, data));
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => {
+                log::warn!(
+                    "Failed to read from control client {}; err = {:?}",
+                    client,
+                    e
+                );
+                break;
+            }
+        }
+    }
+}
+
+fn write(
+    outbox: Frames,
+    clients: Arc<Mutex<HashMap<u64, UnixStream>>>,
+    disconnected: Arc<Mutex<Vec<u64>>>,
+) {
+    crate::realtime::background("control");
+
+    loop {
+        {
+            let mut clients = clients.lock().unwrap();
+            let replies = std::mem::take(&mut *outbox.lock().unwrap());
+
+            for (client, data) in replies {
+                match clients.get_mut(&client) {
+                    Some(stream) => {
+                        if let Err(e) = write_frame(stream, &data) {
+                            log::warn!(
+                                "Failed to write to control client {}; err = {:?}",
+                                client,
+                                e
+                            );
+                            clients.remove(&client);
+                            disconnected.lock().unwrap().push(client);
+                        }
+                    }
+                    None => {
+                        // the reply was counted after the client went, report it again
+                        log::debug!("Control client {} gone, dropping reply", client);
+                        disconnected.lock().unwrap().push(client);
+                    }
+                }
+            }
+        }
+
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_control() {
+        setup();
+
+        let path = std::env::temp_dir().join(format!("control-{}.sock", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+
+        let mut control = Control::new(path).unwrap();
+
+        let mut stream_1 = UnixStream::connect(path).unwrap();
+        let mut stream_2 = UnixStream::connect(path).unwrap();
+        write_frame(&mut stream_1, &[1]).unwrap();
+        write_frame(&mut stream_2, &[2]).unwrap();
+
+        // wait for both requests to arrive
+        let mut received = vec![];
+        while received.len() < 2 {
+            received.append(&mut control.receive());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // replies go back to the client that sent the request
+        for (client, data) in received {
+            control.send(client, [data, vec![9]].concat());
+        }
+
+        assert_eq!(read_frame(&mut stream_1).unwrap(), vec![1, 9]);
+        assert_eq!(read_frame(&mut stream_2).unwrap(), vec![2, 9]);
+
+        // a client sending more than the frame loop takes is disconnected
+        for _ in 0..=MAX_WAITING {
+            write_frame(&mut stream_1, &[1]).unwrap();
+        }
+        assert_eq!(
+            read_frame(&mut stream_1).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert_eq!(control.receive().len(), MAX_WAITING);
+
+        // a frame over the limit ends the connection without being read
+        stream_2
+            .write_all(&((elafry::types::management::MAX_FRAME + 1) as u32).to_be_bytes())
+            .unwrap();
+        assert_eq!(
+            read_frame(&mut stream_2).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert!(control.receive().is_empty());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
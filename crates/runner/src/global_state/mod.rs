@@ -1,15 +1,33 @@
 use std::{collections::HashMap, os::unix::net::UnixStream};
 
 use crate::services::{communication::RouteEndpoint, scheduler::Schedule};
+use crate::timing::CpuTimes;
 use elafry::types::communication::Message;
+use elafry::types::configuration::{Limits, RestartPolicy};
 
+#[allow(dead_code)]
 pub struct Component {
     pub run: bool,
     pub remove: bool,
     pub path: String,
     pub core: usize,
+    pub version: String,
     pub implentation: Option<Implementation>,
-    pub times: Vec<u64>,
+    pub times: CpuTimes,
+    /// Hex encoded SHA-256 the binary must have, checked again on restarts.
+    pub sha256: Option<String>,
+    /// What to do when the process exits on its own.
+    pub restart: RestartPolicy,
+    /// Times the process has been restarted.
+    pub restarts: u32,
+    /// The process exited without being stopped, it has no implementation until restarted.
+    pub crashed: bool,
+    /// The state the component last reported, restored when it is restarted.
+    pub last_state: Vec<u8>,
+    /// Resources the process may use, applied again on restarts.
+    pub limits: Limits,
+    /// Times the process hit its limits or sent a message over the limit.
+    pub faults: u64,
 }
 
 pub struct Implementation {
@@ -17,8 +35,34 @@ pub struct Implementation {
     pub state_socket: Socket,
     pub child: std::process::Child,
     pub child_pid: libc::pid_t,
+    /// `/proc/<pid>/schedstat` kept open so reading it does not allocate.
+    pub schedstat: Option<std::fs::File>,
+    /// Cgroup holding the process, when the runner has a cgroup subtree.
+    pub cgroup: Option<crate::cgroup::Cgroup>,
 }
 
+impl Implementation {
+    /// Total cpu time used by the child so far.
+    pub fn cpu_time(&self) -> Option<std::time::Duration> {
+        use std::os::unix::fs::FileExt;
+
+        let mut buf = [0; 64];
+        let length = self.schedstat.as_ref()?.read_at(&mut buf, 0).ok()?;
+
+        // the first field is the time spent on the cpu in nanoseconds
+        let mut nanos: u64 = 0;
+        for byte in buf[..length]
+            .iter()
+            .take_while(|byte| byte.is_ascii_digit())
+        {
+            nanos = nanos * 10 + (byte - b'0') as u64;
+        }
+
+        Some(std::time::Duration::from_nanos(nanos))
+    }
+}
+
+#[allow(dead_code)]
 pub struct Socket {
     pub socket: UnixStream,
     pub count: u8,
@@ -43,13 +87,25 @@ pub enum StateSyncStatus {
 
 pub struct GlobalState {
     pub components: HashMap<uuid::Uuid, Component>,
-    pub routes: HashMap<RouteEndpoint, RouteEndpoint>,
+    /// Targets messages from each source are copied to, in the order they were added.
+    pub routes: HashMap<RouteEndpoint, Vec<RouteEndpoint>>,
     pub schedule: Schedule,
-    pub messages: HashMap<u32, Vec<Message>>,
+    /// Messages routed to the runner by channel, with the endpoint they came from.
+    pub messages: HashMap<u32, Vec<(RouteEndpoint, Message)>>,
+    /// Messages sent by the runner, as source, target and message.
+    pub outbox: Vec<(RouteEndpoint, RouteEndpoint, Message)>,
+    /// Routes removed since the communication service last forgot their counts.
+    pub removed_routes: Vec<(RouteEndpoint, RouteEndpoint)>,
     pub state_sync: HashMap<uuid::Uuid, StateSync>,
     done: bool,
 }
 
+impl Default for GlobalState {
+    fn default() -> Self {
+        GlobalState::new()
+    }
+}
+
 impl GlobalState {
     pub fn new() -> Self {
         GlobalState {
@@ -60,6 +116,8 @@ impl GlobalState {
                 major_frames: vec![],
             },
             messages: HashMap::new(),
+            outbox: Vec::new(),
+            removed_routes: Vec::new(),
             state_sync: HashMap::new(),
             done: false,
         }
@@ -73,7 +131,7 @@ impl GlobalState {
         self.done = done;
     }
 
-    pub fn start_component(&mut self, id: uuid::Uuid) {
+    pub fn start_component(&mut self, id: uuid::Uuid) -> Result<(), String> {
         log::debug!("Starting component {}", id);
 
         // get the component
@@ -81,19 +139,18 @@ impl GlobalState {
             Some(component) => {
                 // don't start if not finish initializing
                 if component.implentation.is_none() {
-                    panic!("Component {} not initialized", id);
+                    return Err(format!("Component {} not initialized", id));
                 }
 
                 // start the component
                 component.run = true;
+                Ok(())
             }
-            None => {
-                panic!("Component {} not found", id)
-            }
+            None => Err(format!("Component {} not found", id)),
         }
     }
 
-    pub fn stop_component(&mut self, id: uuid::Uuid) {
+    pub fn stop_component(&mut self, id: uuid::Uuid) -> Result<(), String> {
         log::debug!("Stopping component {}", id);
 
         // get the component
@@ -101,28 +158,63 @@ impl GlobalState {
             Some(component) => {
                 // stop the component
                 component.run = false;
+                Ok(())
             }
-            None => {
-                panic!("Component {} not found", id)
-            }
+            None => Err(format!("Component {} not found", id)),
         }
     }
 
-    pub fn add_route(&mut self, source: RouteEndpoint, target: RouteEndpoint) {
+    /// Add a route, returning false if it already existed.
+    pub fn add_route(&mut self, source: RouteEndpoint, target: RouteEndpoint) -> bool {
         log::debug!("Adding route from {:?} to {:?}", source, target);
 
-        // add the route to the state
-        self.routes.insert(source, target);
+        // add the target to the targets of the source
+        let targets = self.routes.entry(source).or_default();
+        if targets.contains(&target) {
+            return false;
+        }
+        targets.push(target);
+        true
+    }
+
+    /// Remove a route, returning false if it did not exist.
+    pub fn remove_route(&mut self, source: RouteEndpoint, target: RouteEndpoint) -> bool {
+        log::debug!("Removing route from {:?} to {:?}", source, target);
+
+        let targets = match self.routes.get_mut(&source) {
+            Some(targets) => targets,
+            None => return false,
+        };
+        let len = targets.len();
+        targets.retain(|t| *t != target);
+        let removed = targets.len() != len;
+
+        // a source without targets is not routed
+        if targets.is_empty() {
+            self.routes.remove(&source);
+        }
+        if removed {
+            self.removed_routes.push((source, target));
+        }
+        removed
     }
 
-    pub fn remove_route(&mut self, from: RouteEndpoint) {
-        log::debug!("Removing route from {:?}", from);
+    /// Remove every route from a source, returning their targets.
+    pub fn remove_routes(&mut self, source: RouteEndpoint) -> Vec<RouteEndpoint> {
+        log::debug!("Removing routes from {:?}", source);
+
+        let targets = self.routes.remove(&source).unwrap_or_default();
+        self.removed_routes
+            .extend(targets.iter().map(|target| (source, *target)));
+        targets
+    }
 
-        // remove the route from the state
-        self.routes.remove(&from);
+    /// Number of routes, counting each target of a source.
+    pub fn route_count(&self) -> usize {
+        self.routes.values().map(|targets| targets.len()).sum()
     }
 
-    pub fn add_component(&mut self, id: uuid::Uuid, path: String, core: usize) {
+    pub fn add_component(&mut self, id: uuid::Uuid, path: String, core: usize, version: String) {
         log::info!("Adding component {}", id);
 
         // add the component to the state
@@ -133,13 +225,25 @@ impl GlobalState {
                 remove: false,
                 path,
                 core,
+                version,
                 implentation: None,
-                times: vec![],
+                times: CpuTimes::new(),
+                sha256: None,
+                restart: RestartPolicy::default(),
+                restarts: 0,
+                crashed: false,
+                last_state: Vec::new(),
+                limits: Limits::default(),
+                faults: 0,
             },
         );
     }
 
-    pub fn add_component_implementation(&mut self, id: uuid::Uuid, implementation: Implementation) {
+    pub fn add_component_implementation(
+        &mut self,
+        id: uuid::Uuid,
+        mut implementation: Implementation,
+    ) -> Result<(), String> {
         log::debug!("Adding implementation to component {}", id);
 
         // get the component
@@ -147,14 +251,18 @@ impl GlobalState {
             Some(component) => {
                 // add the implementation
                 component.implentation = Some(implementation);
+                Ok(())
             }
             None => {
-                panic!("Component {} not found", id)
+                // nothing would ever stop the process otherwise
+                let _ = implementation.child.kill();
+                let _ = implementation.child.wait();
+                Err(format!("Component {} not found", id))
             }
         }
     }
 
-    pub fn remove_component(&mut self, id: uuid::Uuid) {
+    pub fn remove_component(&mut self, id: uuid::Uuid) -> Result<(), String> {
         log::debug!("Removing component {}", id);
 
         // get the component
@@ -163,25 +271,23 @@ impl GlobalState {
                 // remove the component
                 component.remove = true;
                 component.run = false;
+                Ok(())
             }
-            None => {
-                panic!("Component {} not found", id)
-            }
+            None => Err(format!("Component {} not found", id)),
         }
     }
 
-    pub fn remove_component_implementation(&mut self, id: uuid::Uuid) {
+    pub fn remove_component_implementation(
+        &mut self,
+        id: uuid::Uuid,
+    ) -> Result<Option<Implementation>, String> {
         log::debug!("Removing implementation from component {}", id);
 
         // get the component
         match self.components.get_mut(&id) {
-            Some(component) => {
-                // remove the implementation
-                component.implentation = None;
-            }
-            None => {
-                panic!("Component {} not found", id)
-            }
+            // remove the implementation
+            Some(component) => Ok(component.implentation.take()),
+            None => Err(format!("Component {} not found", id)),
         }
     }
 
@@ -197,7 +303,8 @@ impl GlobalState {
         self.components.len()
     }
 
-    pub fn set_schedule(&mut self, schedule: Schedule) {
+    /// Replace the schedule, returning the previous one.
+    pub fn set_schedule(&mut self, schedule: Schedule) -> Result<Schedule, String> {
         log::info!("Setting schedule");
 
         // check if the schedule is valid by checking if all components in the schedule are in the state and initialized
@@ -206,41 +313,58 @@ impl GlobalState {
                 match self.get_component(minor_frame.component_id) {
                     Some(component) => {
                         if component.implentation.is_none() {
-                            panic!("Component {} not initialized", minor_frame.component_id);
+                            return Err(format!(
+                                "Component {} not initialized",
+                                minor_frame.component_id
+                            ));
                         }
                     }
                     None => {
-                        panic!("Component {} not found", minor_frame.component_id);
+                        return Err(format!("Component {} not found", minor_frame.component_id));
                     }
                 }
             }
         }
 
         // set the schedule in the state
-        self.schedule = schedule;
+        let previous = std::mem::replace(&mut self.schedule, schedule);
 
         // print the schedule duration
         log::debug!("Schedule duration: {:?}", self.schedule.period);
+
+        Ok(previous)
     }
 
-    pub fn get_message(&mut self, channel_id: u32) -> Option<Message> {
-        // check if channel_id exists in hashmap
-        if self.messages.contains_key(&channel_id) {
-            // get message from vector
-            let messages = self.messages.get_mut(&channel_id).unwrap();
-            let message = messages.pop();
-            return message;
-        } else {
-            return None;
+    /// Take the oldest message on a channel along with the endpoint it came from.
+    pub fn get_message(&mut self, channel_id: u32) -> Option<(RouteEndpoint, Message)> {
+        match self.messages.get_mut(&channel_id) {
+            Some(messages) if !messages.is_empty() => Some(messages.remove(0)),
+            _ => None,
         }
     }
 
+    /// Queue a message from a runner channel directly to an endpoint, bypassing the routes.
+    pub fn send_message(&mut self, channel_id: u32, target: RouteEndpoint, data: Vec<u8>) {
+        let source = RouteEndpoint {
+            endpoint: crate::services::communication::Endpoint::Runner,
+            channel_id,
+        };
+        let message = Message {
+            channel_id: target.channel_id,
+            count: 0,
+            data,
+        };
+
+        self.outbox.push((source, target, message));
+    }
+
+    /// Add a state sync, returning the one it replaced.
     pub fn add_state_sync(
         &mut self,
         state_sync_id: uuid::Uuid,
         source: StateEndpoint,
         target: StateEndpoint,
-    ) {
+    ) -> Option<StateSync> {
         log::debug!("Adding state sync {}", state_sync_id);
 
         // add the state sync to the state
@@ -251,43 +375,47 @@ impl GlobalState {
                 target,
                 status: StateSyncStatus::Created,
             },
-        );
+        )
     }
 
-    pub fn remove_state_sync(&mut self, state_sync_id: uuid::Uuid) {
+    pub fn remove_state_sync(&mut self, state_sync_id: uuid::Uuid) -> Option<StateSync> {
         log::debug!("Removing state sync {}", state_sync_id);
 
         // remove the state sync from the state
-        self.state_sync.remove(&state_sync_id);
+        self.state_sync.remove(&state_sync_id)
     }
 
-    pub fn get_state_sync_status(&self, state_sync_id: uuid::Uuid) -> StateSyncStatus {
+    pub fn get_state_sync_status(
+        &self,
+        state_sync_id: uuid::Uuid,
+    ) -> Result<StateSyncStatus, String> {
         log::debug!("Getting state sync {} status", state_sync_id);
 
-        // check if state_sync_id exists in hashmap
-        if self.state_sync.contains_key(&state_sync_id) {
-            // get status from state_sync
-            let state_sync = self.state_sync.get(&state_sync_id).unwrap();
-            return state_sync.status;
-        } else {
-            panic!("State sync {} not found", state_sync_id);
+        // get status from state_sync
+        match self.state_sync.get(&state_sync_id) {
+            Some(state_sync) => Ok(state_sync.status),
+            None => Err(format!("State sync {} not found", state_sync_id)),
         }
     }
 
-    pub fn set_state_sync_status(&mut self, state_sync_id: uuid::Uuid, status: StateSyncStatus) {
+    pub fn set_state_sync_status(
+        &mut self,
+        state_sync_id: uuid::Uuid,
+        status: StateSyncStatus,
+    ) -> Result<(), String> {
         log::debug!(
             "Setting state sync {} status to {:?}",
             state_sync_id,
             status
         );
 
-        // check if state_sync_id exists in hashmap
-        if self.state_sync.contains_key(&state_sync_id) {
-            // set status in state_sync
-            let state_sync = self.state_sync.get_mut(&state_sync_id).unwrap();
-            state_sync.status = status;
-        } else {
-            panic!("State sync {} not found", state_sync_id);
+        // set status in state_sync
+        match self.state_sync.get_mut(&state_sync_id) {
+            Some(state_sync) => {
+                state_sync.status = status;
+                Ok(())
+            }
+            None => Err(format!("State sync {} not found", state_sync_id)),
         }
     }
 }
@@ -330,46 +458,42 @@ mod tests {
             },
             child: std::process::Command::new("ls").spawn().unwrap(),
             child_pid: 0,
+            schedstat: None,
+            cgroup: None,
         };
 
-        state.add_component(id, path.clone(), core);
+        state.add_component(id, path.clone(), core, "1.0.0".to_string());
         assert_eq!(state.total_components(), 1);
         assert_eq!(state.get_component(id).unwrap().path, path);
         assert_eq!(state.get_component(id).unwrap().core, core);
-        assert_eq!(
-            state.get_component(id).unwrap().implentation.is_none(),
-            true
-        );
+        assert!(state.get_component(id).unwrap().implentation.is_none());
 
-        state.get_component_mut(id).unwrap().times.push(1);
-        assert_eq!(state.get_component(id).unwrap().times.len(), 1);
+        state
+            .get_component_mut(id)
+            .unwrap()
+            .times
+            .add(std::time::Duration::from_micros(1));
+        assert_eq!(state.get_component(id).unwrap().times.count, 1);
 
-        state.add_component_implementation(id, implementation);
-        assert_eq!(
-            state.get_component(id).unwrap().implentation.is_some(),
-            true
-        );
+        state
+            .add_component_implementation(id, implementation)
+            .unwrap();
+        assert!(state.get_component(id).unwrap().implentation.is_some());
 
-        state.start_component(id);
-        assert_eq!(state.get_component(id).unwrap().run, true);
+        state.start_component(id).unwrap();
+        assert!(state.get_component(id).unwrap().run);
 
-        state.stop_component(id);
-        assert_eq!(state.get_component(id).unwrap().run, false);
+        state.stop_component(id).unwrap();
+        assert!(!state.get_component(id).unwrap().run);
 
-        state.remove_component_implementation(id);
-        assert_eq!(
-            state.get_component(id).unwrap().implentation.is_none(),
-            true
-        );
+        state.remove_component_implementation(id).unwrap();
+        assert!(state.get_component(id).unwrap().implentation.is_none());
 
-        state.remove_component(id);
+        state.remove_component(id).unwrap();
         assert_eq!(state.total_components(), 1);
-        assert_eq!(state.get_component(id).unwrap().remove, true);
-        assert_eq!(state.get_component(id).unwrap().run, false);
-        assert_eq!(
-            state.get_component(id).unwrap().implentation.is_none(),
-            true
-        );
+        assert!(state.get_component(id).unwrap().remove);
+        assert!(!state.get_component(id).unwrap().run);
+        assert!(state.get_component(id).unwrap().implentation.is_none());
     }
 
     #[test]
@@ -395,29 +519,32 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
     fn test_global_state_component_not_found_start() {
         setup();
 
         let mut state = GlobalState::new();
 
         let id = uuid::Uuid::new_v4();
-        state.start_component(id);
+        assert_eq!(
+            state.start_component(id),
+            Err(format!("Component {} not found", id))
+        );
     }
 
     #[test]
-    #[should_panic]
     fn test_global_state_component_not_found_stop() {
         setup();
 
         let mut state = GlobalState::new();
 
         let id = uuid::Uuid::new_v4();
-        state.stop_component(id);
+        assert_eq!(
+            state.stop_component(id),
+            Err(format!("Component {} not found", id))
+        );
     }
 
     #[test]
-    #[should_panic]
     fn test_global_state_component_not_found_add_implementation() {
         setup();
 
@@ -435,35 +562,40 @@ mod tests {
             },
             child: std::process::Command::new("ls").spawn().unwrap(),
             child_pid: 0,
+            schedstat: None,
+            cgroup: None,
         };
 
-        state.add_component_implementation(id, implementation);
+        assert_eq!(
+            state.add_component_implementation(id, implementation),
+            Err(format!("Component {} not found", id))
+        );
     }
 
     #[test]
-    #[should_panic]
     fn test_global_state_component_not_found_remove() {
         setup();
 
         let mut state = GlobalState::new();
 
         let id = uuid::Uuid::new_v4();
-        state.remove_component(id);
+        assert_eq!(
+            state.remove_component(id),
+            Err(format!("Component {} not found", id))
+        );
     }
 
     #[test]
-    #[should_panic]
     fn test_global_state_component_not_found_implementation() {
         setup();
 
         let mut state = GlobalState::new();
 
         let id = uuid::Uuid::new_v4();
-        state.remove_component_implementation(id);
+        assert!(state.remove_component_implementation(id).is_err());
     }
 
     #[test]
-    #[should_panic]
     fn test_global_state_component_not_initialized() {
         setup();
 
@@ -473,8 +605,12 @@ mod tests {
         let path = "path".to_string();
         let core = 0;
 
-        state.add_component(id, path.clone(), core);
-        state.start_component(id);
+        state.add_component(id, path.clone(), core, "1.0.0".to_string());
+        assert_eq!(
+            state.start_component(id),
+            Err(format!("Component {} not initialized", id))
+        );
+        assert!(!state.get_component(id).unwrap().run);
     }
 
     #[test]
@@ -488,7 +624,7 @@ mod tests {
             major_frames: vec![],
         };
 
-        state.set_schedule(schedule);
+        state.set_schedule(schedule).unwrap();
 
         assert_eq!(state.schedule.period, std::time::Duration::from_secs(1));
     }
@@ -513,10 +649,14 @@ mod tests {
             },
             child: std::process::Command::new("ls").spawn().unwrap(),
             child_pid: 0,
+            schedstat: None,
+            cgroup: None,
         };
 
-        state.add_component(id, path.clone(), core);
-        state.add_component_implementation(id, implementation);
+        state.add_component(id, path.clone(), core, "1.0.0".to_string());
+        state
+            .add_component_implementation(id, implementation)
+            .unwrap();
 
         let schedule = Schedule {
             period: std::time::Duration::from_secs(1),
@@ -528,13 +668,12 @@ mod tests {
             }],
         };
 
-        state.set_schedule(schedule);
+        state.set_schedule(schedule).unwrap();
 
         assert_eq!(state.schedule.period, std::time::Duration::from_secs(1));
     }
 
     #[test]
-    #[should_panic]
     fn test_global_state_schedule_invalid() {
         setup();
 
@@ -550,11 +689,11 @@ mod tests {
             }],
         };
 
-        state.set_schedule(schedule);
+        assert!(state.set_schedule(schedule).is_err());
+        assert!(state.schedule.major_frames.is_empty());
     }
 
     #[test]
-    #[should_panic]
     fn test_global_state_schedule_invalid_component() {
         setup();
 
@@ -564,7 +703,7 @@ mod tests {
         let path = "path".to_string();
         let core = 0;
 
-        state.add_component(id, path.clone(), core);
+        state.add_component(id, path.clone(), core, "1.0.0".to_string());
 
         let schedule = Schedule {
             period: std::time::Duration::from_secs(1),
@@ -576,7 +715,10 @@ mod tests {
             }],
         };
 
-        state.set_schedule(schedule);
+        assert_eq!(
+            state.set_schedule(schedule).err(),
+            Some(format!("Component {} not initialized", id))
+        );
     }
 
     #[test]
@@ -595,14 +737,34 @@ mod tests {
             channel_id: 1,
         };
 
-        state.add_route(source, target);
+        let other = RouteEndpoint {
+            endpoint: Endpoint::Runner,
+            channel_id: 2,
+        };
+
+        assert!(state.add_route(source, target));
+        assert!(!state.add_route(source, target));
 
         assert_eq!(state.routes.len(), 1);
-        assert_eq!(state.routes.get(&source).unwrap().endpoint, target.endpoint);
+        assert_eq!(state.routes.get(&source).unwrap(), &vec![target]);
+
+        // a second target is added next to the first
+        assert!(state.add_route(source, other));
+        assert_eq!(state.routes.get(&source).unwrap(), &vec![target, other]);
+        assert_eq!(state.route_count(), 2);
 
-        state.remove_route(source);
+        assert!(state.remove_route(source, target));
+        assert!(!state.remove_route(source, target));
+        assert_eq!(state.routes.get(&source).unwrap(), &vec![other]);
 
+        assert!(state.remove_route(source, other));
         assert_eq!(state.routes.len(), 0);
+
+        state.add_route(source, target);
+        state.add_route(source, other);
+        assert_eq!(state.remove_routes(source), vec![target, other]);
+        assert_eq!(state.routes.len(), 0);
+        assert!(state.remove_routes(source).is_empty());
     }
 
     #[test]
@@ -618,13 +780,73 @@ mod tests {
             count: 0,
         };
 
-        state.messages.insert(channel_id, vec![message.clone()]);
+        let source = RouteEndpoint {
+            endpoint: crate::services::communication::Endpoint::Runner,
+            channel_id: 5,
+        };
 
-        let message = state.get_message(channel_id).unwrap();
+        state
+            .messages
+            .insert(channel_id, vec![(source, message.clone())]);
+
+        let (from, message) = state.get_message(channel_id).unwrap();
+        assert_eq!(from, source);
         assert_eq!(message.channel_id, channel_id);
         assert_eq!(state.messages.get(&channel_id).unwrap().len(), 0);
     }
 
+    #[test]
+    fn test_global_state_message_with_source() {
+        setup();
+
+        let mut state = GlobalState::new();
+
+        let source = RouteEndpoint {
+            endpoint: crate::services::communication::Endpoint::Component(uuid::Uuid::new_v4()),
+            channel_id: 3,
+        };
+        for count in 0..2 {
+            state.messages.entry(0).or_default().push((
+                source,
+                Message {
+                    channel_id: 0,
+                    data: vec![],
+                    count,
+                },
+            ));
+        }
+
+        // oldest first
+        let (from, message) = state.get_message(0).unwrap();
+        assert_eq!(from, source);
+        assert_eq!(message.count, 0);
+        assert_eq!(state.get_message(0).unwrap().1.count, 1);
+        assert!(state.get_message(0).is_none());
+        assert!(state.get_message(1).is_none());
+    }
+
+    #[test]
+    fn test_global_state_send_message() {
+        setup();
+
+        let mut state = GlobalState::new();
+
+        let target = RouteEndpoint {
+            endpoint: crate::services::communication::Endpoint::Component(uuid::Uuid::new_v4()),
+            channel_id: 3,
+        };
+        state.send_message(0, target, vec![1, 2]);
+
+        let (source, to, message) = &state.outbox[0];
+        assert_eq!(
+            source.endpoint,
+            crate::services::communication::Endpoint::Runner
+        );
+        assert_eq!(*to, target);
+        assert_eq!(message.channel_id, 3);
+        assert_eq!(message.data, vec![1, 2]);
+    }
+
     #[test]
     fn test_global_state_message_empty() {
         setup();
@@ -634,7 +856,7 @@ mod tests {
         let channel_id = 0;
 
         let message = state.get_message(channel_id);
-        assert_eq!(message.is_none(), true);
+        assert!(message.is_none());
     }
 
     #[test]
@@ -656,13 +878,15 @@ mod tests {
         assert_eq!(state.state_sync.len(), 1);
         assert_eq!(
             state.get_state_sync_status(state_sync_id),
-            StateSyncStatus::Created
+            Ok(StateSyncStatus::Created)
         );
 
-        state.set_state_sync_status(state_sync_id, StateSyncStatus::Started);
+        state
+            .set_state_sync_status(state_sync_id, StateSyncStatus::Started)
+            .unwrap();
         assert_eq!(
             state.get_state_sync_status(state_sync_id),
-            StateSyncStatus::Started
+            Ok(StateSyncStatus::Started)
         );
 
         state.remove_state_sync(state_sync_id);
@@ -670,25 +894,25 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
     fn test_global_state_sync_not_found() {
         setup();
 
         let state = GlobalState::new();
 
         let state_sync_id = uuid::Uuid::new_v4();
-        state.get_state_sync_status(state_sync_id);
+        assert!(state.get_state_sync_status(state_sync_id).is_err());
     }
 
     #[test]
-    #[should_panic]
     fn test_global_state_sync_not_found_set() {
         setup();
 
         let mut state = GlobalState::new();
 
         let state_sync_id = uuid::Uuid::new_v4();
-        state.set_state_sync_status(state_sync_id, StateSyncStatus::Created);
+        assert!(state
+            .set_state_sync_status(state_sync_id, StateSyncStatus::Created)
+            .is_err());
     }
 
     #[test]
@@ -697,10 +921,10 @@ mod tests {
 
         let mut state = GlobalState::new();
 
-        assert_eq!(state.get_done(), false);
+        assert!(!state.get_done());
 
         state.set_done(true);
-        assert_eq!(state.get_done(), true);
+        assert!(state.get_done());
     }
 
     #[test]
@@ -709,7 +933,7 @@ mod tests {
 
         // test clone
         let status = StateSyncStatus::Created;
-        let status_clone = status.clone();
+        let status_clone = status;
         assert_eq!(status, status_clone);
 
         // test debug
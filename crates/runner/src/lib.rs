@@ -0,0 +1,344 @@
+//! The runner, loading configurations and running their components frame by frame.
+//!
+//! The `runner` binary runs one [`Runner`] until it is shut down. Tests start
+//! one in-process and step it a frame at a time to inspect its state between
+//! frames.
+
+use elafry::telemetry::{Record, Recorder, Service, Span};
+
+use crate::global_state::GlobalState;
+use crate::services::{
+    communication::CommunicationService, management::ManagementService,
+    scheduler::SchedulerService, state::StateService,
+};
+use crate::settings::Settings;
+
+pub mod cgroup;
+pub mod control;
+pub mod global_state;
+pub mod metrics;
+pub mod realtime;
+pub mod recording;
+pub mod sandbox;
+pub mod services;
+pub mod settings;
+pub mod shutdown;
+pub mod simulation;
+pub mod timing;
+
+pub struct Runner {
+    settings: Settings,
+    global_state: GlobalState,
+    communication_service: CommunicationService,
+    management_service: ManagementService,
+    scheduler_service: SchedulerService,
+    state_service: StateService,
+    recorder: Recorder,
+    metrics: Option<metrics::Metrics>,
+    /// Virtual time shared with the components when simulating.
+    clock: Option<std::sync::Arc<simulation::Clock>>,
+    frame_timer: timing::FrameTimer,
+    /// Index of the frame run next.
+    frame: u64,
+    started: std::time::Instant,
+}
+
+impl Runner {
+    /// Set up the services and start loading the initial configuration.
+    pub fn new(settings: Settings) -> Result<Runner, String> {
+        // component processes exiting on their own are noticed and restarted
+        if let Err(e) = services::management::supervise::install() {
+            log::error!("{}", e);
+        }
+
+        // select the scheduling mode before spawning threads, they are scheduled by it
+        realtime::init(settings.scheduling)?;
+
+        let global_state = GlobalState::new();
+
+        let mut communication_service = CommunicationService::new(settings.port);
+        let trusted_keys = settings.trusted_keys()?;
+        if trusted_keys.is_empty() {
+            log::info!("No trusted keys, configurations are loaded without checking signatures");
+        }
+        let registry = if settings.registry.is_empty() {
+            None
+        } else {
+            Some(services::management::registry::Registry::open(
+                std::path::Path::new(&settings.registry),
+            )?)
+        };
+        let cgroups = if settings.cgroup.is_empty() {
+            log::info!("No cgroup, component processes are not limited");
+            None
+        } else {
+            Some(cgroup::Cgroups::open(std::path::Path::new(
+                &settings.cgroup,
+            ))?)
+        };
+        if settings.sandbox.is_none() {
+            log::warn!("No sandbox, component processes run as the runner user");
+        }
+        let clock = match &settings.simulation {
+            Some(_) => {
+                let clock = simulation::Clock::new()?;
+                log::info!("Simulating on virtual time, frames run back to back");
+                Some(std::sync::Arc::new(clock))
+            }
+            None => None,
+        };
+        let management_service = ManagementService::new(
+            std::path::Path::new(&settings.configuration_directory).join(&settings.configuration),
+            std::time::Duration::from_millis(settings.reconfiguration_timeout),
+            trusted_keys,
+            registry,
+            cgroups,
+            settings.sandbox.clone(),
+            clock.clone(),
+        );
+        let scheduler_service = SchedulerService::new(clock.clone());
+        let state_service = StateService::new();
+
+        let recorder =
+            Recorder::new(&settings.telemetry, settings.telemetry_capacity).map_err(|e| {
+                format!(
+                    "Failed to open telemetry {}; err = {}",
+                    settings.telemetry, e
+                )
+            })?;
+
+        let metrics = if settings.metrics.is_empty() {
+            None
+        } else {
+            Some(metrics::Metrics::new(&settings.metrics).map_err(|e| {
+                format!(
+                    "Failed to serve metrics on {}; err = {}",
+                    settings.metrics, e
+                )
+            })?)
+        };
+
+        if !settings.record.is_empty() {
+            communication_service.attach_recorder(recording::Recorder::new(&settings.record)?);
+            log::info!("Recording routed messages to {}", settings.record);
+        }
+        if let Some(replay) = &settings.replay {
+            communication_service.attach_player(recording::Player::open(replay)?);
+            log::info!(
+                "Replaying {} to component {} in place of its live producers",
+                replay.recording,
+                replay.component_id
+            );
+        }
+
+        if !settings.control.is_empty() {
+            let control = control::Control::new(&settings.control).map_err(|e| {
+                format!(
+                    "Failed to open control socket {}; err = {}",
+                    settings.control, e
+                )
+            })?;
+            communication_service.attach_control(control);
+        }
+
+        log::info!(
+            "Starting runner loop with period {}us",
+            global_state.schedule.period.as_micros()
+        );
+
+        let frame_timer = match settings.simulation {
+            Some(_) => timing::FrameTimer::simulated(),
+            None => timing::FrameTimer::new(settings.overrun_policy),
+        };
+
+        Ok(Runner {
+            settings,
+            global_state,
+            communication_service,
+            management_service,
+            scheduler_service,
+            state_service,
+            recorder,
+            metrics,
+            clock,
+            frame_timer,
+            frame: 0,
+            started: std::time::Instant::now(),
+        })
+    }
+
+    /// Index of the frame run next.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// The state between frames, changes take effect in the next frame.
+    pub fn state(&mut self) -> &mut GlobalState {
+        &mut self.global_state
+    }
+
+    pub fn communication(&self) -> &CommunicationService {
+        &self.communication_service
+    }
+
+    /// Run frames until the runner is done.
+    pub fn run(&mut self) {
+        while self.step() {}
+    }
+
+    /// Run one frame and wait for the start of the next, returning false
+    /// without waiting once the runner is done.
+    pub fn step(&mut self) -> bool {
+        let frame = self.frame;
+        self.communication_service
+            .set_frame(frame, self.frame_timer.frame_start());
+
+        let start = elafry::telemetry::now();
+        self.scheduler_service.run(
+            &mut self.global_state,
+            self.frame_timer.frame_start(),
+            &mut self.recorder,
+            frame,
+        );
+        record_service(&mut self.recorder, frame, Service::Scheduler, start);
+
+        let start = elafry::telemetry::now();
+        self.communication_service.run(&mut self.global_state);
+        record_service(&mut self.recorder, frame, Service::Communication, start);
+
+        let start = elafry::telemetry::now();
+        self.state_service.run(&mut self.global_state);
+        record_service(&mut self.recorder, frame, Service::State, start);
+
+        // if there is less than 100us left in the period, skip management
+        let duration = self.frame_timer.elapsed();
+        if duration + std::time::Duration::from_micros(100) <= self.global_state.schedule.period {
+            let start = elafry::telemetry::now();
+            self.management_service
+                .set_frame_start(self.frame_timer.frame_start());
+            self.management_service
+                .run(&mut self.global_state, &mut self.recorder, frame);
+            // reconfigure in the same frames every run
+            if self.clock.is_some() {
+                self.management_service.settle();
+            }
+            record_service(&mut self.recorder, frame, Service::Management, start);
+        }
+
+        self.recorder.record(Record {
+            frame,
+            span: Span::Frame,
+            start: self.frame_timer.started().as_nanos() as u64,
+            end: elafry::telemetry::now(),
+            value: self.frame_timer.overruns,
+        });
+
+        if let Some(metrics) = &self.metrics {
+            metrics.publish(
+                frame,
+                &self.frame_timer,
+                self.recorder.dropped(),
+                &self.global_state,
+                &self.communication_service,
+                &self.management_service,
+            );
+        }
+
+        // a simulation of a set length ends like a shutdown request
+        let frames = self
+            .settings
+            .simulation
+            .as_ref()
+            .and_then(|simulation| simulation.frames);
+        if frames.is_some_and(|frames| frame + 1 >= frames) && !self.global_state.get_done() {
+            log::info!("Simulated {} frames", frame + 1);
+            self.global_state.set_done(true);
+        }
+
+        if elafry::shutdown::requested() && !self.global_state.get_done() {
+            log::info!("Received shutdown signal");
+            self.global_state.set_done(true);
+        }
+
+        // if done, stop before waiting
+        if self.global_state.get_done() {
+            return false;
+        }
+
+        // sleep until the start of the next frame
+        self.frame_timer.wait(self.global_state.schedule.period);
+        self.frame += 1;
+        true
+    }
+
+    /// Stop every component process and return the exit status for the runner.
+    pub fn shutdown(self) -> i32 {
+        let Runner {
+            settings,
+            mut global_state,
+            mut communication_service,
+            management_service,
+            mut recorder,
+            clock,
+            frame_timer,
+            frame,
+            started,
+            ..
+        } = self;
+
+        // send any replies queued in the last frame, such as the shutdown acknowledgement
+        communication_service.run(&mut global_state);
+
+        // nothing is scheduled any more, stop every component process
+        log::info!("Stopping components");
+        let starting = management_service.shutdown();
+        let status = shutdown::stop_components(
+            &mut global_state,
+            starting,
+            std::time::Duration::from_millis(settings.shutdown_timeout),
+            |state| communication_service.run(state),
+        );
+        communication_service.close();
+
+        recorder.close();
+
+        if !settings.control.is_empty() {
+            let _ = std::fs::remove_file(&settings.control);
+        }
+
+        if clock.is_some() {
+            log::info!(
+                "Simulated {}s of virtual time over {} frames in {}s",
+                (frame_timer.frame_start() + global_state.schedule.period).as_secs_f64(),
+                frame + 1,
+                started.elapsed().as_secs_f64()
+            );
+        } else {
+            if realtime::is_best_effort() {
+                log::warn!("Best-effort scheduling, frame timing was not guaranteed");
+            }
+            log::info!(
+                "Frame start jitter min {}us mean {}us max {}us over {} frames, {} overruns, {} frames skipped",
+                frame_timer.jitter.min.as_micros(),
+                frame_timer.jitter.mean().as_micros(),
+                frame_timer.jitter.max.as_micros(),
+                frame_timer.jitter.count,
+                frame_timer.overruns,
+                frame_timer.skipped
+            );
+        }
+
+        log::info!("Runner loop complete");
+        status
+    }
+}
+
+fn record_service(recorder: &mut Recorder, frame: u64, service: Service, start: u64) {
+    recorder.record(Record {
+        frame,
+        span: Span::Service(service),
+        start,
+        end: elafry::telemetry::now(),
+        value: 0,
+    });
+}
@@ -0,0 +1,725 @@
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use std::fmt::Write as _;
+
+use crate::global_state::{GlobalState, StateSyncStatus};
+use crate::services::communication::{CommunicationService, RouteEndpoint, RouteStats};
+use crate::services::management::ManagementService;
+use crate::timing::{CpuTimes, FrameTimer};
+
+/// Values published by the frame loop for the metrics thread to render.
+#[derive(Default, Clone, Debug)]
+pub struct Snapshot {
+    pub frames: u64,
+    pub overruns: u64,
+    pub skipped: u64,
+    pub period: Duration,
+    pub jitter_mean: Duration,
+    pub jitter_max: Duration,
+    pub telemetry_dropped: u64,
+    pub components: Vec<ComponentMetrics>,
+    /// Counts per route and messages without a route by channel, copied when a scrape asks for them.
+    pub routes: Vec<(RouteEndpoint, RouteEndpoint, RouteStats)>,
+    pub unrouted: Vec<(Option<u32>, u64)>,
+    pub state_syncs: Vec<(uuid::Uuid, StateSyncStatus)>,
+    pub management: &'static str,
+    pub management_task: Option<usize>,
+    /// Running without real-time scheduling.
+    pub best_effort: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ComponentMetrics {
+    pub id: uuid::Uuid,
+    pub running: bool,
+    /// Times the process has been restarted after exiting on its own.
+    pub restarts: u32,
+    /// Times the process hit its resource limits.
+    pub faults: u64,
+    pub pid: Option<libc::pid_t>,
+    /// Cpu time used in each scheduled slot, copied when a scrape asks for it.
+    pub times: CpuTimes,
+    /// Total cpu time, filled in by the metrics thread when scraped.
+    pub cpu_time: Option<Duration>,
+}
+
+enum Listener {
+    Tcp(std::net::TcpListener),
+    Unix(UnixListener),
+}
+
+/// Longest a scrape waits for the frame loop to fill in the snapshot.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Serves the latest snapshot in the Prometheus text format.
+///
+/// The frame loop only ever tries the lock, so a scrape in progress costs it
+/// at most one missed update. Copying larger values is left to the frame after
+/// a scrape asks for them.
+pub struct Metrics {
+    snapshot: Arc<Mutex<Snapshot>>,
+    /// Set by a scrape waiting for the frame loop, cleared once it has published.
+    requested: Arc<AtomicBool>,
+}
+
+impl Metrics {
+    /// Listen on `unix:<path>` or a tcp socket address.
+    pub fn new(address: &str) -> io::Result<Metrics> {
+        let listener = match address.strip_prefix("unix:") {
+            Some(path) => {
+                // remove the socket left behind by a previous run
+                let _ = std::fs::remove_file(path);
+                Listener::Unix(UnixListener::bind(path)?)
+            }
+            None => Listener::Tcp(std::net::TcpListener::bind(address)?),
+        };
+
+        let snapshot = Arc::new(Mutex::new(Snapshot::default()));
+        let requested = Arc::new(AtomicBool::new(false));
+
+        {
+            let snapshot = snapshot.clone();
+            let requested = requested.clone();
+            std::thread::Builder::new()
+                .name("metrics".to_string())
+                .spawn(move || serve(listener, snapshot, requested))?;
+        }
+
+        log::info!("Serving metrics on {}", address);
+
+        Ok(Metrics {
+            snapshot,
+            requested,
+        })
+    }
+
+    pub fn publish(
+        &self,
+        frame: u64,
+        frame_timer: &FrameTimer,
+        telemetry_dropped: u64,
+        state: &GlobalState,
+        communication: &CommunicationService,
+        management: &ManagementService,
+    ) {
+        // never wait on a scrape
+        let mut snapshot = match self.snapshot.try_lock() {
+            Ok(snapshot) => snapshot,
+            Err(_) => return,
+        };
+        let scraped = self.requested.load(Ordering::Acquire);
+
+        snapshot.frames = frame + 1;
+        snapshot.overruns = frame_timer.overruns;
+        snapshot.skipped = frame_timer.skipped;
+        snapshot.period = state.schedule.period;
+        snapshot.jitter_mean = frame_timer.jitter.mean();
+        snapshot.jitter_max = frame_timer.jitter.max;
+        snapshot.telemetry_dropped = telemetry_dropped;
+        snapshot.best_effort = crate::realtime::is_best_effort();
+
+        // reuse the vectors so publishing does not allocate once warmed up
+        snapshot
+            .components
+            .resize_with(state.components.len(), Default::default);
+        for (metrics, (id, component)) in
+            snapshot.components.iter_mut().zip(state.components.iter())
+        {
+            metrics.id = *id;
+            metrics.running = component.run;
+            metrics.restarts = component.restarts;
+            metrics.faults = component.faults;
+            metrics.pid = component
+                .implentation
+                .as_ref()
+                .map(|implentation| implentation.child_pid);
+            // the window of slot times is only copied for a scrape
+            if scraped {
+                metrics.times.clone_from(&component.times);
+            }
+            metrics.cpu_time = None;
+        }
+
+        if scraped {
+            snapshot.routes.clear();
+            snapshot.routes.extend(
+                communication
+                    .route_stats()
+                    .iter()
+                    .map(|((source, target), stats)| (*source, *target, *stats)),
+            );
+            snapshot.unrouted.clear();
+            snapshot.unrouted.extend(
+                communication
+                    .unrouted()
+                    .iter()
+                    .map(|(channel, dropped)| (*channel, *dropped)),
+            );
+        }
+
+        snapshot.state_syncs.clear();
+        snapshot.state_syncs.extend(
+            state
+                .state_sync
+                .iter()
+                .map(|(id, state_sync)| (*id, state_sync.status)),
+        );
+
+        let (name, task) = management.status();
+        snapshot.management = name;
+        snapshot.management_task = task;
+
+        if scraped {
+            self.requested.store(false, Ordering::Release);
+        }
+    }
+}
+
+fn serve(listener: Listener, snapshot: Arc<Mutex<Snapshot>>, requested: Arc<AtomicBool>) {
+    crate::realtime::background("metrics");
+
+    loop {
+        let result = match &listener {
+            Listener::Tcp(listener) => listener.accept().and_then(|(stream, _)| {
+                stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+                respond(stream, &snapshot, &requested)
+            }),
+            Listener::Unix(listener) => listener.accept().and_then(|(stream, _)| {
+                stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+                respond(stream, &snapshot, &requested)
+            }),
+        };
+
+        if let Err(e) = result {
+            log::warn!("Failed to serve metrics; err = {:?}", e);
+        }
+    }
+}
+
+fn respond<S: Read + Write>(
+    mut stream: S,
+    snapshot: &Mutex<Snapshot>,
+    requested: &AtomicBool,
+) -> io::Result<()> {
+    // read the request head, the body is never needed
+    let mut request = vec![];
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let length = stream.read(&mut buf)?;
+        if length == 0 || request.len() > 8192 {
+            break;
+        }
+        request.extend_from_slice(&buf[..length]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut line = request.lines().next().unwrap_or("").split_whitespace();
+    let (method, path) = (line.next().unwrap_or(""), line.next().unwrap_or(""));
+
+    let (status, body) = if method != "GET" {
+        ("405 Method Not Allowed", String::new())
+    } else if path != "/" && path != "/metrics" {
+        ("404 Not Found", String::new())
+    } else {
+        // ask the frame loop for the values it only copies for a scrape, a
+        // stopped loop leaves those of the last scrape
+        requested.store(true, Ordering::Release);
+        let deadline = std::time::Instant::now() + PUBLISH_TIMEOUT;
+        while requested.load(Ordering::Acquire) && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // copy out so the lock is held as briefly as possible
+        let mut snapshot = snapshot.lock().unwrap().clone();
+        for component in snapshot.components.iter_mut() {
+            component.cpu_time = component.pid.and_then(cpu_time);
+        }
+        ("200 OK", render(&snapshot))
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn cpu_time(pid: libc::pid_t) -> Option<Duration> {
+    let schedstat = procfs::process::Process::new(pid).ok()?.schedstat().ok()?;
+    Some(Duration::from_nanos(schedstat.sum_exec_runtime))
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+pub fn render(snapshot: &Snapshot) -> String {
+    let mut out = String::new();
+
+    header(&mut out, "elafry_frames_total", "counter", "Frames run.");
+    let _ = writeln!(out, "elafry_frames_total {}", snapshot.frames);
+
+    header(
+        &mut out,
+        "elafry_frame_overruns_total",
+        "counter",
+        "Frames that ran past their deadline.",
+    );
+    let _ = writeln!(out, "elafry_frame_overruns_total {}", snapshot.overruns);
+
+    header(
+        &mut out,
+        "elafry_frames_skipped_total",
+        "counter",
+        "Frames skipped to recover from overruns.",
+    );
+    let _ = writeln!(out, "elafry_frames_skipped_total {}", snapshot.skipped);
+
+    header(
+        &mut out,
+        "elafry_realtime",
+        "gauge",
+        "1 with real-time scheduling, 0 in best-effort mode without timing guarantees.",
+    );
+    let _ = writeln!(out, "elafry_realtime {}", u8::from(!snapshot.best_effort));
+
+    header(
+        &mut out,
+        "elafry_frame_period_seconds",
+        "gauge",
+        "Period of the current schedule.",
+    );
+    let _ = writeln!(
+        out,
+        "elafry_frame_period_seconds {}",
+        snapshot.period.as_secs_f64()
+    );
+
+    header(
+        &mut out,
+        "elafry_frame_jitter_seconds",
+        "gauge",
+        "Lateness of frame starts.",
+    );
+    let _ = writeln!(
+        out,
+        "elafry_frame_jitter_seconds{{stat=\"mean\"}} {}",
+        snapshot.jitter_mean.as_secs_f64()
+    );
+    let _ = writeln!(
+        out,
+        "elafry_frame_jitter_seconds{{stat=\"max\"}} {}",
+        snapshot.jitter_max.as_secs_f64()
+    );
+
+    header(
+        &mut out,
+        "elafry_telemetry_dropped_total",
+        "counter",
+        "Telemetry records dropped because the buffer was full.",
+    );
+    let _ = writeln!(
+        out,
+        "elafry_telemetry_dropped_total {}",
+        snapshot.telemetry_dropped
+    );
+
+    let mut components = snapshot.components.clone();
+    components.sort_by_key(|component| component.id);
+
+    header(
+        &mut out,
+        "elafry_component_running",
+        "gauge",
+        "Whether the component is scheduled.",
+    );
+    for component in components.iter() {
+        let _ = writeln!(
+            out,
+            "elafry_component_running{{component=\"{}\"}} {}",
+            component.id, component.running as u8
+        );
+    }
+
+    header(
+        &mut out,
+        "elafry_component_restarts_total",
+        "counter",
+        "Times the component process was restarted after exiting on its own.",
+    );
+    for component in components.iter() {
+        let _ = writeln!(
+            out,
+            "elafry_component_restarts_total{{component=\"{}\"}} {}",
+            component.id, component.restarts
+        );
+    }
+
+    header(
+        &mut out,
+        "elafry_component_faults_total",
+        "counter",
+        "Times the component process hit its resource limits.",
+    );
+    for component in components.iter() {
+        let _ = writeln!(
+            out,
+            "elafry_component_faults_total{{component=\"{}\"}} {}",
+            component.id, component.faults
+        );
+    }
+
+    header(
+        &mut out,
+        "elafry_component_cpu_seconds_total",
+        "counter",
+        "Cpu time used by the component process.",
+    );
+    for component in components.iter() {
+        if let Some(cpu_time) = component.cpu_time {
+            let _ = writeln!(
+                out,
+                "elafry_component_cpu_seconds_total{{component=\"{}\"}} {}",
+                component.id,
+                cpu_time.as_secs_f64()
+            );
+        }
+    }
+
+    header(
+        &mut out,
+        "elafry_component_slot_cpu_seconds",
+        "gauge",
+        "Cpu time used by the component in its schedule slot, percentiles over recent slots.",
+    );
+    for component in components.iter() {
+        if component.times.count == 0 {
+            continue;
+        }
+        for (stat, value) in [
+            ("last", component.times.last().unwrap_or_default()),
+            ("min", component.times.min),
+            ("mean", component.times.mean()),
+            ("p50", component.times.percentile(50.0)),
+            ("p99", component.times.percentile(99.0)),
+            ("max", component.times.max),
+        ] {
+            let _ = writeln!(
+                out,
+                "elafry_component_slot_cpu_seconds{{component=\"{}\",stat=\"{}\"}} {}",
+                component.id,
+                stat,
+                value.as_secs_f64()
+            );
+        }
+    }
+
+    let mut routes: Vec<(String, String, RouteStats)> = snapshot
+        .routes
+        .iter()
+        .map(|(source, target, stats)| {
+            (
+                escape(&source.to_string()),
+                escape(&target.to_string()),
+                *stats,
+            )
+        })
+        .collect();
+    routes.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+    header(
+        &mut out,
+        "elafry_route_messages_routed_total",
+        "counter",
+        "Messages routed from source to target.",
+    );
+    for (source, target, stats) in routes.iter() {
+        let _ = writeln!(
+            out,
+            "elafry_route_messages_routed_total{{source=\"{}\",target=\"{}\"}} {}",
+            source, target, stats.routed
+        );
+    }
+
+    header(
+        &mut out,
+        "elafry_route_messages_dropped_total",
+        "counter",
+        "Messages dropped on the way from source to target.",
+    );
+    for (source, target, stats) in routes.iter() {
+        let _ = writeln!(
+            out,
+            "elafry_route_messages_dropped_total{{source=\"{}\",target=\"{}\"}} {}",
+            source, target, stats.dropped
+        );
+    }
+
+    let mut unrouted = snapshot.unrouted.clone();
+    unrouted.sort();
+
+    header(
+        &mut out,
+        "elafry_unrouted_messages_dropped_total",
+        "counter",
+        "Messages dropped for lack of a route by channel, an empty channel counts those past the limit.",
+    );
+    for (channel, dropped) in unrouted.iter() {
+        let channel = channel
+            .map(|channel| channel.to_string())
+            .unwrap_or_default();
+        let _ = writeln!(
+            out,
+            "elafry_unrouted_messages_dropped_total{{channel=\"{}\"}} {}",
+            channel, dropped
+        );
+    }
+
+    let mut state_syncs = snapshot.state_syncs.clone();
+    state_syncs.sort_by_key(|(id, _)| *id);
+
+    header(
+        &mut out,
+        "elafry_state_sync_status",
+        "gauge",
+        "Current status of each state sync.",
+    );
+    for (id, status) in state_syncs.iter() {
+        for (name, value) in [
+            ("created", StateSyncStatus::Created),
+            ("started", StateSyncStatus::Started),
+            ("synced", StateSyncStatus::Synced),
+        ] {
+            let _ = writeln!(
+                out,
+                "elafry_state_sync_status{{state_sync=\"{}\",status=\"{}\"}} {}",
+                id,
+                name,
+                (*status == value) as u8
+            );
+        }
+    }
+
+    header(
+        &mut out,
+        "elafry_management_state",
+        "gauge",
+        "Current state of the management service.",
+    );
+    for name in ["idle", "waiting", "loading", "running"] {
+        let _ = writeln!(
+            out,
+            "elafry_management_state{{state=\"{}\"}} {}",
+            name,
+            (snapshot.management == name) as u8
+        );
+    }
+
+    if let Some(task) = snapshot.management_task {
+        header(
+            &mut out,
+            "elafry_management_task",
+            "gauge",
+            "Index of the configuration task being run.",
+        );
+        let _ = writeln!(out, "elafry_management_task {}", task);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::communication::Endpoint;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    fn snapshot() -> Snapshot {
+        let id = uuid::Uuid::from_u128(1);
+        let source = RouteEndpoint {
+            endpoint: Endpoint::Component(id),
+            channel_id: 1,
+        };
+        let target = RouteEndpoint {
+            endpoint: Endpoint::Runner,
+            channel_id: 2,
+        };
+
+        let mut times = CpuTimes::new();
+        times.add(Duration::from_micros(100));
+        times.add(Duration::from_micros(300));
+
+        Snapshot {
+            frames: 10,
+            overruns: 2,
+            period: Duration::from_micros(1000),
+            components: vec![ComponentMetrics {
+                id,
+                running: true,
+                restarts: 2,
+                faults: 3,
+                pid: None,
+                times,
+                cpu_time: Some(Duration::from_millis(1500)),
+            }],
+            routes: vec![(
+                source,
+                target,
+                RouteStats {
+                    routed: 5,
+                    dropped: 1,
+                },
+            )],
+            unrouted: vec![(Some(3), 4), (None, 2)],
+            state_syncs: vec![(id, StateSyncStatus::Started)],
+            management: "running",
+            management_task: Some(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        setup();
+
+        let out = render(&snapshot());
+        let lines: Vec<&str> = out.lines().collect();
+
+        let id = uuid::Uuid::from_u128(1);
+        for expected in [
+            "elafry_frames_total 10".to_string(),
+            "elafry_frame_overruns_total 2".to_string(),
+            "elafry_realtime 1".to_string(),
+            "elafry_frame_period_seconds 0.001".to_string(),
+            format!("elafry_component_running{{component=\"{}\"}} 1", id),
+            format!("elafry_component_restarts_total{{component=\"{}\"}} 2", id),
+            format!("elafry_component_faults_total{{component=\"{}\"}} 3", id),
+            format!("elafry_component_cpu_seconds_total{{component=\"{}\"}} 1.5", id),
+            format!(
+                "elafry_component_slot_cpu_seconds{{component=\"{}\",stat=\"mean\"}} 0.0002",
+                id
+            ),
+            format!(
+                "elafry_component_slot_cpu_seconds{{component=\"{}\",stat=\"max\"}} 0.0003",
+                id
+            ),
+            format!(
+                "elafry_route_messages_routed_total{{source=\"component:{}/1\",target=\"runner/2\"}} 5",
+                id
+            ),
+            format!(
+                "elafry_route_messages_dropped_total{{source=\"component:{}/1\",target=\"runner/2\"}} 1",
+                id
+            ),
+            "elafry_unrouted_messages_dropped_total{channel=\"3\"} 4".to_string(),
+            "elafry_unrouted_messages_dropped_total{channel=\"\"} 2".to_string(),
+            format!("elafry_state_sync_status{{state_sync=\"{}\",status=\"started\"}} 1", id),
+            format!("elafry_state_sync_status{{state_sync=\"{}\",status=\"synced\"}} 0", id),
+            "elafry_management_state{state=\"running\"} 1".to_string(),
+            "elafry_management_state{state=\"idle\"} 0".to_string(),
+            "elafry_management_task 1".to_string(),
+        ] {
+            assert!(lines.contains(&expected.as_str()), "missing {}", expected);
+        }
+
+        // every sample has a type
+        assert!(lines.contains(&"# TYPE elafry_frames_total counter"));
+    }
+
+    #[test]
+    fn test_escape() {
+        setup();
+
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    #[test]
+    fn test_serve_unix() {
+        setup();
+
+        let path = std::env::temp_dir().join(format!("metrics-{}.sock", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+
+        let metrics = Metrics::new(&format!("unix:{}", path)).unwrap();
+
+        // stands in for the frame loop, which publishes once a scrape asks
+        let published = {
+            let shared = metrics.snapshot.clone();
+            let requested = metrics.requested.clone();
+            std::thread::spawn(move || {
+                while !requested.load(Ordering::Acquire) {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                *shared.lock().unwrap() = snapshot();
+                requested.store(false, Ordering::Release);
+            })
+        };
+
+        let mut stream = std::os::unix::net::UnixStream::connect(path).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("elafry_frames_total 10\n"));
+        published.join().unwrap();
+
+        // anything other than the metrics path is not found
+        let mut stream = std::os::unix::net::UnixStream::connect(path).unwrap();
+        stream.write_all(b"GET /other HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_serve_tcp() {
+        setup();
+
+        // find a free port
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        // without a frame loop the scrape gives up waiting and serves what is there
+        let metrics = Metrics::new(&address.to_string()).unwrap();
+        *metrics.snapshot.lock().unwrap() = snapshot();
+
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("elafry_management_state{state=\"running\"} 1\n"));
+    }
+}
@@ -0,0 +1,301 @@
+//! Scheduling policy and core affinity of the runner, its threads and components.
+//!
+//! In real-time mode the frame loop runs at `SCHED_FIFO` 99 on core 1,
+//! background threads run at `SCHED_IDLE` on core 0, and components run at
+//! `SCHED_IDLE` on their configured core, raised to `SCHED_FIFO` 99 for their
+//! slot. That takes root. In best-effort mode everything runs at `SCHED_OTHER`
+//! with nice values ordering the frame loop before components and background
+//! threads, and cores the runner may not use are remapped to ones it may.
+//! Timing guarantees are off.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+/// Core the frame loop runs on.
+const RUNNER_CORE: usize = 1;
+/// Core background threads run on, away from the frame loop.
+const BACKGROUND_CORE: usize = 0;
+
+/// Nice values in best-effort mode. Lowering the runner's only works with
+/// `CAP_SYS_NICE` or a raised `RLIMIT_NICE`. Components inherit the nice value
+/// of the management thread spawning them and cannot be given a lower one
+/// either, so they share it.
+const RUNNER_NICE: libc::c_int = -10;
+const BACKGROUND_NICE: libc::c_int = 10;
+
+static BEST_EFFORT: AtomicBool = AtomicBool::new(false);
+
+/// Cores the runner was allowed to run on when it started.
+static CORES: OnceLock<Vec<usize>> = OnceLock::new();
+
+/// Scheduling mode, set in the runner settings.
+#[derive(Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+pub enum Scheduling {
+    /// Real-time scheduling, failing to start without the privileges for it.
+    #[serde(rename = "realtime")]
+    Realtime,
+    /// Normal scheduling with nice values, without timing guarantees.
+    #[serde(rename = "best-effort")]
+    BestEffort,
+    /// Real-time scheduling if allowed, best-effort otherwise.
+    #[default]
+    #[serde(rename = "auto")]
+    Auto,
+}
+
+/// Schedule the calling thread as the frame loop and select the mode for the rest.
+pub fn init(scheduling: Scheduling) -> Result<(), String> {
+    // read before the runner pins itself to one of them
+    let _ = CORES.set(available()?);
+
+    match scheduling {
+        Scheduling::Realtime => realtime(),
+        Scheduling::BestEffort => {
+            best_effort();
+            Ok(())
+        }
+        Scheduling::Auto => {
+            if let Err(e) = realtime() {
+                log::warn!("{}, falling back to best-effort scheduling", e);
+                best_effort();
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Timing guarantees are off.
+pub fn is_best_effort() -> bool {
+    BEST_EFFORT.load(Ordering::Relaxed)
+}
+
+fn realtime() -> Result<(), String> {
+    set_affinity(0, RUNNER_CORE)
+        .map_err(|e| format!("Failed to run on core {}; err = {}", RUNNER_CORE, e))?;
+    set_scheduler(0, libc::SCHED_FIFO, 99)
+        .map_err(|e| format!("Failed to set real-time scheduler; err = {}", e))?;
+    Ok(())
+}
+
+fn best_effort() {
+    BEST_EFFORT.store(true, Ordering::Relaxed);
+    log::warn!("Best-effort scheduling, timing guarantees are off");
+
+    if let Err(e) = set_affinity(0, core(RUNNER_CORE)) {
+        log::warn!("Failed to set affinity; err = {}", e);
+    }
+    // the runner keeps its nice value when it may not lower it
+    if let Err(e) = set_nice(0, RUNNER_NICE) {
+        log::info!(
+            "Failed to set runner nice value {}; err = {}",
+            RUNNER_NICE,
+            e
+        );
+    }
+}
+
+/// The core to run on for a configured core.
+///
+/// In best-effort mode a core the runner may not use is remapped to one it
+/// may, so configurations written for a bigger machine still run.
+pub fn core(core: usize) -> usize {
+    match CORES.get() {
+        Some(cores) if is_best_effort() => remap(cores, core),
+        _ => core,
+    }
+}
+
+fn remap(cores: &[usize], core: usize) -> usize {
+    if cores.is_empty() || cores.contains(&core) {
+        return core;
+    }
+    cores[core % cores.len()]
+}
+
+/// Move the calling thread away from the frame loop, for threads doing background work.
+pub fn background(name: &str) {
+    if let Err(e) = set_affinity(0, core(BACKGROUND_CORE)) {
+        log::warn!("Failed to set {} affinity; err = {}", name, e);
+    }
+
+    let result = if is_best_effort() {
+        set_nice(0, BACKGROUND_NICE)
+    } else {
+        // only the calling thread, not the whole process
+        match unsafe {
+            libc::pthread_setschedparam(
+                libc::pthread_self(),
+                libc::SCHED_IDLE,
+                &libc::sched_param { sched_priority: 0 },
+            )
+        } {
+            0 => Ok(()),
+            e => Err(std::io::Error::from_raw_os_error(e)),
+        }
+    };
+    if let Err(e) = result {
+        log::warn!("Failed to set {} scheduler; err = {}", name, e);
+    }
+}
+
+/// Schedule a spawned component process on its core.
+pub fn component(pid: libc::pid_t, configured: usize) {
+    let core = core(configured);
+    if core != configured {
+        log::warn!(
+            "Core {} is not available, component process {} runs on core {}",
+            configured,
+            pid,
+            core
+        );
+    }
+    if let Err(e) = set_affinity(pid, core) {
+        log::error!("Failed to set affinity of {}; err = {}", pid, e);
+    }
+
+    let result = if is_best_effort() {
+        set_nice(pid, BACKGROUND_NICE)
+    } else {
+        set_scheduler(pid, libc::SCHED_IDLE, 0)
+    };
+    if let Err(e) = result {
+        log::error!("Failed to set scheduler of {}; err = {}", pid, e);
+    }
+}
+
+/// Raise a component process for its slot, only in real-time mode.
+pub fn raise(pid: libc::pid_t) {
+    if is_best_effort() {
+        return;
+    }
+    if let Err(e) = set_scheduler(pid, libc::SCHED_FIFO, 99) {
+        log::error!("Failed to set scheduler of {}; err = {}", pid, e);
+    }
+}
+
+/// Lower a component process again after its slot, only in real-time mode.
+pub fn lower(pid: libc::pid_t) {
+    if is_best_effort() {
+        return;
+    }
+    if let Err(e) = set_scheduler(pid, libc::SCHED_IDLE, 0) {
+        log::error!("Failed to set scheduler of {}; err = {}", pid, e);
+    }
+}
+
+/// Cores the calling thread may run on.
+fn available() -> Result<Vec<usize>, String> {
+    let mut cpu_set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    check(unsafe { libc::sched_getaffinity(0, std::mem::size_of_val(&cpu_set), &mut cpu_set) })
+        .map_err(|e| format!("Failed to get affinity; err = {}", e))?;
+
+    Ok((0..libc::CPU_SETSIZE as usize)
+        .filter(|core| unsafe { libc::CPU_ISSET(*core, &cpu_set) })
+        .collect())
+}
+
+fn set_affinity(pid: libc::pid_t, core: usize) -> std::io::Result<()> {
+    let mut cpu_set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    unsafe { libc::CPU_SET(core, &mut cpu_set) };
+    check(unsafe { libc::sched_setaffinity(pid, std::mem::size_of_val(&cpu_set), &cpu_set) })
+}
+
+fn set_scheduler(
+    pid: libc::pid_t,
+    policy: libc::c_int,
+    priority: libc::c_int,
+) -> std::io::Result<()> {
+    check(unsafe {
+        libc::sched_setscheduler(
+            pid,
+            policy,
+            &libc::sched_param {
+                sched_priority: priority,
+            },
+        )
+    })
+}
+
+/// Nice value of a thread, 0 for the calling thread.
+fn set_nice(pid: libc::pid_t, nice: libc::c_int) -> std::io::Result<()> {
+    check(unsafe { libc::setpriority(libc::PRIO_PROCESS, pid as libc::id_t, nice) })
+}
+
+fn check(ret: libc::c_int) -> std::io::Result<()> {
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_scheduling() {
+        setup();
+
+        let scheduling: Scheduling = serde_yaml::from_str("best-effort").unwrap();
+        assert_eq!(scheduling, Scheduling::BestEffort);
+        assert_eq!(Scheduling::default(), Scheduling::Auto);
+
+        // cores are only remapped in best-effort mode, the tests never set it
+        assert!(!is_best_effort());
+        assert_eq!(core(4096), 4096);
+    }
+
+    #[test]
+    fn test_remap() {
+        setup();
+
+        // available cores are kept, others spread over the available ones
+        let cores = [0, 2, 3];
+        assert_eq!(remap(&cores, 2), 2);
+        assert_eq!(remap(&cores, 1), 2);
+        assert_eq!(remap(&cores, 4), 2);
+        assert_eq!(remap(&cores, 5), 3);
+        assert_eq!(remap(&cores, 6), 0);
+        assert_eq!(remap(&[], 7), 7);
+    }
+
+    #[test]
+    fn test_available() {
+        setup();
+
+        let cores = available().unwrap();
+        assert!(!cores.is_empty());
+
+        // a child runs at the nice value it is given
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let pid = child.id() as libc::pid_t;
+        set_affinity(pid, cores[0]).unwrap();
+        set_nice(pid, BACKGROUND_NICE).unwrap();
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+        let fields: Vec<&str> = stat
+            .rsplit(')')
+            .next()
+            .unwrap()
+            .split_whitespace()
+            .collect();
+        // nice is field 19, counted from the state after the command name
+        assert_eq!(fields[16], BACKGROUND_NICE.to_string());
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
@@ -0,0 +1,548 @@
+//! Recording routed messages and replaying them to a component.
+//!
+//! A recording is a header followed by one entry per routed message: the frame
+//! and frame start time it was routed in, its source and target endpoints and
+//! the message itself. Entries are written from a background thread, recording
+//! only hands the entry over and never waits on the disk.
+//!
+//! A replay plays the messages recorded to one component into a component of
+//! the running system, in place of its live producers. It starts in the first
+//! frame that component runs and keeps the spacing of the frames the messages
+//! were recorded in.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use elafry::types::communication::Message;
+use serde::Deserialize;
+
+use crate::services::communication::{Endpoint, RouteEndpoint};
+
+/// Magic bytes at the start of every recording.
+pub const MAGIC: [u8; 4] = *b"ELRC";
+/// Version of the entry layout that follows the header.
+pub const VERSION: u16 = 1;
+
+/// Entries waiting to be written before new ones are dropped.
+const CAPACITY: usize = 65536;
+
+/// Longest message an entry may carry, a longer length in a recording is corrupt.
+pub const MAX_LENGTH: usize = 1 << 20;
+
+/// Replay settings, nothing is replayed if not given.
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+pub struct Replay {
+    /// Recording to play.
+    pub recording: String,
+    /// Component the recording is played to, messages routed to it are dropped.
+    #[serde(rename = "component-id")]
+    pub component_id: uuid::Uuid,
+    /// Component whose recorded messages are played, the same component if not given.
+    #[serde(rename = "recorded-component-id", default)]
+    pub recorded_component_id: Option<uuid::Uuid>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Entry {
+    pub frame: u64,
+    /// Start of the frame, virtual when simulated.
+    pub time: Duration,
+    pub source: RouteEndpoint,
+    pub target: RouteEndpoint,
+    pub message: Message,
+}
+
+impl Entry {
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.frame.to_le_bytes());
+        buf.extend_from_slice(&(self.time.as_nanos() as u64).to_le_bytes());
+        encode_endpoint(&self.source, buf);
+        encode_endpoint(&self.target, buf);
+        buf.extend_from_slice(&self.message.channel_id.to_le_bytes());
+        buf.push(self.message.count);
+        buf.extend_from_slice(&(self.message.data.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.message.data);
+    }
+
+    /// Read the next entry, none at the end of the recording.
+    pub fn decode(reader: &mut impl Read) -> io::Result<Option<Entry>> {
+        // the end of the recording is only allowed between entries
+        let mut frame = [0; 8];
+        match reader.read_exact(&mut frame) {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let time = Duration::from_nanos(u64::from_le_bytes(read(reader)?));
+        let source = decode_endpoint(reader)?;
+        let target = decode_endpoint(reader)?;
+        let channel_id = u32::from_le_bytes(read(reader)?);
+        let [count] = read(reader)?;
+        let length = u32::from_le_bytes(read(reader)?) as usize;
+        // checked before allocating, a corrupt length would take any amount of memory
+        if length > MAX_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("message length {} over {}", length, MAX_LENGTH),
+            ));
+        }
+        let mut data = vec![0; length];
+        reader.read_exact(&mut data)?;
+
+        Ok(Some(Entry {
+            frame: u64::from_le_bytes(frame),
+            time,
+            source,
+            target,
+            message: Message {
+                channel_id,
+                count,
+                data,
+            },
+        }))
+    }
+}
+
+// endpoints are a kind byte, the fields of the kind and the channel id
+fn encode_endpoint(endpoint: &RouteEndpoint, buf: &mut Vec<u8>) {
+    match endpoint.endpoint {
+        Endpoint::Component(id) => {
+            buf.push(0);
+            buf.extend_from_slice(id.as_bytes());
+        }
+        Endpoint::Address(SocketAddr::V4(address)) => {
+            buf.push(1);
+            buf.extend_from_slice(&address.ip().octets());
+            buf.extend_from_slice(&address.port().to_le_bytes());
+        }
+        Endpoint::Address(SocketAddr::V6(address)) => {
+            buf.push(2);
+            buf.extend_from_slice(&address.ip().octets());
+            buf.extend_from_slice(&address.port().to_le_bytes());
+        }
+        Endpoint::Runner => buf.push(3),
+        Endpoint::Control(client) => {
+            buf.push(4);
+            buf.extend_from_slice(&client.to_le_bytes());
+        }
+    }
+    buf.extend_from_slice(&endpoint.channel_id.to_le_bytes());
+}
+
+fn decode_endpoint(reader: &mut impl Read) -> io::Result<RouteEndpoint> {
+    let [kind] = read(reader)?;
+    let endpoint = match kind {
+        0 => Endpoint::Component(uuid::Uuid::from_bytes(read(reader)?)),
+        1 => Endpoint::Address(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::from(read::<4>(reader)?)),
+            u16::from_le_bytes(read(reader)?),
+        )),
+        2 => Endpoint::Address(SocketAddr::new(
+            IpAddr::V6(Ipv6Addr::from(read::<16>(reader)?)),
+            u16::from_le_bytes(read(reader)?),
+        )),
+        3 => Endpoint::Runner,
+        4 => Endpoint::Control(u64::from_le_bytes(read(reader)?)),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid endpoint kind {}", kind),
+            ))
+        }
+    };
+
+    Ok(RouteEndpoint {
+        endpoint,
+        channel_id: u32::from_le_bytes(read(reader)?),
+    })
+}
+
+fn read<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Writes routed messages to a recording from a background thread.
+pub struct Recorder {
+    sender: Option<mpsc::SyncSender<Entry>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+    dropped: u64,
+}
+
+impl Recorder {
+    pub fn new(path: &str) -> Result<Recorder, String> {
+        let mut writer = File::create(path)
+            .map(BufWriter::new)
+            .map_err(|e| format!("Failed to create recording {}; err = {}", path, e))?;
+
+        // write the file header
+        writer
+            .write_all(&MAGIC)
+            .and_then(|_| writer.write_all(&VERSION.to_le_bytes()))
+            .map_err(|e| format!("Failed to write recording {}; err = {}", path, e))?;
+
+        let (sender, receiver) = mpsc::sync_channel(CAPACITY);
+        let thread = std::thread::Builder::new()
+            .name("recording".to_string())
+            .spawn(move || {
+                crate::realtime::background("recording");
+
+                write(receiver, writer);
+            })
+            .map_err(|e| format!("Failed to start recording thread; err = {}", e))?;
+
+        Ok(Recorder {
+            sender: Some(sender),
+            thread: Some(thread),
+            dropped: 0,
+        })
+    }
+
+    /// Hand an entry to the writer, dropping it if the writer is behind or
+    /// the message is too long to be read back.
+    pub fn record(&mut self, entry: Entry) {
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return,
+        };
+        if entry.message.data.len() > MAX_LENGTH || sender.try_send(entry).is_err() {
+            self.dropped += 1;
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Write out everything recorded so far and stop the writer thread.
+    pub fn close(&mut self) {
+        // the writer drains the channel and exits once the sender is gone
+        self.sender = None;
+
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::error!("Recording writer panicked");
+            }
+        }
+
+        if self.dropped > 0 {
+            log::warn!("Recording dropped {} messages", self.dropped);
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn write(receiver: mpsc::Receiver<Entry>, mut writer: BufWriter<File>) {
+    let mut buf = Vec::new();
+
+    while let Ok(entry) = receiver.recv() {
+        // write everything waiting, then flush before waiting again
+        for entry in std::iter::once(entry).chain(receiver.try_iter()) {
+            buf.clear();
+            entry.encode(&mut buf);
+            if let Err(e) = writer.write_all(&buf) {
+                log::error!("Failed to write recording; err = {:?}", e);
+                return;
+            }
+        }
+
+        if let Err(e) = writer.flush() {
+            log::error!("Failed to flush recording; err = {:?}", e);
+            return;
+        }
+    }
+}
+
+/// Read every entry of a recording.
+pub fn read_file(path: &str) -> Result<Vec<Entry>, String> {
+    let mut reader = File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| format!("Failed to open recording {}; err = {}", path, e))?;
+
+    // check the header
+    let header: [u8; 6] =
+        read(&mut reader).map_err(|e| format!("Failed to read recording {}; err = {}", path, e))?;
+    if header[0..4] != MAGIC {
+        return Err(format!("{} is not a recording", path));
+    }
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != VERSION {
+        return Err(format!(
+            "Unsupported recording version {} in {}",
+            version, path
+        ));
+    }
+
+    let mut entries = Vec::new();
+    while let Some(entry) = Entry::decode(&mut reader)
+        .map_err(|e| format!("Failed to read recording {}; err = {}", path, e))?
+    {
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Plays recorded messages to a component.
+pub struct Player {
+    component_id: uuid::Uuid,
+    /// Messages still to play, by frame relative to the first one.
+    entries: VecDeque<(u64, RouteEndpoint, Message)>,
+    /// Frame the replay started in.
+    started: Option<u64>,
+}
+
+impl Player {
+    pub fn open(replay: &Replay) -> Result<Player, String> {
+        let entries = read_file(&replay.recording)?;
+        let player = Player::new(
+            entries,
+            replay.component_id,
+            replay.recorded_component_id.unwrap_or(replay.component_id),
+        );
+        if player.entries.is_empty() {
+            return Err(format!(
+                "No messages to {} in recording {}",
+                replay.recorded_component_id.unwrap_or(replay.component_id),
+                replay.recording
+            ));
+        }
+        Ok(player)
+    }
+
+    /// Play the messages recorded to `recorded` to `component_id` instead.
+    pub fn new(entries: Vec<Entry>, component_id: uuid::Uuid, recorded: uuid::Uuid) -> Player {
+        let mut entries: VecDeque<(u64, RouteEndpoint, Message)> = entries
+            .into_iter()
+            .filter(|entry| entry.target.endpoint == Endpoint::Component(recorded))
+            .map(|entry| {
+                let mut message = entry.message;
+                message.channel_id = entry.target.channel_id;
+                (entry.frame, entry.source, message)
+            })
+            .collect();
+
+        // frames count from the first message played
+        let first = entries.front().map(|(frame, _, _)| *frame).unwrap_or(0);
+        for (frame, _, _) in entries.iter_mut() {
+            *frame -= first;
+        }
+
+        Player {
+            component_id,
+            entries,
+            started: None,
+        }
+    }
+
+    pub fn component_id(&self) -> uuid::Uuid {
+        self.component_id
+    }
+
+    /// Whether messages routed to an endpoint are replaced by the recording.
+    pub fn replaces(&self, target: &RouteEndpoint) -> bool {
+        target.endpoint == Endpoint::Component(self.component_id)
+    }
+
+    /// Take the messages to play in a frame along with their recorded source,
+    /// starting in the first frame the component runs.
+    pub fn due(&mut self, frame: u64, running: bool) -> Vec<(RouteEndpoint, Message)> {
+        let started = match self.started {
+            Some(started) => started,
+            None if running && !self.entries.is_empty() => {
+                log::info!("Replaying recording to component {}", self.component_id);
+                self.started = Some(frame);
+                frame
+            }
+            None => return Vec::new(),
+        };
+
+        let mut due = Vec::new();
+        while let Some((offset, _, _)) = self.entries.front() {
+            if started + offset > frame {
+                break;
+            }
+            let (_, source, message) = self.entries.pop_front().unwrap();
+            due.push((source, message));
+        }
+
+        if !due.is_empty() && self.entries.is_empty() {
+            log::info!("Replay to component {} finished", self.component_id);
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    fn entry(frame: u64, source: Endpoint, target: Endpoint, data: Vec<u8>) -> Entry {
+        Entry {
+            frame,
+            time: Duration::from_millis(frame),
+            source: RouteEndpoint {
+                endpoint: source,
+                channel_id: 1,
+            },
+            target: RouteEndpoint {
+                endpoint: target,
+                channel_id: 2,
+            },
+            message: Message {
+                channel_id: 1,
+                count: frame as u8,
+                data,
+            },
+        }
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        setup();
+
+        let endpoints = [
+            Endpoint::Component(uuid::Uuid::new_v4()),
+            Endpoint::Address("127.0.0.1:5000".parse().unwrap()),
+            Endpoint::Address("[::1]:6000".parse().unwrap()),
+            Endpoint::Runner,
+            Endpoint::Control(7),
+        ];
+
+        let mut buf = Vec::new();
+        let entries: Vec<Entry> = endpoints
+            .iter()
+            .zip(endpoints.iter().rev())
+            .enumerate()
+            .map(|(frame, (source, target))| entry(frame as u64, *source, *target, vec![1, 2, 3]))
+            .collect();
+        for entry in entries.iter() {
+            entry.encode(&mut buf);
+        }
+
+        let mut reader = &buf[..];
+        for expected in entries.iter() {
+            assert_eq!(Entry::decode(&mut reader).unwrap().as_ref(), Some(expected));
+        }
+        assert_eq!(Entry::decode(&mut reader).unwrap(), None);
+
+        // an entry cut off is an error, not the end of the recording
+        let mut reader = &buf[..buf.len() - 1];
+        for _ in 0..entries.len() - 1 {
+            Entry::decode(&mut reader).unwrap();
+        }
+        assert!(Entry::decode(&mut reader).is_err());
+
+        // a corrupt length is refused before anything is allocated for it
+        let mut buf = Vec::new();
+        entry(0, Endpoint::Runner, Endpoint::Runner, vec![]).encode(&mut buf);
+        let length = buf.len() - 4;
+        buf[length..].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = Entry::decode(&mut &buf[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            error.to_string(),
+            format!("message length {} over {}", u32::MAX, MAX_LENGTH)
+        );
+    }
+
+    #[test]
+    fn test_recorder() {
+        setup();
+
+        let path = std::env::temp_dir().join(format!("recording-{}.bin", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+
+        let component = Endpoint::Component(uuid::Uuid::new_v4());
+        let mut recorder = Recorder::new(path).unwrap();
+        for frame in 0..100 {
+            recorder.record(entry(frame, component, Endpoint::Runner, vec![frame as u8]));
+        }
+        // a message that could not be read back is not written
+        recorder.record(entry(
+            100,
+            component,
+            Endpoint::Runner,
+            vec![0; MAX_LENGTH + 1],
+        ));
+        recorder.close();
+        assert_eq!(recorder.dropped(), 1);
+
+        let entries = read_file(path).unwrap();
+        assert_eq!(entries.len(), 100);
+        assert_eq!(
+            entries[42],
+            entry(42, component, Endpoint::Runner, vec![42])
+        );
+
+        std::fs::write(path, b"ELTM\x01\x00").unwrap();
+        assert_eq!(read_file(path), Err(format!("{} is not a recording", path)));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_player() {
+        setup();
+
+        let plant = Endpoint::Component(uuid::Uuid::new_v4());
+        let recorded = uuid::Uuid::new_v4();
+        let replayed = uuid::Uuid::new_v4();
+        let entries = vec![
+            entry(10, plant, Endpoint::Component(recorded), vec![1]),
+            entry(11, plant, Endpoint::Runner, vec![2]),
+            entry(12, plant, Endpoint::Component(recorded), vec![3]),
+            entry(12, plant, Endpoint::Component(recorded), vec![4]),
+            entry(15, plant, Endpoint::Component(recorded), vec![5]),
+        ];
+        let mut player = Player::new(entries, replayed, recorded);
+
+        assert!(player.replaces(&RouteEndpoint {
+            endpoint: Endpoint::Component(replayed),
+            channel_id: 9,
+        }));
+        assert!(!player.replaces(&RouteEndpoint {
+            endpoint: Endpoint::Component(recorded),
+            channel_id: 2,
+        }));
+
+        // nothing plays until the component runs
+        assert!(player.due(0, false).is_empty());
+
+        // then the frames keep their spacing, on the channel they were routed to
+        let data = |due: Vec<(RouteEndpoint, Message)>| -> Vec<Vec<u8>> {
+            due.into_iter()
+                .map(|(source, message)| {
+                    assert_eq!(source.endpoint, plant);
+                    assert_eq!(message.channel_id, 2);
+                    message.data
+                })
+                .collect()
+        };
+        assert_eq!(data(player.due(3, true)), vec![vec![1]]);
+        assert!(player.due(4, true).is_empty());
+        assert_eq!(data(player.due(5, true)), vec![vec![3], vec![4]]);
+        assert!(player.due(7, true).is_empty());
+        assert_eq!(data(player.due(8, true)), vec![vec![5]]);
+        assert!(player.due(100, true).is_empty());
+    }
+}
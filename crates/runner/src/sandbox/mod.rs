@@ -0,0 +1,391 @@
+//! Confining component processes.
+//!
+//! Components are what gets updated at runtime, so they are trusted least. The
+//! runner runs as root, a sandboxed component is started in its own mount and
+//! network namespaces, as an unprivileged user and with a seccomp allow-list of
+//! system calls. Its inherited data and state sockets keep working, but it
+//! cannot open new connections or regain privileges.
+
+use serde::Deserialize;
+
+/// Architecture the seccomp filter is written for, other system call tables are refused.
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// System calls a component may make, everything else fails with `ENOSYS`.
+///
+/// Opening files is allowed, what can be opened is up to the user the
+/// component runs as. `execve` is needed to start the component after the
+/// filter is installed.
+const ALLOWED: &[libc::c_long] = &[
+    // files and the inherited sockets
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_readv,
+    libc::SYS_writev,
+    libc::SYS_pread64,
+    libc::SYS_pwrite64,
+    libc::SYS_openat,
+    libc::SYS_close,
+    libc::SYS_lseek,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_readlinkat,
+    libc::SYS_faccessat,
+    libc::SYS_getdents64,
+    libc::SYS_getcwd,
+    libc::SYS_fcntl,
+    libc::SYS_ioctl,
+    libc::SYS_dup,
+    libc::SYS_dup3,
+    libc::SYS_pipe2,
+    libc::SYS_ppoll,
+    libc::SYS_pselect6,
+    libc::SYS_recvfrom,
+    libc::SYS_sendto,
+    libc::SYS_recvmsg,
+    libc::SYS_sendmsg,
+    libc::SYS_shutdown,
+    libc::SYS_getsockopt,
+    // memory
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mprotect,
+    libc::SYS_mremap,
+    libc::SYS_madvise,
+    // signals, sending them is in `OWN_PROCESS`
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    // processes and threads
+    libc::SYS_execve,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    libc::SYS_clone,
+    libc::SYS_futex,
+    libc::SYS_set_tid_address,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_prlimit64,
+    libc::SYS_prctl,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_getppid,
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    libc::SYS_uname,
+    libc::SYS_getrandom,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_sched_getscheduler,
+    libc::SYS_sched_getparam,
+    libc::SYS_sched_setscheduler,
+    // time
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_getres,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_nanosleep,
+    libc::SYS_gettimeofday,
+    // only on x86_64
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_arch_prctl,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_access,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_open,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_stat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_lstat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_readlink,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_dup2,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_pipe,
+];
+
+/// System calls a component may only make on its own process, their first
+/// argument is the process id.
+///
+/// Components stop themselves between slots. Every component runs as the same
+/// user, so without this they could stop or kill each other.
+const OWN_PROCESS: &[libc::c_long] = &[libc::SYS_kill, libc::SYS_tgkill];
+
+/// Instructions before the `OWN_PROCESS` checks, and in each check before its process id.
+const HEADER: usize = 4;
+const PID: usize = 2;
+const CHECK: usize = 5;
+
+fn enabled() -> bool {
+    true
+}
+
+/// How component processes are confined, set in the runner settings.
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+pub struct Sandbox {
+    /// User and group the component processes run as.
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    /// Give each component its own mount and network namespaces.
+    #[serde(default = "enabled")]
+    pub namespaces: bool,
+    /// Only allow the system calls components need.
+    #[serde(default = "enabled")]
+    pub seccomp: bool,
+}
+
+impl Sandbox {
+    /// Confine the calling process, called in the child before exec.
+    ///
+    /// Only makes system calls, so it is safe to call after fork. The filter
+    /// is built by `filter` beforehand for the same reason, the process id it
+    /// allows signals to is filled in here.
+    pub fn enter(&self, filter: &mut [libc::sock_filter]) -> std::io::Result<()> {
+        // namespaces are made while the process still has the privileges to
+        if self.namespaces {
+            check(unsafe { libc::unshare(libc::CLONE_NEWNS | libc::CLONE_NEWNET) })?;
+            // mounts made in the namespace do not reach the host
+            check(unsafe {
+                libc::mount(
+                    std::ptr::null(),
+                    c"/".as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    std::ptr::null(),
+                )
+            })?;
+        }
+
+        // groups first, they cannot be changed once the user is dropped
+        check(unsafe { libc::setgroups(0, std::ptr::null()) })?;
+        check(unsafe { libc::setgid(self.gid) })?;
+        check(unsafe { libc::setuid(self.uid) })?;
+
+        // setuid binaries cannot give privileges back, also needed for seccomp
+        check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+
+        if self.seccomp {
+            let pid = unsafe { libc::getpid() };
+            for index in 0..OWN_PROCESS.len() {
+                filter[HEADER + index * CHECK + PID].k = pid as u32;
+            }
+            let program = libc::sock_fprog {
+                len: filter.len() as libc::c_ushort,
+                filter: filter.as_ptr() as *mut libc::sock_filter,
+            };
+            check(unsafe {
+                libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER,
+                    &program as *const libc::sock_fprog,
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The seccomp program allowing the system calls in `ALLOWED` and `OWN_PROCESS`.
+///
+/// The process id `OWN_PROCESS` calls are checked against is set by `Sandbox::enter`.
+pub fn filter() -> Vec<libc::sock_filter> {
+    let mut filter = vec![
+        // kill processes using another system call table
+        load(std::mem::offset_of!(libc::seccomp_data, arch)),
+        jump(AUDIT_ARCH, 1, 0),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+        load(std::mem::offset_of!(libc::seccomp_data, nr)),
+    ];
+    for nr in OWN_PROCESS {
+        // the low half of the argument, the kernel reads a process id as 32 bits
+        filter.push(jump(*nr as u32, 0, CHECK as u8 - 1));
+        filter.push(load(std::mem::offset_of!(libc::seccomp_data, args)));
+        filter.push(jump(0, 0, 1));
+        filter.push(ret(libc::SECCOMP_RET_ALLOW));
+        filter.push(ret(libc::SECCOMP_RET_ERRNO | libc::EPERM as u32));
+    }
+    for nr in ALLOWED {
+        filter.push(jump(*nr as u32, 0, 1));
+        filter.push(ret(libc::SECCOMP_RET_ALLOW));
+    }
+    // ENOSYS rather than EPERM, so libraries fall back as on an older kernel
+    filter.push(ret(libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32));
+    filter
+}
+
+fn load(offset: usize) -> libc::sock_filter {
+    libc::sock_filter {
+        code: (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16,
+        jt: 0,
+        jf: 0,
+        k: offset as u32,
+    }
+}
+
+/// Skip `jt` instructions if the loaded value equals `k`, otherwise `jf`.
+fn jump(k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+fn ret(k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code: (libc::BPF_RET | libc::BPF_K) as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn check(ret: libc::c_int) -> std::io::Result<()> {
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_sandbox() {
+        setup();
+
+        // dropping privileges takes having them
+        if unsafe { libc::geteuid() } != 0 {
+            log::warn!("Not running as root, skipping");
+            return;
+        }
+
+        let sandbox = Sandbox {
+            uid: 65534,
+            gid: 65534,
+            namespaces: true,
+            seccomp: false,
+        };
+        let output = unsafe {
+            std::process::Command::new("sh")
+                .args(["-c", "id -u; id -g; grep -c : /proc/net/dev"])
+                .pre_exec(move || sandbox.enter(&mut []))
+                .output()
+                .unwrap()
+        };
+        assert!(output.status.success());
+        // the only network device is the namespace's own loopback
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "65534\n65534\n1\n");
+    }
+
+    #[test]
+    fn test_seccomp() {
+        setup();
+
+        if unsafe { libc::geteuid() } != 0 {
+            log::warn!("Not running as root, skipping");
+            return;
+        }
+
+        let sandbox = Sandbox {
+            uid: 65534,
+            gid: 65534,
+            namespaces: false,
+            seccomp: true,
+        };
+        let mut filter = filter();
+        let status = unsafe {
+            std::process::Command::new("true")
+                .pre_exec(move || sandbox.enter(&mut filter))
+                // new sockets are refused, the program itself still runs
+                .pre_exec(|| match libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) {
+                    -1 if *libc::__errno_location() == libc::ENOSYS => Ok(()),
+                    _ => Err(std::io::Error::other("socket allowed")),
+                })
+                .status()
+                .unwrap()
+        };
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_seccomp_signals() {
+        setup();
+
+        if unsafe { libc::geteuid() } != 0 {
+            log::warn!("Not running as root, skipping");
+            return;
+        }
+
+        // still root, so only the filter keeps the child from signalling its parent
+        let sandbox = Sandbox {
+            uid: 0,
+            gid: 0,
+            namespaces: false,
+            seccomp: true,
+        };
+        let mut filter = filter();
+        let status = unsafe {
+            std::process::Command::new("true")
+                .pre_exec(move || sandbox.enter(&mut filter))
+                .pre_exec(|| {
+                    let pid = libc::getpid();
+                    let parent = libc::getppid();
+                    let refused = |ret| ret == -1 && *libc::__errno_location() == libc::EPERM;
+                    if libc::kill(pid, 0) != 0
+                        || libc::syscall(libc::SYS_tgkill, pid, libc::gettid(), 0) != 0
+                    {
+                        return Err(std::io::Error::other("signal to itself refused"));
+                    }
+                    if !refused(libc::kill(parent, 0) as libc::c_long)
+                        || !refused(libc::syscall(libc::SYS_tgkill, parent, parent, 0))
+                    {
+                        return Err(std::io::Error::other("signal to parent allowed"));
+                    }
+                    Ok(())
+                })
+                .status()
+                .unwrap()
+        };
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_settings() {
+        setup();
+
+        let sandbox: Sandbox = serde_yaml::from_str("uid: 1000\ngid: 100\n").unwrap();
+        assert_eq!(
+            sandbox,
+            Sandbox {
+                uid: 1000,
+                gid: 100,
+                namespaces: true,
+                seccomp: true,
+            }
+        );
+    }
+}
@@ -4,7 +4,15 @@ use std::{
     net::SocketAddr,
 };
 
-use elafry::types::communication::Message;
+use elafry::types::{communication::Message, management::CHANNEL};
+
+use crate::recording::{Entry, Player, Recorder, MAX_LENGTH};
+
+/// Channels messages without a route are counted on separately.
+pub const MAX_UNROUTED_CHANNELS: usize = 64;
+
+/// Longest message a component may send, the header and as much data as is recorded.
+pub const MAX_MESSAGE_LENGTH: usize = MAX_LENGTH + 5;
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
 pub struct RouteEndpoint {
@@ -17,12 +25,52 @@ pub enum Endpoint {
     Component(uuid::Uuid),
     Address(SocketAddr),
     Runner,
+    /// A client connected to the control socket.
+    Control(u64),
+}
+
+impl std::fmt::Display for RouteEndpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.endpoint {
+            Endpoint::Component(id) => write!(f, "component:{}/{}", id, self.channel_id),
+            Endpoint::Address(address) => write!(f, "address:{}/{}", address, self.channel_id),
+            Endpoint::Runner => write!(f, "runner/{}", self.channel_id),
+            Endpoint::Control(client) => write!(f, "control:{}/{}", client, self.channel_id),
+        }
+    }
+}
+
+/// Message counts for one route.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct RouteStats {
+    pub routed: u64,
+    pub dropped: u64,
+}
+
+/// A routed message waiting to be written to its target.
+struct Envelope {
+    source: RouteEndpoint,
+    target: RouteEndpoint,
+    message: Message,
 }
 
 pub struct CommunicationService {
     udp_socket: std::net::UdpSocket,
-    component_exit_buffer: HashMap<uuid::Uuid, Vec<Message>>,
-    address_exit_buffer: HashMap<SocketAddr, Vec<Message>>,
+    component_exit_buffer: HashMap<uuid::Uuid, Vec<Envelope>>,
+    address_exit_buffer: HashMap<SocketAddr, Vec<Envelope>>,
+    control: Option<crate::control::Control>,
+    route_stats: HashMap<(RouteEndpoint, RouteEndpoint), RouteStats>,
+    /// Messages dropped for lack of a route, by the channel they were sent on.
+    unrouted: HashMap<Option<u32>, u64>,
+    /// Bytes of oversize messages still to be read past, by component.
+    skipping: HashMap<uuid::Uuid, u64>,
+    /// Where routed messages are recorded, if they are.
+    recorder: Option<Recorder>,
+    /// Recording played to a component in place of its live producers.
+    player: Option<Player>,
+    /// Frame being run and when it started, stamped on recorded messages.
+    frame: u64,
+    frame_start: std::time::Duration,
 }
 
 impl CommunicationService {
@@ -34,11 +82,69 @@ impl CommunicationService {
             udp_socket,
             component_exit_buffer: HashMap::new(),
             address_exit_buffer: HashMap::new(),
+            control: None,
+            route_stats: HashMap::new(),
+            unrouted: HashMap::new(),
+            skipping: HashMap::new(),
+            recorder: None,
+            player: None,
+            frame: 0,
+            frame_start: std::time::Duration::ZERO,
         }
     }
 
+    /// Accept management requests from the control socket as well as over routes.
+    pub fn attach_control(&mut self, control: crate::control::Control) {
+        self.control = Some(control);
+    }
+
+    /// Record every routed message.
+    pub fn attach_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Play a recording to a component, messages routed to it are dropped instead.
+    pub fn attach_player(&mut self, player: Player) {
+        self.player = Some(player);
+    }
+
+    /// Set the frame the following runs belong to and when it started.
+    pub fn set_frame(&mut self, frame: u64, frame_start: std::time::Duration) {
+        self.frame = frame;
+        self.frame_start = frame_start;
+    }
+
+    /// Finish writing replies to control clients and the recording before the runner exits.
+    pub fn close(&mut self) {
+        if let Some(control) = &mut self.control {
+            control.close();
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.close();
+        }
+    }
+
+    /// Counts per source and target, kept while the route exists.
+    pub fn route_stats(&self) -> &HashMap<(RouteEndpoint, RouteEndpoint), RouteStats> {
+        &self.route_stats
+    }
+
+    /// Messages dropped for lack of a route by channel, channels past the
+    /// first `MAX_UNROUTED_CHANNELS` are counted together under none.
+    pub fn unrouted(&self) -> &HashMap<Option<u32>, u64> {
+        &self.unrouted
+    }
+
     pub fn run(&mut self, state: &mut crate::global_state::GlobalState) {
+        // forget the counts of routes that are gone
+        for route in state.removed_routes.drain(..) {
+            self.route_stats.remove(&route);
+        }
         // check for data on components
+        if !self.skipping.is_empty() {
+            self.skipping
+                .retain(|id, _| state.components.contains_key(id));
+        }
         for (id, component) in state.components.iter_mut() {
             let mut length_buf = [0; 4];
 
@@ -56,6 +162,16 @@ impl CommunicationService {
                             break;
                         }
 
+                        // read past the rest of an oversize message first
+                        if let Some(remaining) = self.skipping.get(id) {
+                            let remaining = skip(&mut implentation.data_socket.socket, *remaining);
+                            if remaining > 0 {
+                                self.skipping.insert(*id, remaining);
+                                break;
+                            }
+                            self.skipping.remove(id);
+                        }
+
                         match implentation.data_socket.socket.read_exact(&mut length_buf) {
                             Ok(_) => {
                                 // get length of message
@@ -66,6 +182,19 @@ impl CommunicationService {
                                     continue;
                                 }
 
+                                // don't allocate what the component asks for past the limit
+                                if length as usize > MAX_MESSAGE_LENGTH {
+                                    log::error!(
+                                        "Message of {} bytes from component {} over {}",
+                                        length,
+                                        id,
+                                        MAX_MESSAGE_LENGTH
+                                    );
+                                    component.faults += 1;
+                                    self.skipping.insert(*id, length as u64);
+                                    continue;
+                                }
+
                                 // create buffer with length
                                 let message_buf = {
                                     let mut buf = vec![0; length as usize];
@@ -90,62 +219,11 @@ impl CommunicationService {
                                     }
                                 };
 
-                                let destination: Option<RouteEndpoint>;
-                                {
-                                    destination = state
-                                        .routes
-                                        .get(&RouteEndpoint {
-                                            endpoint: Endpoint::Component(*id),
-                                            channel_id: message.channel_id,
-                                        })
-                                        .cloned();
-                                }
-
-                                match destination {
-                                    Some(destination) => {
-                                        // insert the message into the correct buffer
-                                        match destination.endpoint {
-                                            Endpoint::Component(id) => {
-                                                match self.component_exit_buffer.get_mut(&id) {
-                                                    Some(buffer) => {
-                                                        buffer.push(message);
-                                                    }
-                                                    None => {
-                                                        self.component_exit_buffer
-                                                            .insert(id, vec![message]);
-                                                    }
-                                                }
-                                            }
-                                            Endpoint::Address(address) => {
-                                                match self.address_exit_buffer.get_mut(&address) {
-                                                    Some(buffer) => {
-                                                        buffer.push(message);
-                                                    }
-                                                    None => {
-                                                        self.address_exit_buffer
-                                                            .insert(address, vec![message]);
-                                                    }
-                                                }
-                                            }
-                                            Endpoint::Runner => {
-                                                state
-                                                    .messages
-                                                    .entry(destination.channel_id)
-                                                    .or_insert(Vec::new())
-                                                    .push(message);
-                                            }
-                                        }
-                                    }
-                                    None => {
-                                        log::warn!(
-                                            "No route found for: {:?}",
-                                            RouteEndpoint {
-                                                endpoint: Endpoint::Component(*id),
-                                                channel_id: message.channel_id,
-                                            }
-                                        );
-                                    }
-                                }
+                                let source = RouteEndpoint {
+                                    endpoint: Endpoint::Component(*id),
+                                    channel_id: message.channel_id,
+                                };
+                                self.route(&state.routes, &mut state.messages, source, message);
                             }
                             Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
                                 break;
@@ -157,6 +235,8 @@ impl CommunicationService {
                         }
                     }
                     None => {
+                        // the process is gone, a restart gets new sockets
+                        self.skipping.remove(id);
                         break;
                     }
                 }
@@ -164,11 +244,16 @@ impl CommunicationService {
         }
 
         // check for data on udp socket
-        for _ in 0..state.total_components() * 5 {
+        for _ in 0..(state.total_components() + 1) * 5 {
             let mut udp_buf = [0; 1024];
 
             match self.udp_socket.recv_from(&mut udp_buf) {
-                Ok((_, address)) => {
+                Ok((size, address)) => {
+                    if size < 4 {
+                        log::error!("Datagram from {} too short for a message", address);
+                        continue;
+                    }
+
                     // get length of message
                     let mut length_buf = [0; 4];
                     length_buf.copy_from_slice(&udp_buf[0..4]);
@@ -185,15 +270,19 @@ impl CommunicationService {
                         continue;
                     }
 
-                    // create buffer with length
-                    let message_buf = {
-                        let mut buf = vec![0; length as usize];
-                        buf.copy_from_slice(&udp_buf[4..length as usize + 4]);
-                        buf
-                    };
+                    // larger messages are cut off, uploads are sent in chunks instead
+                    if length as usize + 4 > size {
+                        log::error!(
+                            "Message of {} bytes from {} does not fit a {} byte datagram",
+                            length,
+                            address,
+                            size
+                        );
+                        continue;
+                    }
 
                     // deserialize message
-                    let message: Message = match Message::decode(&message_buf) {
+                    let message: Message = match Message::decode(&udp_buf[4..length as usize + 4]) {
                         Some(message) => message,
                         None => {
                             log::error!("Failed to decode message");
@@ -201,60 +290,11 @@ impl CommunicationService {
                         }
                     };
 
-                    let destination: Option<RouteEndpoint>;
-                    {
-                        destination = state
-                            .routes
-                            .get(&RouteEndpoint {
-                                endpoint: Endpoint::Address(address),
-                                channel_id: message.channel_id,
-                            })
-                            .cloned();
-                    }
-
-                    match destination {
-                        Some(destination) => {
-                            // insert the message into the correct buffer
-                            match destination.endpoint {
-                                Endpoint::Component(id) => {
-                                    match self.component_exit_buffer.get_mut(&id) {
-                                        Some(buffer) => {
-                                            buffer.push(message);
-                                        }
-                                        None => {
-                                            self.component_exit_buffer.insert(id, vec![message]);
-                                        }
-                                    }
-                                }
-                                Endpoint::Address(address) => {
-                                    match self.address_exit_buffer.get_mut(&address) {
-                                        Some(buffer) => {
-                                            buffer.push(message);
-                                        }
-                                        None => {
-                                            self.address_exit_buffer.insert(address, vec![message]);
-                                        }
-                                    }
-                                }
-                                Endpoint::Runner => {
-                                    state
-                                        .messages
-                                        .entry(message.channel_id)
-                                        .or_insert(Vec::new())
-                                        .push(message);
-                                }
-                            }
-                        }
-                        None => {
-                            log::warn!(
-                                "No route found for: {:?}",
-                                RouteEndpoint {
-                                    endpoint: Endpoint::Address(address),
-                                    channel_id: message.channel_id,
-                                }
-                            );
-                        }
-                    }
+                    let source = RouteEndpoint {
+                        endpoint: Endpoint::Address(address),
+                        channel_id: message.channel_id,
+                    };
+                    self.route(&state.routes, &mut state.messages, source, message);
                 }
                 Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
                     break;
@@ -266,25 +306,78 @@ impl CommunicationService {
             }
         }
 
+        // control requests always go to the management channel
+        let requests = match &mut self.control {
+            Some(control) => control.receive(),
+            None => Vec::new(),
+        };
+        for (client, data) in requests {
+            let source = RouteEndpoint {
+                endpoint: Endpoint::Control(client),
+                channel_id: CHANNEL,
+            };
+            let target = RouteEndpoint {
+                endpoint: Endpoint::Runner,
+                channel_id: CHANNEL,
+            };
+            let message = Message {
+                channel_id: CHANNEL,
+                count: 0,
+                data,
+            };
+            self.queue(&mut state.messages, source, target, message);
+        }
+
+        // and of control clients that have disconnected
+        let disconnected = match &mut self.control {
+            Some(control) => control.disconnected(),
+            None => Vec::new(),
+        };
+        for client in disconnected {
+            let endpoint = Endpoint::Control(client);
+            self.route_stats.retain(|(source, target), _| {
+                source.endpoint != endpoint && target.endpoint != endpoint
+            });
+        }
+
+        // queue messages sent by the runner itself
+        for (source, target, message) in state.outbox.drain(..) {
+            self.queue(&mut state.messages, source, target, message);
+        }
+
+        // queue recorded messages due in this frame
+        if let Some(player) = &mut self.player {
+            let component_id = player.component_id();
+            let running = state
+                .components
+                .get(&component_id)
+                .is_some_and(|component| component.run && component.implentation.is_some());
+            for (source, message) in player.due(self.frame, running) {
+                let target = RouteEndpoint {
+                    endpoint: Endpoint::Component(component_id),
+                    channel_id: message.channel_id,
+                };
+                self.deliver(&mut state.messages, source, target, message);
+            }
+        }
+
         // check for data to send to clear the exit component buffer
         for (id, component) in state.components.iter_mut() {
-            let messages = match self.component_exit_buffer.get(id) {
-                Some(messages) => messages,
+            let envelopes = match self.component_exit_buffer.remove(id) {
+                Some(envelopes) => envelopes,
                 None => {
                     continue;
                 }
             };
 
-            // check if stoped
-            if !component.run {
-                // clear buffer
-                self.component_exit_buffer.remove(id);
-
-                continue;
-            }
+            for envelope in envelopes.iter() {
+                // drop if stoped
+                if !component.run {
+                    count_dropped(&mut self.route_stats, envelope);
+                    continue;
+                }
 
-            for message in messages.iter() {
-                let message_buf = Message::encode(message);
+                let message_buf = Message::encode(&envelope.message);
 
                 let length = message_buf.len() as u32;
 
@@ -298,23 +391,29 @@ impl CommunicationService {
                             Ok(_) => {}
                             Err(e) => {
                                 log::error!("Failed to write to socket; err = {:?}", e);
+                                count_dropped(&mut self.route_stats, envelope);
                             }
                         }
                     }
                     None => {
                         log::error!("No implementation found for component: {:?}", id);
-                        continue;
+                        count_dropped(&mut self.route_stats, envelope);
                     }
                 }
             }
+        }
 
-            self.component_exit_buffer.remove(id);
+        // messages to components that no longer exist are dropped
+        for (_, envelopes) in self.component_exit_buffer.drain() {
+            for envelope in envelopes.iter() {
+                count_dropped(&mut self.route_stats, envelope);
+            }
         }
 
         // check for data to send to clear the exit address buffer
-        for (address, messages) in self.address_exit_buffer.iter() {
-            for message in messages.iter() {
-                let message_buf = Message::encode(message);
+        for (address, envelopes) in self.address_exit_buffer.drain() {
+            for envelope in envelopes.iter() {
+                let message_buf = Message::encode(&envelope.message);
 
                 let length = message_buf.len() as u32;
 
@@ -326,14 +425,154 @@ impl CommunicationService {
                     Ok(_) => {}
                     Err(e) => {
                         log::error!("Failed to write to socket; err = {:?}", e);
+                        count_dropped(&mut self.route_stats, envelope);
                     }
                 }
             }
         }
-        self.address_exit_buffer.clear();
+    }
+
+    fn route(
+        &mut self,
+        routes: &HashMap<RouteEndpoint, Vec<RouteEndpoint>>,
+        runner_messages: &mut HashMap<u32, Vec<(RouteEndpoint, Message)>>,
+        source: RouteEndpoint,
+        message: Message,
+    ) {
+        let targets = match routes.get(&source) {
+            Some(targets) if !targets.is_empty() => targets,
+            _ => {
+                log::warn!("No route found for: {:?}", source);
+                // counted by channel, sources without a route are not kept
+                let channel = Some(source.channel_id).filter(|channel| {
+                    self.unrouted.contains_key(&Some(*channel))
+                        || self.unrouted.len() < MAX_UNROUTED_CHANNELS
+                });
+                *self.unrouted.entry(channel).or_default() += 1;
+                return;
+            }
+        };
+
+        // every target gets its own copy, the last one takes the message
+        let (last, rest) = targets.split_last().unwrap();
+        for target in rest {
+            self.queue(runner_messages, source, *target, message.clone());
+        }
+        self.queue(runner_messages, source, *last, message);
+    }
+
+    fn queue(
+        &mut self,
+        runner_messages: &mut HashMap<u32, Vec<(RouteEndpoint, Message)>>,
+        source: RouteEndpoint,
+        target: RouteEndpoint,
+        message: Message,
+    ) {
+        // the component being replayed to only gets the recording
+        if self
+            .player
+            .as_ref()
+            .is_some_and(|player| player.replaces(&target))
+        {
+            self.route_stats
+                .entry((source, target))
+                .or_default()
+                .dropped += 1;
+            return;
+        }
+
+        self.deliver(runner_messages, source, target, message);
+    }
+
+    fn deliver(
+        &mut self,
+        runner_messages: &mut HashMap<u32, Vec<(RouteEndpoint, Message)>>,
+        source: RouteEndpoint,
+        target: RouteEndpoint,
+        message: Message,
+    ) {
+        self.route_stats.entry((source, target)).or_default().routed += 1;
+
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(Entry {
+                frame: self.frame,
+                time: self.frame_start,
+                source,
+                target,
+                message: message.clone(),
+            });
+        }
+
+        // insert the message into the correct buffer
+        match target.endpoint {
+            Endpoint::Component(id) => {
+                self.component_exit_buffer
+                    .entry(id)
+                    .or_default()
+                    .push(Envelope {
+                        source,
+                        target,
+                        message,
+                    });
+            }
+            Endpoint::Address(address) => {
+                self.address_exit_buffer
+                    .entry(address)
+                    .or_default()
+                    .push(Envelope {
+                        source,
+                        target,
+                        message,
+                    });
+            }
+            Endpoint::Control(client) => match &mut self.control {
+                Some(control) => control.send(client, message.data),
+                None => {
+                    log::error!("No control socket for client: {}", client);
+                    self.route_stats
+                        .entry((source, target))
+                        .or_default()
+                        .dropped += 1;
+                }
+            },
+            Endpoint::Runner => {
+                runner_messages
+                    .entry(target.channel_id)
+                    .or_default()
+                    .push((source, message));
+            }
+        }
     }
 }
 
+/// Read past up to `remaining` bytes without keeping them, returning how many are left.
+fn skip(socket: &mut std::os::unix::net::UnixStream, mut remaining: u64) -> u64 {
+    let mut buf = [0; 4096];
+    while remaining > 0 {
+        let length = remaining.min(buf.len() as u64) as usize;
+        match socket.read(&mut buf[..length]) {
+            Ok(0) => break,
+            Ok(read) => remaining -= read as u64,
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+            Err(e) => {
+                log::error!("Failed to read from socket; err = {:?}", e);
+                break;
+            }
+        }
+    }
+    remaining
+}
+
+fn count_dropped(
+    route_stats: &mut HashMap<(RouteEndpoint, RouteEndpoint), RouteStats>,
+    envelope: &Envelope,
+) {
+    route_stats
+        .entry((envelope.source, envelope.target))
+        .or_default()
+        .dropped += 1;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -358,25 +597,30 @@ mod tests {
         let mut state = crate::global_state::GlobalState::new();
         let mut communication_service = CommunicationService::new(5000);
         let id = uuid::Uuid::new_v4();
-        state.add_component(id, "test".to_string(), 1);
-        state.add_component_implementation(
-            id,
-            crate::global_state::Implementation {
-                data_socket: crate::global_state::Socket {
-                    socket: socket.try_clone().unwrap(),
-                    count: 0,
+        state.add_component(id, "test".to_string(), 1, "1.0.0".to_string());
+        state
+            .add_component_implementation(
+                id,
+                crate::global_state::Implementation {
+                    data_socket: crate::global_state::Socket {
+                        socket: socket.try_clone().unwrap(),
+                        count: 0,
+                    },
+                    state_socket: crate::global_state::Socket {
+                        socket: socket.try_clone().unwrap(),
+                        count: 0,
+                    },
+                    child: std::process::Command::new("sleep")
+                        .arg("1")
+                        .spawn()
+                        .unwrap(),
+                    child_pid: 1,
+                    schedstat: None,
+                    cgroup: None,
                 },
-                state_socket: crate::global_state::Socket {
-                    socket: socket.try_clone().unwrap(),
-                    count: 0,
-                },
-                child: std::process::Command::new("sleep")
-                    .arg("1")
-                    .spawn()
-                    .unwrap(),
-                child_pid: 1,
-            },
-        );
+            )
+            .unwrap();
+        state.start_component(id).unwrap();
 
         communication_service.run(&mut state);
 
@@ -406,9 +650,156 @@ mod tests {
 
         communication_service.run(&mut state);
 
-        let message = state.get_message(2).unwrap();
+        let (_, message) = state.get_message(2).unwrap();
 
         assert_eq!(message.data, vec![1, 2, 3]);
+
+        // send a message on a channel without a route
+        let message_buf = Message::encode(&Message {
+            count: 2,
+            channel_id: 3,
+            data: vec![4],
+        });
+        let mut length_buf = (message_buf.len() as u32).to_be_bytes().to_vec();
+        length_buf.extend_from_slice(&message_buf);
+        stream.write_all(&length_buf).unwrap();
+
+        communication_service.run(&mut state);
+
+        let source = RouteEndpoint {
+            endpoint: Endpoint::Component(id),
+            channel_id: 1,
+        };
+        let target = RouteEndpoint {
+            endpoint: Endpoint::Runner,
+            channel_id: 2,
+        };
+        assert_eq!(
+            communication_service.route_stats()[&(source, target)],
+            RouteStats {
+                routed: 1,
+                dropped: 0
+            }
+        );
+
+        // messages without a route are counted by channel, not by source
+        assert_eq!(communication_service.unrouted()[&Some(3)], 1);
+        assert_eq!(communication_service.route_stats().len(), 1);
+
+        // and a route's counts go with it
+        assert!(state.remove_route(source, target));
+        communication_service.run(&mut state);
+        assert!(communication_service.route_stats().is_empty());
+    }
+
+    #[test]
+    fn test_communication_oversize_message() {
+        setup();
+
+        let (socket, child_socket) = std::os::unix::net::UnixStream::pair().unwrap();
+        socket.set_nonblocking(true).unwrap();
+
+        let mut state = crate::global_state::GlobalState::new();
+        let mut communication_service = CommunicationService::new(0);
+        let id = uuid::Uuid::new_v4();
+        state.add_component(id, "test".to_string(), 1, "1.0.0".to_string());
+        state
+            .add_component_implementation(
+                id,
+                crate::global_state::Implementation {
+                    data_socket: crate::global_state::Socket {
+                        socket: socket.try_clone().unwrap(),
+                        count: 0,
+                    },
+                    state_socket: crate::global_state::Socket {
+                        socket: socket.try_clone().unwrap(),
+                        count: 0,
+                    },
+                    child: std::process::Command::new("sleep")
+                        .arg("1")
+                        .spawn()
+                        .unwrap(),
+                    child_pid: 1,
+                    schedstat: None,
+                    cgroup: None,
+                },
+            )
+            .unwrap();
+        state.start_component(id).unwrap();
+        state.add_route(
+            RouteEndpoint {
+                endpoint: Endpoint::Component(id),
+                channel_id: 1,
+            },
+            RouteEndpoint {
+                endpoint: Endpoint::Runner,
+                channel_id: 2,
+            },
+        );
+
+        // a message over the limit, then one within it
+        let writer = std::thread::spawn(move || {
+            let mut stream = child_socket;
+            let oversize = vec![0; MAX_MESSAGE_LENGTH + 1];
+            stream
+                .write_all(&(oversize.len() as u32).to_be_bytes())
+                .unwrap();
+            stream.write_all(&oversize).unwrap();
+            let message_buf = Message::encode(&Message {
+                count: 1,
+                channel_id: 1,
+                data: vec![1, 2, 3],
+            });
+            stream
+                .write_all(&(message_buf.len() as u32).to_be_bytes())
+                .unwrap();
+            stream.write_all(&message_buf).unwrap();
+        });
+
+        // the oversize message is read past, not buffered, and counted as a fault
+        let mut message = None;
+        for _ in 0..1000 {
+            communication_service.run(&mut state);
+            message = state.get_message(2);
+            if message.is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        writer.join().unwrap();
+
+        assert_eq!(message.unwrap().1.data, vec![1, 2, 3]);
+        assert_eq!(state.components[&id].faults, 1);
+        assert!(communication_service.skipping.is_empty());
+    }
+
+    #[test]
+    fn test_communication_unrouted_channels() {
+        setup();
+
+        let mut communication_service = CommunicationService::new(0);
+        let routes = HashMap::new();
+        let mut messages = HashMap::new();
+        for channel_id in 0..MAX_UNROUTED_CHANNELS as u32 + 10 {
+            let source = RouteEndpoint {
+                endpoint: Endpoint::Address(([127, 0, 0, 1], channel_id as u16).into()),
+                channel_id,
+            };
+            let message = Message {
+                channel_id,
+                count: 0,
+                data: vec![],
+            };
+            communication_service.route(&routes, &mut messages, source, message.clone());
+            communication_service.route(&routes, &mut messages, source, message);
+        }
+
+        // channels past the limit are counted together
+        let unrouted = communication_service.unrouted();
+        assert_eq!(unrouted.len(), MAX_UNROUTED_CHANNELS + 1);
+        assert_eq!(unrouted[&Some(0)], 2);
+        assert_eq!(unrouted[&None], 20);
+        assert!(communication_service.route_stats().is_empty());
     }
 
     #[test]
@@ -416,55 +807,67 @@ mod tests {
         setup();
 
         let (socket_1, child_socket_1) = std::os::unix::net::UnixStream::pair().unwrap();
+        socket_1.set_nonblocking(true).unwrap();
         child_socket_1.set_nonblocking(true).unwrap();
 
         let (socket_2, child_socket_2) = std::os::unix::net::UnixStream::pair().unwrap();
+        socket_2.set_nonblocking(true).unwrap();
         child_socket_2.set_nonblocking(true).unwrap();
 
         let mut state = crate::global_state::GlobalState::new();
         let mut communication_service = CommunicationService::new(5001);
 
         let id_1 = uuid::Uuid::new_v4();
-        state.add_component(id_1, "test".to_string(), 1);
-        state.add_component_implementation(
-            id_1,
-            crate::global_state::Implementation {
-                data_socket: crate::global_state::Socket {
-                    socket: socket_1.try_clone().unwrap(),
-                    count: 0,
-                },
-                state_socket: crate::global_state::Socket {
-                    socket: socket_1.try_clone().unwrap(),
-                    count: 0,
+        state.add_component(id_1, "test".to_string(), 1, "1.0.0".to_string());
+        state
+            .add_component_implementation(
+                id_1,
+                crate::global_state::Implementation {
+                    data_socket: crate::global_state::Socket {
+                        socket: socket_1.try_clone().unwrap(),
+                        count: 0,
+                    },
+                    state_socket: crate::global_state::Socket {
+                        socket: socket_1.try_clone().unwrap(),
+                        count: 0,
+                    },
+                    child: std::process::Command::new("sleep")
+                        .arg("1")
+                        .spawn()
+                        .unwrap(),
+                    child_pid: 1,
+                    schedstat: None,
+                    cgroup: None,
                 },
-                child: std::process::Command::new("sleep")
-                    .arg("1")
-                    .spawn()
-                    .unwrap(),
-                child_pid: 1,
-            },
-        );
+            )
+            .unwrap();
 
         let id_2 = uuid::Uuid::new_v4();
-        state.add_component(id_2, "test".to_string(), 1);
-        state.add_component_implementation(
-            id_2,
-            crate::global_state::Implementation {
-                data_socket: crate::global_state::Socket {
-                    socket: socket_2.try_clone().unwrap(),
-                    count: 0,
-                },
-                state_socket: crate::global_state::Socket {
-                    socket: socket_2.try_clone().unwrap(),
-                    count: 0,
+        state.add_component(id_2, "test".to_string(), 1, "1.0.0".to_string());
+        state
+            .add_component_implementation(
+                id_2,
+                crate::global_state::Implementation {
+                    data_socket: crate::global_state::Socket {
+                        socket: socket_2.try_clone().unwrap(),
+                        count: 0,
+                    },
+                    state_socket: crate::global_state::Socket {
+                        socket: socket_2.try_clone().unwrap(),
+                        count: 0,
+                    },
+                    child: std::process::Command::new("sleep")
+                        .arg("1")
+                        .spawn()
+                        .unwrap(),
+                    child_pid: 1,
+                    schedstat: None,
+                    cgroup: None,
                 },
-                child: std::process::Command::new("sleep")
-                    .arg("1")
-                    .spawn()
-                    .unwrap(),
-                child_pid: 1,
-            },
-        );
+            )
+            .unwrap();
+        state.start_component(id_1).unwrap();
+        state.start_component(id_2).unwrap();
 
         communication_service.run(&mut state);
 
@@ -519,25 +922,30 @@ mod tests {
         let mut state = crate::global_state::GlobalState::new();
         let mut communication_service = CommunicationService::new(5002);
         let id = uuid::Uuid::new_v4();
-        state.add_component(id, "test".to_string(), 1);
-        state.add_component_implementation(
-            id,
-            crate::global_state::Implementation {
-                data_socket: crate::global_state::Socket {
-                    socket: socket.try_clone().unwrap(),
-                    count: 0,
+        state.add_component(id, "test".to_string(), 1, "1.0.0".to_string());
+        state
+            .add_component_implementation(
+                id,
+                crate::global_state::Implementation {
+                    data_socket: crate::global_state::Socket {
+                        socket: socket.try_clone().unwrap(),
+                        count: 0,
+                    },
+                    state_socket: crate::global_state::Socket {
+                        socket: socket.try_clone().unwrap(),
+                        count: 0,
+                    },
+                    child: std::process::Command::new("sleep")
+                        .arg("1")
+                        .spawn()
+                        .unwrap(),
+                    child_pid: 1,
+                    schedstat: None,
+                    cgroup: None,
                 },
-                state_socket: crate::global_state::Socket {
-                    socket: socket.try_clone().unwrap(),
-                    count: 0,
-                },
-                child: std::process::Command::new("sleep")
-                    .arg("1")
-                    .spawn()
-                    .unwrap(),
-                child_pid: 1,
-            },
-        );
+            )
+            .unwrap();
+        state.start_component(id).unwrap();
 
         communication_service.run(&mut state);
 
@@ -573,7 +981,7 @@ mod tests {
         let length_buf = &udp_buf[0..4];
         let length = u32::from_be_bytes(length_buf.try_into().unwrap());
         let message_buf = &udp_buf[4..length as usize + 4];
-        let message = Message::decode(&message_buf.to_vec()).unwrap();
+        let message = Message::decode(message_buf).unwrap();
 
         assert_eq!(message.data, vec![1, 2, 3]);
     }
@@ -582,7 +990,7 @@ mod tests {
     fn test_communication_address_to_runner() {
         setup();
 
-        let udp_socket = std::net::UdpSocket::bind("127.0.0.0:0").unwrap();
+        let udp_socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
 
         let mut state = crate::global_state::GlobalState::new();
         let mut communication_service = CommunicationService::new(5003);
@@ -600,6 +1008,17 @@ mod tests {
             },
         );
 
+        // a message longer than the datagram buffer is dropped
+        let oversized = Message {
+            count: 1,
+            channel_id: 7,
+            data: vec![0; 2000],
+        };
+        let message_buf = Message::encode(&oversized);
+        let mut length_buf = (message_buf.len() as u32).to_be_bytes().to_vec();
+        length_buf.extend_from_slice(&message_buf);
+        udp_socket.send_to(&length_buf, "127.0.0.1:5003").unwrap();
+
         // send message
         let message = Message {
             count: 1,
@@ -610,14 +1029,199 @@ mod tests {
         let length = message_buf.len() as u32;
         let mut length_buf = length.to_be_bytes().to_vec();
         length_buf.append(&mut message_buf.clone());
-        udp_socket
-            .send_to(&length_buf, udp_socket.local_addr().unwrap())
-            .unwrap();
+        udp_socket.send_to(&length_buf, "127.0.0.1:5003").unwrap();
 
         communication_service.run(&mut state);
 
-        let message = state.get_message(8).unwrap();
+        let (source, message) = state.get_message(8).unwrap();
 
         assert_eq!(message.data, vec![1, 2, 3]);
+        assert!(state.get_message(8).is_none());
+        assert_eq!(
+            source,
+            RouteEndpoint {
+                endpoint: Endpoint::Address(udp_socket.local_addr().unwrap()),
+                channel_id: 7,
+            }
+        );
+    }
+
+    #[test]
+    fn test_communication_runner_to_address() {
+        setup();
+
+        let udp_socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let mut state = crate::global_state::GlobalState::new();
+        let mut communication_service = CommunicationService::new(5004);
+
+        // messages sent by the runner do not need a route
+        let target = RouteEndpoint {
+            endpoint: Endpoint::Address(udp_socket.local_addr().unwrap()),
+            channel_id: 7,
+        };
+        state.send_message(0, target, vec![4, 5]);
+
+        communication_service.run(&mut state);
+        assert!(state.outbox.is_empty());
+
+        let mut udp_buf = [0; 1024];
+        udp_socket.recv_from(&mut udp_buf).unwrap();
+        let length = u32::from_be_bytes([udp_buf[0], udp_buf[1], udp_buf[2], udp_buf[3]]);
+        let message = Message::decode(&udp_buf[4..length as usize + 4]).unwrap();
+
+        assert_eq!(message.channel_id, 7);
+        assert_eq!(message.data, vec![4, 5]);
+    }
+
+    #[test]
+    fn test_communication_record_replay() {
+        setup();
+
+        let (socket, child_socket) = std::os::unix::net::UnixStream::pair().unwrap();
+        socket.set_nonblocking(true).unwrap();
+        child_socket.set_nonblocking(true).unwrap();
+
+        let mut state = crate::global_state::GlobalState::new();
+        let mut communication_service = CommunicationService::new(5005);
+        let id = uuid::Uuid::new_v4();
+        state.add_component(id, "test".to_string(), 1, "1.0.0".to_string());
+        state
+            .add_component_implementation(
+                id,
+                crate::global_state::Implementation {
+                    data_socket: crate::global_state::Socket {
+                        socket: socket.try_clone().unwrap(),
+                        count: 0,
+                    },
+                    state_socket: crate::global_state::Socket {
+                        socket: socket.try_clone().unwrap(),
+                        count: 0,
+                    },
+                    child: std::process::Command::new("sleep")
+                        .arg("1")
+                        .spawn()
+                        .unwrap(),
+                    child_pid: 1,
+                    schedstat: None,
+                    cgroup: None,
+                },
+            )
+            .unwrap();
+
+        let path = std::env::temp_dir().join(format!("recording-{}.bin", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+        communication_service.attach_recorder(Recorder::new(path).unwrap());
+
+        // a message recorded to another component two frames apart from the next
+        let recorded = uuid::Uuid::new_v4();
+        let plant = RouteEndpoint {
+            endpoint: Endpoint::Address("127.0.0.1:7000".parse().unwrap()),
+            channel_id: 1,
+        };
+        let entry = |frame: u64, data: Vec<u8>| Entry {
+            frame,
+            time: std::time::Duration::from_millis(frame),
+            source: plant,
+            target: RouteEndpoint {
+                endpoint: Endpoint::Component(recorded),
+                channel_id: 2,
+            },
+            message: Message {
+                channel_id: 1,
+                count: 0,
+                data,
+            },
+        };
+        communication_service.attach_player(Player::new(
+            vec![entry(40, vec![1]), entry(42, vec![2])],
+            id,
+            recorded,
+        ));
+
+        // live messages to the component are dropped, others are routed
+        let target = RouteEndpoint {
+            endpoint: Endpoint::Component(id),
+            channel_id: 2,
+        };
+        let runner = RouteEndpoint {
+            endpoint: Endpoint::Runner,
+            channel_id: 8,
+        };
+        let read = || {
+            let mut stream = child_socket.try_clone().unwrap();
+            let mut length_buf = [0; 4];
+            match stream.read_exact(&mut length_buf) {
+                Ok(_) => {}
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => return None,
+                Err(e) => panic!("{}", e),
+            }
+            let mut message_buf = vec![0; u32::from_be_bytes(length_buf) as usize];
+            stream.read_exact(&mut message_buf).unwrap();
+            Message::decode(&message_buf)
+        };
+
+        // nothing is replayed before the component runs
+        communication_service.set_frame(0, std::time::Duration::ZERO);
+        communication_service.run(&mut state);
+        assert_eq!(read(), None);
+
+        state.start_component(id).unwrap();
+        for frame in 1..4 {
+            communication_service.set_frame(frame, std::time::Duration::from_millis(frame));
+            state.send_message(0, target, vec![9]);
+            state.send_message(0, runner, vec![frame as u8]);
+            communication_service.run(&mut state);
+
+            match frame {
+                1 => assert_eq!(read().unwrap().data, vec![1]),
+                3 => {
+                    let message = read().unwrap();
+                    assert_eq!(message.channel_id, 2);
+                    assert_eq!(message.data, vec![2]);
+                }
+                _ => {}
+            }
+            assert_eq!(read(), None);
+        }
+
+        let source = RouteEndpoint {
+            endpoint: Endpoint::Runner,
+            channel_id: 0,
+        };
+        assert_eq!(
+            communication_service.route_stats()[&(source, target)],
+            RouteStats {
+                routed: 0,
+                dropped: 3
+            }
+        );
+        assert_eq!(
+            communication_service.route_stats()[&(plant, target)],
+            RouteStats {
+                routed: 2,
+                dropped: 0
+            }
+        );
+
+        // the replayed and routed messages are recorded, the dropped ones are not
+        communication_service.close();
+        let entries = crate::recording::read_file(path).unwrap();
+        let recorded: Vec<(u64, RouteEndpoint, RouteEndpoint, Vec<u8>)> = entries
+            .into_iter()
+            .map(|entry| (entry.frame, entry.source, entry.target, entry.message.data))
+            .collect();
+        assert_eq!(
+            recorded,
+            vec![
+                (1, source, runner, vec![1]),
+                (1, plant, target, vec![1]),
+                (2, source, runner, vec![2]),
+                (3, source, runner, vec![3]),
+                (3, plant, target, vec![2]),
+            ]
+        );
+
+        std::fs::remove_file(path).unwrap();
     }
 }
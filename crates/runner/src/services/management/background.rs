@@ -2,31 +2,103 @@ use std::collections::HashMap;
 // use std::io::{Read};
 use std::os::fd::{FromRawFd, IntoRawFd, OwnedFd};
 use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
 use std::process::{Command, Stdio};
 use std::sync::mpsc::Sender;
 use std::sync::{mpsc, Arc, Mutex};
 
 use command_fds::{CommandFdExt, FdMapping};
-use elafry::types::configuration::Configuration;
+use elafry::loader::format::Format;
+use elafry::loader::Signed;
+use elafry::signature::VerifyingKey;
+use elafry::types::configuration::{Action, Configuration, Limits, NonBlockingData};
+use elafry::types::desired::{DesiredState, Document};
+use elafry::types::management::{ComponentVersion, Error, InstallData, Reply};
 use uuid::Uuid;
 
+use crate::cgroup::{Cgroup, Cgroups};
 use crate::global_state::{Implementation, StateSyncStatus};
+use crate::sandbox::Sandbox;
+use crate::services::communication::RouteEndpoint;
+use crate::services::management::plan::{self, Current};
+use crate::services::management::registry::{self, Registry};
+use crate::services::management::transaction::{Transaction, Undo};
+use crate::services::management::validate::Model;
 use crate::services::management::ActionState;
+use crate::simulation::{Clock, CLOCK_FD};
 
 pub enum NonBlockingImplementationData {
     LoadConfiguration(LoadConfiguration),
     AddComponent(AddComponentImplementation),
+    /// Spawn a new process for a component whose process exited.
+    RestartComponent(AddComponentImplementation),
     RemoveComponent(RemoveComponentImplementation),
+    ChangeRegistry(ChangeRegistry),
+    Validate(ValidateConfiguration),
+    Plan(PlanDesired),
 }
 
 pub struct LoadConfiguration {
-    pub path: String,
+    pub source: Source,
+    /// Keys the configuration must be signed with, empty to load it unsigned.
+    pub trusted_keys: Vec<VerifyingKey>,
+    /// What a desired state is planned from, nothing else reconfigures the system while loading.
+    pub current: Current,
+}
+
+/// A configuration read by the background thread.
+pub enum Loaded {
+    Tasks(Configuration),
+    /// Planned from a desired state, with no tasks when the system is already in it.
+    Planned(Configuration),
+}
+
+/// Where a configuration is read from.
+#[derive(Clone)]
+pub enum Source {
+    /// A configuration file.
+    File(std::path::PathBuf),
+    /// A document uploaded in chunks.
+    Upload(Vec<u8>),
+    /// A desired state sent inline, it carries no signature.
+    Desired(DesiredState),
 }
 
 pub struct AddComponentImplementation {
     pub component_id: Uuid,
     pub component: String,
     pub core: usize,
+    pub version: String,
+    pub sha256: Option<String>,
+    pub limits: Limits,
+}
+
+/// Install or remove a component version, the outcome is sent to the requester.
+pub struct ChangeRegistry {
+    pub change: RegistryChange,
+    pub requester: (RouteEndpoint, u64),
+}
+
+/// Dry run a configuration, the report is sent to the requester.
+pub struct ValidateConfiguration {
+    pub model: Model,
+    pub configuration: Configuration,
+    pub requester: (RouteEndpoint, u64),
+}
+
+/// Plan the tasks that take the system to a desired state, the plan is sent to the requester.
+pub struct PlanDesired {
+    pub current: Current,
+    pub desired: DesiredState,
+    pub requester: (RouteEndpoint, u64),
+}
+
+/// Replies to requests the background thread handled and who to send them to.
+pub type Replies = Arc<Mutex<Vec<((RouteEndpoint, u64), Reply)>>>;
+
+pub enum RegistryChange {
+    Install(InstallData),
+    Uninstall(ComponentVersion),
 }
 
 pub struct RemoveComponentImplementation {
@@ -34,13 +106,21 @@ pub struct RemoveComponentImplementation {
     pub implementation: Implementation,
 }
 
-pub fn main(
-    receiver: mpsc::Receiver<()>,
-    non_blocking_actions: Arc<Mutex<Vec<NonBlockingImplementationData>>>,
-    done_configuration: Arc<Mutex<Option<Configuration>>>,
-    done_implement: Arc<Mutex<HashMap<Uuid, Implementation>>>,
-    done_remove: Arc<Mutex<Vec<Uuid>>>,
-) {
+pub(super) fn main(receiver: mpsc::Receiver<()>, data: super::BackgroundData) {
+    let super::BackgroundData {
+        actions: non_blocking_actions,
+        done_configuration,
+        done_implement,
+        done_remove,
+        registry,
+        registry_entries,
+        done_replies,
+        done_restart,
+        cgroups,
+        sandbox,
+        clock,
+    } = data;
+
     loop {
         log::debug!("Waiting for signal");
         match receiver.recv() {
@@ -53,15 +133,28 @@ pub fn main(
                 for action in non_blocking_actions.iter_mut() {
                     match action {
                         NonBlockingImplementationData::LoadConfiguration(data) => {
-                            log::debug!("Loading configuration {}", data.path);
-
-                            // read the configuration file
-                            let path = format!("configuration/{}", data.path);
-                            let file = std::fs::File::open(path).unwrap();
-                            let configuration: Result<
-                                elafry::types::configuration::Configuration,
-                                serde_yaml::Error,
-                            > = serde_yaml::from_reader(file);
+                            // read the configuration file or parse the upload
+                            let configuration = match &data.source {
+                                Source::File(path) => {
+                                    log::debug!("Loading configuration {}", path.display());
+                                    elafry::loader::signed_from_file(path)
+                                }
+                                Source::Upload(document) => {
+                                    log::debug!("Loading uploaded configuration");
+                                    load_upload(document)
+                                }
+                                Source::Desired(desired) => Ok(Signed {
+                                    document: Document::Desired(desired.clone()),
+                                    signature: None,
+                                }),
+                            }
+                            .and_then(|signed| verify(signed, &data.trusted_keys))
+                            .and_then(|document| match document {
+                                Document::Tasks(configuration) => Ok(Loaded::Tasks(configuration)),
+                                Document::Desired(desired) => {
+                                    plan::plan(&data.current, &desired).map(Loaded::Planned)
+                                }
+                            });
 
                             // get lock on done_configuration
                             let mut done_configuration = done_configuration.lock().unwrap();
@@ -72,17 +165,35 @@ pub fn main(
                             }
 
                             // set the configuration in the done_configuration
-                            *done_configuration = Some(configuration.unwrap());
+                            *done_configuration = Some(configuration);
                         }
                         NonBlockingImplementationData::AddComponent(data) => {
                             // get the implementation
-                            let implementation: Implementation =
-                                add_component_implementation(data.component.clone(), data.core);
+                            let implementation = spawn(
+                                data,
+                                registry.as_deref(),
+                                cgroups.as_deref(),
+                                sandbox.as_ref(),
+                                clock.as_deref(),
+                            );
 
                             // add the implementation to the list of done implementations
                             let mut done_implement = done_implement.lock().unwrap();
                             done_implement.insert(data.component_id, implementation);
                         }
+                        NonBlockingImplementationData::RestartComponent(data) => {
+                            let implementation = spawn(
+                                data,
+                                registry.as_deref(),
+                                cgroups.as_deref(),
+                                sandbox.as_ref(),
+                                clock.as_deref(),
+                            );
+                            done_restart
+                                .lock()
+                                .unwrap()
+                                .insert(data.component_id, implementation);
+                        }
                         NonBlockingImplementationData::RemoveComponent(data) => {
                             // remove the implementation
                             remove_component_implementation(&mut data.implementation);
@@ -91,6 +202,35 @@ pub fn main(
                             let mut done_remove = done_remove.lock().unwrap();
                             done_remove.push(data.component_id);
                         }
+                        NonBlockingImplementationData::ChangeRegistry(data) => {
+                            let reply = match &registry {
+                                Some(registry) => change_registry(registry, &data.change),
+                                None => Reply::Error(Error::NoRegistry),
+                            };
+                            // the frame loop lists the registry from this copy
+                            if let Some(registry) = &registry {
+                                *registry_entries.lock().unwrap() =
+                                    Some(registry.lock().unwrap().entries());
+                            }
+                            done_replies.lock().unwrap().push((data.requester, reply));
+                        }
+                        NonBlockingImplementationData::Validate(data) => {
+                            // components are checked against the registry as it is now
+                            let registry =
+                                registry.as_ref().map(|registry| registry.lock().unwrap());
+                            let issues = data.model.check(&data.configuration, registry.as_deref());
+                            done_replies
+                                .lock()
+                                .unwrap()
+                                .push((data.requester, Reply::Report(issues)));
+                        }
+                        NonBlockingImplementationData::Plan(data) => {
+                            let reply = match plan::plan(&data.current, &data.desired) {
+                                Ok(configuration) => Reply::Plan(configuration),
+                                Err(e) => Reply::Error(Error::LoadFailed(e)),
+                            };
+                            done_replies.lock().unwrap().push((data.requester, reply));
+                        }
                     }
                 }
 
@@ -99,88 +239,261 @@ pub fn main(
 
                 log::debug!("Done processing signal");
             }
-            Err(_) => {}
+            Err(_) => {
+                log::debug!("Management service dropped, stopping background thread");
+                break;
+            }
         }
     }
 }
 
+/// Parse an uploaded document, its format is guessed from the content.
+fn load_upload(document: &[u8]) -> Result<Signed, String> {
+    std::str::from_utf8(document)
+        .map_err(|e| format!("Upload is not utf-8; err = {}", e))
+        .and_then(|text| elafry::loader::signed_from_text(text, Format::sniff(text)))
+        .map_err(|e| format!("Failed to load upload; err = {}", e))
+}
+
+/// With trusted keys set, only configurations signed by one of them that give
+/// the hash of every component binary they add are loaded.
+fn verify(signed: Signed, trusted_keys: &[VerifyingKey]) -> Result<Document, String> {
+    if trusted_keys.is_empty() {
+        return Ok(signed.document);
+    }
+
+    let signature = signed
+        .signature
+        .as_ref()
+        .ok_or("Configuration is not signed")?;
+    elafry::signature::verify(&signed.document, signature, trusted_keys)
+        .map_err(|e| format!("Failed to verify signature; err = {}", e))?;
+
+    let unpinned = match &signed.document {
+        Document::Tasks(configuration) => configuration
+            .tasks
+            .iter()
+            .filter_map(|task| match &task.actions {
+                Action::NonBlocking(actions) => Some(actions),
+                Action::Blocking(_) => None,
+            })
+            .flatten()
+            .find_map(|action| match &action.data {
+                NonBlockingData::AddComponent(data) if data.sha256.is_none() => {
+                    Some(data.component_id)
+                }
+                _ => None,
+            }),
+        Document::Desired(desired) => desired
+            .components
+            .iter()
+            .find(|component| component.sha256.is_none())
+            .map(|component| component.component_id),
+    };
+    if let Some(component_id) = unpinned {
+        return Err(format!(
+            "Component {} does not give the sha256 of its binary",
+            component_id
+        ));
+    }
+
+    Ok(signed.document)
+}
+
+/// Find the binary of a component and spawn it in its own cgroup and sandbox.
+fn spawn(
+    data: &AddComponentImplementation,
+    registry: Option<&Mutex<Registry>>,
+    cgroups: Option<&Cgroups>,
+    sandbox: Option<&Sandbox>,
+    clock: Option<&Clock>,
+) -> Result<Implementation, String> {
+    let (path, sha256) = component_binary(data, registry)?;
+    let cgroup = match cgroups {
+        Some(cgroups) => Some(cgroups.create(data.component_id, &data.limits)?),
+        None if data.limits.is_empty() => None,
+        None => {
+            return Err(format!(
+                "Component {} has limits but the runner has no cgroup",
+                data.component_id
+            ))
+        }
+    };
+    add_component_implementation(
+        data.component_id,
+        path,
+        data.core,
+        sha256.as_deref(),
+        cgroup,
+        sandbox,
+        clock,
+    )
+}
+
+/// The binary to spawn for a component and the sha256 it must have.
+///
+/// Without a registry the component name is the binary.
+fn component_binary(
+    data: &AddComponentImplementation,
+    registry: Option<&Mutex<Registry>>,
+) -> Result<(String, Option<String>), String> {
+    match registry {
+        Some(registry) => {
+            let (path, sha256) = registry.lock().unwrap().resolve(
+                &data.component,
+                &data.version,
+                data.sha256.as_deref(),
+            )?;
+            Ok((path.to_string_lossy().into_owned(), Some(sha256)))
+        }
+        None => Ok((data.component.clone(), data.sha256.clone())),
+    }
+}
+
+fn change_registry(registry: &Mutex<Registry>, change: &RegistryChange) -> Reply {
+    let result = match change {
+        RegistryChange::Install(data) => {
+            // the copy is made without holding the registry, it is only locked to add it
+            let directory = registry.lock().unwrap().directory().to_path_buf();
+            registry::stage(
+                &directory,
+                &data.component,
+                &data.version,
+                std::path::Path::new(&data.path),
+            )
+            .and_then(|staged| registry.lock().unwrap().add(staged))
+            .map(Reply::Installed)
+        }
+        RegistryChange::Uninstall(data) => registry
+            .lock()
+            .unwrap()
+            .remove(&data.component, &data.version)
+            .map(|_| Reply::Uninstalled),
+    };
+
+    result.unwrap_or_else(|e| {
+        log::error!("{}", e);
+        Reply::Error(Error::RegistryFailed(e))
+    })
+}
+
+/// Spawn a component process, a binary that does not have the given hash is not run.
 pub fn add_component_implementation(
+    component_id: Uuid,
     path: String,
     core: usize,
-) -> crate::global_state::Implementation {
+    sha256: Option<&str>,
+    cgroup: Option<Cgroup>,
+    sandbox: Option<&Sandbox>,
+    clock: Option<&Clock>,
+) -> Result<crate::global_state::Implementation, String> {
     log::trace!("BACKGROUND: Adding component {}", path);
 
+    // run a copy of the bytes that were checked, the file may change after the check
+    let binary = match sha256 {
+        Some(sha256) => Some(super::binary::seal(&path, sha256)?),
+        None => None,
+    };
+
     // create control and data sockets
-    let (data_socket, child_data_socket) = UnixStream::pair().unwrap();
-    data_socket.set_nonblocking(true).unwrap();
+    let (data_socket, child_data_socket) = UnixStream::pair()
+        .and_then(|(socket, child)| socket.set_nonblocking(true).map(|_| (socket, child)))
+        .map_err(|e| format!("Failed to create data socket for {}; err = {}", path, e))?;
 
-    let (state_socket, child_state_socket) = UnixStream::pair().unwrap();
-    state_socket.set_nonblocking(true).unwrap();
+    let (state_socket, child_state_socket) = UnixStream::pair()
+        .and_then(|(socket, child)| socket.set_nonblocking(true).map(|_| (socket, child)))
+        .map_err(|e| format!("Failed to create state socket for {}; err = {}", path, e))?;
 
     // create fds for the child process
     let child_data_socket_fd = child_data_socket.into_raw_fd();
     let child_state_socket_fd = child_state_socket.into_raw_fd();
 
+    let mut fd_mappings = vec![
+        FdMapping {
+            child_fd: 10,
+            parent_fd: unsafe { OwnedFd::from_raw_fd(child_data_socket_fd) },
+        },
+        FdMapping {
+            child_fd: 11,
+            parent_fd: unsafe { OwnedFd::from_raw_fd(child_state_socket_fd) },
+        },
+    ];
+
     // spawn the child process
-    let mut command = Command::new(path);
+    let mut command = match binary {
+        Some(binary) => {
+            fd_mappings.push(FdMapping {
+                child_fd: super::binary::BINARY_FD,
+                parent_fd: binary.into(),
+            });
+            let mut command = Command::new(super::binary::program());
+            command.arg0(&path);
+            command
+        }
+        None => Command::new(&path),
+    };
+    // a simulated component reads time from the runner's clock
+    if let Some(clock) = clock {
+        fd_mappings.push(FdMapping {
+            child_fd: CLOCK_FD,
+            parent_fd: clock.share()?,
+        });
+        command.env("ELAFRY_CLOCK", CLOCK_FD.to_string());
+    }
     command
-        .fd_mappings(vec![
-            FdMapping {
-                child_fd: 10,
-                parent_fd: unsafe { OwnedFd::from_raw_fd(child_data_socket_fd) },
-            },
-            FdMapping {
-                child_fd: 11,
-                parent_fd: unsafe { OwnedFd::from_raw_fd(child_state_socket_fd) },
-            },
-        ])
-        .unwrap();
+        .fd_mappings(fd_mappings)
+        .map_err(|e| format!("Failed to map fds for {}; err = {:?}", path, e))?;
+    // join the cgroup before exec so the limits hold from the start
+    if let Some(cgroup) = &cgroup {
+        let procs = cgroup.procs();
+        unsafe {
+            command.pre_exec(move || crate::cgroup::join(procs));
+        }
+    }
+    // confined last, joining the cgroup takes the runner's privileges
+    if let Some(sandbox) = sandbox {
+        let sandbox = sandbox.clone();
+        let mut filter = crate::sandbox::filter();
+        unsafe {
+            command.pre_exec(move || sandbox.enter(&mut filter));
+        }
+    }
     // redirect the child's stderr to the parent's stderr
     let child = command
+        .env("ELAFRY_COMPONENT_ID", component_id.to_string())
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit())
         .spawn()
-        .unwrap();
+        .map_err(|e| format!("Failed to spawn {}; err = {}", path, e))?;
 
-    // stop socket from being closed when it goes out of scope
-    let _ = unsafe { UnixStream::from_raw_fd(child_data_socket_fd) };
-    let _ = unsafe { UnixStream::from_raw_fd(child_state_socket_fd) };
-
-    // use libc to set the process core affinity to specified core
-    let mut cpu_set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
-    unsafe {
-        libc::CPU_SET(core, &mut cpu_set);
-        let ret = libc::sched_setaffinity(
-            child.id() as libc::pid_t,
-            std::mem::size_of_val(&cpu_set),
-            &cpu_set,
-        );
-        if ret != 0 {
-            log::error!("Failed to set affinity");
-        }
-    }
+    // run on its core, only raised by the scheduler for its slots
+    crate::realtime::component(child.id() as libc::pid_t, core);
 
-    // use libc to set the process sechdeuler to SCHEDULER FFIO
-    unsafe {
-        let ret = libc::sched_setscheduler(
-            child.id() as libc::pid_t,
-            libc::SCHED_IDLE,
-            &libc::sched_param { sched_priority: 0 },
-        );
-        if ret != 0 {
-            log::error!("Failed to set scheduler");
+    // wait for the component to be ready, until it has stopped itself when simulating
+    match clock {
+        Some(_) => {
+            if let Err(e) = crate::simulation::wait_stopped(child.id() as libc::pid_t) {
+                log::warn!("{}", e);
+            }
         }
+        None => std::thread::sleep(std::time::Duration::from_micros(50)),
     }
 
-    // wait for the component to be ready
-    std::thread::sleep(std::time::Duration::from_micros(50));
-
     log::trace!("BACKGROUND: Done adding component");
 
     let pid = child.id() as libc::pid_t;
 
+    // open here so the scheduler can read the cpu time without touching the filesystem
+    let schedstat = match std::fs::File::open(format!("/proc/{}/schedstat", pid)) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            log::warn!("Failed to open schedstat for {}; err = {:?}", pid, e);
+            None
+        }
+    };
+
     // create the component implementation
-    crate::global_state::Implementation {
+    Ok(crate::global_state::Implementation {
         data_socket: crate::global_state::Socket {
             socket: data_socket,
             count: 0,
@@ -191,7 +504,9 @@ pub fn add_component_implementation(
         },
         child,
         child_pid: pid,
-    }
+        schedstat,
+        cgroup,
+    })
 }
 
 pub fn add_component(
@@ -199,9 +514,10 @@ pub fn add_component(
     action_status: &mut ActionState,
     sender: Sender<()>,
     actions: Arc<Mutex<Vec<NonBlockingImplementationData>>>,
-    done_implement: Arc<Mutex<HashMap<Uuid, Implementation>>>,
+    done_implement: Arc<Mutex<HashMap<Uuid, Result<Implementation, String>>>>,
+    transaction: &mut Transaction,
     data: elafry::types::configuration::AddComponentData,
-) {
+) -> Result<(), String> {
     log::debug!(
         "Adding component {} {:?}",
         data.component_id,
@@ -210,8 +526,24 @@ pub fn add_component(
 
     match *action_status {
         ActionState::Started => {
+            // replacing a component would leave its process running unmanaged
+            if state.get_component(data.component_id).is_some() {
+                return Err(format!("Component {} already exists", data.component_id));
+            }
+
             // create the component
-            state.add_component(data.component_id, data.component, data.core);
+            state.add_component(
+                data.component_id,
+                data.component.clone(),
+                data.core,
+                data.version.clone(),
+            );
+            if let Some(component) = state.get_component_mut(data.component_id) {
+                component.sha256 = data.sha256.clone();
+                component.restart = data.restart.clone();
+                component.limits = data.limits.clone();
+            }
+            transaction.record(Undo::AddComponent(data.component_id));
 
             // set the status to running
             *action_status = ActionState::Running;
@@ -225,6 +557,9 @@ pub fn add_component(
                         component_id: data.component_id,
                         component: data.component,
                         core: data.core,
+                        version: data.version,
+                        sha256: data.sha256,
+                        limits: data.limits,
                     },
                 ));
 
@@ -241,14 +576,18 @@ pub fn add_component(
             // try get a lock on the done_implement
             if let Ok(mut done_implement) = done_implement.try_lock() {
                 // pop the implementation from the done_implement hashmap
-                if let Some(implementation) = done_implement.remove(&data.component_id) {
-                    // put the implementation in the component
-                    state.add_component_implementation(data.component_id, implementation);
+                match done_implement.remove(&data.component_id) {
+                    Some(Ok(implementation)) => {
+                        // put the implementation in the component
+                        state.add_component_implementation(data.component_id, implementation)?;
 
-                    // set the status to done
-                    *action_status = ActionState::Completed;
-                } else {
-                    log::debug!("Component {} not done", data.component_id);
+                        // set the status to done
+                        *action_status = ActionState::Completed;
+                    }
+                    Some(Err(e)) => return Err(e),
+                    None => {
+                        log::debug!("Component {} not done", data.component_id);
+                    }
                 }
             } else {
                 log::warn!("Failed to get lock on done_implement");
@@ -258,13 +597,18 @@ pub fn add_component(
             log::warn!("Should not be here");
         }
     }
+
+    Ok(())
 }
 
 fn remove_component_implementation(implementation: &mut Implementation) {
     log::trace!("BACKGROUND: Removing component");
 
-    // send signal to child process to stop
-    implementation.child.kill().unwrap();
+    // send signal to child process to stop, it may already have exited
+    if let Err(e) = implementation.child.kill() {
+        log::warn!("Failed to kill component; err = {:?}", e);
+    }
+    let _ = implementation.child.wait();
 
     log::trace!("BACKGROUND: Done removing component");
 }
@@ -272,11 +616,9 @@ fn remove_component_implementation(implementation: &mut Implementation) {
 pub fn remove_component(
     state: &mut crate::global_state::GlobalState,
     action_status: &mut ActionState,
-    sender: Sender<()>,
-    actions: Arc<Mutex<Vec<NonBlockingImplementationData>>>,
-    _done_remove: Arc<Mutex<Vec<Uuid>>>,
+    transaction: &mut Transaction,
     data: elafry::types::configuration::RemoveComponentData,
-) {
+) -> Result<(), String> {
     log::debug!(
         "Removing component {} {:?}",
         data.component_id,
@@ -285,84 +627,66 @@ pub fn remove_component(
 
     match *action_status {
         ActionState::Started => {
-            // remove the component
-            state.remove_component(data.component_id);
-
-            // set the status to running
-            *action_status = ActionState::Running;
-        }
-        ActionState::Running => {
-            // try get a lock on the actions
-            if let Ok(mut actions) = actions.try_lock() {
-                // get the component from the state
-                let component = state.get_component_mut(data.component_id).unwrap();
-
-                // get the implementation from the component and set component implementation to None
-                let implementation = component.implentation.take();
-
-                // push the action to the actions vector
-                actions.push(NonBlockingImplementationData::RemoveComponent(
-                    RemoveComponentImplementation {
-                        component_id: data.component_id,
-                        implementation: implementation.unwrap(),
-                    },
-                ));
-
-                // send signal to background thread
-                sender.send(()).unwrap();
-
-                // set the status to stopped
-                *action_status = ActionState::Stopped;
-            } else {
-                log::warn!("Failed to get lock on actions");
-            }
-        }
-        ActionState::Stopped => {
-            // try get a lock on the done_remove
-            if let Ok(mut done_remove) = _done_remove.try_lock() {
-                // get the uuid from the done_remove vector and remove it
-                if let Some(uuid) = done_remove.pop() {
-                    // remove the component implementation
-                    state.remove_component_implementation(uuid);
+            let component = state
+                .get_component(data.component_id)
+                .ok_or(format!("Component {} not found", data.component_id))?;
+
+            // report the measured cpu time before the component goes away
+            let times = &component.times;
+            log::info!(
+                "Component {} cpu time min {}us mean {}us p99 {}us max {}us over {} slots",
+                data.component_id,
+                times.min.as_micros(),
+                times.mean().as_micros(),
+                times.percentile(99.0).as_micros(),
+                times.max.as_micros(),
+                times.count
+            );
 
-                    // remove the component from the state
-                    state.remove_component(uuid);
+            transaction.record(Undo::RemoveComponent {
+                component_id: data.component_id,
+                run: component.run,
+            });
 
-                    // set the status to done
-                    *action_status = ActionState::Completed;
-                } else {
-                    log::debug!("Component {} not done", data.component_id);
-                }
-            } else {
-                log::warn!("Failed to get lock on done_remove");
-            }
+            // mark the component removed, its process is stopped once the configuration commits
+            state.remove_component(data.component_id)?;
 
+            // set the status to done
             *action_status = ActionState::Completed;
         }
-        ActionState::Completed => {
+        _ => {
             log::warn!("Should not be here");
         }
     }
+
+    Ok(())
 }
 
 pub fn wait_state_sync(
     state: &mut crate::global_state::GlobalState,
     action_status: &mut ActionState,
+    transaction: &mut Transaction,
     data: elafry::types::configuration::WaitStateSyncData,
-) {
+) -> Result<(), String> {
     log::debug!("Syncing state {} {:?}", data.state_sync_id, *action_status);
 
     match *action_status {
         ActionState::Started => {
+            let status = state.get_state_sync_status(data.state_sync_id)?;
+            transaction.record(Undo::StateSyncStatus {
+                state_sync_id: data.state_sync_id,
+                status,
+            });
+
             // create the state sync
-            state.set_state_sync_status(data.state_sync_id, StateSyncStatus::Started);
+            state.set_state_sync_status(data.state_sync_id, StateSyncStatus::Started)?;
 
             // set the status to running
             *action_status = ActionState::Running;
         }
         ActionState::Running => {
             // wait for the state to be synced
-            let state_sync = state.get_state_sync_status(data.state_sync_id);
+            let state_sync = state.get_state_sync_status(data.state_sync_id)?;
 
             // if the state is synced
             match state_sync {
@@ -382,6 +706,8 @@ pub fn wait_state_sync(
             log::warn!("Should not be here");
         }
     }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -408,12 +734,277 @@ mod tests {
         let path = "ls";
         let core = 0;
 
-        let implementation = add_component_implementation(path.to_string(), core);
+        let implementation = add_component_implementation(
+            Uuid::new_v4(),
+            path.to_string(),
+            core,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
 
         assert_eq!(implementation.data_socket.count, 0);
         assert_eq!(implementation.state_socket.count, 0);
     }
 
+    #[test]
+    fn test_add_component_implementation_missing() {
+        setup();
+
+        let result = add_component_implementation(
+            Uuid::new_v4(),
+            "./no-such-component".to_string(),
+            0,
+            None,
+            None,
+            None,
+            None,
+        );
+
+        assert!(result
+            .err()
+            .unwrap()
+            .starts_with("Failed to spawn ./no-such-component"));
+    }
+
+    #[test]
+    fn test_add_component_implementation_sha256() {
+        setup();
+
+        let binary = super::super::binary::find("ls").unwrap();
+        let sha256 = elafry::signature::sha256(&std::fs::read(&binary).unwrap());
+
+        let mut implementation = add_component_implementation(
+            Uuid::new_v4(),
+            "ls".to_string(),
+            0,
+            Some(&sha256),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        remove_component_implementation(&mut implementation);
+
+        // a binary that changed is not run
+        let wrong = elafry::signature::sha256(b"");
+        assert_eq!(
+            add_component_implementation(
+                Uuid::new_v4(),
+                "ls".to_string(),
+                0,
+                Some(&wrong),
+                None,
+                None,
+                None
+            )
+            .err(),
+            Some(format!(
+                "Component binary {} has sha256 {}, expected {}",
+                binary.display(),
+                sha256,
+                wrong
+            ))
+        );
+    }
+
+    #[test]
+    fn test_spawn_cgroup() {
+        setup();
+
+        let root = std::env::temp_dir().join(format!("elafry-cgroup-{}", Uuid::new_v4()));
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("cgroup.controllers"), "cpu memory pids io\n").unwrap();
+        let cgroups = Cgroups::open(&root).unwrap();
+
+        let mut data = AddComponentImplementation {
+            component_id: Uuid::new_v4(),
+            component: "ls".to_string(),
+            core: 0,
+            version: "0.1.0".to_string(),
+            sha256: None,
+            limits: Limits {
+                pids_max: Some(4),
+                ..Default::default()
+            },
+        };
+
+        // the child moves itself into its cgroup before running the component
+        let mut implementation = spawn(&data, None, Some(&cgroups), None, None).unwrap();
+        remove_component_implementation(&mut implementation);
+        let leaf = root.join(data.component_id.to_string());
+        assert_eq!(std::fs::read_to_string(leaf.join("pids.max")).unwrap(), "4");
+        assert_eq!(
+            std::fs::read_to_string(leaf.join("cgroup.procs")).unwrap(),
+            "0"
+        );
+
+        // limits cannot be applied without a cgroup
+        data.component_id = Uuid::new_v4();
+        assert_eq!(
+            spawn(&data, None, None, None, None).err(),
+            Some(format!(
+                "Component {} has limits but the runner has no cgroup",
+                data.component_id
+            ))
+        );
+
+        drop(implementation);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_spawn_simulated() {
+        setup();
+
+        // the component writes where it finds the clock and the time on it, then stops itself
+        let directory = std::env::temp_dir().join(format!("elafry-clock-{}", Uuid::new_v4()));
+        std::fs::create_dir(&directory).unwrap();
+        let output = directory.join("time");
+        let script = directory.join("component");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/bash\necho $ELAFRY_CLOCK > {0}\nod -An -tu8 -N8 <&12 >> {0}\nkill -STOP $$\n",
+                output.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+
+        let clock = Clock::new().unwrap();
+        clock.set(std::time::Duration::from_micros(42)).unwrap();
+        let data = AddComponentImplementation {
+            component_id: Uuid::new_v4(),
+            component: script.to_string_lossy().into_owned(),
+            core: 0,
+            version: "0.1.0".to_string(),
+            sha256: None,
+            limits: Limits::default(),
+        };
+
+        // spawning waits until the component is ready, nothing is left to chance
+        let mut implementation = spawn(&data, None, None, None, Some(&clock)).unwrap();
+        let written = std::fs::read_to_string(&output).unwrap();
+        let lines: Vec<&str> = written.lines().map(|line| line.trim()).collect();
+        assert_eq!(lines, vec!["12", "42000"]);
+
+        remove_component_implementation(&mut implementation);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_spawn_checked() {
+        setup();
+
+        // a checked script runs from the sealed copy, its interpreter reads it by fd
+        let directory = std::env::temp_dir().join(format!("elafry-checked-{}", Uuid::new_v4()));
+        std::fs::create_dir(&directory).unwrap();
+        let output = directory.join("ran");
+        let script = directory.join("component");
+        let content = format!(
+            "#!/bin/bash\necho $0 > {}\nkill -STOP $$\n",
+            output.display()
+        );
+        std::fs::write(&script, &content).unwrap();
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+
+        let clock = Clock::new().unwrap();
+        let data = AddComponentImplementation {
+            component_id: Uuid::new_v4(),
+            component: script.to_string_lossy().into_owned(),
+            core: 0,
+            version: "0.1.0".to_string(),
+            sha256: Some(elafry::signature::sha256(content.as_bytes())),
+            limits: Limits::default(),
+        };
+
+        let mut implementation = spawn(&data, None, None, None, Some(&clock)).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap().trim(),
+            super::super::binary::program().to_str().unwrap()
+        );
+
+        remove_component_implementation(&mut implementation);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_verify() {
+        setup();
+
+        let key = elafry::signature::generate_key().unwrap();
+        let component_id = Uuid::new_v4();
+        let mut data = elafry::types::configuration::AddComponentData {
+            component_id,
+            component: "ls".to_string(),
+            core: 0,
+            version: "0.1.0".to_string(),
+            sha256: None,
+            restart: Default::default(),
+            limits: Default::default(),
+        };
+        let document = |data: &elafry::types::configuration::AddComponentData| {
+            Document::Tasks(elafry::types::configuration::Configuration {
+                tasks: vec![elafry::types::configuration::Task {
+                    id: Uuid::new_v4(),
+                    actions: Action::NonBlocking(vec![
+                        elafry::types::configuration::NonBlockingAction {
+                            id: Uuid::new_v4(),
+                            data: NonBlockingData::AddComponent(data.clone()),
+                        },
+                    ]),
+                }],
+            })
+        };
+        let signed = |document: Document, signature| Signed {
+            document,
+            signature,
+        };
+
+        // without trusted keys anything loads
+        let unsigned = document(&data);
+        assert_eq!(
+            verify(signed(unsigned.clone(), None), &[]),
+            Ok(unsigned.clone())
+        );
+
+        let trusted = [key.verifying_key()];
+        assert_eq!(
+            verify(signed(unsigned.clone(), None), &trusted),
+            Err("Configuration is not signed".to_string())
+        );
+        let signature = elafry::signature::sign(&unsigned, &key);
+        assert_eq!(
+            verify(signed(unsigned.clone(), Some(signature)), &trusted),
+            Err(format!(
+                "Component {} does not give the sha256 of its binary",
+                component_id
+            ))
+        );
+
+        data.sha256 = Some(elafry::signature::sha256(b"ls"));
+        let pinned = document(&data);
+        let signature = elafry::signature::sign(&pinned, &key);
+        assert_eq!(
+            verify(signed(pinned.clone(), Some(signature.clone())), &trusted),
+            Ok(pinned.clone())
+        );
+
+        // the signature does not cover another document
+        assert_eq!(
+            verify(signed(document(&data), Some(signature)), &trusted),
+            Err(
+                "Failed to verify signature; err = Signature does not match the configuration"
+                    .to_string()
+            )
+        );
+    }
+
     #[test]
     fn test_remove_component_implementation() {
         setup();
@@ -421,7 +1012,16 @@ mod tests {
         let path = "ls";
         let core = 0;
 
-        let mut implementation = add_component_implementation(path.to_string(), core);
+        let mut implementation = add_component_implementation(
+            Uuid::new_v4(),
+            path.to_string(),
+            core,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
 
         remove_component_implementation(&mut implementation);
     }
@@ -435,25 +1035,32 @@ mod tests {
         let done_implement = Arc::new(Mutex::new(HashMap::new()));
         let mut state = crate::global_state::GlobalState::new();
         let mut action_status = ActionState::Started;
+        let mut transaction = Transaction::new();
 
         // start the background thread
-        let actions_clone = actions.clone();
-        let done_implement_clone = done_implement.clone();
-        thread::spawn(move || {
-            main(
-                receiver,
-                actions_clone,
-                Arc::new(Mutex::new(None)),
-                done_implement_clone,
-                Arc::new(Mutex::new(Vec::new())),
-            );
-        });
+        let data = super::super::BackgroundData {
+            actions: actions.clone(),
+            done_configuration: Arc::new(Mutex::new(None)),
+            done_implement: done_implement.clone(),
+            done_remove: Arc::new(Mutex::new(Vec::new())),
+            registry: None,
+            registry_entries: Arc::new(Mutex::new(None)),
+            done_replies: Arc::new(Mutex::new(Vec::new())),
+            done_restart: Arc::new(Mutex::new(HashMap::new())),
+            cgroups: None,
+            sandbox: None,
+            clock: None,
+        };
+        thread::spawn(move || main(receiver, data));
 
         let data = elafry::types::configuration::AddComponentData {
             component_id: Uuid::new_v4(),
             component: "ls".to_string(),
             core: 0,
             version: "0.1.0".to_string(),
+            sha256: None,
+            restart: Default::default(),
+            limits: Default::default(),
         };
 
         add_component(
@@ -462,8 +1069,10 @@ mod tests {
             sender.clone(),
             actions.clone(),
             done_implement.clone(),
+            &mut transaction,
             data.clone(),
-        );
+        )
+        .unwrap();
 
         assert_eq!(action_status, ActionState::Running);
 
@@ -473,8 +1082,10 @@ mod tests {
             sender.clone(),
             actions.clone(),
             done_implement.clone(),
+            &mut transaction,
             data.clone(),
-        );
+        )
+        .unwrap();
 
         assert_eq!(action_status, ActionState::Stopped);
 
@@ -487,8 +1098,10 @@ mod tests {
             sender.clone(),
             actions.clone(),
             done_implement.clone(),
+            &mut transaction,
             data.clone(),
-        );
+        )
+        .unwrap();
 
         assert_eq!(action_status, ActionState::Completed);
 
@@ -505,11 +1118,9 @@ mod tests {
     fn test_remove_component() {
         setup();
 
-        let (sender, receiver) = channel();
-        let actions = Arc::new(Mutex::new(Vec::new()));
-        let done_remove = Arc::new(Mutex::new(Vec::new()));
         let mut state = crate::global_state::GlobalState::new();
         let mut action_status = ActionState::Started;
+        let mut transaction = Transaction::new();
 
         // create a dummy component on the state
         let id = uuid::Uuid::new_v4();
@@ -526,70 +1137,45 @@ mod tests {
             },
             child: std::process::Command::new("ls").spawn().unwrap(),
             child_pid: 0,
+            schedstat: None,
+            cgroup: None,
         };
 
-        state.add_component(id, path.clone(), core);
-        state.add_component_implementation(id, implementation);
-
-        // start the background thread
-        let actions_clone = actions.clone();
-        let done_remove_clone = done_remove.clone();
-        thread::spawn(move || {
-            main(
-                receiver,
-                actions_clone,
-                Arc::new(Mutex::new(None)),
-                Arc::new(Mutex::new(HashMap::new())),
-                done_remove_clone,
-            );
-        });
+        state.add_component(id, path.clone(), core, "1.0.0".to_string());
+        state
+            .add_component_implementation(id, implementation)
+            .unwrap();
+        state.start_component(id).unwrap();
 
         let data = elafry::types::configuration::RemoveComponentData { component_id: id };
 
         remove_component(
             &mut state,
             &mut action_status,
-            sender.clone(),
-            actions.clone(),
-            done_remove.clone(),
-            data.clone(),
-        );
-
-        assert_eq!(action_status, ActionState::Running);
-
-        remove_component(
-            &mut state,
-            &mut action_status,
-            sender.clone(),
-            actions.clone(),
-            done_remove.clone(),
-            data.clone(),
-        );
-
-        assert_eq!(action_status, ActionState::Stopped);
-
-        // wait for the background thread to finish
-        thread::sleep(std::time::Duration::from_secs(1));
-
-        remove_component(
-            &mut state,
-            &mut action_status,
-            sender.clone(),
-            actions.clone(),
-            done_remove.clone(),
+            &mut transaction,
             data.clone(),
-        );
+        )
+        .unwrap();
 
         assert_eq!(action_status, ActionState::Completed);
 
-        // check if the component was removed from the state
+        // the component is stopped but keeps its process until the configuration commits
         assert_eq!(state.total_components(), 1);
-        assert_eq!(state.get_component(id).unwrap().remove, true);
-        assert_eq!(state.get_component(id).unwrap().run, false);
-        assert_eq!(
-            state.get_component(id).unwrap().implentation.is_none(),
-            true
-        );
+        assert!(state.get_component(id).unwrap().remove);
+        assert!(!state.get_component(id).unwrap().run);
+        assert!(state.get_component(id).unwrap().implentation.is_some());
+
+        let stop = transaction.commit(&mut state);
+        assert_eq!(stop.len(), 1);
+        assert!(state.get_component(id).unwrap().implentation.is_none());
+
+        // removing a component that does not exist fails
+        let mut action_status = ActionState::Started;
+        let data = elafry::types::configuration::RemoveComponentData {
+            component_id: uuid::Uuid::new_v4(),
+        };
+        let mut transaction = Transaction::new();
+        assert!(remove_component(&mut state, &mut action_status, &mut transaction, data).is_err());
     }
 
     #[test]
@@ -598,6 +1184,7 @@ mod tests {
 
         let mut state = crate::global_state::GlobalState::new();
         let mut action_status = ActionState::Started;
+        let mut transaction = Transaction::new();
 
         let state_sync_id = uuid::Uuid::new_v4();
         let source = StateEndpoint {
@@ -607,20 +1194,32 @@ mod tests {
             component_id: uuid::Uuid::new_v4(),
         };
 
-        let data = elafry::types::configuration::WaitStateSyncData {
-            state_sync_id: state_sync_id,
-        };
+        let data = elafry::types::configuration::WaitStateSyncData { state_sync_id };
 
         // add the state sync to the state first
         state.add_state_sync(state_sync_id, source, target);
 
-        wait_state_sync(&mut state, &mut action_status, data.clone());
+        wait_state_sync(
+            &mut state,
+            &mut action_status,
+            &mut transaction,
+            data.clone(),
+        )
+        .unwrap();
 
         assert_eq!(action_status, ActionState::Running);
 
-        state.set_state_sync_status(data.state_sync_id, StateSyncStatus::Synced);
+        state
+            .set_state_sync_status(data.state_sync_id, StateSyncStatus::Synced)
+            .unwrap();
 
-        wait_state_sync(&mut state, &mut action_status, data.clone());
+        wait_state_sync(
+            &mut state,
+            &mut action_status,
+            &mut transaction,
+            data.clone(),
+        )
+        .unwrap();
 
         assert_eq!(action_status, ActionState::Completed);
     }
@@ -0,0 +1,178 @@
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::{FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+/// The file a component binary is spawned from, bare names are looked up on the PATH.
+pub fn find(path: &str) -> Option<PathBuf> {
+    if path.contains('/') {
+        return Some(PathBuf::from(path)).filter(|path| is_executable(path));
+    }
+
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(path))
+        .find(|path| is_executable(path))
+}
+
+/// Child fd a checked binary is executed from.
+pub const BINARY_FD: RawFd = 13;
+
+/// Find a component binary and check it has the expected SHA-256, returning its contents.
+pub fn verify(path: &str, sha256: &str) -> Result<Vec<u8>, String> {
+    let binary = find(path).ok_or(format!("Component binary {} not found", path))?;
+    let data = std::fs::read(&binary)
+        .map_err(|e| format!("Failed to read {}; err = {}", binary.display(), e))?;
+
+    let actual = elafry::signature::sha256(&data);
+    if !actual.eq_ignore_ascii_case(sha256) {
+        return Err(format!(
+            "Component binary {} has sha256 {}, expected {}",
+            binary.display(),
+            actual,
+            sha256
+        ));
+    }
+
+    Ok(data)
+}
+
+/// Check a component binary and copy it into a sealed memory file.
+///
+/// The binary is read once, so what runs is what was hashed even if the file
+/// is replaced or written to afterwards. Map the copy to `BINARY_FD` in the
+/// child and spawn `program`.
+pub fn seal(path: &str, sha256: &str) -> Result<File, String> {
+    let data = verify(path, sha256)?;
+
+    let fd = unsafe {
+        libc::memfd_create(
+            c"elafry-component".as_ptr(),
+            libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+        )
+    };
+    if fd < 0 {
+        return Err(format!(
+            "Failed to create copy of {}; err = {}",
+            path,
+            std::io::Error::last_os_error()
+        ));
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(&data)
+        .map_err(|e| format!("Failed to copy {}; err = {}", path, e))?;
+
+    // nothing can change the copy once sealed
+    let seals = libc::F_SEAL_SEAL | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+    if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(format!(
+            "Failed to seal copy of {}; err = {}",
+            path,
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    Ok(file)
+}
+
+/// Path a child runs the binary mapped to `BINARY_FD` from.
+///
+/// The fd is left open in the child, a script's interpreter reads it by the same path.
+pub fn program() -> PathBuf {
+    PathBuf::from(format!("/proc/self/fd/{}", BINARY_FD))
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    match std::fs::metadata(path) {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_find() {
+        setup();
+
+        assert!(find("ls").is_some());
+        assert_eq!(find("/bin/sh"), Some(PathBuf::from("/bin/sh")));
+        assert_eq!(find("elafry-missing-binary"), None);
+        assert_eq!(
+            find(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        setup();
+
+        let data = std::fs::read("/bin/sh").unwrap();
+        let sha256 = elafry::signature::sha256(&data);
+        assert_eq!(verify("/bin/sh", &sha256), Ok(data.clone()));
+        assert_eq!(verify("/bin/sh", &sha256.to_uppercase()), Ok(data));
+
+        let wrong = elafry::signature::sha256(b"");
+        assert_eq!(
+            verify("/bin/sh", &wrong),
+            Err(format!(
+                "Component binary /bin/sh has sha256 {}, expected {}",
+                sha256, wrong
+            ))
+        );
+        assert_eq!(
+            verify("elafry-missing-binary", &wrong),
+            Err("Component binary elafry-missing-binary not found".to_string())
+        );
+    }
+
+    #[test]
+    fn test_seal() {
+        setup();
+
+        use std::io::{Read, Seek};
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("elafry-seal-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"checked").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let sha256 = elafry::signature::sha256(b"checked");
+        let mut copy = seal(path.to_str().unwrap(), &sha256).unwrap();
+
+        // the copy keeps what was checked when the file is written to
+        std::fs::write(&path, b"changed").unwrap();
+        let mut data = Vec::new();
+        copy.rewind().unwrap();
+        copy.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"checked");
+
+        // and cannot be written to itself
+        assert!(copy.write_all(b"changed").is_err());
+
+        assert_eq!(
+            seal(path.to_str().unwrap(), &sha256).err(),
+            Some(format!(
+                "Component binary {} has sha256 {}, expected {}",
+                path.display(),
+                elafry::signature::sha256(b"changed"),
+                sha256
+            ))
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
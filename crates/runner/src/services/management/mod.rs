@@ -2,28 +2,57 @@ use std::collections::HashMap;
 use std::sync::mpsc::Sender;
 use std::sync::{mpsc, Arc, Mutex};
 
+use elafry::signature::VerifyingKey;
+use elafry::telemetry::{
+    ActionKind, Record, Recorder, Span, ACTION_COMPLETED, ACTION_FAILED, ACTION_STARTED,
+};
+use elafry::types::configuration::{Configuration, SetScheduleData};
+use elafry::types::management::{
+    Command, ComponentStatus, CpuTime, Error, ManagementState, RegistryEntry, Reply, Request,
+    Response, RouteStatus, Status,
+};
 use uuid::Uuid;
 
+use crate::cgroup::Cgroups;
 use crate::global_state::{Implementation, StateEndpoint};
+use crate::sandbox::Sandbox;
 use crate::services::communication::Endpoint;
 use crate::services::scheduler::{MajorFrame, MinorFrame};
+use crate::simulation::Clock;
 
 use super::communication::RouteEndpoint;
 use super::scheduler::Schedule;
 
 pub mod background;
+pub mod binary;
+pub mod plan;
+pub mod registry;
+pub mod supervise;
+pub mod transaction;
+pub mod upload;
+pub mod validate;
+
+use background::{Loaded, RegistryChange, Source};
+use registry::Registry;
+use transaction::{Transaction, Undo};
+use upload::Uploads;
 
 enum State {
     Idle,
+    /// A dry run only reports what loading the configuration would do.
     Waiting {
-        configuration: String,
+        configuration: Source,
+        dry_run: bool,
+    },
+    Loading {
+        dry_run: bool,
     },
-    Loading,
     Running {
         current_task: usize,
         tasks: Vec<elafry::types::configuration::Task>,
         blocked: bool,
         action_status: HashMap<uuid::Uuid, ActionState>,
+        transaction: Transaction,
     },
 }
 
@@ -36,7 +65,7 @@ pub enum ActionState {
 }
 
 struct Background {
-    _thread: std::thread::JoinHandle<()>,
+    thread: std::thread::JoinHandle<()>,
     sender: Sender<()>,
     data: BackgroundData,
 }
@@ -44,87 +73,222 @@ struct Background {
 #[derive(Clone)]
 struct BackgroundData {
     actions: Arc<Mutex<Vec<background::NonBlockingImplementationData>>>,
-    done_configuration: Arc<Mutex<Option<elafry::types::configuration::Configuration>>>,
-    done_implement: Arc<Mutex<HashMap<Uuid, Implementation>>>,
+    done_configuration: Arc<Mutex<Option<Result<Loaded, String>>>>,
+    done_implement: Arc<Mutex<HashMap<Uuid, Result<Implementation, String>>>>,
     done_remove: Arc<Mutex<Vec<Uuid>>>,
+    registry: Option<Arc<Mutex<Registry>>>,
+    /// Registry contents published by the background thread after each change.
+    registry_entries: Arc<Mutex<Option<Vec<RegistryEntry>>>>,
+    /// Replies to requests the background thread handled and who asked for them.
+    done_replies: background::Replies,
+    /// Processes spawned to replace crashed ones.
+    done_restart: Arc<Mutex<HashMap<Uuid, Result<Implementation, String>>>>,
+    /// Subtree component processes are put in, to apply their limits.
+    cgroups: Option<Arc<Cgroups>>,
+    /// How component processes are confined.
+    sandbox: Option<Sandbox>,
+    /// Virtual time handed to component processes when simulating.
+    clock: Option<Arc<Clock>>,
 }
 
 pub struct ManagementService {
     state: State,
     background: Background,
+    /// Endpoint and request id to tell when the reconfiguration in progress finishes.
+    requester: Option<(RouteEndpoint, u64)>,
+    /// Longest a reconfiguration may run before it is rolled back.
+    timeout: std::time::Duration,
+    /// Component processes waiting to be handed to the background thread to stop.
+    stopping: Vec<background::RemoveComponentImplementation>,
+    /// Configuration documents being received in chunks.
+    uploads: Uploads,
+    /// Keys configurations must be signed with, empty to load unsigned configurations.
+    trusted_keys: Vec<VerifyingKey>,
+    /// Registry changes waiting to be handed to the background thread.
+    registry_changes: Vec<background::ChangeRegistry>,
+    /// Registry contents as of the last change, listed without waiting on the registry.
+    registry_entries: Vec<RegistryEntry>,
+    /// Dry runs waiting to be handed to the background thread.
+    validations: Vec<background::ValidateConfiguration>,
+    /// Plans waiting to be handed to the background thread.
+    plans: Vec<background::PlanDesired>,
+    /// Crashed components and when to restart them.
+    supervisor: supervise::Supervisor,
+    /// Restarts waiting to be handed to the background thread.
+    restarting: Vec<background::AddComponentImplementation>,
+    /// Directory configurations are loaded from by name.
+    directory: std::path::PathBuf,
+    /// Start of the current frame, virtual when simulating.
+    frame_start: std::time::Duration,
 }
 
 impl ManagementService {
-    pub fn new(configuration: String) -> ManagementService {
+    /// Configurations loaded by name are read from the directory of the initial `configuration`.
+    pub fn new(
+        configuration: std::path::PathBuf,
+        timeout: std::time::Duration,
+        trusted_keys: Vec<VerifyingKey>,
+        registry: Option<Registry>,
+        cgroups: Option<Cgroups>,
+        sandbox: Option<Sandbox>,
+        clock: Option<Arc<Clock>>,
+    ) -> ManagementService {
         let (sender, receiver) = mpsc::channel();
-        let non_blocking_actions = Arc::new(Mutex::new(Vec::new()));
-        let done_configuration = Arc::new(Mutex::new(None));
-        let done_implement = Arc::new(Mutex::new(HashMap::new()));
-        let done_remove = Arc::new(Mutex::new(Vec::new()));
-
-        let non_blocking_actions_clone = non_blocking_actions.clone();
-        let done_configuration_clone = done_configuration.clone();
-        let done_implement_clone = done_implement.clone();
-        let done_remove_clone = done_remove.clone();
+        let registry_entries = registry
+            .as_ref()
+            .map(|registry| registry.entries())
+            .unwrap_or_default();
+        let data = BackgroundData {
+            actions: Arc::new(Mutex::new(Vec::new())),
+            done_configuration: Arc::new(Mutex::new(None)),
+            done_implement: Arc::new(Mutex::new(HashMap::new())),
+            done_remove: Arc::new(Mutex::new(Vec::new())),
+            registry: registry.map(|registry| Arc::new(Mutex::new(registry))),
+            registry_entries: Arc::new(Mutex::new(None)),
+            done_replies: Arc::new(Mutex::new(Vec::new())),
+            done_restart: Arc::new(Mutex::new(HashMap::new())),
+            cgroups: cgroups.map(Arc::new),
+            sandbox,
+            clock,
+        };
+        let data_clone = data.clone();
+        let directory = match configuration.parent() {
+            Some(directory) => directory.to_path_buf(),
+            None => std::path::PathBuf::from("."),
+        };
 
         let thread = std::thread::spawn(move || {
-            // set core affinity to core 0
-            unsafe {
-                let mut cpu_set: libc::cpu_set_t = std::mem::zeroed();
-                libc::CPU_SET(0, &mut cpu_set);
-                let ret = libc::sched_setaffinity(0, std::mem::size_of_val(&cpu_set), &cpu_set);
-                if ret != 0 {
-                    println!("Failed to set affinity");
-                }
-            }
-
-            // use libc to set the process sechdeuler to SCHEDULER NORMAL with a priority of -20
-            unsafe {
-                let ret = libc::pthread_setschedparam(
-                    libc::pthread_self(),
-                    libc::SCHED_IDLE,
-                    &libc::sched_param { sched_priority: 0 },
-                );
-                if ret != 0 {
-                    println!("Failed to set scheduler");
-                }
-            }
+            crate::realtime::background("management");
 
-            background::main(
-                receiver,
-                non_blocking_actions,
-                done_configuration,
-                done_implement,
-                done_remove,
-            );
+            background::main(receiver, data_clone);
         });
 
         ManagementService {
-            state: State::Waiting { configuration },
+            state: State::Waiting {
+                configuration: Source::File(configuration),
+                dry_run: false,
+            },
             background: Background {
-                _thread: thread,
+                thread,
                 sender,
-                data: BackgroundData {
-                    actions: non_blocking_actions_clone,
-                    done_configuration: done_configuration_clone,
-                    done_implement: done_implement_clone,
-                    done_remove: done_remove_clone,
-                },
+                data,
             },
+            requester: None,
+            timeout,
+            stopping: Vec::new(),
+            uploads: Uploads::new(),
+            trusted_keys,
+            registry_changes: Vec::new(),
+            registry_entries,
+            validations: Vec::new(),
+            plans: Vec::new(),
+            supervisor: supervise::Supervisor::new(),
+            restarting: Vec::new(),
+            directory,
+            frame_start: std::time::Duration::ZERO,
         }
     }
 
-    pub fn run(&mut self, state: &mut crate::global_state::GlobalState) {
-        // get exit message
+    /// Stop the background thread and hand back the component processes it was
+    /// starting or had not stopped yet, so they are shut down with the rest.
+    pub fn shutdown(self) -> Vec<(Uuid, Implementation)> {
+        let Background {
+            thread,
+            sender,
+            data,
+        } = self.background;
 
-        let messages = state.get_message(0);
+        // the thread finishes what it was sent, then exits once the sender is gone
+        drop(sender);
+        if thread.join().is_err() {
+            log::error!("Background thread panicked");
+        }
 
-        match messages {
-            Some(_) => {
-                log::info!("Received kill message");
-                state.set_done(true);
+        let mut implementations: Vec<(Uuid, Implementation)> = self
+            .stopping
+            .into_iter()
+            .map(|data| (data.component_id, data.implementation))
+            .collect();
+        if let Ok(mut done_implement) = data.done_implement.lock() {
+            for (id, implementation) in done_implement.drain() {
+                if let Ok(implementation) = implementation {
+                    implementations.push((id, implementation));
+                }
+            }
+        }
+        if let Ok(mut done_restart) = data.done_restart.lock() {
+            for (id, implementation) in done_restart.drain() {
+                if let Ok(implementation) = implementation {
+                    implementations.push((id, implementation));
+                }
+            }
+        }
+        implementations
+    }
+
+    /// The virtual start of the frame run next, restarts are timed on it when simulating.
+    pub fn set_frame_start(&mut self, frame_start: std::time::Duration) {
+        self.frame_start = frame_start;
+    }
+
+    /// Time restarts are due by, the frame start when simulating so they come
+    /// in the same frame every run.
+    fn now(&self) -> std::time::Duration {
+        match &self.background.data.clock {
+            Some(_) => self.frame_start,
+            None => crate::timing::now(),
+        }
+    }
+
+    /// Name of the current state and the index of the task being run.
+    pub fn status(&self) -> (&'static str, Option<usize>) {
+        match &self.state {
+            State::Idle => ("idle", None),
+            State::Waiting { .. } => ("waiting", None),
+            State::Loading { .. } => ("loading", None),
+            State::Running { current_task, .. } => ("running", Some(*current_task)),
+        }
+    }
+
+    pub fn run(
+        &mut self,
+        state: &mut crate::global_state::GlobalState,
+        recorder: &mut Recorder,
+        frame_number: u64,
+    ) {
+        // handle a bounded number of management requests each frame
+        for _ in 0..5 {
+            match state.get_message(elafry::types::management::CHANNEL) {
+                Some((source, message)) => self.handle_request(state, source, &message.data),
+                None => break,
+            }
+        }
+
+        // notice crashed components and restart them as their policy says
+        self.supervise(state);
+
+        // hand stopped component processes, registry changes, dry runs and plans to the background thread
+        self.send_pending();
+
+        // tell whoever asked how their registry changes, dry runs and plans went
+        if let Ok(mut done_replies) = self.background.data.done_replies.try_lock() {
+            for ((requester, request_id), response) in done_replies.drain(..) {
+                reply(state, requester, request_id, response);
+            }
+        }
+
+        // keep a copy of the registry to list it from
+        if let Ok(mut registry_entries) = self.background.data.registry_entries.try_lock() {
+            if let Some(entries) = registry_entries.take() {
+                self.registry_entries = entries;
+            }
+        }
+
+        // drop components whose process has been stopped
+        if let Ok(mut done_remove) = self.background.data.done_remove.try_lock() {
+            for id in done_remove.drain(..) {
+                state.components.remove(&id);
             }
-            None => {}
         }
 
         // log::debug!("Running management service");
@@ -134,25 +298,24 @@ impl ManagementService {
             State::Idle => {
                 // log::debug!("State Idle");
 
-                // check if there are any requests to change the configuration
-                let messages = state.get_message(1);
-
-                match messages {
-                    Some(message) => {
-                        log::debug!("Received message: {:?}", message);
-
-                        // convert message to string
-                        let message = std::str::from_utf8(&message.data).unwrap();
-                        self.state = State::Waiting {
-                            configuration: message.to_string(),
-                        };
-                    }
-                    None => {
-                        // log::debug!("No message");
+                // processes spawned for a reconfiguration that was rolled back
+                if let Ok(mut done_implement) = self.background.data.done_implement.try_lock() {
+                    for (id, implementation) in done_implement.drain() {
+                        if let Ok(implementation) = implementation {
+                            log::warn!("Stopping component {} left by a rolled back load", id);
+                            self.stopping
+                                .push(background::RemoveComponentImplementation {
+                                    component_id: id,
+                                    implementation,
+                                });
+                        }
                     }
                 }
             }
-            State::Waiting { configuration } => {
+            State::Waiting {
+                configuration,
+                dry_run,
+            } => {
                 log::debug!("State Waiting");
 
                 // try get a lock on actions
@@ -161,7 +324,9 @@ impl ManagementService {
                     actions.push(
                         background::NonBlockingImplementationData::LoadConfiguration(
                             background::LoadConfiguration {
-                                path: configuration.to_string(),
+                                source: configuration.clone(),
+                                trusted_keys: self.trusted_keys.clone(),
+                                current: plan::Current::new(state),
                             },
                         ),
                     );
@@ -170,49 +335,69 @@ impl ManagementService {
                     self.background.sender.send(()).unwrap();
 
                     // change state to loading
-                    self.state = State::Loading;
+                    self.state = State::Loading { dry_run: *dry_run };
                 } else {
                     log::warn!("Failed to get lock on actions");
                 }
             }
 
-            State::Loading => {
+            State::Loading { dry_run } => {
                 log::debug!("State Loading");
 
                 // try get a lock on done_configuration
-                if let Ok(mut done_configuration) =
-                    self.background.data.done_configuration.try_lock()
-                {
-                    // get clone of done_configuration
-                    let configuration = done_configuration.clone();
-
-                    match configuration {
-                        Some(configuration) => {
-                            // check if the configuration has any tasks
-                            if configuration.tasks.is_empty() {
-                                log::error!("No tasks in configuration file");
-                                self.state = State::Idle;
-                            } else {
-                                self.state = State::Running {
-                                    current_task: 0,
-                                    tasks: configuration.tasks,
-                                    blocked: false,
-                                    action_status: HashMap::new(),
-                                };
-
-                                // empty the done_configuration
-                                *done_configuration = None;
-
-                                // return
-                                return;
-                            }
+                let configuration = match self.background.data.done_configuration.try_lock() {
+                    Ok(mut done_configuration) => done_configuration.take(),
+                    Err(_) => {
+                        log::warn!("Failed to get lock on done_configuration");
+                        None
+                    }
+                };
+
+                let dry_run = *dry_run;
+
+                match configuration {
+                    // desired states are planned by the background thread
+                    Some(Ok(Loaded::Planned(configuration))) if configuration.tasks.is_empty() => {
+                        log::info!("Already in the desired state");
+                        let response = if dry_run {
+                            Reply::Report(vec![])
+                        } else {
+                            Reply::Loaded
+                        };
+                        self.finish(state, response);
+                    }
+                    Some(Ok(Loaded::Tasks(configuration) | Loaded::Planned(configuration)))
+                        if dry_run =>
+                    {
+                        self.state = State::Idle;
+                        if let Some(requester) = self.requester.take() {
+                            self.validate(state, configuration, requester);
                         }
-                        None => {
-                            log::debug!("Not received configuration from background thread");
+                    }
+                    Some(Ok(Loaded::Planned(configuration))) => {
+                        self.state = running(configuration);
+                    }
+                    Some(Ok(Loaded::Tasks(configuration))) => {
+                        // check if the configuration has any tasks
+                        if configuration.tasks.is_empty() {
+                            log::error!("No tasks in configuration file");
+                            self.finish(
+                                state,
+                                Reply::Error(Error::LoadFailed(
+                                    "No tasks in configuration".to_string(),
+                                )),
+                            );
+                        } else {
+                            self.state = running(configuration);
                         }
                     }
-                } else {
-                    log::warn!("Failed to get lock on done_configuration");
+                    Some(Err(e)) => {
+                        log::error!("{}", e);
+                        self.finish(state, Reply::Error(Error::LoadFailed(e)));
+                    }
+                    None => {
+                        log::debug!("Not received configuration from background thread");
+                    }
                 }
             }
             State::Running {
@@ -220,20 +405,51 @@ impl ManagementService {
                 tasks,
                 blocked,
                 action_status,
+                transaction,
             } => {
                 log::debug!("State Running");
 
+                // a reconfiguration that never finishes is treated as failed
+                let mut failure = None;
+                if transaction.elapsed() > self.timeout {
+                    failure = Some(format!("Timed out after {}ms", self.timeout.as_millis()));
+                }
+
                 // get actions from first task
                 let actions = tasks[*current_task].actions.clone();
 
                 // execute actions
                 match actions {
+                    _ if failure.is_some() => {}
                     elafry::types::configuration::Action::Blocking(actions) => {
                         if *blocked {
                             log::warn!("Non-blocking actions already running");
                         } else {
                             for action in actions {
-                                Self::execute_blocking(state, action.data);
+                                let kind = blocking_kind(&action.data);
+                                match Self::execute_blocking(state, action.data) {
+                                    Ok(undo) => {
+                                        transaction.record(undo);
+                                        record_action(
+                                            recorder,
+                                            frame_number,
+                                            kind,
+                                            action.id,
+                                            ACTION_COMPLETED,
+                                        );
+                                    }
+                                    Err(e) => {
+                                        record_action(
+                                            recorder,
+                                            frame_number,
+                                            kind,
+                                            action.id,
+                                            ACTION_FAILED,
+                                        );
+                                        failure = Some(e);
+                                        break;
+                                    }
+                                }
                             }
                         }
                     }
@@ -242,13 +458,38 @@ impl ManagementService {
                         if *blocked {
                             for action in actions {
                                 let status = action_status.get_mut(&action.id).unwrap();
-                                Self::execute_non_blocking(
+                                let kind = non_blocking_kind(&action.data);
+                                let completed = *status == ActionState::Completed;
+                                let result = Self::execute_non_blocking(
                                     state,
                                     status,
                                     self.background.sender.clone(),
                                     self.background.data.clone(),
+                                    transaction,
                                     action.data,
                                 );
+
+                                if let Err(e) = result {
+                                    record_action(
+                                        recorder,
+                                        frame_number,
+                                        kind,
+                                        action.id,
+                                        ACTION_FAILED,
+                                    );
+                                    failure = Some(e);
+                                    break;
+                                }
+
+                                if !completed && *status == ActionState::Completed {
+                                    record_action(
+                                        recorder,
+                                        frame_number,
+                                        kind,
+                                        action.id,
+                                        ACTION_COMPLETED,
+                                    );
+                                }
                             }
 
                             // if all non-blocking actions are done, set blocked to false
@@ -263,84 +504,541 @@ impl ManagementService {
                             for action in actions {
                                 action_status.insert(action.id, ActionState::Started);
                                 *blocked = true;
+                                record_action(
+                                    recorder,
+                                    frame_number,
+                                    non_blocking_kind(&action.data),
+                                    action.id,
+                                    ACTION_STARTED,
+                                );
                             }
                         }
                     }
                 }
 
-                // check if non-blocking actions are done
-                if !*blocked {
+                if let Some(e) = failure {
+                    log::error!(
+                        "Reconfiguration failed in task {}; err = {}",
+                        current_task,
+                        e
+                    );
+                    self.rollback(state, Reply::Error(Error::RolledBack(e)));
+                } else if !*blocked {
                     // check if there are more tasks
                     if *current_task < tasks.len() - 1 {
-                        self.state = State::Running {
-                            current_task: *current_task + 1,
-                            tasks: tasks.clone(),
-                            blocked: blocked.clone(),
-                            action_status: HashMap::new(),
-                        };
+                        *current_task += 1;
+                        action_status.clear();
                     } else {
-                        self.state = State::Idle;
+                        log::info!("Configuration complete");
+                        self.commit(state);
+                        self.finish(state, Reply::Loaded);
                     }
                 }
             }
         }
     }
 
+    fn handle_request(
+        &mut self,
+        state: &mut crate::global_state::GlobalState,
+        source: RouteEndpoint,
+        data: &[u8],
+    ) {
+        let request = match Request::decode(data) {
+            Some(request) => request,
+            None => {
+                log::error!("Failed to decode management request from {}", source);
+                reply(state, source, 0, Reply::Error(Error::Malformed));
+                return;
+            }
+        };
+
+        log::info!(
+            "Received management request {} {:?} from {}",
+            request.request_id,
+            request.command,
+            source
+        );
+
+        if request.version != elafry::types::management::VERSION {
+            reply(
+                state,
+                source,
+                request.request_id,
+                Reply::Error(Error::UnsupportedVersion(request.version)),
+            );
+            return;
+        }
+
+        let response = match request.command {
+            Command::Load(name) => {
+                if !matches!(self.state, State::Idle) {
+                    Reply::Error(Error::Busy)
+                } else if name.is_empty() || name.contains('/') || name.starts_with('.') {
+                    // only files directly in the configuration directory
+                    Reply::Error(Error::InvalidName(name))
+                } else {
+                    self.state = State::Waiting {
+                        configuration: Source::File(self.directory.join(name)),
+                        dry_run: false,
+                    };
+                    self.requester = Some((source, request.request_id));
+                    Reply::Accepted
+                }
+            }
+            Command::LoadInline(configuration) => {
+                if !matches!(self.state, State::Idle) {
+                    Reply::Error(Error::Busy)
+                } else if !self.trusted_keys.is_empty() {
+                    // inline configurations carry no signature
+                    Reply::Error(Error::Unsigned)
+                } else if configuration.tasks.is_empty() {
+                    Reply::Error(Error::LoadFailed("No tasks in configuration".to_string()))
+                } else {
+                    self.state = running(configuration);
+                    self.requester = Some((source, request.request_id));
+                    Reply::Accepted
+                }
+            }
+            Command::ApplyInline(desired) => {
+                if !matches!(self.state, State::Idle) {
+                    Reply::Error(Error::Busy)
+                } else if !self.trusted_keys.is_empty() {
+                    Reply::Error(Error::Unsigned)
+                } else {
+                    // planned by the background thread like a desired state read from a file
+                    self.state = State::Waiting {
+                        configuration: Source::Desired(desired),
+                        dry_run: false,
+                    };
+                    self.requester = Some((source, request.request_id));
+                    Reply::Accepted
+                }
+            }
+            Command::PlanInline(desired) => {
+                self.plans.push(background::PlanDesired {
+                    current: plan::Current::new(state),
+                    desired,
+                    requester: (source, request.request_id),
+                });
+                Reply::Accepted
+            }
+            Command::Validate(name) => {
+                // the file is read by the background thread like a load
+                if !matches!(self.state, State::Idle) {
+                    Reply::Error(Error::Busy)
+                } else if name.is_empty() || name.contains('/') || name.starts_with('.') {
+                    Reply::Error(Error::InvalidName(name))
+                } else {
+                    self.state = State::Waiting {
+                        configuration: Source::File(self.directory.join(name)),
+                        dry_run: true,
+                    };
+                    self.requester = Some((source, request.request_id));
+                    Reply::Accepted
+                }
+            }
+            Command::ValidateInline(configuration) => {
+                self.validate(state, configuration, (source, request.request_id));
+                Reply::Accepted
+            }
+            Command::Upload(chunk) => match self.uploads.add(chunk) {
+                Ok(received) => Reply::Received(received),
+                Err(e) => Reply::Error(Error::InvalidChunk(e)),
+            },
+            Command::LoadUpload(upload) => {
+                self.load_upload(source, request.request_id, upload, false)
+            }
+            Command::ValidateUpload(upload) => {
+                self.load_upload(source, request.request_id, upload, true)
+            }
+            Command::Install(data) => {
+                self.change_registry(source, request.request_id, RegistryChange::Install(data))
+            }
+            Command::Uninstall(data) => {
+                // a running process keeps its binary
+                let users: Vec<String> = state
+                    .components
+                    .iter()
+                    .filter(|(_, component)| {
+                        component.path == data.component && component.version == data.version
+                    })
+                    .map(|(id, _)| id.to_string())
+                    .collect();
+                if users.is_empty() {
+                    self.change_registry(
+                        source,
+                        request.request_id,
+                        RegistryChange::Uninstall(data),
+                    )
+                } else {
+                    Reply::Error(Error::RegistryFailed(format!(
+                        "Component {} version {} is used by {}",
+                        data.component,
+                        data.version,
+                        users.join(", ")
+                    )))
+                }
+            }
+            Command::ListRegistry => match &self.background.data.registry {
+                Some(_) => Reply::Registry(self.registry_entries.clone()),
+                None => Reply::Error(Error::NoRegistry),
+            },
+            Command::Status => Reply::Status(self.status_reply(state)),
+            Command::ListComponents => {
+                let mut components: Vec<ComponentStatus> = state
+                    .components
+                    .iter()
+                    .map(|(id, component)| ComponentStatus {
+                        component_id: *id,
+                        component: component.path.clone(),
+                        version: component.version.clone(),
+                        core: component.core,
+                        running: component.run,
+                        crashed: component.crashed,
+                        restarts: component.restarts,
+                        faults: component.faults,
+                        cpu_time: CpuTime {
+                            count: component.times.count,
+                            min: component.times.min.as_nanos() as u64,
+                            mean: component.times.mean().as_nanos() as u64,
+                            p99: component.times.percentile(99.0).as_nanos() as u64,
+                            max: component.times.max.as_nanos() as u64,
+                        },
+                    })
+                    .collect();
+                components.sort_by_key(|component| component.component_id);
+                Reply::Components(components)
+            }
+            Command::ListRoutes => {
+                let mut routes: Vec<RouteStatus> = state
+                    .routes
+                    .iter()
+                    .flat_map(|(source, targets)| {
+                        targets.iter().filter_map(|target| {
+                            Some(RouteStatus {
+                                source: configuration_endpoint(source)?,
+                                target: configuration_endpoint(target)?,
+                            })
+                        })
+                    })
+                    .collect();
+                // targets of a source stay in the order they were added
+                routes.sort_by_key(|route| format!("{:?}", route.source));
+                Reply::Routes(routes)
+            }
+            Command::Schedule => Reply::Schedule(schedule_data(&state.schedule)),
+            Command::Abort => match self.state {
+                State::Idle => Reply::Error(Error::NothingToAbort),
+                // the background thread owns the load until it is done
+                State::Loading { .. } => Reply::Error(Error::Busy),
+                State::Waiting { .. } | State::Running { .. } => {
+                    log::warn!("Aborting reconfiguration");
+                    self.rollback(state, Reply::Aborted);
+                    Reply::Aborted
+                }
+            },
+            Command::Shutdown => {
+                log::info!("Received shutdown request");
+                state.set_done(true);
+                Reply::ShuttingDown
+            }
+        };
+
+        reply(state, source, request.request_id, response);
+    }
+
+    /// Load a complete upload, it is parsed by the background thread like a file.
+    fn load_upload(
+        &mut self,
+        source: RouteEndpoint,
+        request_id: u64,
+        upload: u64,
+        dry_run: bool,
+    ) -> Reply {
+        if !matches!(self.state, State::Idle) {
+            return Reply::Error(Error::Busy);
+        }
+
+        match self.uploads.take(upload) {
+            Some(document) => {
+                self.state = State::Waiting {
+                    configuration: Source::Upload(document),
+                    dry_run,
+                };
+                self.requester = Some((source, request_id));
+                Reply::Accepted
+            }
+            None => Reply::Error(Error::UnknownUpload(upload)),
+        }
+    }
+
+    /// Queue a registry change for the background thread, its outcome is sent when done.
+    fn change_registry(
+        &mut self,
+        source: RouteEndpoint,
+        request_id: u64,
+        change: RegistryChange,
+    ) -> Reply {
+        if self.background.data.registry.is_none() {
+            return Reply::Error(Error::NoRegistry);
+        }
+
+        self.registry_changes.push(background::ChangeRegistry {
+            change,
+            requester: (source, request_id),
+        });
+        Reply::Accepted
+    }
+
+    /// Hand a dry run to the background thread, the report is sent to the requester when it is done.
+    ///
+    /// Checking components reads their binaries, which is kept off the frame loop.
+    fn validate(
+        &mut self,
+        state: &crate::global_state::GlobalState,
+        configuration: Configuration,
+        requester: (RouteEndpoint, u64),
+    ) {
+        self.validations.push(background::ValidateConfiguration {
+            model: validate::Model::new(state, self.background.data.cgroups.is_some()),
+            configuration,
+            requester,
+        });
+    }
+
+    fn status_reply(&self, state: &crate::global_state::GlobalState) -> Status {
+        let (management, task, tasks) = match &self.state {
+            State::Idle => (ManagementState::Idle, None, 0),
+            State::Waiting { .. } => (ManagementState::Waiting, None, 0),
+            State::Loading { .. } => (ManagementState::Loading, None, 0),
+            State::Running {
+                current_task,
+                tasks,
+                ..
+            } => (ManagementState::Running, Some(*current_task), tasks.len()),
+        };
+
+        Status {
+            state: management,
+            task,
+            tasks,
+            components: state.total_components(),
+            routes: state.route_count(),
+            period: state.schedule.period.as_micros() as u64,
+            best_effort: crate::realtime::is_best_effort(),
+        }
+    }
+
+    /// Keep the changes of the reconfiguration in progress and stop the removed components.
+    fn commit(&mut self, state: &mut crate::global_state::GlobalState) {
+        if let State::Running { transaction, .. } = std::mem::replace(&mut self.state, State::Idle)
+        {
+            let stop = transaction.commit(state);
+            self.stop_components(stop);
+        }
+    }
+
+    /// Undo the reconfiguration in progress and report how it ended.
+    fn rollback(&mut self, state: &mut crate::global_state::GlobalState, response: Reply) {
+        if let State::Running { transaction, .. } = std::mem::replace(&mut self.state, State::Idle)
+        {
+            let stop = transaction.rollback(state);
+            self.stop_components(stop);
+        }
+
+        self.finish(state, response);
+    }
+
+    fn stop_components(&mut self, implementations: Vec<(Uuid, Implementation)>) {
+        for (component_id, implementation) in implementations {
+            self.stopping
+                .push(background::RemoveComponentImplementation {
+                    component_id,
+                    implementation,
+                });
+        }
+
+        self.send_pending();
+    }
+
+    /// Mark components whose process exited as crashed, restart them when due and
+    /// hand the restarted processes their last reported state.
+    fn supervise(&mut self, state: &mut crate::global_state::GlobalState) {
+        let now = self.now();
+        self.supervisor.check(state, now);
+
+        for id in self.supervisor.due(now) {
+            let component = match state.components.get_mut(&id) {
+                Some(component) if component.crashed && !component.remove => component,
+                _ => continue,
+            };
+
+            component.restarts += 1;
+            self.restarting
+                .push(background::AddComponentImplementation {
+                    component_id: id,
+                    component: component.path.clone(),
+                    core: component.core,
+                    version: component.version.clone(),
+                    sha256: component.sha256.clone(),
+                    limits: component.limits.clone(),
+                });
+        }
+
+        let mut done_restart = match self.background.data.done_restart.try_lock() {
+            Ok(done_restart) => done_restart,
+            Err(_) => return,
+        };
+        for (id, implementation) in done_restart.drain() {
+            let component = match state.components.get_mut(&id) {
+                Some(component) => component,
+                None => {
+                    // removed while it was being restarted
+                    if let Ok(implementation) = implementation {
+                        self.stopping
+                            .push(background::RemoveComponentImplementation {
+                                component_id: id,
+                                implementation,
+                            });
+                    }
+                    continue;
+                }
+            };
+
+            match implementation {
+                Ok(implementation) if component.remove || !component.crashed => {
+                    self.stopping
+                        .push(background::RemoveComponentImplementation {
+                            component_id: id,
+                            implementation,
+                        });
+                }
+                Ok(mut implementation) => {
+                    supervise::restore(&mut implementation, &component.last_state);
+                    component.implentation = Some(implementation);
+                    component.crashed = false;
+                    log::info!("Restarted component {}", id);
+                }
+                Err(e) => {
+                    log::error!("Failed to restart component {}; err = {}", id, e);
+                    self.supervisor.schedule(id, component, now);
+                }
+            }
+        }
+    }
+
+    fn send_pending(&mut self) {
+        if self.stopping.is_empty()
+            && self.registry_changes.is_empty()
+            && self.restarting.is_empty()
+            && self.validations.is_empty()
+            && self.plans.is_empty()
+        {
+            return;
+        }
+
+        // try get a lock on actions, otherwise try again next frame
+        if let Ok(mut actions) = self.background.data.actions.try_lock() {
+            for data in self.stopping.drain(..) {
+                actions.push(background::NonBlockingImplementationData::RemoveComponent(
+                    data,
+                ));
+            }
+            for data in self.registry_changes.drain(..) {
+                actions.push(background::NonBlockingImplementationData::ChangeRegistry(
+                    data,
+                ));
+            }
+            for data in self.restarting.drain(..) {
+                actions.push(background::NonBlockingImplementationData::RestartComponent(
+                    data,
+                ));
+            }
+            for data in self.validations.drain(..) {
+                actions.push(background::NonBlockingImplementationData::Validate(data));
+            }
+            for data in self.plans.drain(..) {
+                actions.push(background::NonBlockingImplementationData::Plan(data));
+            }
+
+            // send signal to background thread
+            self.background.sender.send(()).unwrap();
+        }
+    }
+
+    /// Wait until the background thread has handled every action handed to it.
+    ///
+    /// Called every frame when simulating, so loading a configuration or
+    /// restarting a component takes the same frames every run.
+    pub fn settle(&self) {
+        // the background thread holds the actions while handling them and clears them after
+        while !self.background.data.actions.lock().unwrap().is_empty() {
+            std::thread::yield_now();
+        }
+    }
+
+    /// Go back to idle and tell whoever asked for the reconfiguration how it ended.
+    fn finish(&mut self, state: &mut crate::global_state::GlobalState, response: Reply) {
+        self.state = State::Idle;
+
+        if let Some((requester, request_id)) = self.requester.take() {
+            reply(state, requester, request_id, response);
+        }
+    }
+
+    /// Apply a blocking action, returning how to undo it.
     fn execute_blocking(
         state: &mut crate::global_state::GlobalState,
         data: elafry::types::configuration::BlockingData,
-    ) {
+    ) -> Result<Undo, String> {
         match data {
             elafry::types::configuration::BlockingData::StartComponent(data) => {
-                state.start_component(data.component_id);
+                let run = state
+                    .get_component(data.component_id)
+                    .map(|component| component.run);
+                state.start_component(data.component_id)?;
+                Ok(Undo::Run {
+                    component_id: data.component_id,
+                    run: run.unwrap_or(false),
+                })
             }
             elafry::types::configuration::BlockingData::StopComponent(data) => {
-                state.stop_component(data.component_id);
+                let run = state
+                    .get_component(data.component_id)
+                    .map(|component| component.run);
+                state.stop_component(data.component_id)?;
+                Ok(Undo::Run {
+                    component_id: data.component_id,
+                    run: run.unwrap_or(false),
+                })
             }
             elafry::types::configuration::BlockingData::AddRoute(data) => {
-                state.add_route(
-                    RouteEndpoint {
-                        endpoint: match &data.source.endpoint {
-                            elafry::types::configuration::Endpoint::Component(endpoint) => {
-                                Endpoint::Component(endpoint.clone())
-                            }
-                            elafry::types::configuration::Endpoint::Address(endpoint) => {
-                                Endpoint::Address(endpoint.parse().unwrap())
-                            }
-                            elafry::types::configuration::Endpoint::Runner => Endpoint::Runner,
-                        },
-                        channel_id: data.source.channel_id,
-                    },
-                    RouteEndpoint {
-                        endpoint: match &data.target.endpoint {
-                            elafry::types::configuration::Endpoint::Component(endpoint) => {
-                                Endpoint::Component(endpoint.clone())
-                            }
-                            elafry::types::configuration::Endpoint::Address(endpoint) => {
-                                Endpoint::Address(endpoint.clone().parse().unwrap())
-                            }
-                            elafry::types::configuration::Endpoint::Runner => Endpoint::Runner,
-                        },
-                        channel_id: data.target.channel_id,
-                    },
-                );
+                let source = runner_endpoint(&data.source)?;
+                let target = runner_endpoint(&data.target)?;
+                let added = state.add_route(source, target);
+                Ok(Undo::AddRoute {
+                    source,
+                    target: added.then_some(target),
+                })
             }
             elafry::types::configuration::BlockingData::RemoveRoute(data) => {
-                state.remove_route(RouteEndpoint {
-                    endpoint: match &data.source.endpoint {
-                        elafry::types::configuration::Endpoint::Component(endpoint) => {
-                            Endpoint::Component(endpoint.clone())
-                        }
-                        elafry::types::configuration::Endpoint::Address(endpoint) => {
-                            Endpoint::Address(endpoint.parse().unwrap())
+                let source = runner_endpoint(&data.source)?;
+                let targets = match data.target {
+                    Some(target) => {
+                        let target = runner_endpoint(&target)?;
+                        if state.remove_route(source, target) {
+                            vec![target]
+                        } else {
+                            vec![]
                         }
-                        elafry::types::configuration::Endpoint::Runner => Endpoint::Runner,
-                    },
-                    channel_id: data.source.channel_id,
-                });
+                    }
+                    None => state.remove_routes(source),
+                };
+                Ok(Undo::RemoveRoute { source, targets })
             }
             elafry::types::configuration::BlockingData::SetSchedule(data) => {
-                state.set_schedule(Schedule {
+                let previous = state.set_schedule(Schedule {
                     period: std::time::Duration::from_micros(data.deadline),
                     major_frames: data
                         .major_frames
@@ -356,10 +1054,11 @@ impl ManagementService {
                                 .collect(),
                         })
                         .collect(),
-                });
+                })?;
+                Ok(Undo::Schedule(previous))
             }
             elafry::types::configuration::BlockingData::AddStateSync(data) => {
-                state.add_state_sync(
+                let state_sync = state.add_state_sync(
                     data.state_sync_id,
                     StateEndpoint {
                         component_id: data.source.component_id,
@@ -368,9 +1067,17 @@ impl ManagementService {
                         component_id: data.target.component_id,
                     },
                 );
+                Ok(Undo::StateSync {
+                    state_sync_id: data.state_sync_id,
+                    state_sync,
+                })
             }
             elafry::types::configuration::BlockingData::RemoveStateSync(data) => {
-                state.remove_state_sync(data.state_sync_id);
+                let state_sync = state.remove_state_sync(data.state_sync_id);
+                Ok(Undo::StateSync {
+                    state_sync_id: data.state_sync_id,
+                    state_sync,
+                })
             }
         }
     }
@@ -380,25 +1087,1164 @@ impl ManagementService {
         status: &mut ActionState,
         sender: Sender<()>,
         background: BackgroundData,
+        transaction: &mut Transaction,
         data: elafry::types::configuration::NonBlockingData,
-    ) {
+    ) -> Result<(), String> {
         // dereference background data
-        let (actions, done_implement, done_remove) = (
+        let (actions, done_implement) = (
             background.actions.clone(),
             background.done_implement.clone(),
-            background.done_remove.clone(),
         );
 
         match data {
             elafry::types::configuration::NonBlockingData::AddComponent(data) => {
-                background::add_component(state, status, sender, actions, done_implement, data);
+                background::add_component(
+                    state,
+                    status,
+                    sender,
+                    actions,
+                    done_implement,
+                    transaction,
+                    data,
+                )
             }
             elafry::types::configuration::NonBlockingData::RemoveComponent(data) => {
-                background::remove_component(state, status, sender, actions, done_remove, data);
+                background::remove_component(state, status, transaction, data)
             }
             elafry::types::configuration::NonBlockingData::WaitStateSync(data) => {
-                background::wait_state_sync(state, status, data);
+                background::wait_state_sync(state, status, transaction, data)
             }
         }
     }
 }
+
+/// Start running the tasks of a configuration.
+fn running(configuration: Configuration) -> State {
+    State::Running {
+        current_task: 0,
+        tasks: configuration.tasks,
+        blocked: false,
+        action_status: HashMap::new(),
+        transaction: Transaction::new(),
+    }
+}
+
+/// A schedule in the form of a set-schedule action.
+fn schedule_data(schedule: &Schedule) -> SetScheduleData {
+    SetScheduleData {
+        deadline: schedule.period.as_micros() as u64,
+        major_frames: schedule
+            .major_frames
+            .iter()
+            .map(|frame| elafry::types::configuration::MajorFrame {
+                minor_frames: frame
+                    .minor_frames
+                    .iter()
+                    .map(|frame| elafry::types::configuration::MinorFrame {
+                        component_id: frame.component_id,
+                        deadline: frame.deadline.as_micros() as u64,
+                    })
+                    .collect(),
+            })
+            .collect(),
+    }
+}
+
+fn blocking_kind(data: &elafry::types::configuration::BlockingData) -> ActionKind {
+    match data {
+        elafry::types::configuration::BlockingData::StartComponent(_) => ActionKind::StartComponent,
+        elafry::types::configuration::BlockingData::StopComponent(_) => ActionKind::StopComponent,
+        elafry::types::configuration::BlockingData::AddRoute(_) => ActionKind::AddRoute,
+        elafry::types::configuration::BlockingData::RemoveRoute(_) => ActionKind::RemoveRoute,
+        elafry::types::configuration::BlockingData::SetSchedule(_) => ActionKind::SetSchedule,
+        elafry::types::configuration::BlockingData::AddStateSync(_) => ActionKind::AddStateSync,
+        elafry::types::configuration::BlockingData::RemoveStateSync(_) => {
+            ActionKind::RemoveStateSync
+        }
+    }
+}
+
+/// Route endpoint from a configuration, addresses must be valid socket addresses.
+fn runner_endpoint(
+    endpoint: &elafry::types::configuration::RouteEndpoint,
+) -> Result<RouteEndpoint, String> {
+    let converted = match &endpoint.endpoint {
+        elafry::types::configuration::Endpoint::Component(id) => Endpoint::Component(*id),
+        elafry::types::configuration::Endpoint::Address(address) => Endpoint::Address(
+            address
+                .parse()
+                .map_err(|e| format!("Failed to parse address {}; err = {}", address, e))?,
+        ),
+        elafry::types::configuration::Endpoint::Runner => Endpoint::Runner,
+    };
+
+    Ok(RouteEndpoint {
+        endpoint: converted,
+        channel_id: endpoint.channel_id,
+    })
+}
+
+/// Route endpoint as written in a configuration, control clients have no such form.
+fn configuration_endpoint(
+    endpoint: &RouteEndpoint,
+) -> Option<elafry::types::configuration::RouteEndpoint> {
+    let converted = match endpoint.endpoint {
+        Endpoint::Component(id) => elafry::types::configuration::Endpoint::Component(id),
+        Endpoint::Address(address) => {
+            elafry::types::configuration::Endpoint::Address(address.to_string())
+        }
+        Endpoint::Runner => elafry::types::configuration::Endpoint::Runner,
+        Endpoint::Control(_) => return None,
+    };
+
+    Some(elafry::types::configuration::RouteEndpoint {
+        endpoint: converted,
+        channel_id: endpoint.channel_id,
+    })
+}
+
+fn non_blocking_kind(data: &elafry::types::configuration::NonBlockingData) -> ActionKind {
+    match data {
+        elafry::types::configuration::NonBlockingData::AddComponent(_) => ActionKind::AddComponent,
+        elafry::types::configuration::NonBlockingData::RemoveComponent(_) => {
+            ActionKind::RemoveComponent
+        }
+        elafry::types::configuration::NonBlockingData::WaitStateSync(_) => {
+            ActionKind::WaitStateSync
+        }
+    }
+}
+
+fn record_action(
+    recorder: &mut Recorder,
+    frame_number: u64,
+    kind: ActionKind,
+    id: Uuid,
+    value: u64,
+) {
+    let now = elafry::telemetry::now();
+    recorder.record(Record {
+        frame: frame_number,
+        span: Span::Action(kind, id),
+        start: now,
+        end: now,
+        value,
+    });
+}
+
+fn reply(
+    state: &mut crate::global_state::GlobalState,
+    target: RouteEndpoint,
+    request_id: u64,
+    reply: Reply,
+) {
+    let response = Response::new(request_id, reply);
+    state.send_message(
+        elafry::types::management::CHANNEL,
+        target,
+        response.encode(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::global_state::GlobalState;
+    use elafry::types::communication::Message;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    fn recorder() -> Recorder {
+        let path = std::env::temp_dir().join(format!("telemetry-{}.bin", Uuid::new_v4()));
+        Recorder::new(path.to_str().unwrap(), 16).unwrap()
+    }
+
+    fn requester() -> RouteEndpoint {
+        RouteEndpoint {
+            endpoint: Endpoint::Address("127.0.0.1:6000".parse().unwrap()),
+            channel_id: 4,
+        }
+    }
+
+    fn request(state: &mut GlobalState, request_id: u64, command: Command) {
+        let message = Message {
+            channel_id: elafry::types::management::CHANNEL,
+            count: 0,
+            data: Request::new(request_id, command).encode(),
+        };
+        state
+            .messages
+            .entry(elafry::types::management::CHANNEL)
+            .or_default()
+            .push((requester(), message));
+    }
+
+    fn responses(state: &mut GlobalState) -> Vec<Response> {
+        state
+            .outbox
+            .drain(..)
+            .map(|(_, target, message)| {
+                assert_eq!(target, requester());
+                assert_eq!(message.channel_id, 4);
+                Response::decode(&message.data).unwrap()
+            })
+            .collect()
+    }
+
+    // a management service that is idle rather than loading its initial configuration
+    fn idle(state: &mut GlobalState, recorder: &mut Recorder) -> ManagementService {
+        let mut management_service = ManagementService::new(
+            std::path::PathBuf::from("configuration/missing.yaml"),
+            std::time::Duration::from_secs(10),
+            vec![],
+            None,
+            None,
+            None,
+            None,
+        );
+        request(state, 1, Command::Abort);
+        management_service.run(state, recorder, 0);
+        assert_eq!(responses(state), vec![Response::new(1, Reply::Aborted)]);
+        management_service
+    }
+
+    #[test]
+    fn test_management_status() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let mut recorder = recorder();
+        let mut management_service = idle(&mut state, &mut recorder);
+
+        request(&mut state, 2, Command::Status);
+        request(&mut state, 3, Command::ListComponents);
+        management_service.run(&mut state, &mut recorder, 1);
+
+        assert_eq!(
+            responses(&mut state),
+            vec![
+                Response::new(
+                    2,
+                    Reply::Status(Status {
+                        state: ManagementState::Idle,
+                        task: None,
+                        tasks: 0,
+                        components: 0,
+                        routes: 0,
+                        period: 1000,
+                        best_effort: false,
+                    })
+                ),
+                Response::new(3, Reply::Components(vec![])),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_management_routes_schedule() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let mut recorder = recorder();
+        let mut management_service = idle(&mut state, &mut recorder);
+
+        let id = uuid::Uuid::new_v4();
+        state.add_route(
+            RouteEndpoint {
+                endpoint: Endpoint::Component(id),
+                channel_id: 1,
+            },
+            requester(),
+        );
+        // a second target of the same source is listed after the first
+        state.add_route(
+            RouteEndpoint {
+                endpoint: Endpoint::Component(id),
+                channel_id: 1,
+            },
+            RouteEndpoint {
+                endpoint: Endpoint::Runner,
+                channel_id: 5,
+            },
+        );
+        // routes to control clients are not listed
+        state.add_route(
+            RouteEndpoint {
+                endpoint: Endpoint::Control(1),
+                channel_id: 0,
+            },
+            RouteEndpoint {
+                endpoint: Endpoint::Runner,
+                channel_id: 0,
+            },
+        );
+        // set directly, the component is not running
+        state.schedule = Schedule {
+            period: std::time::Duration::from_micros(2000),
+            major_frames: vec![MajorFrame {
+                minor_frames: vec![MinorFrame {
+                    component_id: id,
+                    deadline: std::time::Duration::from_micros(500),
+                }],
+            }],
+        };
+
+        request(&mut state, 2, Command::ListRoutes);
+        request(&mut state, 3, Command::Schedule);
+        management_service.run(&mut state, &mut recorder, 1);
+
+        assert_eq!(
+            responses(&mut state),
+            vec![
+                Response::new(
+                    2,
+                    Reply::Routes(vec![
+                        RouteStatus {
+                            source: elafry::types::configuration::RouteEndpoint {
+                                endpoint: elafry::types::configuration::Endpoint::Component(id),
+                                channel_id: 1,
+                            },
+                            target: elafry::types::configuration::RouteEndpoint {
+                                endpoint: elafry::types::configuration::Endpoint::Address(
+                                    "127.0.0.1:6000".to_string()
+                                ),
+                                channel_id: 4,
+                            },
+                        },
+                        RouteStatus {
+                            source: elafry::types::configuration::RouteEndpoint {
+                                endpoint: elafry::types::configuration::Endpoint::Component(id),
+                                channel_id: 1,
+                            },
+                            target: elafry::types::configuration::RouteEndpoint {
+                                endpoint: elafry::types::configuration::Endpoint::Runner,
+                                channel_id: 5,
+                            },
+                        }
+                    ])
+                ),
+                Response::new(
+                    3,
+                    Reply::Schedule(SetScheduleData {
+                        deadline: 2000,
+                        major_frames: vec![elafry::types::configuration::MajorFrame {
+                            minor_frames: vec![elafry::types::configuration::MinorFrame {
+                                component_id: id,
+                                deadline: 500,
+                            }],
+                        }],
+                    })
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_management_invalid_requests() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let mut recorder = recorder();
+        let mut management_service = idle(&mut state, &mut recorder);
+
+        // the old file name protocol is not a valid request
+        state
+            .messages
+            .entry(elafry::types::management::CHANNEL)
+            .or_default()
+            .push((
+                requester(),
+                Message {
+                    channel_id: 0,
+                    count: 0,
+                    data: b"plant_1.yaml".to_vec(),
+                },
+            ));
+        management_service.run(&mut state, &mut recorder, 1);
+        assert_eq!(
+            responses(&mut state),
+            vec![Response::new(0, Reply::Error(Error::Malformed))]
+        );
+
+        let mut future = Request::new(2, Command::Status);
+        future.version = 99;
+        state
+            .messages
+            .entry(elafry::types::management::CHANNEL)
+            .or_default()
+            .push((
+                requester(),
+                Message {
+                    channel_id: 0,
+                    count: 0,
+                    data: future.encode(),
+                },
+            ));
+        request(&mut state, 3, Command::Load("../secret.yaml".to_string()));
+        request(&mut state, 4, Command::Abort);
+        management_service.run(&mut state, &mut recorder, 2);
+        assert_eq!(
+            responses(&mut state),
+            vec![
+                Response::new(2, Reply::Error(Error::UnsupportedVersion(99))),
+                Response::new(
+                    3,
+                    Reply::Error(Error::InvalidName("../secret.yaml".to_string()))
+                ),
+                Response::new(4, Reply::Error(Error::NothingToAbort)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_management_load_inline() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let mut recorder = recorder();
+        let mut management_service = idle(&mut state, &mut recorder);
+
+        let configuration = elafry::types::configuration::Configuration {
+            tasks: vec![elafry::types::configuration::Task {
+                id: Uuid::new_v4(),
+                actions: elafry::types::configuration::Action::Blocking(vec![
+                    elafry::types::configuration::BlockingAction {
+                        id: Uuid::new_v4(),
+                        data: elafry::types::configuration::BlockingData::SetSchedule(
+                            elafry::types::configuration::SetScheduleData {
+                                deadline: 2000,
+                                major_frames: vec![],
+                            },
+                        ),
+                    },
+                ]),
+            }],
+        };
+
+        request(&mut state, 2, Command::LoadInline(configuration.clone()));
+        request(&mut state, 3, Command::LoadInline(configuration));
+        management_service.run(&mut state, &mut recorder, 1);
+
+        // the only task runs in the same frame, a second load is rejected while busy
+        assert_eq!(
+            responses(&mut state),
+            vec![
+                Response::new(2, Reply::Accepted),
+                Response::new(3, Reply::Error(Error::Busy)),
+                Response::new(2, Reply::Loaded),
+            ]
+        );
+        assert_eq!(state.schedule.period.as_micros(), 2000);
+        assert_eq!(management_service.status(), ("idle", None));
+    }
+
+    #[test]
+    fn test_management_validate_inline() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let mut recorder = recorder();
+        let mut management_service = idle(&mut state, &mut recorder);
+
+        let component_id = Uuid::new_v4();
+        let schedule_id = Uuid::new_v4();
+        let action_id = Uuid::new_v4();
+        let configuration = elafry::types::configuration::Configuration {
+            tasks: vec![elafry::types::configuration::Task {
+                id: Uuid::new_v4(),
+                actions: elafry::types::configuration::Action::Blocking(vec![
+                    elafry::types::configuration::BlockingAction {
+                        id: schedule_id,
+                        data: elafry::types::configuration::BlockingData::SetSchedule(
+                            elafry::types::configuration::SetScheduleData {
+                                deadline: 2000,
+                                major_frames: vec![],
+                            },
+                        ),
+                    },
+                    elafry::types::configuration::BlockingAction {
+                        id: action_id,
+                        data: elafry::types::configuration::BlockingData::StartComponent(
+                            elafry::types::configuration::StartComponentData { component_id },
+                        ),
+                    },
+                ]),
+            }],
+        };
+
+        // the dry run is done in the background, the report follows the acceptance
+        request(&mut state, 2, Command::ValidateInline(configuration));
+        let mut frame = 1;
+        let mut replies = vec![];
+        while replies.len() < 2 {
+            management_service.run(&mut state, &mut recorder, frame);
+            replies.extend(responses(&mut state));
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            frame += 1;
+        }
+
+        assert_eq!(
+            replies,
+            vec![
+                Response::new(2, Reply::Accepted),
+                Response::new(
+                    2,
+                    Reply::Report(vec![
+                        elafry::types::management::Issue {
+                            severity: elafry::types::management::Severity::Warning,
+                            task: Some(0),
+                            action: Some(schedule_id),
+                            message: "Schedule has no major frames".to_string(),
+                        },
+                        elafry::types::management::Issue {
+                            severity: elafry::types::management::Severity::Error,
+                            task: Some(0),
+                            action: Some(action_id),
+                            message: format!("Component {} not found", component_id),
+                        },
+                    ])
+                )
+            ]
+        );
+
+        // nothing was applied
+        assert_eq!(state.schedule.period.as_micros(), 1000);
+        assert_eq!(management_service.status(), ("idle", None));
+    }
+
+    #[test]
+    fn test_management_apply_inline() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let mut recorder = recorder();
+        let mut management_service = idle(&mut state, &mut recorder);
+
+        let desired = elafry::types::desired::DesiredState {
+            components: vec![],
+            routes: vec![],
+            schedule: SetScheduleData {
+                deadline: 2000,
+                major_frames: vec![],
+            },
+        };
+        let mut frame = 1;
+        let mut replies = |state: &mut GlobalState, request_id: u64, command: Command| {
+            request(state, request_id, command);
+            let mut replies = vec![];
+            while replies.len() < 2 {
+                management_service.run(state, &mut recorder, frame);
+                replies.extend(responses(state));
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                frame += 1;
+            }
+            replies
+        };
+
+        // planned in the background, the plan follows the acceptance
+        let plan = replies(&mut state, 2, Command::PlanInline(desired.clone()));
+        assert_eq!(plan[0], Response::new(2, Reply::Accepted));
+        match &plan[1].reply {
+            Reply::Plan(configuration) => match &configuration.tasks[..] {
+                [task] => match &task.actions {
+                    elafry::types::configuration::Action::Blocking(actions) => {
+                        assert_eq!(
+                            actions[0].data,
+                            elafry::types::configuration::BlockingData::SetSchedule(
+                                desired.schedule.clone()
+                            )
+                        );
+                    }
+                    actions => panic!("unexpected actions {:?}", actions),
+                },
+                tasks => panic!("unexpected tasks {:?}", tasks),
+            },
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+        assert_eq!(state.schedule.period.as_micros(), 1000);
+
+        // applied like a desired state read from a file
+        assert_eq!(
+            replies(&mut state, 3, Command::ApplyInline(desired.clone())),
+            vec![
+                Response::new(3, Reply::Accepted),
+                Response::new(3, Reply::Loaded)
+            ]
+        );
+        assert_eq!(state.schedule.period.as_micros(), 2000);
+
+        // nothing left to do
+        assert_eq!(
+            replies(&mut state, 4, Command::ApplyInline(desired)),
+            vec![
+                Response::new(4, Reply::Accepted),
+                Response::new(4, Reply::Loaded)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_management_upload() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let mut recorder = recorder();
+        let mut management_service = idle(&mut state, &mut recorder);
+
+        let schedule_id = Uuid::new_v4();
+        let document = format!(
+            "{{\"tasks\": [{{\"id\": \"{}\", \"actions\": {{\"blocking\": [{{\"id\": \"{}\", \"data\": {{\"set-schedule\": {{\"deadline\": 2000, \"major-frames\": []}}}}}}]}}}}]}}",
+            Uuid::new_v4(),
+            schedule_id
+        );
+        let (first, second) = document.as_bytes().split_at(document.len() / 2);
+        let chunk = |index: u32, data: &[u8]| {
+            Command::Upload(elafry::types::management::Chunk {
+                upload: 5,
+                index,
+                count: 2,
+                data: data.to_vec(),
+            })
+        };
+
+        // the upload cannot be loaded before every chunk has arrived
+        request(&mut state, 2, chunk(1, second));
+        request(&mut state, 3, Command::ValidateUpload(5));
+        request(&mut state, 4, chunk(0, first));
+        request(&mut state, 5, Command::ValidateUpload(5));
+        management_service.run(&mut state, &mut recorder, 1);
+        assert_eq!(
+            responses(&mut state),
+            vec![
+                Response::new(2, Reply::Received(1)),
+                Response::new(3, Reply::Error(Error::UnknownUpload(5))),
+                Response::new(4, Reply::Received(2)),
+                Response::new(5, Reply::Accepted),
+            ]
+        );
+
+        // a validated upload is parsed and checked by the background thread and reported on
+        let mut frame = 2;
+        let mut replies = vec![];
+        while replies.is_empty() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            management_service.run(&mut state, &mut recorder, frame);
+            replies.extend(responses(&mut state));
+            frame += 1;
+        }
+        assert_eq!(
+            replies,
+            vec![Response::new(
+                5,
+                Reply::Report(vec![elafry::types::management::Issue {
+                    severity: elafry::types::management::Severity::Warning,
+                    task: Some(0),
+                    action: Some(schedule_id),
+                    message: "Schedule has no major frames".to_string(),
+                }])
+            )]
+        );
+
+        // loading takes the upload, it has to be sent again
+        request(&mut state, 6, Command::LoadUpload(5));
+        request(&mut state, 7, chunk(0, first));
+        request(&mut state, 8, chunk(1, second));
+        request(&mut state, 9, Command::LoadUpload(5));
+        management_service.run(&mut state, &mut recorder, frame);
+        assert_eq!(
+            responses(&mut state),
+            vec![
+                Response::new(6, Reply::Error(Error::UnknownUpload(5))),
+                Response::new(7, Reply::Received(1)),
+                Response::new(8, Reply::Received(2)),
+                Response::new(9, Reply::Accepted),
+            ]
+        );
+        while management_service.status().0 != "idle" {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            management_service.run(&mut state, &mut recorder, frame);
+            frame += 1;
+        }
+        assert_eq!(responses(&mut state), vec![Response::new(9, Reply::Loaded)]);
+        assert_eq!(state.schedule.period.as_micros(), 2000);
+
+        request(
+            &mut state,
+            10,
+            Command::Upload(elafry::types::management::Chunk {
+                upload: 6,
+                index: 2,
+                count: 2,
+                data: vec![],
+            }),
+        );
+        management_service.run(&mut state, &mut recorder, frame);
+        assert_eq!(
+            responses(&mut state),
+            vec![Response::new(
+                10,
+                Reply::Error(Error::InvalidChunk(
+                    "Chunk 2 of 2 is out of range".to_string()
+                ))
+            )]
+        );
+    }
+
+    #[test]
+    fn test_management_rollback() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let mut recorder = recorder();
+        let mut management_service = idle(&mut state, &mut recorder);
+
+        let route = |address: &str| elafry::types::configuration::BlockingAction {
+            id: Uuid::new_v4(),
+            data: elafry::types::configuration::BlockingData::AddRoute(
+                elafry::types::configuration::AddRouteData {
+                    source: elafry::types::configuration::RouteEndpoint {
+                        endpoint: elafry::types::configuration::Endpoint::Address(
+                            address.to_string(),
+                        ),
+                        channel_id: 1,
+                    },
+                    target: elafry::types::configuration::RouteEndpoint {
+                        endpoint: elafry::types::configuration::Endpoint::Runner,
+                        channel_id: 1,
+                    },
+                },
+            ),
+        };
+
+        let configuration = elafry::types::configuration::Configuration {
+            tasks: vec![
+                elafry::types::configuration::Task {
+                    id: Uuid::new_v4(),
+                    actions: elafry::types::configuration::Action::Blocking(vec![
+                        route("127.0.0.1:7000"),
+                        elafry::types::configuration::BlockingAction {
+                            id: Uuid::new_v4(),
+                            data: elafry::types::configuration::BlockingData::SetSchedule(
+                                elafry::types::configuration::SetScheduleData {
+                                    deadline: 2000,
+                                    major_frames: vec![],
+                                },
+                            ),
+                        },
+                    ]),
+                },
+                elafry::types::configuration::Task {
+                    id: Uuid::new_v4(),
+                    actions: elafry::types::configuration::Action::Blocking(vec![route(
+                        "not an address",
+                    )]),
+                },
+            ],
+        };
+
+        // the first task is applied in the first frame
+        request(&mut state, 2, Command::LoadInline(configuration));
+        management_service.run(&mut state, &mut recorder, 1);
+        assert_eq!(
+            responses(&mut state),
+            vec![Response::new(2, Reply::Accepted)]
+        );
+        assert_eq!(state.routes.len(), 1);
+        assert_eq!(state.schedule.period.as_micros(), 2000);
+
+        // the second task fails and the first is undone
+        management_service.run(&mut state, &mut recorder, 2);
+        match &responses(&mut state)[..] {
+            [Response {
+                request_id: 2,
+                reply: Reply::Error(Error::RolledBack(reason)),
+                ..
+            }] => assert!(reason.starts_with("Failed to parse address not an address")),
+            responses => panic!("unexpected responses {:?}", responses),
+        }
+        assert!(state.routes.is_empty());
+        assert_eq!(state.schedule.period.as_micros(), 1000);
+        assert_eq!(management_service.status(), ("idle", None));
+    }
+
+    #[test]
+    fn test_management_rollback_timeout() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let mut recorder = recorder();
+        let mut management_service = idle(&mut state, &mut recorder);
+        management_service.timeout = std::time::Duration::from_millis(20);
+
+        // wait for a state sync that never completes
+        let state_sync_id = Uuid::new_v4();
+        let configuration = elafry::types::configuration::Configuration {
+            tasks: vec![
+                elafry::types::configuration::Task {
+                    id: Uuid::new_v4(),
+                    actions: elafry::types::configuration::Action::Blocking(vec![
+                        elafry::types::configuration::BlockingAction {
+                            id: Uuid::new_v4(),
+                            data: elafry::types::configuration::BlockingData::AddStateSync(
+                                elafry::types::configuration::AddStateSyncData {
+                                    state_sync_id,
+                                    source: elafry::types::configuration::StateEndpoint {
+                                        component_id: Uuid::new_v4(),
+                                    },
+                                    target: elafry::types::configuration::StateEndpoint {
+                                        component_id: Uuid::new_v4(),
+                                    },
+                                },
+                            ),
+                        },
+                    ]),
+                },
+                elafry::types::configuration::Task {
+                    id: Uuid::new_v4(),
+                    actions: elafry::types::configuration::Action::NonBlocking(vec![
+                        elafry::types::configuration::NonBlockingAction {
+                            id: Uuid::new_v4(),
+                            data: elafry::types::configuration::NonBlockingData::WaitStateSync(
+                                elafry::types::configuration::WaitStateSyncData { state_sync_id },
+                            ),
+                        },
+                    ]),
+                },
+            ],
+        };
+
+        request(&mut state, 2, Command::LoadInline(configuration));
+        let mut frame = 1;
+        loop {
+            management_service.run(&mut state, &mut recorder, frame);
+            if management_service.status().0 == "idle" {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            frame += 1;
+        }
+
+        assert_eq!(
+            responses(&mut state),
+            vec![
+                Response::new(2, Reply::Accepted),
+                Response::new(
+                    2,
+                    Reply::Error(Error::RolledBack("Timed out after 20ms".to_string()))
+                ),
+            ]
+        );
+        assert!(state.state_sync.is_empty());
+    }
+
+    #[test]
+    fn test_management_trusted_keys() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let mut recorder = recorder();
+        let mut management_service = idle(&mut state, &mut recorder);
+        let key = elafry::signature::generate_key().unwrap();
+        management_service.trusted_keys = vec![key.verifying_key()];
+
+        // inline configurations cannot be signed
+        request(
+            &mut state,
+            2,
+            Command::LoadInline(Configuration { tasks: vec![] }),
+        );
+        request(
+            &mut state,
+            3,
+            Command::ApplyInline(elafry::types::desired::DesiredState {
+                components: vec![],
+                routes: vec![],
+                schedule: SetScheduleData {
+                    deadline: 1000,
+                    major_frames: vec![],
+                },
+            }),
+        );
+        management_service.run(&mut state, &mut recorder, 1);
+        assert_eq!(
+            responses(&mut state),
+            vec![
+                Response::new(2, Reply::Error(Error::Unsigned)),
+                Response::new(3, Reply::Error(Error::Unsigned)),
+            ]
+        );
+
+        let text = format!(
+            "tasks:\n  - id: {}\n    actions: !blocking\n      - id: {}\n        data: !set-schedule\n          deadline: 2000\n          major-frames: []\n",
+            Uuid::new_v4(),
+            Uuid::new_v4()
+        );
+        let signature = elafry::signature::sign(&elafry::loader::from_yaml(&text).unwrap(), &key);
+        let signed = format!(
+            "{}signature:\n  key: {}\n  signature: {}\n",
+            text, signature.key, signature.signature
+        );
+
+        let mut frame = 2;
+        let mut load = |state: &mut GlobalState, upload: u64, document: &str| {
+            for chunk in elafry::types::management::Chunk::split(upload, document.as_bytes()) {
+                request(state, 4, Command::Upload(chunk));
+            }
+            request(state, 5, Command::LoadUpload(upload));
+            management_service.run(state, &mut recorder, frame);
+            while management_service.status().0 != "idle" {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                frame += 1;
+                management_service.run(state, &mut recorder, frame);
+            }
+            responses(state).pop().unwrap()
+        };
+
+        assert_eq!(
+            load(&mut state, 1, &text),
+            Response::new(
+                5,
+                Reply::Error(Error::LoadFailed("Configuration is not signed".to_string()))
+            )
+        );
+        assert_eq!(state.schedule.period.as_micros(), 1000);
+        assert_eq!(
+            load(&mut state, 2, &signed),
+            Response::new(5, Reply::Loaded)
+        );
+        assert_eq!(state.schedule.period.as_micros(), 2000);
+    }
+
+    #[test]
+    fn test_management_registry() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let mut recorder = recorder();
+        let mut management_service = idle(&mut state, &mut recorder);
+
+        let install = Command::Install(elafry::types::management::InstallData {
+            component: "sh".to_string(),
+            version: "1.0.0".to_string(),
+            path: "/bin/sh".to_string(),
+        });
+        request(&mut state, 2, install.clone());
+        request(&mut state, 3, Command::ListRegistry);
+        management_service.run(&mut state, &mut recorder, 1);
+        assert_eq!(
+            responses(&mut state),
+            vec![
+                Response::new(2, Reply::Error(Error::NoRegistry)),
+                Response::new(3, Reply::Error(Error::NoRegistry)),
+            ]
+        );
+
+        let directory = std::env::temp_dir().join(format!("elafry-registry-{}", Uuid::new_v4()));
+        let mut management_service = ManagementService::new(
+            std::path::PathBuf::from("configuration/missing.yaml"),
+            std::time::Duration::from_secs(10),
+            vec![],
+            Some(Registry::open(&directory).unwrap()),
+            None,
+            None,
+            None,
+        );
+        request(&mut state, 1, Command::Abort);
+        management_service.run(&mut state, &mut recorder, 1);
+        assert_eq!(
+            responses(&mut state),
+            vec![Response::new(1, Reply::Aborted)]
+        );
+
+        // the copy is made in the background, the outcome follows the acceptance
+        request(&mut state, 4, install);
+        let mut frame = 2;
+        let mut replies = vec![];
+        while replies.len() < 2 {
+            management_service.run(&mut state, &mut recorder, frame);
+            replies.extend(responses(&mut state));
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            frame += 1;
+        }
+        let sha256 = elafry::signature::sha256(&std::fs::read("/bin/sh").unwrap());
+        assert_eq!(
+            replies,
+            vec![
+                Response::new(4, Reply::Accepted),
+                Response::new(4, Reply::Installed(sha256.clone())),
+            ]
+        );
+
+        request(&mut state, 5, Command::ListRegistry);
+        management_service.run(&mut state, &mut recorder, frame);
+        assert_eq!(
+            responses(&mut state),
+            vec![Response::new(
+                5,
+                Reply::Registry(vec![elafry::types::management::RegistryEntry {
+                    component: "sh".to_string(),
+                    version: "1.0.0".to_string(),
+                    sha256,
+                }])
+            )]
+        );
+
+        // a version in use is kept
+        let id = Uuid::new_v4();
+        state.add_component(id, "sh".to_string(), 1, "1.0.0".to_string());
+        let uninstall = Command::Uninstall(elafry::types::management::ComponentVersion {
+            component: "sh".to_string(),
+            version: "1.0.0".to_string(),
+        });
+        request(&mut state, 6, uninstall.clone());
+        management_service.run(&mut state, &mut recorder, frame);
+        assert_eq!(
+            responses(&mut state),
+            vec![Response::new(
+                6,
+                Reply::Error(Error::RegistryFailed(format!(
+                    "Component sh version 1.0.0 is used by {}",
+                    id
+                )))
+            )]
+        );
+
+        state.components.remove(&id);
+        request(&mut state, 7, uninstall);
+        let mut replies = vec![];
+        while replies.len() < 2 {
+            frame += 1;
+            management_service.run(&mut state, &mut recorder, frame);
+            replies.extend(responses(&mut state));
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(
+            replies,
+            vec![
+                Response::new(7, Reply::Accepted),
+                Response::new(7, Reply::Uninstalled),
+            ]
+        );
+        assert!(!directory.join("sh").exists());
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_management_load_failed() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let mut recorder = recorder();
+        let mut management_service = idle(&mut state, &mut recorder);
+
+        request(&mut state, 2, Command::Load("missing.yaml".to_string()));
+        management_service.run(&mut state, &mut recorder, 1);
+        assert_eq!(
+            responses(&mut state),
+            vec![Response::new(2, Reply::Accepted)]
+        );
+
+        // wait for the background thread to fail to open the file
+        let mut frame = 2;
+        while management_service.status().0 != "idle" {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            management_service.run(&mut state, &mut recorder, frame);
+            frame += 1;
+        }
+
+        match &responses(&mut state)[..] {
+            [Response {
+                request_id: 2,
+                reply: Reply::Error(Error::LoadFailed(_)),
+                ..
+            }] => {}
+            responses => panic!("unexpected responses {:?}", responses),
+        }
+    }
+
+    #[test]
+    fn test_management_shutdown() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let mut recorder = recorder();
+        let mut management_service = idle(&mut state, &mut recorder);
+
+        request(&mut state, 2, Command::Shutdown);
+        management_service.run(&mut state, &mut recorder, 1);
+
+        assert!(state.get_done());
+        assert_eq!(
+            responses(&mut state),
+            vec![Response::new(2, Reply::ShuttingDown)]
+        );
+    }
+
+    #[test]
+    fn test_management_restart() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let mut recorder = recorder();
+        let mut management_service = idle(&mut state, &mut recorder);
+
+        // the restarted process writes the state it is handed to a file
+        let directory = std::env::temp_dir().join(format!("elafry-restart-{}", Uuid::new_v4()));
+        std::fs::create_dir(&directory).unwrap();
+        let restored = directory.join("state");
+        let script = directory.join("component");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/bash\nexec dd bs=1 count=7 of={} <&11 2>/dev/null\n",
+                restored.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+
+        // a component whose process has crashed
+        let id = Uuid::new_v4();
+        state.add_component(
+            id,
+            script.to_string_lossy().into_owned(),
+            1,
+            "1.0.0".to_string(),
+        );
+        let component = state.components.get_mut(&id).unwrap();
+        component.restart.policy = elafry::types::configuration::Restart::Always;
+        component.crashed = true;
+        component.last_state = b"abc".to_vec();
+        management_service
+            .supervisor
+            .schedule(id, &state.components[&id], crate::timing::now());
+
+        let mut frame = 1;
+        while state.components[&id].crashed {
+            assert!(frame < 5000);
+            management_service.run(&mut state, &mut recorder, frame);
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            frame += 1;
+        }
+        assert_eq!(state.components[&id].restarts, 1);
+
+        // the process is handed the last state, length first
+        let mut implementation = state.remove_component_implementation(id).unwrap().unwrap();
+        assert!(implementation.child.wait().unwrap().success());
+        assert_eq!(
+            std::fs::read(&restored).unwrap(),
+            [&3u32.to_be_bytes()[..], b"abc"].concat()
+        );
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
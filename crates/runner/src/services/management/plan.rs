@@ -0,0 +1,626 @@
+use std::collections::{HashMap, HashSet};
+
+use elafry::types::configuration::{
+    Action, AddComponentData, AddRouteData, AddStateSyncData, BlockingAction, BlockingData,
+    Configuration, NonBlockingAction, NonBlockingData, RemoveComponentData, RemoveRouteData,
+    RemoveStateSyncData, RouteEndpoint, SetScheduleData, StartComponentData, StateEndpoint,
+    StopComponentData, Task, WaitStateSyncData,
+};
+use elafry::types::desired::DesiredState;
+use uuid::Uuid;
+
+use crate::global_state::GlobalState;
+
+use super::{configuration_endpoint, schedule_data};
+
+/// What a plan starts from, copied from the runner state so planning can run
+/// off the frame loop.
+pub struct Current {
+    components: HashMap<Uuid, Component>,
+    /// Routes as a configuration gives them, routes to control clients left out.
+    routes: HashSet<(RouteEndpoint, RouteEndpoint)>,
+    schedule: SetScheduleData,
+}
+
+struct Component {
+    path: String,
+    version: String,
+    core: usize,
+    run: bool,
+    remove: bool,
+}
+
+impl Current {
+    pub fn new(state: &GlobalState) -> Current {
+        Current {
+            components: state
+                .components
+                .iter()
+                .map(|(id, component)| {
+                    (
+                        *id,
+                        Component {
+                            path: component.path.clone(),
+                            version: component.version.clone(),
+                            core: component.core,
+                            run: component.run,
+                            remove: component.remove,
+                        },
+                    )
+                })
+                .collect(),
+            routes: state
+                .routes
+                .iter()
+                .flat_map(|(source, targets)| {
+                    targets.iter().filter_map(move |target| {
+                        Some((
+                            configuration_endpoint(source)?,
+                            configuration_endpoint(target)?,
+                        ))
+                    })
+                })
+                .collect(),
+            schedule: schedule_data(&state.schedule),
+        }
+    }
+}
+
+/// Plan the tasks that take the current state to a desired state.
+///
+/// New components are added first and take over the state of the components
+/// they replace. Routes, starts, stops and the schedule then change together in
+/// one blocking task, before the components that are no longer wanted are removed.
+pub fn plan(state: &Current, desired: &DesiredState) -> Result<Configuration, String> {
+    // components already being removed are not part of the current system
+    let mut current: Vec<_> = state
+        .components
+        .iter()
+        .filter(|(_, component)| !component.remove)
+        .collect();
+    current.sort_by_key(|(id, _)| **id);
+
+    let mut wanted = HashSet::new();
+    let mut add = Vec::new();
+    let mut handovers = Vec::new();
+    let mut start = Vec::new();
+
+    for component in desired.components.iter() {
+        if !wanted.insert(component.component_id) {
+            return Err(format!(
+                "Component {} listed more than once",
+                component.component_id
+            ));
+        }
+
+        match state.components.get(&component.component_id) {
+            Some(existing) if !existing.remove => {
+                // a running process cannot be changed in place
+                if existing.path != component.component
+                    || existing.version != component.version
+                    || existing.core != component.core
+                {
+                    return Err(format!(
+                        "Component {} runs {} version {} on core {}, use a new component id to replace it",
+                        component.component_id, existing.path, existing.version, existing.core
+                    ));
+                }
+
+                if !existing.run {
+                    start.push(component.component_id);
+                }
+            }
+            Some(_) => {
+                return Err(format!(
+                    "Component {} is still being removed",
+                    component.component_id
+                ));
+            }
+            None => {
+                add.push(NonBlockingData::AddComponent(AddComponentData {
+                    component_id: component.component_id,
+                    component: component.component.clone(),
+                    core: component.core,
+                    version: component.version.clone(),
+                    sha256: component.sha256.clone(),
+                    restart: component.restart.clone(),
+                    limits: component.limits.clone(),
+                }));
+                start.push(component.component_id);
+
+                if let Some(source) = component.state_from {
+                    if !current.iter().any(|(id, _)| **id == source) {
+                        return Err(format!("Component {} not found to take state from", source));
+                    }
+                    handovers.push((Uuid::new_v4(), source, component.component_id));
+                }
+            }
+        }
+    }
+
+    let mut switch = Vec::new();
+    let mut remove = Vec::new();
+
+    // the handed over state is no longer needed once the new components run
+    for (state_sync_id, _, _) in handovers.iter() {
+        switch.push(BlockingData::RemoveStateSync(RemoveStateSyncData {
+            state_sync_id: *state_sync_id,
+        }));
+    }
+
+    for (id, component) in current.iter() {
+        if wanted.contains(*id) {
+            continue;
+        }
+
+        if component.run {
+            switch.push(BlockingData::StopComponent(StopComponentData {
+                component_id: **id,
+            }));
+        }
+        remove.push(NonBlockingData::RemoveComponent(RemoveComponentData {
+            component_id: **id,
+        }));
+    }
+
+    let routes = &state.routes;
+
+    let mut wanted_routes = HashSet::new();
+    for route in desired.routes.iter() {
+        if !wanted_routes.insert((route.source.clone(), route.target.clone())) {
+            return Err(format!(
+                "Route from {:?} to {:?} listed more than once",
+                route.source, route.target
+            ));
+        }
+    }
+
+    // routes that go away are removed before any are added
+    let mut stale: Vec<&(RouteEndpoint, RouteEndpoint)> = routes
+        .iter()
+        .filter(|route| !wanted_routes.contains(route))
+        .collect();
+    stale.sort_by_key(|route| format!("{:?}", route));
+    for (source, target) in stale {
+        switch.push(BlockingData::RemoveRoute(RemoveRouteData {
+            source: source.clone(),
+            target: Some(target.clone()),
+        }));
+    }
+
+    for component_id in start {
+        switch.push(BlockingData::StartComponent(StartComponentData {
+            component_id,
+        }));
+    }
+
+    for route in desired.routes.iter() {
+        if !routes.contains(&(route.source.clone(), route.target.clone())) {
+            switch.push(BlockingData::AddRoute(AddRouteData {
+                source: route.source.clone(),
+                target: route.target.clone(),
+            }));
+        }
+    }
+
+    if state.schedule != desired.schedule {
+        switch.push(BlockingData::SetSchedule(desired.schedule.clone()));
+    }
+
+    let mut tasks = Vec::new();
+
+    if !add.is_empty() {
+        tasks.push(non_blocking(add));
+    }
+
+    if !handovers.is_empty() {
+        tasks.push(blocking(
+            handovers
+                .iter()
+                .map(|(state_sync_id, source, target)| {
+                    BlockingData::AddStateSync(AddStateSyncData {
+                        state_sync_id: *state_sync_id,
+                        source: StateEndpoint {
+                            component_id: *source,
+                        },
+                        target: StateEndpoint {
+                            component_id: *target,
+                        },
+                    })
+                })
+                .collect(),
+        ));
+        tasks.push(non_blocking(
+            handovers
+                .iter()
+                .map(|(state_sync_id, _, _)| {
+                    NonBlockingData::WaitStateSync(WaitStateSyncData {
+                        state_sync_id: *state_sync_id,
+                    })
+                })
+                .collect(),
+        ));
+    }
+
+    if !switch.is_empty() {
+        tasks.push(blocking(switch));
+    }
+
+    if !remove.is_empty() {
+        tasks.push(non_blocking(remove));
+    }
+
+    Ok(Configuration { tasks })
+}
+
+fn blocking(actions: Vec<BlockingData>) -> Task {
+    Task {
+        id: Uuid::new_v4(),
+        actions: Action::Blocking(
+            actions
+                .into_iter()
+                .map(|data| BlockingAction {
+                    id: Uuid::new_v4(),
+                    data,
+                })
+                .collect(),
+        ),
+    }
+}
+
+fn non_blocking(actions: Vec<NonBlockingData>) -> Task {
+    Task {
+        id: Uuid::new_v4(),
+        actions: Action::NonBlocking(
+            actions
+                .into_iter()
+                .map(|data| NonBlockingAction {
+                    id: Uuid::new_v4(),
+                    data,
+                })
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::global_state::{Implementation, Socket};
+    use crate::services::communication::{self, Endpoint};
+    use crate::services::scheduler::{MajorFrame, MinorFrame, Schedule};
+    use elafry::types::desired::Document;
+    use std::os::unix::net::UnixStream;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    fn implementation() -> Implementation {
+        Implementation {
+            data_socket: Socket {
+                socket: UnixStream::pair().unwrap().0,
+                count: 0,
+            },
+            state_socket: Socket {
+                socket: UnixStream::pair().unwrap().0,
+                count: 0,
+            },
+            child: std::process::Command::new("ls").spawn().unwrap(),
+            child_pid: 0,
+            schedstat: None,
+            cgroup: None,
+        }
+    }
+
+    fn desired(name: &str) -> DesiredState {
+        let path = format!(
+            "{}/../../configuration/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        let mut desired = match elafry::loader::from_file(std::path::Path::new(&path)).unwrap() {
+            Document::Desired(desired) => desired,
+            Document::Tasks(_) => panic!("{} is not a desired state", name),
+        };
+
+        // the component binaries are not on the PATH while testing
+        for component in desired.components.iter_mut() {
+            component.component = "ls".to_string();
+        }
+
+        desired
+    }
+
+    /// Kind of each action in each task, to compare plans without their random ids.
+    fn outline(configuration: &Configuration) -> Vec<Vec<String>> {
+        configuration
+            .tasks
+            .iter()
+            .map(|task| match &task.actions {
+                Action::Blocking(actions) => actions
+                    .iter()
+                    .map(|action| match &action.data {
+                        BlockingData::StartComponent(data) => {
+                            format!("start {}", data.component_id)
+                        }
+                        BlockingData::StopComponent(data) => format!("stop {}", data.component_id),
+                        BlockingData::AddRoute(data) => format!("add-route {:?}", data.source),
+                        BlockingData::RemoveRoute(data) => {
+                            format!("remove-route {:?}", data.source)
+                        }
+                        BlockingData::SetSchedule(_) => "set-schedule".to_string(),
+                        BlockingData::AddStateSync(data) => format!(
+                            "add-state-sync {} {}",
+                            data.source.component_id, data.target.component_id
+                        ),
+                        BlockingData::RemoveStateSync(_) => "remove-state-sync".to_string(),
+                    })
+                    .collect(),
+                Action::NonBlocking(actions) => actions
+                    .iter()
+                    .map(|action| match &action.data {
+                        NonBlockingData::AddComponent(data) => format!("add {}", data.component_id),
+                        NonBlockingData::RemoveComponent(data) => {
+                            format!("remove {}", data.component_id)
+                        }
+                        NonBlockingData::WaitStateSync(_) => "wait-state-sync".to_string(),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    // the state plant_a.yaml describes, as left by applying its plan
+    fn plant_a(state: &mut GlobalState) {
+        let desired = desired("plant_a.yaml");
+        for component in desired.components.iter() {
+            state.add_component(
+                component.component_id,
+                component.component.clone(),
+                component.core,
+                component.version.clone(),
+            );
+            state
+                .add_component_implementation(component.component_id, implementation())
+                .unwrap();
+            state.start_component(component.component_id).unwrap();
+        }
+        for route in desired.routes.iter() {
+            state.add_route(
+                super::super::runner_endpoint(&route.source).unwrap(),
+                super::super::runner_endpoint(&route.target).unwrap(),
+            );
+        }
+        state.schedule = Schedule {
+            period: std::time::Duration::from_micros(desired.schedule.deadline),
+            major_frames: desired
+                .schedule
+                .major_frames
+                .iter()
+                .map(|frame| MajorFrame {
+                    minor_frames: frame
+                        .minor_frames
+                        .iter()
+                        .map(|frame| MinorFrame {
+                            component_id: frame.component_id,
+                            deadline: std::time::Duration::from_micros(frame.deadline),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        };
+    }
+
+    #[test]
+    fn test_plan_from_empty() {
+        setup();
+
+        let state = GlobalState::new();
+        let desired = desired("plant_a.yaml");
+        let configuration = plan(&Current::new(&state), &desired).unwrap();
+
+        let ids: Vec<Uuid> = desired
+            .components
+            .iter()
+            .map(|component| component.component_id)
+            .collect();
+        let outline = outline(&configuration);
+        assert_eq!(outline.len(), 2);
+        assert_eq!(
+            outline[0],
+            ids.iter()
+                .map(|id| format!("add {}", id))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            outline[1],
+            ids.iter()
+                .map(|id| format!("start {}", id))
+                .chain(
+                    desired
+                        .routes
+                        .iter()
+                        .map(|route| format!("add-route {:?}", route.source))
+                )
+                .chain(["set-schedule".to_string()])
+                .collect::<Vec<_>>()
+        );
+
+        // the plan is valid against the state it was made for
+        assert_eq!(
+            super::super::validate::validate(&state, &configuration, None, false),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_plan_handover() {
+        setup();
+
+        let mut state = GlobalState::new();
+        plant_a(&mut state);
+
+        // a route to a control client is left alone
+        state.add_route(
+            communication::RouteEndpoint {
+                endpoint: Endpoint::Control(1),
+                channel_id: 0,
+            },
+            communication::RouteEndpoint {
+                endpoint: Endpoint::Runner,
+                channel_id: 0,
+            },
+        );
+
+        let desired = desired("plant_b.yaml");
+        let configuration = plan(&Current::new(&state), &desired).unwrap();
+
+        // fcs_a is replaced by fcs_b, which takes over its state
+        let fcs_a = desired.components[2].state_from.unwrap();
+        let fcs_b = desired.components[2].component_id;
+        let plant = desired.components[1].component_id;
+        let route = |id: Uuid, channel_id: u32| {
+            format!(
+                "{:?}",
+                RouteEndpoint {
+                    endpoint: elafry::types::configuration::Endpoint::Component(id),
+                    channel_id,
+                }
+            )
+        };
+        assert_eq!(
+            outline(&configuration),
+            vec![
+                vec![format!("add {}", fcs_b)],
+                vec![format!("add-state-sync {} {}", fcs_a, fcs_b)],
+                vec!["wait-state-sync".to_string()],
+                vec![
+                    "remove-state-sync".to_string(),
+                    format!("stop {}", fcs_a),
+                    format!("remove-route {}", route(fcs_a, 2)),
+                    format!("remove-route {}", route(plant, 1)),
+                    format!("start {}", fcs_b),
+                    format!("add-route {}", route(plant, 1)),
+                    format!("add-route {}", route(fcs_b, 2)),
+                    "set-schedule".to_string(),
+                ],
+                vec![format!("remove {}", fcs_a)],
+            ]
+        );
+        assert_eq!(
+            super::super::validate::validate(&state, &configuration, None, false),
+            vec![]
+        );
+
+        // nothing to do once the system is in the desired state
+        let desired = self::desired("plant_a.yaml");
+        let mut state = GlobalState::new();
+        plant_a(&mut state);
+        assert_eq!(plan(&Current::new(&state), &desired).unwrap().tasks, vec![]);
+
+        // a second target of a source is added next to the first
+        let mut fan_out = desired.clone();
+        fan_out.routes.push(elafry::types::desired::Route {
+            source: desired.routes[0].source.clone(),
+            target: RouteEndpoint {
+                endpoint: elafry::types::configuration::Endpoint::Runner,
+                channel_id: 7,
+            },
+        });
+        let configuration = plan(&Current::new(&state), &fan_out).unwrap();
+        assert_eq!(
+            outline(&configuration),
+            vec![vec![format!("add-route {:?}", desired.routes[0].source)]]
+        );
+        assert_eq!(
+            super::super::validate::validate(&state, &configuration, None, false),
+            vec![]
+        );
+
+        // and removed on its own
+        state.add_route(
+            super::super::runner_endpoint(&fan_out.routes[0].source).unwrap(),
+            communication::RouteEndpoint {
+                endpoint: Endpoint::Runner,
+                channel_id: 7,
+            },
+        );
+        let configuration = plan(&Current::new(&state), &desired).unwrap();
+        match &configuration.tasks[..] {
+            [Task {
+                actions: Action::Blocking(actions),
+                ..
+            }] if actions.len() == 1 => assert_eq!(
+                actions[0].data,
+                BlockingData::RemoveRoute(RemoveRouteData {
+                    source: desired.routes[0].source.clone(),
+                    target: Some(fan_out.routes.last().unwrap().target.clone()),
+                })
+            ),
+            tasks => panic!("unexpected tasks {:?}", tasks),
+        }
+    }
+
+    #[test]
+    fn test_plan_errors() {
+        setup();
+
+        let mut state = GlobalState::new();
+        plant_a(&mut state);
+
+        // a component cannot change binary in place
+        let mut changed = desired("plant_a.yaml");
+        changed.components[1].core = 0;
+        assert_eq!(
+            plan(&Current::new(&state), &changed),
+            Err(format!(
+                "Component {} runs ls version 1.0.0 on core 2, use a new component id to replace it",
+                changed.components[1].component_id
+            ))
+        );
+
+        // nor change version
+        let mut changed = desired("plant_a.yaml");
+        changed.components[1].version = "2.0.0".to_string();
+        assert!(plan(&Current::new(&state), &changed)
+            .unwrap_err()
+            .contains("runs ls version 1.0.0 on core"));
+
+        let mut twice = desired("plant_a.yaml");
+        twice.components.push(twice.components[0].clone());
+        assert_eq!(
+            plan(&Current::new(&state), &twice),
+            Err(format!(
+                "Component {} listed more than once",
+                twice.components[0].component_id
+            ))
+        );
+
+        let mut twice = desired("plant_a.yaml");
+        twice.routes.push(twice.routes[0].clone());
+        assert_eq!(
+            plan(&Current::new(&state), &twice),
+            Err(format!(
+                "Route from {:?} to {:?} listed more than once",
+                twice.routes[0].source, twice.routes[0].target
+            ))
+        );
+
+        let mut missing = desired("plant_b.yaml");
+        let unknown = Uuid::new_v4();
+        missing.components[2].state_from = Some(unknown);
+        assert_eq!(
+            plan(&Current::new(&state), &missing),
+            Err(format!(
+                "Component {} not found to take state from",
+                unknown
+            ))
+        );
+    }
+}
@@ -0,0 +1,366 @@
+//! Component binaries by name and version.
+//!
+//! The registry is a directory holding every installed version of every
+//! component and an `index.yaml` that lists them with their sha256:
+//!
+//! ```text
+//! components/
+//!   index.yaml
+//!   fcs_a/1.0.0/fcs_a
+//!   fcs_a/1.1.0/fcs_a
+//! ```
+//!
+//! Versions are never overwritten, a changed binary is installed as a new
+//! version. The index is the record of what may run, a binary that no longer
+//! matches its hash is refused.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use elafry::types::management::RegistryEntry;
+use serde::{Deserialize, Serialize};
+
+const INDEX: &str = "index.yaml";
+
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+struct Index {
+    /// Sha256 of each version of each component.
+    components: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+pub struct Registry {
+    directory: PathBuf,
+    index: Index,
+}
+
+/// A binary copied into the registry directory but not yet installed.
+pub struct Staged {
+    component: String,
+    version: String,
+    sha256: String,
+    partial: PathBuf,
+}
+
+impl Registry {
+    /// Open a registry, an empty one is created if the directory does not exist.
+    pub fn open(directory: &Path) -> Result<Registry, String> {
+        std::fs::create_dir_all(directory).map_err(|e| {
+            format!(
+                "Failed to create registry {}; err = {}",
+                directory.display(),
+                e
+            )
+        })?;
+
+        let path = directory.join(INDEX);
+        let index = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_yaml::from_str(&text)
+                .map_err(|e| format!("Failed to parse {}; err = {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Index::default(),
+            Err(e) => return Err(format!("Failed to read {}; err = {}", path.display(), e)),
+        };
+
+        Ok(Registry {
+            directory: directory.to_path_buf(),
+            index,
+        })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// The binary of a component version and the sha256 it must have.
+    ///
+    /// A configuration that gives a sha256 as well must agree with the registry.
+    pub fn resolve(
+        &self,
+        component: &str,
+        version: &str,
+        expected: Option<&str>,
+    ) -> Result<(PathBuf, String), String> {
+        let versions = self
+            .index
+            .components
+            .get(component)
+            .ok_or(format!("Component {} is not in the registry", component))?;
+        let sha256 = versions.get(version).ok_or(format!(
+            "Component {} has no version {}, the registry has {}",
+            component,
+            version,
+            versions.keys().cloned().collect::<Vec<_>>().join(", ")
+        ))?;
+        if let Some(expected) = expected {
+            if !expected.eq_ignore_ascii_case(sha256) {
+                return Err(format!(
+                    "Component {} version {} has sha256 {} in the registry, expected {}",
+                    component, version, sha256, expected
+                ));
+            }
+        }
+
+        Ok((binary(&self.directory, component, version), sha256.clone()))
+    }
+
+    /// Move a staged binary in place and add it to the index.
+    pub fn add(&mut self, staged: Staged) -> Result<String, String> {
+        if self
+            .resolve(&staged.component, &staged.version, None)
+            .is_ok()
+        {
+            let _ = std::fs::remove_file(&staged.partial);
+            return Err(format!(
+                "Component {} version {} is already installed",
+                staged.component, staged.version
+            ));
+        }
+
+        let path = binary(&self.directory, &staged.component, &staged.version);
+        std::fs::rename(&staged.partial, &path)
+            .map_err(|e| format!("Failed to install {}; err = {}", path.display(), e))?;
+
+        self.index
+            .components
+            .entry(staged.component.clone())
+            .or_default()
+            .insert(staged.version.clone(), staged.sha256.clone());
+        if let Err(e) = self.save() {
+            self.forget(&staged.component, &staged.version);
+            return Err(e);
+        }
+
+        log::info!(
+            "Installed component {} version {} with sha256 {}",
+            staged.component,
+            staged.version,
+            staged.sha256
+        );
+        Ok(staged.sha256)
+    }
+
+    /// Remove a version from the index and delete its binary.
+    pub fn remove(&mut self, component: &str, version: &str) -> Result<(), String> {
+        self.resolve(component, version, None)?;
+
+        let sha256 = self.forget(component, version);
+        if let Err(e) = self.save() {
+            if let Some(sha256) = sha256 {
+                self.index
+                    .components
+                    .entry(component.to_string())
+                    .or_default()
+                    .insert(version.to_string(), sha256);
+            }
+            return Err(e);
+        }
+
+        // the index no longer lists it, a file left behind is harmless
+        let directory = self.directory.join(component).join(version);
+        if let Err(e) = std::fs::remove_dir_all(&directory) {
+            log::warn!("Failed to remove {}; err = {}", directory.display(), e);
+        }
+        let _ = std::fs::remove_dir(self.directory.join(component));
+
+        log::info!("Uninstalled component {} version {}", component, version);
+        Ok(())
+    }
+
+    pub fn entries(&self) -> Vec<RegistryEntry> {
+        self.index
+            .components
+            .iter()
+            .flat_map(|(component, versions)| {
+                versions.iter().map(|(version, sha256)| RegistryEntry {
+                    component: component.clone(),
+                    version: version.clone(),
+                    sha256: sha256.clone(),
+                })
+            })
+            .collect()
+    }
+
+    fn forget(&mut self, component: &str, version: &str) -> Option<String> {
+        let versions = self.index.components.get_mut(component)?;
+        let sha256 = versions.remove(version);
+        if versions.is_empty() {
+            self.index.components.remove(component);
+        }
+        sha256
+    }
+
+    /// Write the index so a crash never leaves it half written.
+    fn save(&self) -> Result<(), String> {
+        let path = self.directory.join(INDEX);
+        let temporary = self.directory.join(format!("{}.tmp", INDEX));
+        let text = serde_yaml::to_string(&self.index)
+            .map_err(|e| format!("Failed to format registry index; err = {}", e))?;
+
+        std::fs::write(&temporary, text)
+            .and_then(|_| std::fs::rename(&temporary, &path))
+            .map_err(|e| format!("Failed to write {}; err = {}", path.display(), e))
+    }
+}
+
+/// Copy a binary into the registry directory, it is installed by [`Registry::add`].
+///
+/// Copying may take a while, so it is done without holding the registry.
+pub fn stage(
+    directory: &Path,
+    component: &str,
+    version: &str,
+    source: &Path,
+) -> Result<Staged, String> {
+    for (kind, name) in [("component", component), ("version", version)] {
+        if name.is_empty() || name.contains('/') || name.starts_with('.') || name == INDEX {
+            return Err(format!("Invalid {} name {:?}", kind, name));
+        }
+    }
+
+    let path = binary(directory, component, version);
+    let parent = path.parent().unwrap_or(directory);
+    let partial = parent.join(format!(".{}.{}", component, uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(parent)
+        .and_then(|_| std::fs::copy(source, &partial))
+        .map_err(|e| format!("Failed to copy {}; err = {}", source.display(), e))?;
+
+    // hash the copy, it is what will run
+    let data = std::fs::read(&partial)
+        .map_err(|e| format!("Failed to read {}; err = {}", partial.display(), e))?;
+    let sha256 = elafry::signature::sha256(&data);
+
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o555))
+            .map_err(|e| format!("Failed to install {}; err = {}", path.display(), e))?;
+    }
+
+    Ok(Staged {
+        component: component.to_string(),
+        version: version.to_string(),
+        sha256,
+        partial,
+    })
+}
+
+fn binary(directory: &Path, component: &str, version: &str) -> PathBuf {
+    directory.join(component).join(version).join(component)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    fn directory() -> PathBuf {
+        std::env::temp_dir().join(format!("elafry-registry-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn install(registry: &mut Registry, component: &str, version: &str) -> Result<String, String> {
+        let staged = stage(
+            registry.directory(),
+            component,
+            version,
+            Path::new("/bin/sh"),
+        )?;
+        registry.add(staged)
+    }
+
+    #[test]
+    fn test_registry() {
+        setup();
+
+        let directory = directory();
+        let mut registry = Registry::open(&directory).unwrap();
+        let sha256 = elafry::signature::sha256(&std::fs::read("/bin/sh").unwrap());
+
+        assert_eq!(install(&mut registry, "fcs", "1.0.0"), Ok(sha256.clone()));
+        assert_eq!(install(&mut registry, "fcs", "1.1.0"), Ok(sha256.clone()));
+        assert_eq!(
+            registry.resolve("fcs", "1.0.0", None),
+            Ok((directory.join("fcs/1.0.0/fcs"), sha256.clone()))
+        );
+        assert_eq!(
+            registry.resolve("fcs", "1.0.0", Some("00")),
+            Err(format!(
+                "Component fcs version 1.0.0 has sha256 {} in the registry, expected 00",
+                sha256
+            ))
+        );
+        assert!(super::super::binary::verify(
+            directory.join("fcs/1.0.0/fcs").to_str().unwrap(),
+            &sha256
+        )
+        .is_ok());
+
+        // the index is read back when the runner starts again
+        let reopened = Registry::open(&directory).unwrap();
+        assert_eq!(reopened.entries(), registry.entries());
+        assert_eq!(registry.entries().len(), 2);
+
+        registry.remove("fcs", "1.0.0").unwrap();
+        assert!(!directory.join("fcs/1.0.0").exists());
+        assert_eq!(
+            registry.resolve("fcs", "1.0.0", None),
+            Err("Component fcs has no version 1.0.0, the registry has 1.1.0".to_string())
+        );
+        registry.remove("fcs", "1.1.0").unwrap();
+        assert_eq!(registry.entries(), vec![]);
+        assert!(!directory.join("fcs").exists());
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_registry_errors() {
+        setup();
+
+        let directory = directory();
+        let mut registry = Registry::open(&directory).unwrap();
+
+        install(&mut registry, "fcs", "1.0.0").unwrap();
+        assert_eq!(
+            install(&mut registry, "fcs", "1.0.0"),
+            Err("Component fcs version 1.0.0 is already installed".to_string())
+        );
+        // the refused copy is not left behind
+        assert_eq!(
+            std::fs::read_dir(directory.join("fcs/1.0.0"))
+                .unwrap()
+                .count(),
+            1
+        );
+        assert_eq!(
+            install(&mut registry, "../fcs", "1.0.0").err(),
+            Some("Invalid component name \"../fcs\"".to_string())
+        );
+        assert_eq!(
+            install(&mut registry, "fcs", "").err(),
+            Some("Invalid version name \"\"".to_string())
+        );
+        assert!(
+            stage(&directory, "plant", "1.0.0", Path::new("/no/such/binary"))
+                .err()
+                .unwrap()
+                .starts_with("Failed to copy /no/such/binary")
+        );
+
+        assert_eq!(
+            registry.resolve("plant", "1.0.0", None),
+            Err("Component plant is not in the registry".to_string())
+        );
+        assert_eq!(
+            registry.remove("plant", "1.0.0"),
+            Err("Component plant is not in the registry".to_string())
+        );
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,291 @@
+//! Noticing component processes that exit on their own.
+//!
+//! A SIGCHLD handler only sets a flag, the processes are checked in the next
+//! frame. A component whose process exited loses its implementation, so it is
+//! no longer resumed or read from, and is marked crashed until it is
+//! restarted as its restart policy says.
+//!
+//! Limit hits counted in the cgroups of the processes are reported as faults,
+//! read once a second and when a process exits.
+//!
+//! Times are given by the caller, frame starts on the virtual clock when
+//! simulating so restarts come in the same frame every run.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::cgroup::Hits;
+use crate::global_state::{Component, GlobalState, Implementation};
+
+/// How often limit hits are read while no process exits.
+const LIMITS_PERIOD: Duration = Duration::from_secs(1);
+
+static EXITED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle(_signal: libc::c_int) {
+    EXITED.store(true, Ordering::SeqCst);
+}
+
+/// Be told when a child process exits, children stopping themselves do not count.
+pub fn install() -> Result<(), String> {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handle as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART | libc::SA_NOCLDSTOP;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        if libc::sigaction(libc::SIGCHLD, &action, std::ptr::null_mut()) != 0 {
+            return Err(format!(
+                "Failed to handle SIGCHLD; err = {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+pub struct Supervisor {
+    /// Crashed components and when they are due to be restarted.
+    due: HashMap<Uuid, Duration>,
+    /// When limit hits were last read.
+    limits_checked: Duration,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Supervisor::new()
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Supervisor {
+        Supervisor {
+            due: HashMap::new(),
+            limits_checked: Duration::ZERO,
+        }
+    }
+
+    /// Mark the components whose process has exited as crashed and count the
+    /// times processes hit their limits.
+    pub fn check(&mut self, state: &mut GlobalState, now: Duration) {
+        // clear the flag first, an exit after this is seen next frame
+        let exited = EXITED.swap(false, Ordering::SeqCst);
+        let limits = now.saturating_sub(self.limits_checked) >= LIMITS_PERIOD;
+        if !exited && !limits {
+            return;
+        }
+        if limits {
+            self.limits_checked = now;
+        }
+
+        for (id, component) in state.components.iter_mut() {
+            let implementation = match &mut component.implentation {
+                Some(implementation) => implementation,
+                None => continue,
+            };
+
+            // read before the cgroup goes with the process, to see it was killed out of memory
+            if let Some(cgroup) = &mut implementation.cgroup {
+                component.faults += report(*id, cgroup.hits());
+            }
+            if !exited {
+                continue;
+            }
+
+            let status = match implementation.child.try_wait() {
+                Ok(Some(status)) => status.to_string(),
+                Ok(None) => continue,
+                Err(e) => format!("unknown status; err = {}", e),
+            };
+
+            // the process is gone, dropping the implementation closes its sockets
+            component.implentation = None;
+            component.crashed = true;
+            log::error!("Component {} exited with {}", id, status);
+
+            self.schedule(*id, component, now);
+        }
+    }
+
+    /// Plan the next restart of a crashed component, if its policy allows one.
+    pub fn schedule(&mut self, id: Uuid, component: &Component, now: Duration) {
+        if component.remove {
+            return;
+        }
+
+        match component.restart.delay(component.restarts) {
+            Some(delay) => {
+                log::info!("Restarting component {} in {}ms", id, delay.as_millis());
+                self.due.insert(id, now + delay);
+            }
+            None => log::warn!(
+                "Component {} is not restarted after {} restarts",
+                id,
+                component.restarts
+            ),
+        }
+    }
+
+    /// Components whose restart is due.
+    pub fn due(&mut self, now: Duration) -> Vec<Uuid> {
+        let due: Vec<Uuid> = self
+            .due
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in due.iter() {
+            self.due.remove(id);
+        }
+        due
+    }
+}
+
+/// Log the limits a component hit and return how many times it hit them.
+fn report(id: Uuid, hits: Hits) -> u64 {
+    if hits.memory > 0 {
+        log::error!(
+            "Component {} hit its memory limit {} times",
+            id,
+            hits.memory
+        );
+    }
+    if hits.oom_kill > 0 {
+        log::error!("Component {} was killed out of memory", id);
+    }
+    if hits.pids > 0 {
+        log::error!("Component {} hit its pids limit {} times", id, hits.pids);
+    }
+    hits.total()
+}
+
+/// Hand a restarted process the state its previous process last reported.
+pub fn restore(implementation: &mut Implementation, state: &[u8]) {
+    if state.is_empty() {
+        return;
+    }
+
+    let mut buf = (state.len() as u32).to_be_bytes().to_vec();
+    buf.extend_from_slice(state);
+    if let Err(e) = implementation.state_socket.socket.write_all(&buf) {
+        log::error!("Failed to restore state; err = {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::global_state::{Implementation, Socket};
+    use elafry::types::configuration::{BackoffData, Restart, RestartPolicy};
+    use std::os::unix::net::UnixStream;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    fn add(state: &mut GlobalState, script: &str, policy: Restart) -> Uuid {
+        let child = std::process::Command::new("sh")
+            .args(["-c", script])
+            .spawn()
+            .unwrap();
+        let pid = child.id() as libc::pid_t;
+
+        let id = Uuid::new_v4();
+        state.add_component(id, "sh".to_string(), 1, "1.0.0".to_string());
+        state.components.get_mut(&id).unwrap().restart = RestartPolicy {
+            policy,
+            max_restarts: Some(1),
+        };
+        state
+            .add_component_implementation(
+                id,
+                Implementation {
+                    data_socket: Socket {
+                        socket: UnixStream::pair().unwrap().0,
+                        count: 0,
+                    },
+                    state_socket: Socket {
+                        socket: UnixStream::pair().unwrap().0,
+                        count: 0,
+                    },
+                    child,
+                    child_pid: pid,
+                    schedstat: None,
+                    cgroup: None,
+                },
+            )
+            .unwrap();
+        id
+    }
+
+    #[test]
+    fn test_supervisor() {
+        setup();
+
+        install().unwrap();
+
+        let mut state = GlobalState::new();
+        let mut supervisor = Supervisor::new();
+        let crashing = add(&mut state, "exit 3", Restart::Always);
+        let never = add(&mut state, "exit 0", Restart::Never);
+        let running = add(&mut state, "sleep 10", Restart::Always);
+
+        // restarts are timed on the clock the caller gives, virtual when simulating
+        let now = Duration::from_secs(100);
+        let start = std::time::Instant::now();
+        while state.components[&crashing].implentation.is_some()
+            || state.components[&never].implentation.is_some()
+        {
+            assert!(start.elapsed() < std::time::Duration::from_secs(5));
+            std::thread::sleep(std::time::Duration::from_millis(1));
+
+            // other tests running at the same time clear the flag as well
+            EXITED.store(true, Ordering::SeqCst);
+            supervisor.check(&mut state, now);
+        }
+
+        assert!(state.components[&crashing].crashed);
+        assert!(state.components[&never].crashed);
+        assert!(!state.components[&running].crashed);
+        assert!(state.components[&running].implentation.is_some());
+
+        // only the component whose policy allows it is restarted
+        assert_eq!(supervisor.due(now), vec![crashing]);
+        assert_eq!(supervisor.due(now), vec![]);
+
+        // after its backoff has passed on that clock
+        state.components.get_mut(&crashing).unwrap().restart.policy =
+            Restart::Backoff(BackoffData {
+                initial: 100,
+                max: 1000,
+            });
+        supervisor.schedule(crashing, &state.components[&crashing], now);
+        assert_eq!(supervisor.due(now + Duration::from_millis(99)), vec![]);
+        assert_eq!(
+            supervisor.due(now + Duration::from_millis(100)),
+            vec![crashing]
+        );
+
+        // until it has used up its restarts
+        state.components.get_mut(&crashing).unwrap().restarts = 1;
+        supervisor.schedule(crashing, &state.components[&crashing], now);
+        assert_eq!(supervisor.due(now + Duration::from_secs(10)), vec![]);
+
+        let mut implementation = state
+            .remove_component_implementation(running)
+            .unwrap()
+            .unwrap();
+        implementation.child.kill().unwrap();
+        implementation.child.wait().unwrap();
+    }
+}
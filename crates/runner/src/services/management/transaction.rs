@@ -0,0 +1,335 @@
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::global_state::{GlobalState, Implementation, StateSync, StateSyncStatus};
+use crate::services::communication::RouteEndpoint;
+use crate::services::scheduler::Schedule;
+
+/// The inverse of an applied action, holding whatever the action replaced.
+pub enum Undo {
+    /// Restore the running flag of a started or stopped component.
+    Run {
+        component_id: Uuid,
+        run: bool,
+    },
+    /// Remove an added route, nothing if the route already existed.
+    AddRoute {
+        source: RouteEndpoint,
+        target: Option<RouteEndpoint>,
+    },
+    /// Restore the routes removed from a source.
+    RemoveRoute {
+        source: RouteEndpoint,
+        targets: Vec<RouteEndpoint>,
+    },
+    Schedule(Schedule),
+    /// Restore a state sync, removing it if there was none.
+    StateSync {
+        state_sync_id: Uuid,
+        state_sync: Option<StateSync>,
+    },
+    StateSyncStatus {
+        state_sync_id: Uuid,
+        status: StateSyncStatus,
+    },
+    /// Remove a component added by the transaction and stop its process.
+    AddComponent(Uuid),
+    /// Bring back a component marked for removal, its process is only stopped on commit.
+    RemoveComponent {
+        component_id: Uuid,
+        run: bool,
+    },
+}
+
+/// Journal of the changes a configuration made to the global state.
+pub struct Transaction {
+    journal: Vec<Undo>,
+    started: Instant,
+}
+
+impl Default for Transaction {
+    fn default() -> Self {
+        Transaction::new()
+    }
+}
+
+impl Transaction {
+    pub fn new() -> Transaction {
+        Transaction {
+            journal: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn record(&mut self, undo: Undo) {
+        self.journal.push(undo);
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Keep the changes, returning the processes of removed components to stop.
+    pub fn commit(self, state: &mut GlobalState) -> Vec<(Uuid, Implementation)> {
+        let mut stop = Vec::new();
+
+        for undo in self.journal {
+            if let Undo::RemoveComponent { component_id, .. } = undo {
+                match state.remove_component_implementation(component_id) {
+                    Ok(Some(implementation)) => stop.push((component_id, implementation)),
+                    // a crashed component has no process left to stop
+                    Ok(None) => {
+                        state.components.remove(&component_id);
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+
+        stop
+    }
+
+    /// Undo the changes newest first, returning the processes of added components to stop.
+    pub fn rollback(self, state: &mut GlobalState) -> Vec<(Uuid, Implementation)> {
+        let mut stop = Vec::new();
+
+        log::warn!("Rolling back {} actions", self.journal.len());
+
+        for undo in self.journal.into_iter().rev() {
+            match undo {
+                Undo::Run { component_id, run } => {
+                    if let Some(component) = state.get_component_mut(component_id) {
+                        component.run = run;
+                    }
+                }
+                Undo::AddRoute { source, target } => {
+                    if let Some(target) = target {
+                        state.remove_route(source, target);
+                    }
+                }
+                Undo::RemoveRoute { source, targets } => {
+                    for target in targets {
+                        state.add_route(source, target);
+                    }
+                }
+                Undo::Schedule(schedule) => {
+                    // the previous schedule was valid with the components being restored
+                    state.schedule = schedule;
+                }
+                Undo::StateSync {
+                    state_sync_id,
+                    state_sync,
+                } => match state_sync {
+                    Some(state_sync) => {
+                        state.state_sync.insert(state_sync_id, state_sync);
+                    }
+                    None => {
+                        state.remove_state_sync(state_sync_id);
+                    }
+                },
+                Undo::StateSyncStatus {
+                    state_sync_id,
+                    status,
+                } => {
+                    let _ = state.set_state_sync_status(state_sync_id, status);
+                }
+                Undo::AddComponent(component_id) => {
+                    if let Some(component) = state.components.remove(&component_id) {
+                        if let Some(implementation) = component.implentation {
+                            stop.push((component_id, implementation));
+                        }
+                    }
+                }
+                Undo::RemoveComponent { component_id, run } => {
+                    if let Some(component) = state.get_component_mut(component_id) {
+                        component.remove = false;
+                        component.run = run;
+                    }
+                }
+            }
+        }
+
+        stop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::global_state::{Socket, StateEndpoint};
+    use crate::services::communication::Endpoint;
+    use crate::services::scheduler::{MajorFrame, MinorFrame};
+    use std::os::unix::net::UnixStream;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    fn implementation() -> Implementation {
+        Implementation {
+            data_socket: Socket {
+                socket: UnixStream::pair().unwrap().0,
+                count: 0,
+            },
+            state_socket: Socket {
+                socket: UnixStream::pair().unwrap().0,
+                count: 0,
+            },
+            child: std::process::Command::new("ls").spawn().unwrap(),
+            child_pid: 0,
+            schedstat: None,
+            cgroup: None,
+        }
+    }
+
+    fn endpoint(id: Uuid, channel_id: u32) -> RouteEndpoint {
+        RouteEndpoint {
+            endpoint: Endpoint::Component(id),
+            channel_id,
+        }
+    }
+
+    #[test]
+    fn test_transaction_rollback() {
+        setup();
+
+        let mut state = GlobalState::new();
+
+        // a running component with a route and a schedule
+        let old = Uuid::new_v4();
+        state.add_component(old, "old".to_string(), 0, "1.0.0".to_string());
+        state
+            .add_component_implementation(old, implementation())
+            .unwrap();
+        state.start_component(old).unwrap();
+        state.add_route(endpoint(old, 1), endpoint(old, 2));
+        let sync = Uuid::new_v4();
+        state.add_state_sync(
+            sync,
+            StateEndpoint { component_id: old },
+            StateEndpoint { component_id: old },
+        );
+
+        let mut transaction = Transaction::new();
+
+        // add and start a new component
+        let new = Uuid::new_v4();
+        state.add_component(new, "new".to_string(), 0, "1.0.0".to_string());
+        transaction.record(Undo::AddComponent(new));
+        state
+            .add_component_implementation(new, implementation())
+            .unwrap();
+        state.start_component(new).unwrap();
+        transaction.record(Undo::Run {
+            component_id: new,
+            run: false,
+        });
+
+        // move the route and schedule over to it
+        let targets = state.remove_routes(endpoint(old, 1));
+        transaction.record(Undo::RemoveRoute {
+            source: endpoint(old, 1),
+            targets,
+        });
+        state.add_route(endpoint(old, 1), endpoint(new, 2));
+        transaction.record(Undo::AddRoute {
+            source: endpoint(old, 1),
+            target: Some(endpoint(new, 2)),
+        });
+        state.add_route(endpoint(new, 1), endpoint(old, 2));
+        transaction.record(Undo::AddRoute {
+            source: endpoint(new, 1),
+            target: Some(endpoint(old, 2)),
+        });
+        // adding an existing route is undone by nothing
+        assert!(!state.add_route(endpoint(new, 1), endpoint(old, 2)));
+        transaction.record(Undo::AddRoute {
+            source: endpoint(new, 1),
+            target: None,
+        });
+        let previous = state
+            .set_schedule(Schedule {
+                period: std::time::Duration::from_micros(2000),
+                major_frames: vec![MajorFrame {
+                    minor_frames: vec![MinorFrame {
+                        component_id: new,
+                        deadline: std::time::Duration::from_micros(500),
+                    }],
+                }],
+            })
+            .unwrap();
+        transaction.record(Undo::Schedule(previous));
+
+        // replace the state sync and remove the old component
+        let state_sync = state.remove_state_sync(sync);
+        transaction.record(Undo::StateSync {
+            state_sync_id: sync,
+            state_sync,
+        });
+        transaction.record(Undo::RemoveComponent {
+            component_id: old,
+            run: true,
+        });
+        state.remove_component(old).unwrap();
+
+        let stop = transaction.rollback(&mut state);
+
+        // only the added component is stopped
+        assert_eq!(stop.len(), 1);
+        assert_eq!(stop[0].0, new);
+
+        assert_eq!(state.total_components(), 1);
+        let component = state.get_component(old).unwrap();
+        assert!(component.run);
+        assert!(!component.remove);
+        assert!(component.implentation.is_some());
+
+        assert_eq!(state.routes.len(), 1);
+        assert_eq!(state.routes[&endpoint(old, 1)], vec![endpoint(old, 2)]);
+        assert_eq!(
+            state.schedule.period,
+            std::time::Duration::from_micros(1000)
+        );
+        assert!(state.schedule.major_frames.is_empty());
+        assert_eq!(
+            state.get_state_sync_status(sync),
+            Ok(StateSyncStatus::Created)
+        );
+    }
+
+    #[test]
+    fn test_transaction_commit() {
+        setup();
+
+        let mut state = GlobalState::new();
+
+        let id = Uuid::new_v4();
+        state.add_component(id, "old".to_string(), 0, "1.0.0".to_string());
+        state
+            .add_component_implementation(id, implementation())
+            .unwrap();
+        state.start_component(id).unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction.record(Undo::RemoveComponent {
+            component_id: id,
+            run: true,
+        });
+        state.remove_component(id).unwrap();
+
+        // the process of the removed component is only stopped on commit
+        assert!(state.get_component(id).unwrap().implentation.is_some());
+
+        let stop = transaction.commit(&mut state);
+        assert_eq!(stop.len(), 1);
+        assert_eq!(stop[0].0, id);
+        assert!(state.get_component(id).unwrap().remove);
+        assert!(state.get_component(id).unwrap().implentation.is_none());
+    }
+}
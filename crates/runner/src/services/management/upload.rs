@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use elafry::types::management::Chunk;
+
+/// Largest document that can be uploaded.
+const MAX_SIZE: usize = 1 << 20;
+
+/// Most chunks an upload may be split into, chunks are allocated up front.
+const MAX_CHUNKS: u32 = 4096;
+
+/// Uploads collected at the same time, from different senders.
+const MAX_UPLOADS: usize = 8;
+
+/// Uploads whose sender stopped before the last chunk are dropped after this long.
+const EXPIRY: Duration = Duration::from_secs(60);
+
+struct Upload {
+    chunks: Vec<Option<Vec<u8>>>,
+    received: u32,
+    size: usize,
+    updated: Instant,
+}
+
+/// Configuration documents being sent to the runner in chunks.
+#[derive(Default)]
+pub struct Uploads {
+    uploads: HashMap<u64, Upload>,
+}
+
+impl Uploads {
+    pub fn new() -> Uploads {
+        Uploads::default()
+    }
+
+    /// Store a chunk, returns how many chunks of its upload have been received.
+    ///
+    /// Chunks may arrive in any order and more than once. A chunk with a
+    /// different count than the upload it belongs to starts the upload over.
+    pub fn add(&mut self, chunk: Chunk) -> Result<u32, String> {
+        self.uploads
+            .retain(|_, upload| upload.updated.elapsed() < EXPIRY);
+
+        if chunk.count == 0 || chunk.index >= chunk.count {
+            return Err(format!(
+                "Chunk {} of {} is out of range",
+                chunk.index, chunk.count
+            ));
+        }
+        if chunk.count > MAX_CHUNKS {
+            return Err(format!("Upload of {} chunks is too large", chunk.count));
+        }
+
+        // the sender started over with a different document
+        if let Some(upload) = self.uploads.get(&chunk.upload) {
+            if upload.chunks.len() != chunk.count as usize {
+                self.uploads.remove(&chunk.upload);
+            }
+        }
+
+        if !self.uploads.contains_key(&chunk.upload) && self.uploads.len() >= MAX_UPLOADS {
+            return Err("Too many uploads in progress".to_string());
+        }
+        let upload = self.uploads.entry(chunk.upload).or_insert_with(|| Upload {
+            chunks: vec![None; chunk.count as usize],
+            received: 0,
+            size: 0,
+            updated: Instant::now(),
+        });
+
+        let slot = &mut upload.chunks[chunk.index as usize];
+        let size = upload.size - slot.as_ref().map_or(0, |data| data.len()) + chunk.data.len();
+        if size > MAX_SIZE {
+            self.uploads.remove(&chunk.upload);
+            return Err(format!("Upload is larger than {} bytes", MAX_SIZE));
+        }
+        if slot.is_none() {
+            upload.received += 1;
+        }
+        *slot = Some(chunk.data);
+        upload.size = size;
+        upload.updated = Instant::now();
+
+        Ok(upload.received)
+    }
+
+    /// Remove a complete upload and return the document, incomplete uploads are kept.
+    pub fn take(&mut self, upload: u64) -> Option<Vec<u8>> {
+        match self.uploads.get(&upload) {
+            Some(pending) if pending.received as usize == pending.chunks.len() => {}
+            _ => return None,
+        }
+
+        let upload = self.uploads.remove(&upload)?;
+        Some(upload.chunks.into_iter().flatten().flatten().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    fn chunk(upload: u64, index: u32, count: u32, data: &[u8]) -> Chunk {
+        Chunk {
+            upload,
+            index,
+            count,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_uploads() {
+        setup();
+
+        let mut uploads = Uploads::new();
+
+        // out of order and repeated chunks
+        assert_eq!(uploads.add(chunk(1, 2, 3, b"ks: []")), Ok(1));
+        assert_eq!(uploads.add(chunk(1, 0, 3, b"ta")), Ok(2));
+        assert_eq!(uploads.add(chunk(1, 0, 3, b"ta")), Ok(2));
+        assert_eq!(uploads.take(1), None);
+        assert_eq!(uploads.add(chunk(1, 1, 3, b"s")), Ok(3));
+        assert_eq!(uploads.take(1), Some(b"tasks: []".to_vec()));
+        assert_eq!(uploads.take(1), None);
+
+        // a different count starts the upload over
+        assert_eq!(uploads.add(chunk(2, 0, 2, b"a")), Ok(1));
+        assert_eq!(uploads.add(chunk(2, 0, 1, b"b")), Ok(1));
+        assert_eq!(uploads.take(2), Some(b"b".to_vec()));
+    }
+
+    #[test]
+    fn test_upload_limits() {
+        setup();
+
+        let mut uploads = Uploads::new();
+
+        assert_eq!(
+            uploads.add(chunk(1, 3, 3, b"")),
+            Err("Chunk 3 of 3 is out of range".to_string())
+        );
+        assert_eq!(
+            uploads.add(chunk(1, 0, 0, b"")),
+            Err("Chunk 0 of 0 is out of range".to_string())
+        );
+        assert_eq!(
+            uploads.add(chunk(1, 0, u32::MAX, b"")),
+            Err(format!("Upload of {} chunks is too large", u32::MAX))
+        );
+
+        // too large in total, the upload is dropped
+        let data = vec![0; MAX_SIZE / 2 + 1];
+        assert_eq!(uploads.add(chunk(1, 0, 2, &data)), Ok(1));
+        assert_eq!(
+            uploads.add(chunk(1, 1, 2, &data)),
+            Err(format!("Upload is larger than {} bytes", MAX_SIZE))
+        );
+        assert_eq!(uploads.add(chunk(1, 1, 2, b"")), Ok(1));
+
+        for upload in 2..=MAX_UPLOADS as u64 {
+            assert_eq!(uploads.add(chunk(upload, 0, 2, b"")), Ok(1));
+        }
+        assert_eq!(
+            uploads.add(chunk(100, 0, 2, b"")),
+            Err("Too many uploads in progress".to_string())
+        );
+
+        // chunks of uploads already in progress are still taken
+        assert_eq!(uploads.add(chunk(2, 1, 2, b"")), Ok(2));
+        assert_eq!(uploads.take(2), Some(vec![]));
+        assert_eq!(uploads.add(chunk(100, 0, 2, b"")), Ok(1));
+    }
+}
@@ -0,0 +1,922 @@
+use std::collections::{HashMap, HashSet};
+
+use elafry::types::configuration::{Action, BlockingData, Configuration, NonBlockingData};
+use elafry::types::management::{Issue, Severity};
+use uuid::Uuid;
+
+use crate::global_state::GlobalState;
+use crate::services::communication::{Endpoint, RouteEndpoint};
+
+use super::registry::Registry;
+use super::{binary, runner_endpoint};
+
+/// What a dry run needs to know about a component.
+struct Component {
+    /// The process is up, components added by a configuration are only ready once their task is done.
+    ready: bool,
+    run: bool,
+    remove: bool,
+}
+
+/// Copy of the parts of the global state that actions change.
+///
+/// Taken on the frame loop, the configuration is then checked against it on
+/// the background thread where reading component binaries does no harm.
+pub struct Model {
+    components: HashMap<Uuid, Component>,
+    routes: HashSet<(RouteEndpoint, RouteEndpoint)>,
+    state_syncs: HashSet<Uuid>,
+    /// Components that appear in the schedule.
+    scheduled: Vec<Uuid>,
+    /// Whether component processes are put in cgroups, so limits can be applied.
+    cgroups: bool,
+}
+
+/// Issues found so far, tagged with the task and action being checked.
+struct Report {
+    issues: Vec<Issue>,
+    task: Option<usize>,
+    action: Option<Uuid>,
+}
+
+impl Report {
+    fn add(&mut self, severity: Severity, message: String) {
+        self.issues.push(Issue {
+            severity,
+            task: self.task,
+            action: self.action,
+            message,
+        });
+    }
+
+    fn error(&mut self, message: String) {
+        self.add(Severity::Error, message);
+    }
+
+    fn warning(&mut self, message: String) {
+        self.add(Severity::Warning, message);
+    }
+}
+
+/// Simulate loading a configuration against the current state without changing it.
+///
+/// With a registry, components are checked against it rather than looked up as binaries.
+/// Without `cgroups` the runner cannot apply limits to components.
+pub fn validate(
+    state: &GlobalState,
+    configuration: &Configuration,
+    registry: Option<&Registry>,
+    cgroups: bool,
+) -> Vec<Issue> {
+    Model::new(state, cgroups).check(configuration, registry)
+}
+
+impl Model {
+    /// Copy the parts of the state a configuration could change.
+    pub fn new(state: &GlobalState, cgroups: bool) -> Model {
+        Model {
+            components: state
+                .components
+                .iter()
+                .map(|(id, component)| {
+                    (
+                        *id,
+                        Component {
+                            ready: component.implentation.is_some(),
+                            run: component.run,
+                            remove: component.remove,
+                        },
+                    )
+                })
+                .collect(),
+            routes: state
+                .routes
+                .iter()
+                .flat_map(|(source, targets)| targets.iter().map(|target| (*source, *target)))
+                .collect(),
+            state_syncs: state.state_sync.keys().copied().collect(),
+            scheduled: state
+                .schedule
+                .major_frames
+                .iter()
+                .flat_map(|frame| frame.minor_frames.iter())
+                .map(|frame| frame.component_id)
+                .collect(),
+            cgroups,
+        }
+    }
+
+    /// Simulate loading a configuration against the copied state, which is changed as it goes.
+    pub fn check(
+        &mut self,
+        configuration: &Configuration,
+        registry: Option<&Registry>,
+    ) -> Vec<Issue> {
+        let mut report = Report {
+            issues: Vec::new(),
+            task: None,
+            action: None,
+        };
+
+        if configuration.tasks.is_empty() {
+            report.error("No tasks in configuration".to_string());
+        }
+
+        for (index, task) in configuration.tasks.iter().enumerate() {
+            report.task = Some(index);
+
+            match &task.actions {
+                Action::Blocking(actions) => {
+                    for action in actions {
+                        report.action = Some(action.id);
+                        self.blocking(&mut report, &action.data);
+                    }
+                }
+                Action::NonBlocking(actions) => {
+                    // non-blocking actions are tracked by id while they run
+                    let mut ids = HashSet::new();
+                    let mut added = Vec::new();
+
+                    for action in actions {
+                        report.action = Some(action.id);
+                        if !ids.insert(action.id) {
+                            report.error(format!("Action {} appears twice in the task", action.id));
+                        }
+                        if let Some(id) = self.non_blocking(&mut report, &action.data, registry) {
+                            added.push(id);
+                        }
+                    }
+
+                    // added components are up once every action of the task is done
+                    for id in added {
+                        if let Some(component) = self.components.get_mut(&id) {
+                            component.ready = true;
+                        }
+                    }
+                }
+            }
+
+            report.action = None;
+        }
+
+        // the schedule left behind must only run components that stay
+        report.task = None;
+        self.scheduled.sort();
+        self.scheduled.dedup();
+        for id in self.scheduled.iter() {
+            match self.components.get(id) {
+                Some(component) if component.remove => {
+                    report.error(format!("Component {} is removed but still scheduled", id))
+                }
+                Some(component) if !component.run => {
+                    report.warning(format!("Component {} is scheduled but not running", id))
+                }
+                Some(_) => {}
+                None => report.error(format!("Component {} is scheduled but not found", id)),
+            }
+        }
+
+        report.issues
+    }
+
+    fn blocking(&mut self, report: &mut Report, data: &BlockingData) {
+        match data {
+            BlockingData::StartComponent(data) => {
+                if self.ready(report, data.component_id) {
+                    self.components.get_mut(&data.component_id).unwrap().run = true;
+                }
+            }
+            BlockingData::StopComponent(data) => {
+                match self.components.get_mut(&data.component_id) {
+                    Some(component) => component.run = false,
+                    None => report.error(format!("Component {} not found", data.component_id)),
+                }
+            }
+            BlockingData::AddRoute(data) => {
+                let (source, target) =
+                    match (runner_endpoint(&data.source), runner_endpoint(&data.target)) {
+                        (Ok(source), Ok(target)) => (source, target),
+                        (Err(e), _) | (_, Err(e)) => return report.error(e),
+                    };
+
+                self.endpoint(report, &source);
+                self.endpoint(report, &target);
+
+                if !self.routes.insert((source, target)) {
+                    report.warning(format!(
+                        "Route from {} to {} already exists",
+                        source, target
+                    ));
+                }
+            }
+            BlockingData::RemoveRoute(data) => {
+                let source = match runner_endpoint(&data.source) {
+                    Ok(source) => source,
+                    Err(e) => return report.error(e),
+                };
+
+                match &data.target {
+                    Some(target) => match runner_endpoint(target) {
+                        Ok(target) => {
+                            if !self.routes.remove(&(source, target)) {
+                                report.warning(format!("No route from {} to {}", source, target));
+                            }
+                        }
+                        Err(e) => report.error(e),
+                    },
+                    None => {
+                        // every target of the source goes
+                        let len = self.routes.len();
+                        self.routes.retain(|(from, _)| *from != source);
+                        if self.routes.len() == len {
+                            report.warning(format!("No route from {}", source));
+                        }
+                    }
+                }
+            }
+            BlockingData::SetSchedule(data) => {
+                if data.deadline == 0 {
+                    report.error("Schedule period is zero".to_string());
+                }
+                if data.major_frames.is_empty() {
+                    report.warning("Schedule has no major frames".to_string());
+                }
+
+                let mut scheduled = Vec::new();
+                for (index, major_frame) in data.major_frames.iter().enumerate() {
+                    // minor frames run back to back within one period
+                    let length: u64 = major_frame
+                        .minor_frames
+                        .iter()
+                        .map(|frame| frame.deadline)
+                        .sum();
+                    if length > data.deadline {
+                        report.error(format!(
+                            "Major frame {} needs {}us, more than the {}us period",
+                            index, length, data.deadline
+                        ));
+                    }
+
+                    for minor_frame in major_frame.minor_frames.iter() {
+                        self.ready(report, minor_frame.component_id);
+                        scheduled.push(minor_frame.component_id);
+                    }
+                }
+
+                self.scheduled = scheduled;
+            }
+            BlockingData::AddStateSync(data) => {
+                for component_id in [data.source.component_id, data.target.component_id] {
+                    if !self.components.contains_key(&component_id) {
+                        report.warning(format!(
+                            "State sync {} component {} not found",
+                            data.state_sync_id, component_id
+                        ));
+                    }
+                }
+
+                if !self.state_syncs.insert(data.state_sync_id) {
+                    report.warning(format!(
+                        "State sync {} replaces an existing state sync",
+                        data.state_sync_id
+                    ));
+                }
+            }
+            BlockingData::RemoveStateSync(data) => {
+                if !self.state_syncs.remove(&data.state_sync_id) {
+                    report.warning(format!("State sync {} not found", data.state_sync_id));
+                }
+            }
+        }
+    }
+
+    /// Check a non-blocking action, returning the id of a component it adds.
+    fn non_blocking(
+        &mut self,
+        report: &mut Report,
+        data: &NonBlockingData,
+        registry: Option<&Registry>,
+    ) -> Option<Uuid> {
+        match data {
+            NonBlockingData::AddComponent(data) => {
+                if self.components.contains_key(&data.component_id) {
+                    report.error(format!("Component {} already exists", data.component_id));
+                    return None;
+                }
+                if let Err(e) = data.limits.check() {
+                    report.error(format!(
+                        "Component {} has invalid limits; {}",
+                        data.component_id, e
+                    ));
+                } else if !data.limits.is_empty() && !self.cgroups {
+                    report.error(format!(
+                        "Component {} has limits but the runner has no cgroup",
+                        data.component_id
+                    ));
+                }
+                match registry {
+                    Some(registry) => {
+                        let binary = registry
+                            .resolve(&data.component, &data.version, data.sha256.as_deref())
+                            .and_then(|(path, sha256)| {
+                                binary::verify(&path.to_string_lossy(), &sha256)
+                            });
+                        if let Err(e) = binary {
+                            report.error(e);
+                        }
+                    }
+                    None if binary::find(&data.component).is_none() => {
+                        report.error(format!("Component binary {} not found", data.component));
+                    }
+                    None => {
+                        if let Some(sha256) = &data.sha256 {
+                            if let Err(e) = binary::verify(&data.component, sha256) {
+                                report.error(e);
+                            }
+                        }
+                    }
+                }
+
+                self.components.insert(
+                    data.component_id,
+                    Component {
+                        ready: false,
+                        run: false,
+                        remove: false,
+                    },
+                );
+                Some(data.component_id)
+            }
+            NonBlockingData::RemoveComponent(data) => {
+                match self.components.get_mut(&data.component_id) {
+                    Some(component) if component.remove => report.warning(format!(
+                        "Component {} is already removed",
+                        data.component_id
+                    )),
+                    Some(component) => {
+                        component.remove = true;
+                        component.run = false;
+                    }
+                    None => report.error(format!("Component {} not found", data.component_id)),
+                }
+                None
+            }
+            NonBlockingData::WaitStateSync(data) => {
+                if !self.state_syncs.contains(&data.state_sync_id) {
+                    report.error(format!("State sync {} not found", data.state_sync_id));
+                }
+                None
+            }
+        }
+    }
+
+    /// Check that a component can be started or scheduled.
+    fn ready(&self, report: &mut Report, id: Uuid) -> bool {
+        match self.components.get(&id) {
+            Some(component) if !component.ready => {
+                report.error(format!("Component {} not initialized", id));
+                false
+            }
+            Some(component) if component.remove => {
+                report.error(format!("Component {} is being removed", id));
+                false
+            }
+            Some(_) => true,
+            None => {
+                report.error(format!("Component {} not found", id));
+                false
+            }
+        }
+    }
+
+    /// Messages routed from or to a component that does not exist are dropped.
+    fn endpoint(&self, report: &mut Report, endpoint: &RouteEndpoint) {
+        if let Endpoint::Component(id) = endpoint.endpoint {
+            match self.components.get(&id) {
+                Some(component) if !component.remove => {}
+                _ => report.warning(format!("Route endpoint {} has no component", endpoint)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::global_state::{Implementation, Socket};
+    use crate::services::scheduler::{MajorFrame, MinorFrame, Schedule};
+    use elafry::types::configuration::{
+        AddComponentData, AddRouteData, BlockingAction, Limits,
+        MajorFrame as ConfigurationMajorFrame, MinorFrame as ConfigurationMinorFrame,
+        NonBlockingAction, RemoveComponentData, RemoveRouteData, SetScheduleData,
+        StartComponentData, Task, WaitStateSyncData,
+    };
+    use std::os::unix::net::UnixStream;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    fn implementation() -> Implementation {
+        Implementation {
+            data_socket: Socket {
+                socket: UnixStream::pair().unwrap().0,
+                count: 0,
+            },
+            state_socket: Socket {
+                socket: UnixStream::pair().unwrap().0,
+                count: 0,
+            },
+            child: std::process::Command::new("ls").spawn().unwrap(),
+            child_pid: 0,
+            schedstat: None,
+            cgroup: None,
+        }
+    }
+
+    fn blocking(data: Vec<BlockingData>) -> Task {
+        Task {
+            id: Uuid::new_v4(),
+            actions: Action::Blocking(
+                data.into_iter()
+                    .map(|data| BlockingAction {
+                        id: Uuid::new_v4(),
+                        data,
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    fn non_blocking(data: Vec<NonBlockingData>) -> Task {
+        Task {
+            id: Uuid::new_v4(),
+            actions: Action::NonBlocking(
+                data.into_iter()
+                    .map(|data| NonBlockingAction {
+                        id: Uuid::new_v4(),
+                        data,
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    fn schedule(deadline: u64, minor_frames: Vec<(Uuid, u64)>) -> BlockingData {
+        BlockingData::SetSchedule(SetScheduleData {
+            deadline,
+            major_frames: vec![ConfigurationMajorFrame {
+                minor_frames: minor_frames
+                    .into_iter()
+                    .map(|(component_id, deadline)| ConfigurationMinorFrame {
+                        component_id,
+                        deadline,
+                    })
+                    .collect(),
+            }],
+        })
+    }
+
+    fn messages(issues: &[Issue]) -> Vec<(Severity, Option<usize>, &str)> {
+        issues
+            .iter()
+            .map(|issue| (issue.severity, issue.task, issue.message.as_str()))
+            .collect()
+    }
+
+    // a running component that is scheduled
+    fn running(state: &mut GlobalState) -> Uuid {
+        let id = Uuid::new_v4();
+        state.add_component(id, "old".to_string(), 0, "1.0.0".to_string());
+        state
+            .add_component_implementation(id, implementation())
+            .unwrap();
+        state.start_component(id).unwrap();
+        state.schedule = Schedule {
+            period: std::time::Duration::from_micros(1000),
+            major_frames: vec![MajorFrame {
+                minor_frames: vec![MinorFrame {
+                    component_id: id,
+                    deadline: std::time::Duration::from_micros(500),
+                }],
+            }],
+        };
+        id
+    }
+
+    #[test]
+    fn test_validate_replace_component() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let old = running(&mut state);
+        let new = Uuid::new_v4();
+
+        // add a component, move the schedule over to it and remove the old one
+        let configuration = Configuration {
+            tasks: vec![
+                non_blocking(vec![NonBlockingData::AddComponent(AddComponentData {
+                    component_id: new,
+                    component: "ls".to_string(),
+                    core: 1,
+                    version: "0.1.0".to_string(),
+                    sha256: None,
+                    restart: Default::default(),
+                    limits: Default::default(),
+                })]),
+                blocking(vec![
+                    BlockingData::StartComponent(StartComponentData { component_id: new }),
+                    schedule(1000, vec![(new, 500)]),
+                ]),
+                non_blocking(vec![NonBlockingData::RemoveComponent(
+                    RemoveComponentData { component_id: old },
+                )]),
+            ],
+        };
+
+        assert_eq!(validate(&state, &configuration, None, false), vec![]);
+
+        // nothing was changed
+        assert_eq!(state.total_components(), 1);
+        assert!(state.get_component(new).is_none());
+    }
+
+    #[test]
+    fn test_validate_errors() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let old = running(&mut state);
+        let new = Uuid::new_v4();
+        let wrong = Uuid::new_v4();
+
+        let configuration = Configuration {
+            tasks: vec![
+                non_blocking(vec![
+                    NonBlockingData::AddComponent(AddComponentData {
+                        component_id: new,
+                        component: "target/debug/missing".to_string(),
+                        core: 1,
+                        version: "0.1.0".to_string(),
+                        sha256: None,
+                        restart: Default::default(),
+                        limits: Default::default(),
+                    }),
+                    NonBlockingData::RemoveComponent(RemoveComponentData { component_id: old }),
+                ]),
+                blocking(vec![
+                    BlockingData::StartComponent(StartComponentData {
+                        component_id: wrong,
+                    }),
+                    schedule(1000, vec![(new, 600), (new, 600)]),
+                    BlockingData::AddRoute(AddRouteData {
+                        source: elafry::types::configuration::RouteEndpoint {
+                            endpoint: elafry::types::configuration::Endpoint::Address(
+                                "not an address".to_string(),
+                            ),
+                            channel_id: 1,
+                        },
+                        target: elafry::types::configuration::RouteEndpoint {
+                            endpoint: elafry::types::configuration::Endpoint::Runner,
+                            channel_id: 1,
+                        },
+                    }),
+                ]),
+                non_blocking(vec![NonBlockingData::WaitStateSync(WaitStateSyncData {
+                    state_sync_id: wrong,
+                })]),
+            ],
+        };
+
+        let issues = validate(&state, &configuration, None, false);
+        assert_eq!(
+            messages(&issues),
+            vec![
+                (
+                    Severity::Error,
+                    Some(0),
+                    "Component binary target/debug/missing not found"
+                ),
+                (
+                    Severity::Error,
+                    Some(1),
+                    format!("Component {} not found", wrong).as_str()
+                ),
+                (
+                    Severity::Error,
+                    Some(1),
+                    "Major frame 0 needs 1200us, more than the 1000us period"
+                ),
+                (
+                    Severity::Error,
+                    Some(1),
+                    "Failed to parse address not an address; err = invalid socket address syntax"
+                ),
+                (
+                    Severity::Error,
+                    Some(2),
+                    format!("State sync {} not found", wrong).as_str()
+                ),
+                (
+                    Severity::Warning,
+                    None,
+                    format!("Component {} is scheduled but not running", new).as_str()
+                ),
+            ]
+        );
+        match &configuration.tasks[1].actions {
+            Action::Blocking(actions) => assert_eq!(issues[1].action, Some(actions[0].id)),
+            Action::NonBlocking(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_validate_routes() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let id = running(&mut state);
+        let endpoint = |channel_id| elafry::types::configuration::RouteEndpoint {
+            endpoint: elafry::types::configuration::Endpoint::Component(id),
+            channel_id,
+        };
+        let add = |source, target| {
+            BlockingData::AddRoute(AddRouteData {
+                source: endpoint(source),
+                target: endpoint(target),
+            })
+        };
+        let remove = |source, target: Option<u32>| {
+            BlockingData::RemoveRoute(RemoveRouteData {
+                source: endpoint(source),
+                target: target.map(endpoint),
+            })
+        };
+        state.add_route(
+            runner_endpoint(&endpoint(1)).unwrap(),
+            runner_endpoint(&endpoint(2)).unwrap(),
+        );
+
+        // a second target of a source is a route of its own
+        let configuration = Configuration {
+            tasks: vec![blocking(vec![
+                add(1, 3),
+                add(1, 2),
+                remove(1, Some(2)),
+                remove(1, Some(2)),
+                remove(1, None),
+                remove(1, None),
+            ])],
+        };
+
+        let source = runner_endpoint(&endpoint(1)).unwrap();
+        let target = runner_endpoint(&endpoint(2)).unwrap();
+        assert_eq!(
+            messages(&validate(&state, &configuration, None, false)),
+            vec![
+                (
+                    Severity::Warning,
+                    Some(0),
+                    format!("Route from {} to {} already exists", source, target).as_str()
+                ),
+                (
+                    Severity::Warning,
+                    Some(0),
+                    format!("No route from {} to {}", source, target).as_str()
+                ),
+                (
+                    Severity::Warning,
+                    Some(0),
+                    format!("No route from {}", source).as_str()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_binary_hash() {
+        setup();
+
+        let state = GlobalState::new();
+        let new = Uuid::new_v4();
+        let sha256 = elafry::signature::sha256(&std::fs::read("/bin/sh").unwrap());
+        let configuration = |expected: &str| Configuration {
+            tasks: vec![
+                non_blocking(vec![NonBlockingData::AddComponent(AddComponentData {
+                    component_id: new,
+                    component: "/bin/sh".to_string(),
+                    core: 1,
+                    version: "0.1.0".to_string(),
+                    sha256: Some(expected.to_string()),
+                    restart: Default::default(),
+                    limits: Default::default(),
+                })]),
+                blocking(vec![
+                    BlockingData::StartComponent(StartComponentData { component_id: new }),
+                    schedule(1000, vec![(new, 500)]),
+                ]),
+            ],
+        };
+
+        assert_eq!(
+            validate(&state, &configuration(&sha256), None, false),
+            vec![]
+        );
+
+        let wrong = elafry::signature::sha256(b"");
+        assert_eq!(
+            messages(&validate(&state, &configuration(&wrong), None, false)),
+            vec![(
+                Severity::Error,
+                Some(0),
+                format!(
+                    "Component binary /bin/sh has sha256 {}, expected {}",
+                    sha256, wrong
+                )
+                .as_str()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_validate_registry() {
+        setup();
+
+        let directory = std::env::temp_dir().join(format!("elafry-registry-{}", Uuid::new_v4()));
+        let mut registry = super::super::registry::Registry::open(&directory).unwrap();
+        let staged = super::super::registry::stage(
+            &directory,
+            "sh",
+            "1.0.0",
+            std::path::Path::new("/bin/sh"),
+        )
+        .unwrap();
+        let sha256 = registry.add(staged).unwrap();
+
+        let state = GlobalState::new();
+        let new = Uuid::new_v4();
+        let configuration = |version: &str, expected: Option<&str>| Configuration {
+            tasks: vec![
+                non_blocking(vec![NonBlockingData::AddComponent(AddComponentData {
+                    component_id: new,
+                    component: "sh".to_string(),
+                    core: 1,
+                    version: version.to_string(),
+                    sha256: expected.map(|sha256| sha256.to_string()),
+                    restart: Default::default(),
+                    limits: Default::default(),
+                })]),
+                blocking(vec![
+                    BlockingData::StartComponent(StartComponentData { component_id: new }),
+                    schedule(1000, vec![(new, 500)]),
+                ]),
+            ],
+        };
+
+        // names are looked up in the registry rather than on the PATH
+        assert_eq!(
+            validate(
+                &state,
+                &configuration("1.0.0", None),
+                Some(&registry),
+                false
+            ),
+            vec![]
+        );
+        assert_eq!(
+            validate(
+                &state,
+                &configuration("1.0.0", Some(&sha256)),
+                Some(&registry),
+                false
+            ),
+            vec![]
+        );
+        assert_eq!(
+            messages(&validate(
+                &state,
+                &configuration("2.0.0", None),
+                Some(&registry),
+                false
+            )),
+            vec![(
+                Severity::Error,
+                Some(0),
+                "Component sh has no version 2.0.0, the registry has 1.0.0"
+            )]
+        );
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_validate_limits() {
+        setup();
+
+        let state = GlobalState::new();
+        let new = Uuid::new_v4();
+        let configuration = |limits: Limits| Configuration {
+            tasks: vec![
+                non_blocking(vec![NonBlockingData::AddComponent(AddComponentData {
+                    component_id: new,
+                    component: "/bin/sh".to_string(),
+                    core: 1,
+                    version: "0.1.0".to_string(),
+                    sha256: None,
+                    restart: Default::default(),
+                    limits,
+                })]),
+                blocking(vec![
+                    BlockingData::StartComponent(StartComponentData { component_id: new }),
+                    schedule(1000, vec![(new, 500)]),
+                ]),
+            ],
+        };
+        let limits = Limits {
+            memory_max: Some(1 << 20),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            validate(&state, &configuration(limits.clone()), None, true),
+            vec![]
+        );
+        assert_eq!(
+            messages(&validate(&state, &configuration(limits), None, false)),
+            vec![(
+                Severity::Error,
+                Some(0),
+                format!("Component {} has limits but the runner has no cgroup", new).as_str()
+            )]
+        );
+
+        let invalid = Limits {
+            cpu_quota: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            messages(&validate(&state, &configuration(invalid), None, true)),
+            vec![(
+                Severity::Error,
+                Some(0),
+                format!(
+                    "Component {} has invalid limits; cpu-quota must be greater than 0",
+                    new
+                )
+                .as_str()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_validate_removed_still_scheduled() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let old = running(&mut state);
+
+        // the schedule is never moved away from the removed component
+        let configuration = Configuration {
+            tasks: vec![
+                non_blocking(vec![NonBlockingData::RemoveComponent(
+                    RemoveComponentData { component_id: old },
+                )]),
+                blocking(vec![BlockingData::StartComponent(StartComponentData {
+                    component_id: old,
+                })]),
+            ],
+        };
+
+        assert_eq!(
+            messages(&validate(&state, &configuration, None, false)),
+            vec![
+                (
+                    Severity::Error,
+                    Some(1),
+                    format!("Component {} is being removed", old).as_str()
+                ),
+                (
+                    Severity::Error,
+                    None,
+                    format!("Component {} is removed but still scheduled", old).as_str()
+                ),
+            ]
+        );
+
+        // an empty configuration is an error on its own
+        assert_eq!(
+            messages(&validate(
+                &state,
+                &Configuration { tasks: vec![] },
+                None,
+                false
+            )),
+            vec![(Severity::Error, None, "No tasks in configuration")]
+        );
+    }
+}
@@ -1,3 +1,9 @@
+use std::sync::Arc;
+
+use elafry::telemetry::{Record, Recorder, Span};
+
+use crate::simulation::Clock;
+
 pub struct Schedule {
     pub period: std::time::Duration,
     pub major_frames: Vec<MajorFrame>,
@@ -14,14 +20,25 @@ pub struct MinorFrame {
 
 pub struct SchedulerService {
     frame_index: usize,
+    /// Virtual time when simulating, slots then last until the component stops.
+    clock: Option<Arc<Clock>>,
 }
 
 impl SchedulerService {
-    pub fn new() -> Self {
-        SchedulerService { frame_index: 0 }
+    pub fn new(clock: Option<Arc<Clock>>) -> Self {
+        SchedulerService {
+            frame_index: 0,
+            clock,
+        }
     }
 
-    fn execute(&mut self, state: &mut crate::global_state::GlobalState) {
+    fn execute(
+        &mut self,
+        state: &mut crate::global_state::GlobalState,
+        frame_start: std::time::Duration,
+        recorder: &mut Recorder,
+        frame_number: u64,
+    ) {
         // if there are no major frames, return
         if state.schedule.major_frames.is_empty() {
             log::warn!("No major frames");
@@ -36,8 +53,14 @@ impl SchedulerService {
         // get the current major frame
         let major_frame = &state.schedule.major_frames[self.frame_index];
 
+        // minor frame deadlines are absolute so overheads do not push later frames back
+        let mut deadline = frame_start;
+
         // run the minor frames
-        for (_, frame) in major_frame.minor_frames.iter().enumerate() {
+        for frame in major_frame.minor_frames.iter() {
+            let slot_start = deadline;
+            deadline += frame.deadline;
+
             // log::debug!("Running component {:?}", frame.component_id);
 
             let component = match state.components.get_mut(&frame.component_id) {
@@ -48,6 +71,11 @@ impl SchedulerService {
                 }
             };
 
+            // a crashed component waits for its restart
+            if component.crashed {
+                continue;
+            }
+
             // if the component is not running, continue
             if !component.run {
                 log::error!("Component not running {:?}", frame.component_id);
@@ -57,25 +85,51 @@ impl SchedulerService {
             match &mut component.implentation {
                 Some(implentation) => {
                     // set the priority of the component to the highest
-                    unsafe {
-                        let ret = libc::sched_setscheduler(
-                            implentation.child_pid,
-                            libc::SCHED_FIFO,
-                            &libc::sched_param { sched_priority: 99 },
-                        );
-                        if ret != 0 {
-                            println!("Failed to set scheduler");
+                    crate::realtime::raise(implentation.child_pid);
+
+                    // a simulated component sees the virtual start of its slot
+                    if let Some(clock) = &self.clock {
+                        if let Err(e) = clock.set(slot_start) {
+                            log::error!("{}", e);
                         }
                     }
 
                     // resume the child
+                    let cpu_start = implentation.cpu_time();
+                    let start = elafry::telemetry::now();
                     unsafe {
                         libc::kill(implentation.child_pid, libc::SIGCONT);
                     }
 
-                    // sleep for the deadline
-                    std::thread::sleep(frame.deadline);
-                    
+                    // sleep until the deadline, or until the component is done when simulating
+                    match &self.clock {
+                        Some(_) => {
+                            if let Err(e) = crate::simulation::wait_stopped(implentation.child_pid)
+                            {
+                                log::error!("{}", e);
+                            }
+                        }
+                        None => crate::timing::sleep_until(deadline),
+                    }
+
+                    // cpu time used by the component in its slot
+                    let end = elafry::telemetry::now();
+                    let cpu_time = match (cpu_start, implentation.cpu_time()) {
+                        (Some(cpu_start), Some(cpu_end)) => {
+                            let cpu_time = cpu_end.saturating_sub(cpu_start);
+                            component.times.add(cpu_time);
+                            cpu_time
+                        }
+                        _ => std::time::Duration::ZERO,
+                    };
+
+                    recorder.record(Record {
+                        frame: frame_number,
+                        span: Span::Component(frame.component_id),
+                        start,
+                        end,
+                        value: cpu_time.as_nanos() as u64,
+                    });
 
                     // // check if the component is still running
                     // let child_proc = procfs::process::Process::new(implentation.child_pid).unwrap();
@@ -84,16 +138,7 @@ impl SchedulerService {
                     // // if over deadline change priority to lowest
                     // if child_state != 'T' {
                     //     log::error!("Component over deadline {:?} {:?} {}", frame.component_id, child_state, frame.deadline.as_micros());
-                        unsafe {
-                            let ret = libc::sched_setscheduler(
-                                implentation.child_pid,
-                                libc::SCHED_IDLE,
-                                &libc::sched_param { sched_priority: 0 },
-                            );
-                            if ret != 0 {
-                                println!("Failed to set scheduler");
-                            }
-                        }
+                    crate::realtime::lower(implentation.child_pid);
                     // }
                 }
                 None => {
@@ -103,8 +148,14 @@ impl SchedulerService {
         }
     }
 
-    pub fn run(&mut self, state: &mut crate::global_state::GlobalState) {
-        self.execute(state);
+    pub fn run(
+        &mut self,
+        state: &mut crate::global_state::GlobalState,
+        frame_start: std::time::Duration,
+        recorder: &mut Recorder,
+        frame_number: u64,
+    ) {
+        self.execute(state, frame_start, recorder, frame_number);
         self.frame_index += 1;
     }
 }
@@ -4,6 +4,12 @@ pub struct StateService {
     input_state: HashMap<uuid::Uuid, Vec<u8>>,
 }
 
+impl Default for StateService {
+    fn default() -> Self {
+        StateService::new()
+    }
+}
+
 impl StateService {
     pub fn new() -> Self {
         StateService {
@@ -55,6 +61,9 @@ impl StateService {
                                     }
                                 };
 
+                                // kept to restore the component if its process crashes
+                                component.last_state.clone_from(&state_buf);
+
                                 // set input_state
                                 self.input_state.insert(*id, state_buf);
                             }
@@ -0,0 +1,209 @@
+use serde::Deserialize;
+
+use crate::realtime::Scheduling;
+use crate::recording::Replay;
+use crate::sandbox::Sandbox;
+use crate::simulation::Simulation;
+use crate::timing::OverrunPolicy;
+
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct Settings {
+    pub port: u16,
+    /// Initial configuration, in the configuration directory.
+    pub configuration: String,
+    /// Directory configurations are loaded from by name.
+    #[serde(rename = "configuration-directory")]
+    pub configuration_directory: String,
+    #[serde(rename = "overrun-policy")]
+    pub overrun_policy: OverrunPolicy,
+    pub telemetry: String,
+    #[serde(rename = "telemetry-capacity")]
+    pub telemetry_capacity: usize,
+    /// Tcp address or `unix:<path>` to serve metrics on, empty to disable.
+    pub metrics: String,
+    /// Unix socket path for elafryctl, empty to disable.
+    pub control: String,
+    /// Milliseconds a reconfiguration may take before it is rolled back.
+    #[serde(rename = "reconfiguration-timeout")]
+    pub reconfiguration_timeout: u64,
+    /// Hex public keys configurations must be signed with, empty to load unsigned configurations.
+    #[serde(rename = "trusted-keys")]
+    pub trusted_keys: Vec<String>,
+    /// Directory of installed component versions, empty to run components from their paths.
+    pub registry: String,
+    /// Milliseconds components are given to exit when the runner shuts down.
+    #[serde(rename = "shutdown-timeout")]
+    pub shutdown_timeout: u64,
+    /// Cgroup v2 directory the runner puts component processes in, empty to not limit them.
+    pub cgroup: String,
+    /// How component processes are confined, they run as the runner user if not given.
+    pub sandbox: Option<Sandbox>,
+    /// Real-time or best-effort scheduling of the runner and its components.
+    pub scheduling: Scheduling,
+    /// Run on virtual time, the runner runs in real time if not given.
+    pub simulation: Option<Simulation>,
+    /// File every routed message is recorded to, empty to disable.
+    pub record: String,
+    /// Recorded messages to play to a component, nothing is replayed if not given.
+    pub replay: Option<Replay>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            port: 5000,
+            configuration: "default.yaml".to_string(),
+            configuration_directory: "configuration".to_string(),
+            overrun_policy: OverrunPolicy::Skip,
+            telemetry: "telemetry.bin".to_string(),
+            telemetry_capacity: 65536,
+            metrics: "127.0.0.1:9464".to_string(),
+            control: "elafry.sock".to_string(),
+            reconfiguration_timeout: 10000,
+            trusted_keys: vec![],
+            registry: String::new(),
+            shutdown_timeout: 1000,
+            cgroup: String::new(),
+            sandbox: None,
+            scheduling: Scheduling::Auto,
+            simulation: None,
+            record: String::new(),
+            replay: None,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(path: &str) -> Result<Settings, String> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| format!("Failed to open settings {}; err = {}", path, e))?;
+
+        let settings: Settings = serde_yaml::from_reader(file)
+            .map_err(|e| format!("Failed to parse settings {}; err = {}", path, e))?;
+        settings
+            .trusted_keys()
+            .map_err(|e| format!("Invalid trusted key in settings {}; err = {}", path, e))?;
+
+        Ok(settings)
+    }
+
+    /// The trusted keys, checked when the settings are loaded.
+    pub fn trusted_keys(&self) -> Result<Vec<elafry::signature::VerifyingKey>, String> {
+        self.trusted_keys
+            .iter()
+            .map(|key| elafry::signature::verifying_key(key))
+            .collect()
+    }
+
+    pub fn from_args() -> Settings {
+        // the first argument is an optional path to the runner settings
+        match std::env::args().nth(1) {
+            Some(path) => match Settings::load(&path) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    log::error!("{}", e);
+                    std::process::exit(1);
+                }
+            },
+            None => {
+                log::info!("No settings file given, using defaults");
+                Settings::default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_settings_default() {
+        setup();
+
+        let settings: Settings = serde_yaml::from_str("{}").unwrap();
+
+        assert_eq!(settings, Settings::default());
+    }
+
+    #[test]
+    fn test_settings() {
+        setup();
+
+        let settings: Settings = serde_yaml::from_str(
+            "port: 6000\nconfiguration: plant_1.yaml\nconfiguration-directory: /etc/elafry\noverrun-policy: compress\ntelemetry: run.bin\nmetrics: unix:/tmp/runner.sock\ncontrol: /tmp/elafry.sock\nreconfiguration-timeout: 500\nregistry: /opt/elafry/components\nshutdown-timeout: 250\ncgroup: /sys/fs/cgroup/elafry\nsandbox:\n  uid: 65534\n  gid: 65534\n  seccomp: false\nscheduling: best-effort\nsimulation:\n  frames: 3600000\nrecord: flight.rec\nreplay:\n  recording: field.rec\n  component-id: 4310fde1-8c21-4054-a5d6-8b64c2c2afcd\n  recorded-component-id: 4d2f439b-ae60-42b6-ae45-b4db5d020f21\n",
+        )
+        .unwrap();
+
+        assert_eq!(settings.port, 6000);
+        assert_eq!(settings.configuration, "plant_1.yaml");
+        assert_eq!(settings.configuration_directory, "/etc/elafry");
+        assert_eq!(settings.overrun_policy, OverrunPolicy::Compress);
+        assert_eq!(settings.telemetry, "run.bin");
+        assert_eq!(settings.telemetry_capacity, 65536);
+        assert_eq!(settings.metrics, "unix:/tmp/runner.sock");
+        assert_eq!(settings.control, "/tmp/elafry.sock");
+        assert_eq!(settings.reconfiguration_timeout, 500);
+        assert_eq!(settings.registry, "/opt/elafry/components");
+        assert_eq!(settings.shutdown_timeout, 250);
+        assert_eq!(settings.cgroup, "/sys/fs/cgroup/elafry");
+        assert_eq!(
+            settings.sandbox,
+            Some(Sandbox {
+                uid: 65534,
+                gid: 65534,
+                namespaces: true,
+                seccomp: false,
+            })
+        );
+        assert_eq!(settings.scheduling, Scheduling::BestEffort);
+        assert_eq!(
+            settings.simulation,
+            Some(Simulation {
+                frames: Some(3600000)
+            })
+        );
+        assert_eq!(settings.record, "flight.rec");
+        assert_eq!(
+            settings.replay,
+            Some(Replay {
+                recording: "field.rec".to_string(),
+                component_id: uuid::Uuid::parse_str("4310fde1-8c21-4054-a5d6-8b64c2c2afcd")
+                    .unwrap(),
+                recorded_component_id: Some(
+                    uuid::Uuid::parse_str("4d2f439b-ae60-42b6-ae45-b4db5d020f21").unwrap()
+                ),
+            })
+        );
+        assert_eq!(settings.trusted_keys().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_settings_trusted_keys() {
+        setup();
+
+        let key = elafry::signature::generate_key().unwrap().verifying_key();
+        let settings: Settings = serde_yaml::from_str(&format!(
+            "trusted-keys:\n  - {}\n",
+            elafry::signature::to_hex(key.as_bytes())
+        ))
+        .unwrap();
+        assert_eq!(settings.trusted_keys().unwrap(), vec![key]);
+
+        let settings: Settings = serde_yaml::from_str("trusted-keys: [abcd]\n").unwrap();
+        assert_eq!(
+            settings.trusted_keys(),
+            Err("Key abcd is not 32 bytes".to_string())
+        );
+    }
+}
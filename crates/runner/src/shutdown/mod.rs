@@ -0,0 +1,234 @@
+//! Stopping the component processes when the runner exits.
+//!
+//! Components are sent SIGTERM and continued, `elafry::run` then calls their
+//! `on_shutdown` and exits. Whatever has not exited when the timeout runs out,
+//! or when a second signal arrives, is killed. Every process is reaped so none
+//! is left stopped or as a zombie.
+
+use std::time::{Duration, Instant};
+
+use crate::global_state::{GlobalState, Implementation};
+
+/// Exit status when every component exited by itself.
+pub const CLEAN: i32 = 0;
+/// Exit status when a component had to be killed or exited with an error.
+pub const FORCED: i32 = 2;
+
+/// Stop every component process and return the exit status for the runner.
+///
+/// `others` are processes not in the state, such as components still being
+/// started. `route` is called while waiting so messages sent by components as
+/// they shut down are still delivered.
+pub fn stop_components(
+    state: &mut GlobalState,
+    mut others: Vec<(uuid::Uuid, Implementation)>,
+    timeout: Duration,
+    mut route: impl FnMut(&mut GlobalState),
+) -> i32 {
+    let mut status = CLEAN;
+    let signals = elafry::shutdown::signals();
+
+    // a stopped process only handles the signal once it is continued
+    for pid in pids(state, &others) {
+        unsafe {
+            libc::kill(pid, libc::SIGTERM);
+            libc::kill(pid, libc::SIGCONT);
+        }
+    }
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        let mut exited = Vec::new();
+        for (id, component) in state.components.iter_mut() {
+            if let Some(implementation) = &mut component.implentation {
+                if poll(*id, implementation, &mut status) {
+                    exited.push(*id);
+                }
+            }
+        }
+
+        // anything written before a process exited is still in its socket
+        route(state);
+        for id in exited {
+            let _ = state.remove_component_implementation(id);
+        }
+        others.retain_mut(|(id, implementation)| !poll(*id, implementation, &mut status));
+
+        let remaining = pids(state, &others);
+        if remaining.is_empty() {
+            break;
+        }
+        if Instant::now() >= deadline || elafry::shutdown::signals() > signals {
+            log::warn!(
+                "{} components did not stop in time, killing them",
+                remaining.len()
+            );
+            break;
+        }
+
+        // a component running its slot when asked may have stopped itself since
+        for pid in remaining {
+            unsafe {
+                libc::kill(pid, libc::SIGCONT);
+            }
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    let left = state
+        .components
+        .iter_mut()
+        .filter_map(|(id, component)| Some((*id, component.implentation.take()?)));
+    for (id, mut implementation) in left.chain(others) {
+        log::warn!("Killing component {}", id);
+        let _ = implementation.child.kill();
+        let _ = implementation.child.wait();
+        status = FORCED;
+    }
+
+    status
+}
+
+fn pids(state: &GlobalState, others: &[(uuid::Uuid, Implementation)]) -> Vec<libc::pid_t> {
+    state
+        .components
+        .values()
+        .filter_map(|component| component.implentation.as_ref())
+        .chain(others.iter().map(|(_, implementation)| implementation))
+        .map(|implementation| implementation.child_pid)
+        .collect()
+}
+
+/// Check whether a component has exited, reaping it if so.
+fn poll(id: uuid::Uuid, implementation: &mut Implementation, status: &mut i32) -> bool {
+    match implementation.child.try_wait() {
+        Ok(Some(exit)) if exit.success() => {
+            log::info!("Component {} stopped", id);
+            true
+        }
+        Ok(Some(exit)) => {
+            log::warn!("Component {} stopped with {}", id, exit);
+            *status = FORCED;
+            true
+        }
+        Ok(None) => false,
+        Err(e) => {
+            log::error!("Failed to wait for component {}; err = {}", id, e);
+            *status = FORCED;
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::global_state::Socket;
+    use std::os::unix::net::UnixStream;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    fn spawn(script: &str) -> Implementation {
+        let child = std::process::Command::new("sh")
+            .args(["-c", script])
+            .spawn()
+            .unwrap();
+        let pid = child.id() as libc::pid_t;
+
+        Implementation {
+            data_socket: Socket {
+                socket: UnixStream::pair().unwrap().0,
+                count: 0,
+            },
+            state_socket: Socket {
+                socket: UnixStream::pair().unwrap().0,
+                count: 0,
+            },
+            child,
+            child_pid: pid,
+            schedstat: None,
+            cgroup: None,
+        }
+    }
+
+    fn add(state: &mut GlobalState, script: &str) -> uuid::Uuid {
+        let id = uuid::Uuid::new_v4();
+        state.add_component(id, "sh".to_string(), 1, "1.0.0".to_string());
+        state
+            .add_component_implementation(id, spawn(script))
+            .unwrap();
+        id
+    }
+
+    #[test]
+    fn test_stop_components() {
+        setup();
+
+        let mut state = GlobalState::new();
+        let id = add(
+            &mut state,
+            "trap 'exit 0' TERM; while :; do sleep 0.01; done",
+        );
+
+        // a signal arriving before the shell sets its trap would kill it
+        std::thread::sleep(Duration::from_millis(200));
+
+        let mut routed = 0;
+        let start = Instant::now();
+        let status = stop_components(&mut state, vec![], Duration::from_secs(10), |_| routed += 1);
+
+        assert_eq!(status, CLEAN);
+        assert!(routed > 0);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // the component stays in the state, only its process is gone
+        assert!(state.components[&id].implentation.is_none());
+    }
+
+    #[test]
+    fn test_stop_components_forced() {
+        setup();
+
+        let mut state = GlobalState::new();
+        add(&mut state, "trap '' TERM; exec sleep 10");
+        add(
+            &mut state,
+            "trap 'exit 3' TERM; while :; do sleep 0.01; done",
+        );
+        let starting = spawn("trap '' TERM; exec sleep 10");
+        let starting_pid = starting.child_pid;
+
+        // a stopped process is continued so it can handle the signal
+        let stopped = spawn("trap 'exit 0' TERM; while :; do sleep 0.01; done");
+        unsafe {
+            libc::kill(stopped.child_pid, libc::SIGSTOP);
+        }
+
+        let start = Instant::now();
+        let status = stop_components(
+            &mut state,
+            vec![
+                (uuid::Uuid::new_v4(), starting),
+                (uuid::Uuid::new_v4(), stopped),
+            ],
+            Duration::from_millis(200),
+            |_| {},
+        );
+
+        assert_eq!(status, FORCED);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(state
+            .components
+            .values()
+            .all(|component| component.implentation.is_none()));
+
+        // every process was reaped
+        assert_eq!(unsafe { libc::kill(starting_pid, 0) }, -1);
+    }
+}
@@ -0,0 +1,188 @@
+//! Deterministic simulation with virtual time.
+//!
+//! A simulating runner does not sleep. Frames run back to back on a virtual
+//! clock that advances by the period each frame, and each component slot lasts
+//! until the component has stopped itself rather than until its deadline.
+//! Components read the virtual start of their slot from a clock the runner
+//! shares with them, and reconfigurations are waited for so they take effect in
+//! the same frame every run. The same configuration gives the same outputs
+//! every time, as fast as the components can compute them.
+
+use std::fs::File;
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::unix::fs::FileExt;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// File descriptor components find the clock on.
+pub const CLOCK_FD: std::os::fd::RawFd = 12;
+
+/// How long to keep checking a component that has not stopped yet before
+/// checking less often.
+const SPIN: Duration = Duration::from_millis(1);
+const POLL: Duration = Duration::from_micros(100);
+
+/// Simulation settings, the runner runs in real time if not given.
+#[derive(Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct Simulation {
+    /// Frames to run before shutting down, until shut down if not given.
+    pub frames: Option<u64>,
+}
+
+/// The virtual time shared with component processes.
+pub struct Clock {
+    file: File,
+}
+
+impl Clock {
+    pub fn new() -> Result<Clock, String> {
+        let fd = unsafe { libc::memfd_create(c"elafry-clock".as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(format!(
+                "Failed to create clock; err = {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        let clock = Clock {
+            file: unsafe { File::from_raw_fd(fd) },
+        };
+        clock.set(Duration::ZERO)?;
+        Ok(clock)
+    }
+
+    /// Set the time components read, only while none of them is running.
+    pub fn set(&self, time: Duration) -> Result<(), String> {
+        self.file
+            .write_all_at(&(time.as_nanos() as u64).to_ne_bytes(), 0)
+            .map_err(|e| format!("Failed to set clock; err = {}", e))
+    }
+
+    /// A descriptor of the clock to hand to a component process.
+    pub fn share(&self) -> Result<OwnedFd, String> {
+        self.file
+            .try_clone()
+            .map(OwnedFd::from)
+            .map_err(|e| format!("Failed to share clock; err = {}", e))
+    }
+}
+
+/// Wait until a component process has stopped itself or exited.
+///
+/// An exited process is left to be reaped by the supervisor. Gives up when the
+/// runner is asked to shut down, a component that never stops would otherwise
+/// hold it forever.
+pub fn wait_stopped(pid: libc::pid_t) -> Result<(), String> {
+    let start = std::time::Instant::now();
+
+    loop {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let ret = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WSTOPPED | libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+            )
+        };
+        if ret < 0 {
+            return Err(format!(
+                "Failed to wait for {}; err = {}",
+                pid,
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        // no child changed state if the pid is not filled in
+        if unsafe { info.si_pid() } == pid {
+            if info.si_code == libc::CLD_STOPPED {
+                // consume the stop, the next one is waited for after resuming
+                unsafe {
+                    libc::waitid(
+                        libc::P_PID,
+                        pid as libc::id_t,
+                        &mut info,
+                        libc::WSTOPPED | libc::WNOHANG,
+                    );
+                }
+            }
+            return Ok(());
+        }
+
+        if elafry::shutdown::requested() {
+            return Err(format!("Stopped waiting for {} to shut down", pid));
+        }
+
+        // components usually stop within microseconds, then check less often
+        if start.elapsed() < SPIN {
+            std::thread::yield_now();
+        } else {
+            std::thread::sleep(POLL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_clock() {
+        setup();
+
+        let clock = Clock::new().unwrap();
+        let shared = File::from(clock.share().unwrap());
+        let read = || {
+            let mut buf = [0; 8];
+            shared.read_exact_at(&mut buf, 0).unwrap();
+            u64::from_ne_bytes(buf)
+        };
+        assert_eq!(read(), 0);
+
+        clock.set(Duration::from_micros(2500)).unwrap();
+        assert_eq!(read(), 2_500_000);
+    }
+
+    #[test]
+    fn test_wait_stopped() {
+        setup();
+
+        // stops itself twice like a component running two slots, then exits
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "kill -STOP $$; kill -STOP $$; exit 3"])
+            .spawn()
+            .unwrap();
+        let pid = child.id() as libc::pid_t;
+
+        wait_stopped(pid).unwrap();
+        for _ in 0..2 {
+            unsafe { libc::kill(pid, libc::SIGCONT) };
+            wait_stopped(pid).unwrap();
+        }
+
+        // the exit is left for whoever reaps the child
+        assert_eq!(child.wait().unwrap().code(), Some(3));
+        assert!(wait_stopped(pid).is_err());
+    }
+
+    #[test]
+    fn test_settings() {
+        setup();
+
+        let simulation: Simulation = serde_yaml::from_str("frames: 100\n").unwrap();
+        assert_eq!(simulation.frames, Some(100));
+
+        let simulation: Simulation = serde_yaml::from_str("{}").unwrap();
+        assert_eq!(simulation, Simulation::default());
+    }
+}
@@ -0,0 +1,480 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// What to do with the frame grid after a frame runs past its period.
+#[derive(Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum OverrunPolicy {
+    /// Drop the missed frame starts and realign to the next one in the future.
+    #[serde(rename = "skip")]
+    Skip,
+    /// Keep every frame start and run late frames back to back until caught up.
+    #[serde(rename = "compress")]
+    Compress,
+}
+
+/// Current value of the monotonic clock.
+pub fn now() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time);
+    }
+
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+/// Sleep until the monotonic clock reaches `deadline`, returns straight away if
+/// the deadline has already passed.
+pub fn sleep_until(deadline: Duration) {
+    let time = libc::timespec {
+        tv_sec: deadline.as_secs() as libc::time_t,
+        tv_nsec: deadline.subsec_nanos() as libc::c_long,
+    };
+
+    loop {
+        let ret = unsafe {
+            libc::clock_nanosleep(
+                libc::CLOCK_MONOTONIC,
+                libc::TIMER_ABSTIME,
+                &time,
+                std::ptr::null_mut(),
+            )
+        };
+
+        // restart the sleep if interrupted by a signal
+        if ret != libc::EINTR {
+            if ret != 0 {
+                log::error!("Failed to sleep; err = {}", ret);
+            }
+            break;
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct JitterStats {
+    pub count: u64,
+    pub min: Duration,
+    pub max: Duration,
+    pub total: Duration,
+}
+
+impl JitterStats {
+    pub fn add(&mut self, jitter: Duration) {
+        if self.count == 0 || jitter < self.min {
+            self.min = jitter;
+        }
+        if jitter > self.max {
+            self.max = jitter;
+        }
+        self.total += jitter;
+        self.count += 1;
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos((self.total.as_nanos() / self.count as u128) as u64)
+    }
+}
+
+/// Number of recent samples kept by [`CpuTimes`] for percentiles.
+pub const CPU_TIMES_WINDOW: usize = 1024;
+
+/// Cpu time a component used in each of its slots.
+///
+/// Min, max and mean cover the whole run, percentiles cover the most recent
+/// [`CPU_TIMES_WINDOW`] slots so memory stays bounded.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CpuTimes {
+    pub count: u64,
+    pub min: Duration,
+    pub max: Duration,
+    pub total: Duration,
+    window: Vec<Duration>,
+    next: usize,
+}
+
+impl CpuTimes {
+    pub fn new() -> CpuTimes {
+        CpuTimes {
+            window: Vec::with_capacity(CPU_TIMES_WINDOW),
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, time: Duration) {
+        if self.count == 0 || time < self.min {
+            self.min = time;
+        }
+        if time > self.max {
+            self.max = time;
+        }
+        self.total += time;
+        self.count += 1;
+
+        // overwrite the oldest sample once the window is full
+        if self.window.len() < CPU_TIMES_WINDOW {
+            self.window.push(time);
+        } else {
+            self.window[self.next] = time;
+        }
+        self.next = (self.next + 1) % CPU_TIMES_WINDOW;
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos((self.total.as_nanos() / self.count as u128) as u64)
+    }
+
+    /// The most recent sample.
+    pub fn last(&self) -> Option<Duration> {
+        if self.window.is_empty() {
+            return None;
+        }
+        Some(self.window[(self.next + CPU_TIMES_WINDOW - 1) % CPU_TIMES_WINDOW])
+    }
+
+    /// Nearest rank percentile of the recent samples, `percentile` is 0 to 100.
+    ///
+    /// Sorts a copy of the window, so keep it out of the frame loop.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.window.is_empty() {
+            return Duration::ZERO;
+        }
+
+        let mut sorted = self.window.clone();
+        sorted.sort();
+
+        let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1]
+    }
+}
+
+/// Paces the runner loop against absolute frame start times so that sleep and
+/// wake-up errors do not accumulate over a run.
+pub struct FrameTimer {
+    policy: OverrunPolicy,
+    frame_start: Duration,
+    /// Frames start on a virtual clock and are not waited for.
+    simulated: bool,
+    /// When the current frame actually started.
+    woken: Duration,
+    pub overruns: u64,
+    pub skipped: u64,
+    pub jitter: JitterStats,
+}
+
+impl FrameTimer {
+    pub fn new(policy: OverrunPolicy) -> FrameTimer {
+        let start = now();
+
+        FrameTimer {
+            policy,
+            frame_start: start,
+            simulated: false,
+            woken: start,
+            overruns: 0,
+            skipped: 0,
+            jitter: JitterStats::default(),
+        }
+    }
+
+    /// A timer for virtual time starting at zero, frames run back to back and never overrun.
+    pub fn simulated() -> FrameTimer {
+        FrameTimer {
+            frame_start: Duration::ZERO,
+            simulated: true,
+            ..FrameTimer::new(OverrunPolicy::Compress)
+        }
+    }
+
+    /// The ideal start time of the current frame, virtual when simulated.
+    pub fn frame_start(&self) -> Duration {
+        self.frame_start
+    }
+
+    /// When the current frame started on the monotonic clock, also when simulated.
+    pub fn started(&self) -> Duration {
+        if self.simulated {
+            return self.woken;
+        }
+        self.frame_start
+    }
+
+    /// Time spent in the current frame so far, none in virtual time.
+    pub fn elapsed(&self) -> Duration {
+        if self.simulated {
+            return Duration::ZERO;
+        }
+        now().saturating_sub(self.frame_start)
+    }
+
+    /// Block until the start of the next frame, returns how long was slept.
+    pub fn wait(&mut self, period: Duration) -> Duration {
+        if self.simulated {
+            self.frame_start += period;
+            self.woken = now();
+            return Duration::ZERO;
+        }
+
+        let current = now();
+        let (next_start, missed) = next_frame_start(self.policy, self.frame_start, period, current);
+
+        if current > self.frame_start + period {
+            self.overruns += 1;
+            self.skipped += missed;
+            log::error!(
+                "Warning: loop took longer than period {}us - {}us",
+                current.saturating_sub(self.frame_start).as_micros(),
+                period.as_micros()
+            );
+        }
+
+        sleep_until(next_start);
+
+        // measure how late the frame actually started
+        let woken = now();
+        self.jitter.add(woken.saturating_sub(next_start));
+        self.woken = woken;
+
+        self.frame_start = next_start;
+
+        next_start.saturating_sub(current)
+    }
+}
+
+/// Work out the start of the frame following one that began at `frame_start`,
+/// along with the number of frame starts dropped to get there.
+pub fn next_frame_start(
+    policy: OverrunPolicy,
+    frame_start: Duration,
+    period: Duration,
+    now: Duration,
+) -> (Duration, u64) {
+    let next_start = frame_start + period;
+
+    // on time, or catching up one frame at a time
+    if now <= next_start || policy == OverrunPolicy::Compress || period.is_zero() {
+        return (next_start, 0);
+    }
+
+    // skip to the first frame start that is still in the future
+    let late = (now - frame_start).as_nanos();
+    let frames = late.div_ceil(period.as_nanos()) as u64;
+
+    let skipped = Duration::from_nanos((period.as_nanos() * frames as u128) as u64);
+    (frame_start + skipped, frames - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_next_frame_start_on_time() {
+        setup();
+
+        let period = Duration::from_micros(1000);
+        let start = Duration::from_micros(5000);
+
+        for policy in [OverrunPolicy::Skip, OverrunPolicy::Compress] {
+            let (next, missed) = next_frame_start(policy, start, period, start + period / 2);
+            assert_eq!(next, Duration::from_micros(6000));
+            assert_eq!(missed, 0);
+        }
+    }
+
+    #[test]
+    fn test_next_frame_start_skip() {
+        setup();
+
+        let period = Duration::from_micros(1000);
+        let start = Duration::from_micros(5000);
+
+        let (next, missed) = next_frame_start(
+            OverrunPolicy::Skip,
+            start,
+            period,
+            Duration::from_micros(7500),
+        );
+
+        assert_eq!(next, Duration::from_micros(8000));
+        assert_eq!(missed, 2);
+    }
+
+    #[test]
+    fn test_next_frame_start_skip_many() {
+        setup();
+
+        // more frames missed than fit a u32
+        let period = Duration::from_nanos(2);
+        let start = Duration::from_secs(1);
+        let (next, missed) = next_frame_start(
+            OverrunPolicy::Skip,
+            start,
+            period,
+            start + Duration::from_nanos((1 << 34) + 1),
+        );
+
+        assert_eq!(next, start + Duration::from_nanos((1 << 34) + 2));
+        assert_eq!(missed, 1 << 33);
+    }
+
+    #[test]
+    fn test_next_frame_start_compress() {
+        setup();
+
+        let period = Duration::from_micros(1000);
+        let start = Duration::from_micros(5000);
+
+        let (next, missed) = next_frame_start(
+            OverrunPolicy::Compress,
+            start,
+            period,
+            Duration::from_micros(7500),
+        );
+
+        assert_eq!(next, Duration::from_micros(6000));
+        assert_eq!(missed, 0);
+    }
+
+    #[test]
+    fn test_sleep_until() {
+        setup();
+
+        let deadline = now() + Duration::from_millis(2);
+        sleep_until(deadline);
+
+        assert!(now() >= deadline);
+    }
+
+    #[test]
+    fn test_frame_timer() {
+        setup();
+
+        let period = Duration::from_millis(2);
+        let mut timer = FrameTimer::new(OverrunPolicy::Compress);
+        let first = timer.frame_start();
+
+        for _ in 0..5 {
+            timer.wait(period);
+        }
+
+        // frame starts stay on the absolute grid
+        assert_eq!(timer.frame_start(), first + period * 5);
+        assert_eq!(timer.jitter.count, 5);
+    }
+
+    #[test]
+    fn test_frame_timer_simulated() {
+        setup();
+
+        // a long period passes without sleeping
+        let period = Duration::from_secs(3600);
+        let mut timer = FrameTimer::simulated();
+        let start = now();
+
+        for _ in 0..5 {
+            assert_eq!(timer.elapsed(), Duration::ZERO);
+            assert_eq!(timer.wait(period), Duration::ZERO);
+        }
+
+        assert_eq!(timer.frame_start(), period * 5);
+        assert!(timer.started() >= start);
+        assert!(now() - start < Duration::from_secs(1));
+        assert_eq!(timer.overruns, 0);
+        assert_eq!(timer.jitter.count, 0);
+    }
+
+    #[test]
+    fn test_jitter_stats() {
+        setup();
+
+        let mut stats = JitterStats::default();
+        assert_eq!(stats.mean(), Duration::ZERO);
+
+        stats.add(Duration::from_micros(10));
+        stats.add(Duration::from_micros(30));
+        stats.add(Duration::from_micros(20));
+
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.min, Duration::from_micros(10));
+        assert_eq!(stats.max, Duration::from_micros(30));
+        assert_eq!(stats.mean(), Duration::from_micros(20));
+
+        // counts past u32::MAX
+        let stats = JitterStats {
+            count: 1 << 32,
+            total: Duration::from_micros(3 << 32),
+            ..Default::default()
+        };
+        assert_eq!(stats.mean(), Duration::from_micros(3));
+    }
+
+    #[test]
+    fn test_cpu_times() {
+        setup();
+
+        let mut times = CpuTimes::new();
+        assert_eq!(times.last(), None);
+        assert_eq!(times.percentile(99.0), Duration::ZERO);
+
+        for micros in 1..=100 {
+            times.add(Duration::from_micros(micros));
+        }
+
+        assert_eq!(times.count, 100);
+        assert_eq!(times.min, Duration::from_micros(1));
+        assert_eq!(times.max, Duration::from_micros(100));
+        assert_eq!(times.mean(), Duration::from_nanos(50_500));
+        assert_eq!(times.last(), Some(Duration::from_micros(100)));
+        assert_eq!(times.percentile(50.0), Duration::from_micros(50));
+        assert_eq!(times.percentile(99.0), Duration::from_micros(99));
+        assert_eq!(times.percentile(100.0), Duration::from_micros(100));
+        assert_eq!(times.percentile(0.0), Duration::from_micros(1));
+
+        // counts past u32::MAX
+        let times = CpuTimes {
+            count: (1 << 32) + 1,
+            total: Duration::from_micros(2 * ((1 << 32) + 1)),
+            ..CpuTimes::new()
+        };
+        assert_eq!(times.mean(), Duration::from_micros(2));
+    }
+
+    #[test]
+    fn test_cpu_times_window() {
+        setup();
+
+        let mut times = CpuTimes::new();
+
+        // fill the window with large samples, then replace them all
+        for _ in 0..CPU_TIMES_WINDOW {
+            times.add(Duration::from_millis(10));
+        }
+        for _ in 0..CPU_TIMES_WINDOW {
+            times.add(Duration::from_micros(10));
+        }
+
+        assert_eq!(times.count, 2 * CPU_TIMES_WINDOW as u64);
+        assert_eq!(times.max, Duration::from_millis(10));
+        assert_eq!(times.last(), Some(Duration::from_micros(10)));
+        assert_eq!(times.percentile(100.0), Duration::from_micros(10));
+    }
+}
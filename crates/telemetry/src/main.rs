@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use elafry::telemetry::{Reader, Record, Span, ACTION_COMPLETED, ACTION_STARTED};
+use serde_json::json;
+
+fn usage() -> ! {
+    eprintln!("Usage: telemetry csv <input> [output]");
+    eprintln!("       telemetry trace <output> <input>...");
+    std::process::exit(2);
+}
+
+fn span_name(span: &Span) -> (&'static str, String) {
+    match span {
+        Span::Frame => ("frame", String::new()),
+        Span::Service(service) => ("service", service.name().to_string()),
+        Span::Component(id) => ("component", id.to_string()),
+        Span::Action(kind, id) => ("action", format!("{}:{}", kind.name(), id)),
+    }
+}
+
+/// Convert a telemetry file to csv, one row per record.
+fn to_csv<R: Read, W: Write>(reader: Reader<R>, writer: W) -> io::Result<u64> {
+    let mut writer = csv::Writer::from_writer(writer);
+    let mut count = 0;
+
+    writer.write_record(["frame", "span", "id", "start", "end", "duration", "value"])?;
+
+    for record in reader {
+        let record = record?;
+        let (span, id) = span_name(&record.span);
+
+        writer.write_record([
+            record.frame.to_string(),
+            span.to_string(),
+            id,
+            record.start.to_string(),
+            record.end.to_string(),
+            record.duration().to_string(),
+            record.value.to_string(),
+        ])?;
+        count += 1;
+    }
+
+    writer.flush()?;
+
+    Ok(count)
+}
+
+/// Trace event for one record on thread `tid`, timestamps are in microseconds.
+fn trace_event(record: &Record, pid: usize, tid: usize) -> serde_json::Value {
+    let ts = record.start as f64 / 1000.0;
+    let dur = record.duration() as f64 / 1000.0;
+
+    match record.span {
+        Span::Frame => json!({
+            "name": "frame",
+            "cat": "frame",
+            "ph": "X",
+            "ts": ts,
+            "dur": dur,
+            "pid": pid,
+            "tid": tid,
+            "args": { "frame": record.frame, "overruns": record.value },
+        }),
+        Span::Service(service) => json!({
+            "name": service.name(),
+            "cat": "service",
+            "ph": "X",
+            "ts": ts,
+            "dur": dur,
+            "pid": pid,
+            "tid": tid,
+            "args": { "frame": record.frame },
+        }),
+        Span::Component(id) => json!({
+            "name": format!("component {}", id),
+            "cat": "component",
+            "ph": "X",
+            "ts": ts,
+            "dur": dur,
+            "pid": pid,
+            "tid": tid,
+            "args": { "frame": record.frame, "cpu_ns": record.value },
+        }),
+        Span::Action(kind, id) => json!({
+            "name": kind.name(),
+            "cat": "action",
+            "ph": "i",
+            "s": "p",
+            "ts": ts,
+            "pid": pid,
+            "tid": tid,
+            "args": {
+                "frame": record.frame,
+                "id": id.to_string(),
+                "phase": match record.value {
+                    ACTION_STARTED => "started",
+                    ACTION_COMPLETED => "completed",
+                    _ => "failed",
+                },
+            },
+        }),
+    }
+}
+
+fn write_event<W: Write>(
+    writer: &mut W,
+    first: &mut bool,
+    event: &serde_json::Value,
+) -> io::Result<()> {
+    if !*first {
+        writer.write_all(b",\n")?;
+    }
+    *first = false;
+
+    serde_json::to_writer(writer, event)?;
+
+    Ok(())
+}
+
+/// Convert telemetry files to a Chrome trace event file, each input is shown as its own process.
+///
+/// The frame, services and actions are on the runner thread, each component
+/// gets a thread of its own in the order it first appears.
+fn to_trace<R: Read, W: Write>(inputs: Vec<(String, Reader<R>)>, mut writer: W) -> io::Result<u64> {
+    let mut count = 0;
+    let mut first = true;
+
+    writer.write_all(b"{\"displayTimeUnit\":\"ns\",\"traceEvents\":[")?;
+
+    for (pid, (name, reader)) in inputs.into_iter().enumerate() {
+        let pid = pid + 1;
+
+        // name the process after the file it came from
+        let metadata = json!({
+            "name": "process_name",
+            "ph": "M",
+            "pid": pid,
+            "args": { "name": name },
+        });
+        write_event(&mut writer, &mut first, &metadata)?;
+
+        let mut tids = HashMap::new();
+        let mut components = 0;
+        for record in reader {
+            let record = record?;
+            let track = match record.span {
+                Span::Component(id) => Some(id),
+                _ => None,
+            };
+            let tid = match tids.get(&track) {
+                Some(tid) => *tid,
+                None => {
+                    // name a thread the first time it is used
+                    let (tid, name) = match track {
+                        Some(id) => {
+                            components += 1;
+                            (components + 1, format!("component {}", id))
+                        }
+                        None => (1, "runner".to_string()),
+                    };
+                    let metadata = json!({
+                        "name": "thread_name",
+                        "ph": "M",
+                        "pid": pid,
+                        "tid": tid,
+                        "args": { "name": name },
+                    });
+                    write_event(&mut writer, &mut first, &metadata)?;
+                    tids.insert(track, tid);
+                    tid
+                }
+            };
+
+            write_event(&mut writer, &mut first, &trace_event(&record, pid, tid))?;
+            count += 1;
+        }
+    }
+
+    writer.write_all(b"]}\n")?;
+    writer.flush()?;
+
+    Ok(count)
+}
+
+fn main() {
+    env_logger::init();
+
+    let args: Vec<String> = std::env::args().collect();
+
+    let result = match args.get(1).map(|command| command.as_str()) {
+        Some("csv") => {
+            let input = args.get(2).unwrap_or_else(|| usage());
+            let output = args
+                .get(3)
+                .cloned()
+                .unwrap_or_else(|| format!("{}.csv", input.trim_end_matches(".bin")));
+
+            Reader::open(input).and_then(|reader| {
+                let file = std::fs::File::create(&output)?;
+                let count = to_csv(reader, file)?;
+                log::info!("Wrote {} records to {}", count, output);
+                Ok(())
+            })
+        }
+        Some("trace") => {
+            let output = args.get(2).unwrap_or_else(|| usage());
+            if args.len() < 4 {
+                usage();
+            }
+
+            args[3..]
+                .iter()
+                .map(|input| Reader::open(input).map(|reader| (input.clone(), reader)))
+                .collect::<io::Result<Vec<_>>>()
+                .and_then(|inputs| {
+                    let file = io::BufWriter::new(std::fs::File::create(output)?);
+                    let count = to_trace(inputs, file)?;
+                    log::info!("Wrote {} events to {}", count, output);
+                    Ok(())
+                })
+        }
+        _ => usage(),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Failed to convert telemetry; err = {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elafry::telemetry::{ActionKind, Service, MAGIC, VERSION};
+
+    // setup logging
+    fn setup() {
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or("warn,info,debug,trace"),
+        )
+        .is_test(true)
+        .try_init();
+    }
+
+    #[test]
+    fn test_to_csv() {
+        setup();
+
+        let id = uuid::Uuid::new_v4();
+
+        // build a telemetry file in memory
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        for span in [
+            Span::Frame,
+            Span::Service(Service::Communication),
+            Span::Component(id),
+        ] {
+            let record = Record {
+                frame: 3,
+                span,
+                start: 10,
+                end: 25,
+                value: 1,
+            };
+            data.extend_from_slice(&record.encode());
+        }
+
+        let mut output = vec![];
+        let count = to_csv(Reader::new(&data[..]).unwrap(), &mut output).unwrap();
+        assert_eq!(count, 3);
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "frame,span,id,start,end,duration,value");
+        assert_eq!(lines[1], "3,frame,,10,25,15,1");
+        assert_eq!(lines[2], "3,service,communication,10,25,15,1");
+        assert_eq!(lines[3], format!("3,component,{},10,25,15,1", id));
+    }
+
+    fn telemetry_file(records: &[Record]) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        for record in records {
+            data.extend_from_slice(&record.encode());
+        }
+        data
+    }
+
+    #[test]
+    fn test_to_trace() {
+        setup();
+
+        let id = uuid::Uuid::new_v4();
+        let action_id = uuid::Uuid::new_v4();
+        let other = uuid::Uuid::new_v4();
+
+        let runner = telemetry_file(&[
+            Record {
+                frame: 1,
+                span: Span::Frame,
+                start: 1_000_000,
+                end: 1_900_000,
+                value: 0,
+            },
+            Record {
+                frame: 1,
+                span: Span::Service(Service::Scheduler),
+                start: 1_000_000,
+                end: 1_500_000,
+                value: 0,
+            },
+            Record {
+                frame: 1,
+                span: Span::Action(ActionKind::AddRoute, action_id),
+                start: 1_600_000,
+                end: 1_600_000,
+                value: ACTION_COMPLETED,
+            },
+            Record {
+                frame: 1,
+                span: Span::Component(id),
+                start: 1_100_000,
+                end: 1_150_000,
+                value: 0,
+            },
+            Record {
+                frame: 1,
+                span: Span::Component(other),
+                start: 1_150_000,
+                end: 1_200_000,
+                value: 0,
+            },
+        ]);
+        let component = telemetry_file(&[Record {
+            frame: 0,
+            span: Span::Component(id),
+            start: 1_100_000,
+            end: 1_150_500,
+            value: 0,
+        }]);
+
+        let mut output = vec![];
+        let count = to_trace(
+            vec![
+                (
+                    "telemetry.bin".to_string(),
+                    Reader::new(&runner[..]).unwrap(),
+                ),
+                (
+                    "telemetry-component.bin".to_string(),
+                    Reader::new(&component[..]).unwrap(),
+                ),
+            ],
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(count, 6);
+
+        let trace: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 12);
+
+        // each input is a process
+        assert_eq!(events[0]["ph"], "M");
+        assert_eq!(events[0]["args"]["name"], "telemetry.bin");
+        assert_eq!(events[9]["pid"], 2);
+
+        // the runner is a thread, named when first used
+        assert_eq!(events[1]["name"], "thread_name");
+        assert_eq!(events[1]["tid"], 1);
+        assert_eq!(events[1]["args"]["name"], "runner");
+
+        assert_eq!(events[2]["name"], "frame");
+        assert_eq!(events[2]["tid"], 1);
+        assert_eq!(events[2]["ts"], 1000.0);
+        assert_eq!(events[2]["dur"], 900.0);
+        assert_eq!(events[3]["name"], "scheduler");
+        assert_eq!(events[3]["tid"], 1);
+
+        assert_eq!(events[4]["name"], "add-route");
+        assert_eq!(events[4]["tid"], 1);
+        assert_eq!(events[4]["ph"], "i");
+        assert_eq!(events[4]["args"]["id"], action_id.to_string());
+        assert_eq!(events[4]["args"]["phase"], "completed");
+
+        // and so is each component
+        assert_eq!(events[5]["name"], "thread_name");
+        assert_eq!(events[5]["args"]["name"], format!("component {}", id));
+        assert_eq!(events[6]["tid"], 2);
+        assert_eq!(events[7]["args"]["name"], format!("component {}", other));
+        assert_eq!(events[8]["tid"], 3);
+
+        // threads are numbered per process
+        assert_eq!(events[10]["tid"], 2);
+        assert_eq!(events[11]["name"], format!("component {}", id));
+        assert_eq!(events[11]["tid"], 2);
+        assert_eq!(events[11]["dur"], 50.5);
+    }
+}